@@ -1,14 +1,20 @@
+use crate::sys::mem_map::virtio::VIRTIO_SLOTS;
 use bytesize::ByteSize;
 use clap::{Parser, ValueHint};
 use clap_num::maybe_hex;
-use std::path::PathBuf;
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    os::unix::fs::FileTypeExt,
+    path::PathBuf,
+};
 
 /// A simple RISC-V simulation
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(version)]
 pub struct Config {
-    /// Binary file to load into RAM
-    #[arg(required = true, value_hint = ValueHint::FilePath)]
+    /// Binary or ELF file to load into RAM (an ELF also sets the entry point)
+    #[arg(required_unless_present_any = ["restore", "sbi"], value_hint = ValueHint::FilePath)]
     pub binary: Option<PathBuf>,
 
     /// Size of the RAM
@@ -23,19 +29,239 @@ pub struct Config {
     #[arg(long, value_hint = ValueHint::FilePath)]
     pub dtb: Option<PathBuf>,
 
-    /// Kernel (load at 0x00400000)
+    /// Kernel (load at 0x00400000, or at its own addresses if ELF)
     #[arg(short = 'k', long, value_hint = ValueHint::FilePath)]
     pub kernel: Option<PathBuf>,
 
     /// Print extra information
     #[arg(short = 'v', long)]
     pub verbose: bool,
+
+    /// Number of PMP entries
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u8).range(0..=64))]
+    pub pmp_entries: u8,
+
+    /// Disable the bitmanip extensions (Zba, Zbb, Zbc and Zbs), making them illegal instructions
+    #[arg(long = "no-bitmanip", action = clap::ArgAction::SetFalse)]
+    pub bitmanip: bool,
+
+    /// Length of the vector registers in bits (a power of 2 from 32 to 1024)
+    #[arg(long, default_value_t = 128, value_parser = parse_vlen)]
+    pub vlen: u32,
+
+    /// Ticks of the timer a pause instruction skips ahead, also letting another hart run
+    /// (by default, pause is a NOP)
+    #[arg(long, value_name = "TICKS", default_value_t = 0)]
+    pub pause_skip: u64,
+
+    /// Wait for a GDB connection on this port (localhost) before running
+    #[arg(long, value_name = "PORT")]
+    pub gdb: Option<u16>,
+
+    /// Save a snapshot of the machine to this file after --snapshot-at steps
+    #[arg(long, value_name = "FILE", requires = "snapshot_at", value_hint = ValueHint::FilePath)]
+    pub snapshot: Option<PathBuf>,
+
+    /// Number of steps to run before saving the snapshot
+    #[arg(long, value_name = "STEPS", requires = "snapshot")]
+    pub snapshot_at: Option<u64>,
+
+    /// Resume from a snapshot file
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub restore: Option<PathBuf>,
+
+    /// Number of harts
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=32))]
+    pub harts: u8,
+
+    /// Number of steps a hart runs before switching to another one
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    pub quantum: u64,
+
+    /// Interleave harts pseudo-randomly (with random quanta) using this seed
+    #[arg(long, value_name = "SEED")]
+    pub sched_seed: Option<u64>,
+
+    /// Use the built-in SBI firmware, starting the kernel in S-mode
+    #[arg(long)]
+    pub sbi: bool,
+
+    /// Run the binary as a statically linked Linux program in U-mode, emulating its system calls
+    #[arg(long, requires = "binary", conflicts_with_all = ["sbi", "kernel"])]
+    pub user: bool,
+
+    /// Arguments of the program (with --user)
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, requires = "user")]
+    pub args: Vec<String>,
+
+    /// Service semihosting calls (ebreak between slli and srai) with host files and stdout
+    #[arg(long)]
+    pub semihosting: bool,
+
+    /// Address of tohost for HTIF (by default, the tohost symbol of the ELF if any)
+    #[arg(long, value_name = "ADDR", value_parser = maybe_hex::<u32>)]
+    pub tohost: Option<u32>,
+
+    /// Disk image for a virtio block device: file=PATH[,readonly=on][,snapshot=on]
+    /// (snapshot keeps the writes in memory; repeat for more devices)
+    #[arg(long, value_name = "OPTIONS", value_parser = parse_drive)]
+    pub drive: Vec<Drive>,
+
+    /// Virtio network device: BACKEND[,mac=XX:XX:XX:XX:XX:XX][,link=up|down], where BACKEND is
+    /// socket,path=PATH,peer=PATH (datagrams between two simulators), pcap,file=PATH (capture
+    /// the sent frames), loopback (receive the sent frames), or echo (answer ARP and ping)
+    #[arg(long, value_name = "OPTIONS", value_parser = parse_nic)]
+    pub nic: Vec<Nic>,
+}
+
+fn parse_vlen(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(vlen) if vlen.is_power_of_two() && (32..=1024).contains(&vlen) => Ok(vlen),
+        _ => Err(format!("expected a power of 2 in 32..=1024, found '{s}'")),
+    }
+}
+
+// Raw disk image backing a virtio block device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Drive {
+    pub file: PathBuf,
+    pub read_only: bool,
+    pub snapshot: bool, // Copy-on-write: the image is never written
+}
+
+impl Drive {
+    // Written only if it is neither read-only nor copy-on-write
+    pub fn open(&self) -> io::Result<File> {
+        let writable = !self.read_only && !self.snapshot;
+        OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .open(&self.file)
+    }
+}
+
+fn parse_drive(s: &str) -> Result<Drive, String> {
+    let mut drive = Drive {
+        file: PathBuf::new(),
+        read_only: false,
+        snapshot: false,
+    };
+    for opt in s.split(',') {
+        let (key, val) = opt
+            .split_once('=')
+            .ok_or(format!("expected key=value, found '{opt}'"))?;
+        match key {
+            "file" => drive.file = PathBuf::from(val),
+            "readonly" => drive.read_only = parse_switch(key, val, "on", "off")?,
+            "snapshot" => drive.snapshot = parse_switch(key, val, "on", "off")?,
+            _ => return Err(format!("unknown drive option '{key}'")),
+        }
+    }
+    if drive.file.as_os_str().is_empty() {
+        return Err("missing file=PATH".to_string());
+    }
+    Ok(drive)
+}
+
+// Backend of a virtio network device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NicBackend {
+    Socket { path: PathBuf, peer: PathBuf }, // Bound to path, sending to peer
+    Pcap(PathBuf),
+    Loopback,
+    Echo,
+}
+
+impl NicBackend {
+    // The path of a backend which cannot be opened: the socket must be in an existing
+    // directory (replacing a previous socket only), and the capture must be writable
+    fn invalid_path(&self) -> Option<&PathBuf> {
+        match self {
+            NicBackend::Socket { path, .. } => {
+                let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+                let in_dir = dir.is_none_or(|d| d.is_dir());
+                let is_free =
+                    fs::symlink_metadata(path).map_or(true, |m| m.file_type().is_socket());
+                (!in_dir || !is_free).then_some(path)
+            }
+            NicBackend::Pcap(path) => {
+                let file = OpenOptions::new().append(true).create(true).open(path);
+                file.is_err().then_some(path)
+            }
+            NicBackend::Loopback | NicBackend::Echo => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nic {
+    pub backend: NicBackend,
+    pub mac: Option<[u8; 6]>, // By default, 52:54:00:12:34:56 plus the number of the device
+    pub link_up: bool,
+}
+
+fn parse_nic(s: &str) -> Result<Nic, String> {
+    let (name, opts) = s.split_once(',').unwrap_or((s, ""));
+    let (mut mac, mut link_up) = (None, true);
+    let (mut path, mut peer, mut file) = (None, None, None);
+    for opt in opts.split(',').filter(|o| !o.is_empty()) {
+        let (key, val) = opt
+            .split_once('=')
+            .ok_or(format!("expected key=value, found '{opt}'"))?;
+        match key {
+            "mac" => mac = Some(parse_mac(val).ok_or(format!("invalid MAC address '{val}'"))?),
+            "link" => link_up = parse_switch(key, val, "up", "down")?,
+            "path" => path = Some(PathBuf::from(val)),
+            "peer" => peer = Some(PathBuf::from(val)),
+            "file" => file = Some(PathBuf::from(val)),
+            _ => return Err(format!("unknown nic option '{key}'")),
+        }
+    }
+    let backend = match (name, path, peer, file) {
+        ("socket", Some(path), Some(peer), None) => NicBackend::Socket { path, peer },
+        ("socket", ..) => return Err("socket needs path=PATH and peer=PATH".to_string()),
+        ("pcap", None, None, Some(file)) => NicBackend::Pcap(file),
+        ("pcap", ..) => return Err("pcap needs file=PATH".to_string()),
+        ("loopback", None, None, None) => NicBackend::Loopback,
+        ("echo", None, None, None) => NicBackend::Echo,
+        ("loopback" | "echo", ..) => return Err(format!("{name} takes no path")),
+        _ => return Err(format!("unknown nic backend '{name}'")),
+    };
+    Ok(Nic {
+        backend,
+        mac,
+        link_up,
+    })
+}
+
+fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let bytes = s
+        .split(':')
+        .map(|b| match b.len() {
+            2 => u8::from_str_radix(b, 16).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    bytes.try_into().ok()
+}
+
+fn parse_switch(key: &str, val: &str, on: &str, off: &str) -> Result<bool, String> {
+    match val {
+        _ if val == on => Ok(true),
+        _ if val == off => Ok(false),
+        _ => Err(format!("expected {on} or {off} for {key}, found '{val}'")),
+    }
 }
 
 pub enum ConfigError {
     InvalidBinary(PathBuf),
     InvalidDtb(PathBuf),
     InvalidKernel(PathBuf),
+    InvalidRestore(PathBuf),
+    InvalidDrive(PathBuf),
+    InvalidNic(PathBuf),
+    TooManyDevices(usize),
+    NoSnapshot(&'static str), // Snapshots cannot capture the state of this option
 }
 
 impl Config {
@@ -47,6 +273,24 @@ impl Config {
             dtb: None,
             kernel: None,
             verbose: true,
+            pmp_entries: 16,
+            bitmanip: true,
+            vlen: 128,
+            pause_skip: 0,
+            gdb: None,
+            snapshot: None,
+            snapshot_at: None,
+            restore: None,
+            harts: 1,
+            quantum: 100,
+            sched_seed: None,
+            sbi: false,
+            user: false,
+            args: vec![],
+            semihosting: false,
+            tohost: None,
+            drive: vec![],
+            nic: vec![],
         }
     }
 
@@ -66,6 +310,166 @@ impl Config {
                 return Err(ConfigError::InvalidKernel(self.dtb.unwrap()));
             }
         }
+        if let Some(path) = &self.restore {
+            if !path.is_file() {
+                return Err(ConfigError::InvalidRestore(self.restore.unwrap()));
+            }
+        }
+        // The host side of an emulated process (brk, mappings and files) is not saved
+        if self.user && (self.snapshot.is_some() || self.restore.is_some()) {
+            return Err(ConfigError::NoSnapshot("--user"));
+        }
+        // Nor are the files opened by semihosting calls
+        if self.semihosting && (self.snapshot.is_some() || self.restore.is_some()) {
+            return Err(ConfigError::NoSnapshot("--semihosting"));
+        }
+        for drive in self.drive.iter() {
+            if !drive.file.is_file() || drive.open().is_err() {
+                return Err(ConfigError::InvalidDrive(drive.file.clone()));
+            }
+        }
+        for nic in self.nic.iter() {
+            if let Some(path) = nic.backend.invalid_path() {
+                return Err(ConfigError::InvalidNic(path.clone()));
+            }
+        }
+        // Drives and network devices share the virtio slots
+        let num_devices = self.drive.len() + self.nic.len();
+        if num_devices > VIRTIO_SLOTS {
+            return Err(ConfigError::TooManyDevices(num_devices));
+        }
         Ok(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_drive() {
+        assert_eq!(
+            parse_drive("file=disk.img"),
+            Ok(Drive {
+                file: PathBuf::from("disk.img"),
+                read_only: false,
+                snapshot: false,
+            })
+        );
+        assert_eq!(
+            parse_drive("file=a.img,readonly=on,snapshot=off"),
+            Ok(Drive {
+                file: PathBuf::from("a.img"),
+                read_only: true,
+                snapshot: false,
+            })
+        );
+        assert!(parse_drive("snapshot=on").is_err());
+        assert!(parse_drive("file=a.img,readonly=yes").is_err());
+        assert!(parse_drive("file=a.img,format=raw").is_err());
+        assert!(parse_drive("a.img").is_err());
+    }
+
+    #[test]
+    fn test_parse_vlen() {
+        assert_eq!(parse_vlen("32"), Ok(32));
+        assert_eq!(parse_vlen("1024"), Ok(1024));
+        assert!(parse_vlen("16").is_err());
+        assert!(parse_vlen("96").is_err());
+        assert!(parse_vlen("2048").is_err());
+    }
+
+    #[test]
+    fn test_parse_nic() {
+        assert_eq!(
+            parse_nic("socket,path=a.sock,peer=b.sock,link=down"),
+            Ok(Nic {
+                backend: NicBackend::Socket {
+                    path: PathBuf::from("a.sock"),
+                    peer: PathBuf::from("b.sock"),
+                },
+                mac: None,
+                link_up: false,
+            })
+        );
+        assert_eq!(
+            parse_nic("echo,mac=02:00:00:aa:bb:0c"),
+            Ok(Nic {
+                backend: NicBackend::Echo,
+                mac: Some([0x02, 0x00, 0x00, 0xaa, 0xbb, 0x0c]),
+                link_up: true,
+            })
+        );
+        assert_eq!(
+            parse_nic("pcap,file=out.pcap").unwrap().backend,
+            NicBackend::Pcap(PathBuf::from("out.pcap"))
+        );
+        assert_eq!(parse_nic("loopback").unwrap().backend, NicBackend::Loopback);
+        assert!(parse_nic("socket,path=a.sock").is_err());
+        assert!(parse_nic("echo,file=out.pcap").is_err());
+        assert!(parse_nic("tap").is_err());
+        assert!(parse_nic("echo,mac=02:00:00:aa:bb").is_err());
+        assert!(parse_nic("echo,mac=02:00:00:aa:bb:0").is_err());
+        assert!(parse_nic("echo,link=on").is_err());
+    }
+
+    #[test]
+    fn test_validate_snapshot() {
+        let cfg = Config {
+            user: true,
+            snapshot: Some(PathBuf::from("a.snap")),
+            snapshot_at: Some(10),
+            ..Config::new()
+        };
+        assert!(matches!(
+            cfg.validate(),
+            Err(ConfigError::NoSnapshot("--user"))
+        ));
+        let cfg = Config {
+            semihosting: true,
+            snapshot: Some(PathBuf::from("a.snap")),
+            snapshot_at: Some(10),
+            ..Config::new()
+        };
+        assert!(matches!(
+            cfg.validate(),
+            Err(ConfigError::NoSnapshot("--semihosting"))
+        ));
+        let cfg = Config {
+            snapshot: Some(PathBuf::from("a.snap")),
+            snapshot_at: Some(10),
+            ..Config::new()
+        };
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_nic() {
+        let nic = |backend| Config {
+            nic: vec![Nic {
+                backend,
+                mac: None,
+                link_up: true,
+            }],
+            ..Config::new()
+        };
+        let dir = std::env::temp_dir();
+        let missing = dir.join("riscv_sim_missing").join("a.sock");
+        let cfg = nic(NicBackend::Socket {
+            path: missing.clone(),
+            peer: missing.clone(),
+        });
+        assert!(matches!(cfg.validate(), Err(ConfigError::InvalidNic(p)) if p == missing));
+        // Not a socket
+        let cfg = nic(NicBackend::Socket {
+            path: dir.clone(),
+            peer: missing.clone(),
+        });
+        assert!(matches!(cfg.validate(), Err(ConfigError::InvalidNic(_))));
+        assert!(matches!(
+            nic(NicBackend::Pcap(dir.clone())).validate(),
+            Err(ConfigError::InvalidNic(_))
+        ));
+        assert!(nic(NicBackend::Loopback).validate().is_ok());
+    }
+}
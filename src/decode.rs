@@ -1,4 +1,8 @@
-use crate::instr::{csr::CsrReg, format::*, funct::*, *};
+use crate::instr::{csr::CsrReg, format::*, funct::*, reg::Reg, *};
+
+mod compressed;
+
+pub use compressed::{decode_compressed, is_compressed};
 
 const OPCODE_MASK: u32 = (1 << 7) - 1;
 
@@ -14,6 +18,22 @@ const OPCODE_BRANCH: u8 = 0b1100011;
 const OPCODE_AMO: u8 = 0b0101111;
 const OPCODE_MISC: u8 = 0b0001111;
 const OPCODE_SYSTEM: u8 = 0b1110011;
+const OPCODE_LOAD_FP: u8 = 0b0000111;
+const OPCODE_STORE_FP: u8 = 0b0100111;
+const OPCODE_MADD: u8 = 0b1000011;
+const OPCODE_MSUB: u8 = 0b1000111;
+const OPCODE_NMSUB: u8 = 0b1001011;
+const OPCODE_NMADD: u8 = 0b1001111;
+const OPCODE_OP_FP: u8 = 0b1010011;
+const OPCODE_OP_V: u8 = 0b1010111;
+
+// pause is the fence with pred = W, succ = 0 and fm = 0 (a hint)
+const PAUSE: u32 = 0x0100000f;
+// Fixed bits of the may-be-operations mop.r.N and mop.rr.N (Zimop)
+const MOP_R_MASK: u32 = 0xb3c0707f;
+const MOP_R: u32 = 0x81c04073;
+const MOP_RR_MASK: u32 = 0xb200707f;
+const MOP_RR: u32 = 0x82004073;
 
 pub fn decode(code: u32) -> Option<Instr> {
     let opcode = (code & OPCODE_MASK) as u8;
@@ -22,9 +42,14 @@ pub fn decode(code: u32) -> Option<Instr> {
         OPCODE_OPIMM => {
             let funct = OpImmFunct::from(code)?;
             match funct {
-                OpImmFunct::Sll | OpImmFunct::Srl | OpImmFunct::Sra => {
-                    Some(Instr::OpImm(IType::from_shamt(code), funct))
-                }
+                OpImmFunct::Sll
+                | OpImmFunct::Srl
+                | OpImmFunct::Sra
+                | OpImmFunct::Rori
+                | OpImmFunct::Bclri
+                | OpImmFunct::Bexti
+                | OpImmFunct::Binvi
+                | OpImmFunct::Bseti => Some(Instr::OpImm(IType::from_shamt(code), funct)),
                 _ => Some(Instr::OpImm(IType::from(code), funct)),
             }
         }
@@ -36,8 +61,39 @@ pub fn decode(code: u32) -> Option<Instr> {
         OPCODE_JALR => Some(Instr::Jalr(IType::from(code))),
         OPCODE_BRANCH => Some(Instr::Branch(BType::from(code), BranchFunct::from(code)?)),
         OPCODE_AMO => Some(Instr::Atomic(RType::from(code), AtomicFunct::from(code)?)),
-        OPCODE_MISC => Some(Instr::Fence),
+        OPCODE_MISC if code == PAUSE => Some(Instr::Pause),
+        OPCODE_MISC => Some(Instr::Fence(FenceType::from(code), FenceFunct::from(code)?)),
+        OPCODE_SYSTEM if code & MOP_R_MASK == MOP_R || code & MOP_RR_MASK == MOP_RR => {
+            Some(Instr::Mop(IType::from(code).rd))
+        }
         OPCODE_SYSTEM => decode_system(code),
+        // Vector loads and stores share the opcodes with the other widths
+        OPCODE_LOAD_FP => match LoadFpFunct::from(code) {
+            Some(f) => Some(Instr::LoadFp(IType::from(code), f)),
+            None => Some(Instr::LoadV(
+                VMemType::from(code),
+                VMemFunct::from(code, true)?,
+            )),
+        },
+        OPCODE_STORE_FP => match StoreFpFunct::from(code) {
+            Some(f) => Some(Instr::StoreFp(SType::from(code), f)),
+            None => Some(Instr::StoreV(
+                VMemType::from(code),
+                VMemFunct::from(code, false)?,
+            )),
+        },
+        OPCODE_MADD | OPCODE_MSUB | OPCODE_NMSUB | OPCODE_NMADD => Some(Instr::Fma(
+            R4Type::from(code),
+            FpFmt::from(code)?,
+            FmaFunct::from(code)?,
+        )),
+        OPCODE_OP_FP => Some(Instr::OpFp(
+            RType::from(code),
+            FpFmt::from(code)?,
+            OpFpFunct::from(code)?,
+        )),
+        OPCODE_OP_V if funct3(code) == 0b111 => Some(Instr::SetVl(VSetVlType::from(code)?)),
+        OPCODE_OP_V => Some(Instr::OpV(OpVType::from(code), OpVFunct::from(code)?)),
         _ => None,
     }
 }
@@ -46,6 +102,10 @@ fn decode_system(code: u32) -> Option<Instr> {
     let IType { rd, rs1, imm } = IType::from(code);
     let f3 = funct3(code);
     match f3 {
+        // sfence.vma rs1, rs2
+        0b000 if imm >> 5 == 0b0001001 && rd.index() == 0 => {
+            Some(Instr::Env(EnvFunct::SfenceVma(rs1, Reg::extract_rs2(code))))
+        }
         0b000 => {
             if rd.index() != 0 || rs1.index() != 0 {
                 return None;
@@ -56,7 +116,6 @@ fn decode_system(code: u32) -> Option<Instr> {
                 0b0001000_00010 => Some(Instr::Env(EnvFunct::Sret)),
                 0b0011000_00010 => Some(Instr::Env(EnvFunct::Mret)),
                 0b0001000_00101 => Some(Instr::Env(EnvFunct::Wfi)),
-                0b0001001_00000..=0b0001001_11111 => Some(Instr::Env(EnvFunct::SfenceVma)),
                 _ => None,
             }
         }
@@ -174,9 +233,17 @@ mod tests {
                 imm: -1903
             })
         );
-        assert_eq!(decode(0x0ff0000f).unwrap(), Instr::Fence);
         assert_eq!(decode(0x00000073).unwrap(), Instr::Env(EnvFunct::Call));
         assert_eq!(decode(0x00100073).unwrap(), Instr::Env(EnvFunct::Break));
+        assert_eq!(
+            decode(0x12b50073).unwrap(),
+            Instr::Env(EnvFunct::SfenceVma(Reg::new(10), Reg::new(11)))
+        );
+        assert_eq!(
+            decode(0x12000073).unwrap(),
+            Instr::Env(EnvFunct::SfenceVma(Reg::zero(), Reg::zero()))
+        );
+        assert_eq!(decode(0x12b500f3), None);
     }
 
     #[test]
@@ -198,6 +265,161 @@ mod tests {
         assert_eq!(decode(0x02157933).unwrap(), Instr::Op(RType { rd: Reg::new(18), rs1: Reg::new(10), rs2: Reg::new( 1)}, OpFunct::M(OpMFunct::Remu)));
     }
 
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_op_bitmanip() {
+        let r = |rd, rs1, rs2| RType { rd: Reg::new(rd), rs1: Reg::new(rs1), rs2: Reg::new(rs2) };
+        assert_eq!(decode(0x21ad2533).unwrap(), Instr::Op(r(10, 26, 26), OpFunct::B(OpBFunct::Sh1add)));
+        assert_eq!(decode(0x20df4933).unwrap(), Instr::Op(r(18, 30, 13), OpFunct::B(OpBFunct::Sh2add)));
+        assert_eq!(decode(0x20f5ef33).unwrap(), Instr::Op(r(30, 11, 15), OpFunct::B(OpBFunct::Sh3add)));
+        assert_eq!(decode(0x41707033).unwrap(), Instr::Op(r( 0,  0, 23), OpFunct::B(OpBFunct::Andn)));
+        assert_eq!(decode(0x40926db3).unwrap(), Instr::Op(r(27,  4,  9), OpFunct::B(OpBFunct::Orn)));
+        assert_eq!(decode(0x40274733).unwrap(), Instr::Op(r(14, 14,  2), OpFunct::B(OpBFunct::Xnor)));
+        assert_eq!(decode(0x0bcd6db3).unwrap(), Instr::Op(r(27, 26, 28), OpFunct::B(OpBFunct::Max)));
+        assert_eq!(decode(0x0bfaf133).unwrap(), Instr::Op(r( 2, 21, 31), OpFunct::B(OpBFunct::Maxu)));
+        assert_eq!(decode(0x0a1bc3b3).unwrap(), Instr::Op(r( 7, 23,  1), OpFunct::B(OpBFunct::Min)));
+        assert_eq!(decode(0x0a72d4b3).unwrap(), Instr::Op(r( 9,  5,  7), OpFunct::B(OpBFunct::Minu)));
+        assert_eq!(decode(0x60ae90b3).unwrap(), Instr::Op(r( 1, 29, 10), OpFunct::B(OpBFunct::Rol)));
+        assert_eq!(decode(0x61fcdab3).unwrap(), Instr::Op(r(21, 25, 31), OpFunct::B(OpBFunct::Ror)));
+        assert_eq!(decode(0x0805c533).unwrap(), Instr::Op(r(10, 11,  0), OpFunct::B(OpBFunct::ZextH)));
+        assert_eq!(decode(0x0a2594b3).unwrap(), Instr::Op(r( 9, 11,  2), OpFunct::B(OpBFunct::Clmul)));
+        assert_eq!(decode(0x0a47bf33).unwrap(), Instr::Op(r(30, 15,  4), OpFunct::B(OpBFunct::Clmulh)));
+        assert_eq!(decode(0x0a46a633).unwrap(), Instr::Op(r(12, 13,  4), OpFunct::B(OpBFunct::Clmulr)));
+        assert_eq!(decode(0x498f13b3).unwrap(), Instr::Op(r( 7, 30, 24), OpFunct::B(OpBFunct::Bclr)));
+        assert_eq!(decode(0x489ad633).unwrap(), Instr::Op(r(12, 21,  9), OpFunct::B(OpBFunct::Bext)));
+        assert_eq!(decode(0x69791d33).unwrap(), Instr::Op(r(26, 18, 23), OpFunct::B(OpBFunct::Binv)));
+        assert_eq!(decode(0x294c1e33).unwrap(), Instr::Op(r(28, 24, 20), OpFunct::B(OpBFunct::Bset)));
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_zicond() {
+        let r = |rd, rs1, rs2| RType { rd: Reg::new(rd), rs1: Reg::new(rs1), rs2: Reg::new(rs2) };
+        assert_eq!(decode(0x0ec5d533).unwrap(), Instr::Op(r(10, 11, 12), OpFunct::Cond(OpCondFunct::CzeroEqz)));
+        assert_eq!(decode(0x0ec5f533).unwrap(), Instr::Op(r(10, 11, 12), OpFunct::Cond(OpCondFunct::CzeroNez)));
+        assert_eq!(decode(0x0ec5e533), None); // funct3 = 110
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_fence() {
+        let fence = |pred, succ, f| Instr::Fence(FenceType { pred, succ }, f);
+        assert_eq!(decode(0x0ff0000f).unwrap(), fence(0b1111, 0b1111, FenceFunct::Fence)); // fence
+        assert_eq!(decode(0x0310000f).unwrap(), fence(0b0011, 0b0001, FenceFunct::Fence)); // fence rw, w
+        assert_eq!(decode(0x0840000f).unwrap(), fence(0b1000, 0b0100, FenceFunct::Fence)); // fence i, o
+        assert_eq!(decode(0x8330000f).unwrap(), fence(0b0011, 0b0011, FenceFunct::Tso));   // fence.tso
+        assert_eq!(decode(0x4330000f).unwrap(), fence(0b0011, 0b0011, FenceFunct::Fence)); // Reserved fm
+        assert_eq!(decode(0x0000100f).unwrap(), fence(0, 0, FenceFunct::I));               // fence.i
+        assert_eq!(decode(0x0000200f), None);
+        assert_eq!(decode(0x0ff0700f), None);
+    }
+
+    #[test]
+    fn test_decode_hints() {
+        assert_eq!(decode(0x0100000f).unwrap(), Instr::Pause);
+        assert!(matches!(decode(0x0110000f), Some(Instr::Fence(..)))); // fence w, w
+        assert_eq!(decode(0x81c342f3).unwrap(), Instr::Mop(Reg::new(5))); // mop.r.0 t0, t1
+        assert_eq!(decode(0xcdf5c573).unwrap(), Instr::Mop(Reg::new(10))); // mop.r.31 a0, a1
+        assert_eq!(decode(0xcec5c573).unwrap(), Instr::Mop(Reg::new(10))); // mop.rr.7 a0, a1, a2
+        assert_eq!(decode(0x80004073), None);
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_opimm_bitmanip() {
+        assert_eq!(decode(0x600c1f93).unwrap(), Instr::OpImm(IType { rd: Reg::new(31), rs1: Reg::new(24), imm: 1536 }, OpImmFunct::Clz));
+        assert_eq!(decode(0x60109893).unwrap(), Instr::OpImm(IType { rd: Reg::new(17), rs1: Reg::new( 1), imm: 1537 }, OpImmFunct::Ctz));
+        assert_eq!(decode(0x60251293).unwrap(), Instr::OpImm(IType { rd: Reg::new( 5), rs1: Reg::new(10), imm: 1538 }, OpImmFunct::Cpop));
+        assert_eq!(decode(0x60401f93).unwrap(), Instr::OpImm(IType { rd: Reg::new(31), rs1: Reg::new( 0), imm: 1540 }, OpImmFunct::SextB));
+        assert_eq!(decode(0x60521f93).unwrap(), Instr::OpImm(IType { rd: Reg::new(31), rs1: Reg::new( 4), imm: 1541 }, OpImmFunct::SextH));
+        assert_eq!(decode(0x287a5713).unwrap(), Instr::OpImm(IType { rd: Reg::new(14), rs1: Reg::new(20), imm: 647  }, OpImmFunct::OrcB));
+        assert_eq!(decode(0x6985d393).unwrap(), Instr::OpImm(IType { rd: Reg::new( 7), rs1: Reg::new(11), imm: 1688 }, OpImmFunct::Rev8));
+        assert_eq!(decode(0x615b5513).unwrap(), Instr::OpImm(IType { rd: Reg::new(10), rs1: Reg::new(22), imm: 21   }, OpImmFunct::Rori));
+        assert_eq!(decode(0x48f99b13).unwrap(), Instr::OpImm(IType { rd: Reg::new(22), rs1: Reg::new(19), imm: 15   }, OpImmFunct::Bclri));
+        assert_eq!(decode(0x48455313).unwrap(), Instr::OpImm(IType { rd: Reg::new( 6), rs1: Reg::new(10), imm: 4    }, OpImmFunct::Bexti));
+        assert_eq!(decode(0x697d9593).unwrap(), Instr::OpImm(IType { rd: Reg::new(11), rs1: Reg::new(27), imm: 23   }, OpImmFunct::Binvi));
+        assert_eq!(decode(0x28f11513).unwrap(), Instr::OpImm(IType { rd: Reg::new(10), rs1: Reg::new( 2), imm: 15   }, OpImmFunct::Bseti));
+    }
+
+    #[test]
+    fn test_decode_bitmanip_illegal() {
+        assert_eq!(decode(0x0815c533), None); // zext.h with rs2 != 0
+        assert_eq!(decode(0x60359513), None); // clz with reserved rs2 = 3
+        assert_eq!(decode(0x6885d513), None); // rev8 (RV64 encoding)
+        assert_eq!(decode(0x63f5d513), None); // rori with shamt[5] set
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_vsetvl() {
+        let setvl = |rd, avl, vtype| Instr::SetVl(VSetVlType { rd: Reg::new(rd), avl, vtype });
+        assert_eq!(decode(0x0515f557).unwrap(), setvl(10, VlSrc::Reg(Reg::new(11)), VTypeSrc::Imm(0x51)));
+        assert_eq!(decode(0xc878f2d7).unwrap(), setvl( 5, VlSrc::Imm(17), VTypeSrc::Imm(0x87)));
+        assert_eq!(decode(0x80d674d7).unwrap(), setvl( 9, VlSrc::Reg(Reg::new(12)), VTypeSrc::Reg(Reg::new(13))));
+        assert_eq!(decode(0x82d674d7), None); // vsetvl with bits 30:25 set
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_op_v() {
+        let v = |vd, vs2, src, vm| OpVType { vd: Reg::new(vd), vs2: Reg::new(vs2), src, vm };
+        let (vv, vx) = (|r| VSrc::V(Reg::new(r)), |r| VSrc::X(Reg::new(r)));
+        let vf = |r| VSrc::F(Reg::new(r));
+        assert_eq!(decode(0x02860257).unwrap(), Instr::OpV(v( 4,  8, vv(12),  true), OpVFunct::Add));
+        assert_eq!(decode(0x0087c257).unwrap(), Instr::OpV(v( 4,  8, vx(15), false), OpVFunct::Add));
+        assert_eq!(decode(0x026eb157).unwrap(), Instr::OpV(v( 2,  6, VSrc::I(-3), true), OpVFunct::Add));
+        assert_eq!(decode(0x0e63b157).unwrap(), Instr::OpV(v( 2,  6, VSrc::I(7), true), OpVFunct::Rsub));
+        assert_eq!(decode(0x9621a0d7).unwrap(), Instr::OpV(v( 1,  2, vv( 3),  true), OpVFunct::Mul));
+        assert_eq!(decode(0xc221a257).unwrap(), Instr::OpV(v( 4,  2, vv( 3),  true), OpVFunct::Waddu));
+        assert_eq!(decode(0xbe2230d7).unwrap(), Instr::OpV(v( 1,  2, VSrc::I(4), true), OpVFunct::Nclip));
+        assert_eq!(decode(0x62434057).unwrap(), Instr::OpV(v( 0,  4, vx( 6),  true), OpVFunct::Mseq));
+        assert_eq!(decode(0x444281d7).unwrap(), Instr::OpV(v( 3,  4, vv( 5), false), OpVFunct::Madc));
+        assert_eq!(decode(0x4042b1d7).unwrap(), Instr::OpV(v( 3,  4, VSrc::I(5), false), OpVFunct::Adc));
+        assert_eq!(decode(0x5c2540d7).unwrap(), Instr::OpV(v( 1,  2, vx(10), false), OpVFunct::Merge));
+        assert_eq!(decode(0x5e01b0d7).unwrap(), Instr::OpV(v( 1,  0, VSrc::I(3), true), OpVFunct::Mv));
+        assert_eq!(decode(0x0221a0d7).unwrap(), Instr::OpV(v( 1,  2, vv( 3),  true), OpVFunct::Redsum));
+        assert_eq!(decode(0xc22180d7).unwrap(), Instr::OpV(v( 1,  2, vv( 3),  true), OpVFunct::Wredsumu));
+        assert_eq!(decode(0x42502557).unwrap(), Instr::OpV(v(10,  5, vv( 0),  true), OpVFunct::MvXS));
+        assert_eq!(decode(0x420562d7).unwrap(), Instr::OpV(v( 5,  0, vx(10),  true), OpVFunct::MvSX));
+        assert_eq!(decode(0x9e81b257).unwrap(), Instr::OpV(v( 4,  8, VSrc::I(3), true), OpVFunct::MvNr(4)));
+        assert_eq!(decode(0x3a25c0d7).unwrap(), Instr::OpV(v( 1,  2, vx(11),  true), OpVFunct::Slideup));
+        assert_eq!(decode(0x3e25e0d7).unwrap(), Instr::OpV(v( 1,  2, vx(11),  true), OpVFunct::Slide1down));
+        assert_eq!(decode(0x3a2180d7).unwrap(), Instr::OpV(v( 1,  2, vv( 3),  true), OpVFunct::Rgatherei16));
+        assert_eq!(decode(0x5e21a0d7).unwrap(), Instr::OpV(v( 1,  2, vv( 3),  true), OpVFunct::Compress));
+        assert_eq!(decode(0x6221a0d7).unwrap(), Instr::OpV(v( 1,  2, vv( 3),  true), OpVFunct::Mandn));
+        assert_eq!(decode(0x42282557).unwrap(), Instr::OpV(v(10,  2, vv(16),  true), OpVFunct::Cpop));
+        assert_eq!(decode(0x4028a557).unwrap(), Instr::OpV(v(10,  2, vv(17), false), OpVFunct::First));
+        assert_eq!(decode(0x52282257).unwrap(), Instr::OpV(v( 4,  2, vv(16),  true), OpVFunct::Iota));
+        assert_eq!(decode(0x5208a257).unwrap(), Instr::OpV(v( 4,  0, vv(17),  true), OpVFunct::Id));
+        assert_eq!(decode(0x4a822257).unwrap(), Instr::OpV(v( 4,  8, vv( 4),  true), OpVFunct::Zext(4)));
+        assert_eq!(decode(0x9e2180d7).unwrap(), Instr::OpV(v( 1,  2, vv( 3),  true), OpVFunct::Smul));
+        assert_eq!(decode(0x022190d7).unwrap(), Instr::OpV(v( 1,  2, vv( 3),  true), OpVFunct::F(OpFVFunct::Add)));
+        assert_eq!(decode(0x0221d0d7).unwrap(), Instr::OpV(v( 1,  2, vf( 3),  true), OpVFunct::F(OpFVFunct::Add)));
+        assert_eq!(decode(0x4a2090d7).unwrap(), Instr::OpV(v( 1,  2, vv( 1),  true), OpVFunct::F(OpFVFunct::CvtXF)));
+        assert_eq!(decode(0x42501557).unwrap(), Instr::OpV(v(10,  5, vv( 0),  true), OpVFunct::F(OpFVFunct::MvFS)));
+        assert_eq!(decode(0xc22190d7), None); // vfwadd.vv (Zve32f has no widening arithmetic)
+        assert_eq!(decode(0x40502557), None); // vmv.x.s with vm clear
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_vmem() {
+        let m = |vd, rs1, rs2, vm| VMemType { vd: Reg::new(vd), rs1: Reg::new(rs1), rs2: Reg::new(rs2), vm };
+        assert_eq!(decode(0x02056087).unwrap(), Instr::LoadV(m(1, 10,  0,  true), VMemFunct::Unit(VEew::E32, 1)));
+        assert_eq!(decode(0x01050087).unwrap(), Instr::LoadV(m(1, 10, 16, false), VMemFunct::FaultFirst(VEew::E8, 1)));
+        assert_eq!(decode(0x0ac5d107).unwrap(), Instr::LoadV(m(2, 11, 12,  true), VMemFunct::Strided(VEew::E16, 1)));
+        assert_eq!(decode(0x06460187).unwrap(), Instr::LoadV(m(3, 12,  4,  true), VMemFunct::Indexed(VEew::E8, 1)));
+        assert_eq!(decode(0x0e4661a7).unwrap(), Instr::StoreV(m(3, 12,  4,  true), VMemFunct::Indexed(VEew::E32, 1)));
+        assert_eq!(decode(0x42055207).unwrap(), Instr::LoadV(m(4, 10,  0,  true), VMemFunct::Unit(VEew::E16, 3)));
+        assert_eq!(decode(0x22856107).unwrap(), Instr::LoadV(m(2, 10,  8,  true), VMemFunct::Whole(VEew::E32, 2)));
+        assert_eq!(decode(0x62850227).unwrap(), Instr::StoreV(m(4, 10,  8,  true), VMemFunct::Whole(VEew::E8, 4)));
+        assert_eq!(decode(0x02b50087).unwrap(), Instr::LoadV(m(1, 10, 11,  true), VMemFunct::Mask));
+        assert_eq!(decode(0x02b500a7).unwrap(), Instr::StoreV(m(1, 10, 11,  true), VMemFunct::Mask));
+        assert_eq!(decode(0x020500a7).unwrap(), Instr::StoreV(m(1, 10,  0,  true), VMemFunct::Unit(VEew::E8, 1)));
+        assert_eq!(decode(0x02057087), None); // vle64.v (beyond ELEN)
+        assert_eq!(decode(0x42856107), None); // vl3re32.v
+    }
+
     #[test]
     #[rustfmt::skip]
     fn test_decode_amo() {
@@ -214,6 +436,56 @@ mod tests {
         assert_eq!(decode(0xe0512daf).unwrap(), Instr::Atomic(RType { rd: Reg::new(27), rs1: Reg::new( 2), rs2: Reg::new( 5)}, AtomicFunct::Amo(AmoFunct::Maxu)));
     }
 
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_fp_load_store_fma() {
+        assert_eq!(decode(0x00852007).unwrap(), Instr::LoadFp(IType   { rd: Reg::new( 0), rs1: Reg::new(10), imm: 8    }, LoadFpFunct::W));
+        assert_eq!(decode(0xff013487).unwrap(), Instr::LoadFp(IType   { rd: Reg::new( 9), rs1: Reg::new( 2), imm: -16  }, LoadFpFunct::D));
+        assert_eq!(decode(0x00f32627).unwrap(), Instr::StoreFp(SType  { rs1: Reg::new( 6), rs2: Reg::new(15), imm: 12   }, StoreFpFunct::W));
+        assert_eq!(decode(0x7ff2bc27).unwrap(), Instr::StoreFp(SType  { rs1: Reg::new( 5), rs2: Reg::new(31), imm: 2040 }, StoreFpFunct::D));
+        assert_eq!(decode(0x68c5f543).unwrap(), Instr::Fma(R4Type { rd: Reg::new(10), rs1: Reg::new(11), rs2: Reg::new(12), rs3: Reg::new(13) }, FpFmt::S, FmaFunct::Madd(FpRm::Dyn)));
+        assert_eq!(decode(0x223110c7).unwrap(), Instr::Fma(R4Type { rd: Reg::new( 1), rs1: Reg::new( 2), rs2: Reg::new( 3), rs3: Reg::new( 4) }, FpFmt::D, FmaFunct::Msub(FpRm::Rtz)));
+        assert_eq!(decode(0x9924f44b).unwrap(), Instr::Fma(R4Type { rd: Reg::new( 8), rs1: Reg::new( 9), rs2: Reg::new(18), rs3: Reg::new(19) }, FpFmt::S, FmaFunct::Nmsub(FpRm::Dyn)));
+        assert_eq!(decode(0x6ac5c54f).unwrap(), Instr::Fma(R4Type { rd: Reg::new(10), rs1: Reg::new(11), rs2: Reg::new(12), rs3: Reg::new(13) }, FpFmt::D, FmaFunct::Nmadd(FpRm::Rmm)));
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_op_fp() {
+        let r = |rd, rs1, rs2| RType { rd: Reg::new(rd), rs1: Reg::new(rs1), rs2: Reg::new(rs2) };
+        assert_eq!(decode(0x00c5f553).unwrap(), Instr::OpFp(r(10, 11, 12), FpFmt::S, OpFpFunct::Add(FpRm::Dyn)));
+        assert_eq!(decode(0x0a20a053).unwrap(), Instr::OpFp(r( 0,  1,  2), FpFmt::D, OpFpFunct::Sub(FpRm::Rdn)));
+        assert_eq!(decode(0x10c5b553).unwrap(), Instr::OpFp(r(10, 11, 12), FpFmt::S, OpFpFunct::Mul(FpRm::Rup)));
+        assert_eq!(decode(0x1ac5f553).unwrap(), Instr::OpFp(r(10, 11, 12), FpFmt::D, OpFpFunct::Div(FpRm::Dyn)));
+        assert_eq!(decode(0x5805f553).unwrap(), Instr::OpFp(r(10, 11,  0), FpFmt::S, OpFpFunct::Sqrt(FpRm::Dyn)));
+        assert_eq!(decode(0x22c58553).unwrap(), Instr::OpFp(r(10, 11, 12), FpFmt::D, OpFpFunct::Sgnj));
+        assert_eq!(decode(0x20c59553).unwrap(), Instr::OpFp(r(10, 11, 12), FpFmt::S, OpFpFunct::Sgnjn));
+        assert_eq!(decode(0x22c5a553).unwrap(), Instr::OpFp(r(10, 11, 12), FpFmt::D, OpFpFunct::Sgnjx));
+        assert_eq!(decode(0x28c58553).unwrap(), Instr::OpFp(r(10, 11, 12), FpFmt::S, OpFpFunct::Min));
+        assert_eq!(decode(0x2ac59553).unwrap(), Instr::OpFp(r(10, 11, 12), FpFmt::D, OpFpFunct::Max));
+        assert_eq!(decode(0x4015f553).unwrap(), Instr::OpFp(r(10, 11,  1), FpFmt::S, OpFpFunct::CvtFF(FpRm::Dyn)));
+        assert_eq!(decode(0x42058553).unwrap(), Instr::OpFp(r(10, 11,  0), FpFmt::D, OpFpFunct::CvtFF(FpRm::Rne)));
+        assert_eq!(decode(0xa0c5a553).unwrap(), Instr::OpFp(r(10, 11, 12), FpFmt::S, OpFpFunct::Eq));
+        assert_eq!(decode(0xa2c59553).unwrap(), Instr::OpFp(r(10, 11, 12), FpFmt::D, OpFpFunct::Lt));
+        assert_eq!(decode(0xa0c58553).unwrap(), Instr::OpFp(r(10, 11, 12), FpFmt::S, OpFpFunct::Le));
+        assert_eq!(decode(0xe2059553).unwrap(), Instr::OpFp(r(10, 11,  0), FpFmt::D, OpFpFunct::Class));
+        assert_eq!(decode(0xc0059553).unwrap(), Instr::OpFp(r(10, 11,  0), FpFmt::S, OpFpFunct::CvtWF(FpRm::Rtz)));
+        assert_eq!(decode(0xc2159553).unwrap(), Instr::OpFp(r(10, 11,  1), FpFmt::D, OpFpFunct::CvtWuF(FpRm::Rtz)));
+        assert_eq!(decode(0xd005f553).unwrap(), Instr::OpFp(r(10, 11,  0), FpFmt::S, OpFpFunct::CvtFW(FpRm::Dyn)));
+        assert_eq!(decode(0xd2158553).unwrap(), Instr::OpFp(r(10, 11,  1), FpFmt::D, OpFpFunct::CvtFWu(FpRm::Rne)));
+        assert_eq!(decode(0xe0058553).unwrap(), Instr::OpFp(r(10, 11,  0), FpFmt::S, OpFpFunct::MvXF));
+        assert_eq!(decode(0xf0058553).unwrap(), Instr::OpFp(r(10, 11,  0), FpFmt::S, OpFpFunct::MvFX));
+    }
+
+    #[test]
+    fn test_decode_fp_illegal() {
+        assert_eq!(decode(0x00c5d553), None); // fadd.s with reserved rm = 101
+        assert_eq!(decode(0x04c5f553), None); // fadd.q (fmt = 10)
+        assert_eq!(decode(0x4005f553), None); // fcvt.s.s
+        assert_eq!(decode(0xe2058553), None); // fmv.x.d (RV64 only)
+        assert_eq!(decode(0x00854007), None); // flq
+    }
+
     #[test]
     #[rustfmt::skip]
     fn test_decode_csr() {
@@ -0,0 +1,476 @@
+use crate::instr::{format::*, funct::*, reg::Reg, *};
+
+// Expansion of the RV32C compressed instructions into their 32-bit equivalents
+
+const QUADRANT_MASK: u16 = 0b11;
+
+pub fn is_compressed(code: u32) -> bool {
+    code as u16 & QUADRANT_MASK != 0b11
+}
+
+pub fn decode_compressed(code: u16) -> Option<Instr> {
+    let f3 = bits(code, 15, 13);
+    match (code & QUADRANT_MASK, f3) {
+        // Quadrant 0
+        (0b00, 0b000) => c_addi4spn(code),
+        (0b00, 0b001) => Some(Instr::LoadFp(c_ld_sd_itype(code), LoadFpFunct::D)),
+        (0b00, 0b010) => Some(Instr::Load(c_lw_sw_itype(code), LoadFunct::W)),
+        (0b00, 0b011) => Some(Instr::LoadFp(c_lw_sw_itype(code), LoadFpFunct::W)),
+        (0b00, 0b101) => Some(Instr::StoreFp(c_ld_sd_stype(code), StoreFpFunct::D)),
+        (0b00, 0b110) => Some(Instr::Store(c_lw_sw_stype(code), StoreFunct::W)),
+        (0b00, 0b111) => Some(Instr::StoreFp(c_lw_sw_stype(code), StoreFpFunct::W)),
+        // Quadrant 1
+        (0b01, 0b000) => Some(Instr::OpImm(c_addi_itype(code), OpImmFunct::Add)),
+        (0b01, 0b001) => Some(Instr::Jal(JType {
+            rd: Reg::new(1),
+            imm: cj_imm(code),
+        })),
+        (0b01, 0b010) => {
+            let IType { rd, imm, .. } = c_addi_itype(code);
+            Some(Instr::OpImm(
+                IType {
+                    rd,
+                    rs1: Reg::zero(),
+                    imm,
+                },
+                OpImmFunct::Add,
+            ))
+        }
+        (0b01, 0b011) => c_lui_addi16sp(code),
+        (0b01, 0b100) => c_misc_alu(code),
+        (0b01, 0b101) => Some(Instr::Jal(JType {
+            rd: Reg::zero(),
+            imm: cj_imm(code),
+        })),
+        (0b01, 0b110) => Some(Instr::Branch(c_branch_btype(code), BranchFunct::Eq)),
+        (0b01, 0b111) => Some(Instr::Branch(c_branch_btype(code), BranchFunct::Ne)),
+        // Quadrant 2
+        (0b10, 0b000) => c_slli(code),
+        (0b10, 0b001) => Some(Instr::LoadFp(c_ldsp_itype(code), LoadFpFunct::D)),
+        (0b10, 0b010) => c_lwsp(code),
+        (0b10, 0b011) => Some(Instr::LoadFp(c_lwsp_itype(code), LoadFpFunct::W)),
+        (0b10, 0b100) => c_jr_mv_add(code),
+        (0b10, 0b101) => Some(Instr::StoreFp(c_sdsp_stype(code), StoreFpFunct::D)),
+        (0b10, 0b110) => Some(Instr::Store(c_swsp_stype(code), StoreFunct::W)),
+        (0b10, 0b111) => Some(Instr::StoreFp(c_swsp_stype(code), StoreFpFunct::W)),
+        _ => None,
+    }
+}
+
+fn bits(code: u16, hi: u32, lo: u32) -> u32 {
+    (code as u32 >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+fn sign_extend(imm: u32, len: u32) -> i32 {
+    ((imm as i32) << (32 - len)) >> (32 - len)
+}
+
+// Full register in bits 11:7 (rd/rs1) and 6:2 (rs2)
+fn reg_rd(code: u16) -> Reg {
+    Reg::new(bits(code, 11, 7) as u8)
+}
+
+fn reg_rs2(code: u16) -> Reg {
+    Reg::new(bits(code, 6, 2) as u8)
+}
+
+// Popular register (x8-x15) in bits 9:7 (rd'/rs1') and 4:2 (rd'/rs2')
+fn creg_hi(code: u16) -> Reg {
+    Reg::new(8 + bits(code, 9, 7) as u8)
+}
+
+fn creg_lo(code: u16) -> Reg {
+    Reg::new(8 + bits(code, 4, 2) as u8)
+}
+
+// ---------------- Quadrant 0 ------------------
+fn c_addi4spn(code: u16) -> Option<Instr> {
+    // nzuimm[5:4|9:6|2|3]
+    let imm = bits(code, 12, 11) << 4
+        | bits(code, 10, 7) << 6
+        | bits(code, 6, 6) << 2
+        | bits(code, 5, 5) << 3;
+    if imm == 0 {
+        return None;
+    }
+    let rd = creg_lo(code);
+    Some(Instr::OpImm(
+        IType {
+            rd,
+            rs1: Reg::new(2),
+            imm: imm as i32,
+        },
+        OpImmFunct::Add,
+    ))
+}
+
+fn c_lw_sw_offset(code: u16) -> i32 {
+    // uimm[5:3] in 12:10, uimm[2|6] in 6:5
+    (bits(code, 12, 10) << 3 | bits(code, 6, 6) << 2 | bits(code, 5, 5) << 6) as i32
+}
+
+fn c_lw_sw_itype(code: u16) -> IType {
+    IType {
+        rd: creg_lo(code),
+        rs1: creg_hi(code),
+        imm: c_lw_sw_offset(code),
+    }
+}
+
+fn c_lw_sw_stype(code: u16) -> SType {
+    SType {
+        rs1: creg_hi(code),
+        rs2: creg_lo(code),
+        imm: c_lw_sw_offset(code),
+    }
+}
+
+fn c_ld_sd_offset(code: u16) -> i32 {
+    // uimm[5:3] in 12:10, uimm[7:6] in 6:5
+    (bits(code, 12, 10) << 3 | bits(code, 6, 5) << 6) as i32
+}
+
+fn c_ld_sd_itype(code: u16) -> IType {
+    IType {
+        rd: creg_lo(code),
+        rs1: creg_hi(code),
+        imm: c_ld_sd_offset(code),
+    }
+}
+
+fn c_ld_sd_stype(code: u16) -> SType {
+    SType {
+        rs1: creg_hi(code),
+        rs2: creg_lo(code),
+        imm: c_ld_sd_offset(code),
+    }
+}
+
+// ---------------- Quadrant 1 ------------------
+fn ci_imm(code: u16) -> i32 {
+    // imm[5] in 12, imm[4:0] in 6:2
+    sign_extend(bits(code, 12, 12) << 5 | bits(code, 6, 2), 6)
+}
+
+fn c_addi_itype(code: u16) -> IType {
+    let rd = reg_rd(code);
+    let rs1 = reg_rd(code);
+    IType {
+        rd,
+        rs1,
+        imm: ci_imm(code),
+    }
+}
+
+fn cj_imm(code: u16) -> i32 {
+    // imm[11|4|9:8|10|6|7|3:1|5]
+    let imm = bits(code, 12, 12) << 11
+        | bits(code, 11, 11) << 4
+        | bits(code, 10, 9) << 8
+        | bits(code, 8, 8) << 10
+        | bits(code, 7, 7) << 6
+        | bits(code, 6, 6) << 7
+        | bits(code, 5, 3) << 1
+        | bits(code, 2, 2) << 5;
+    sign_extend(imm, 12)
+}
+
+fn c_lui_addi16sp(code: u16) -> Option<Instr> {
+    let rd = reg_rd(code);
+    if rd.index() == 2 {
+        // C.ADDI16SP: nzimm[9|4|6|8:7|5]
+        let imm = bits(code, 12, 12) << 9
+            | bits(code, 6, 6) << 4
+            | bits(code, 5, 5) << 6
+            | bits(code, 4, 3) << 7
+            | bits(code, 2, 2) << 5;
+        if imm == 0 {
+            return None;
+        }
+        let imm = sign_extend(imm, 10);
+        Some(Instr::OpImm(
+            IType {
+                rd,
+                rs1: Reg::new(2),
+                imm,
+            },
+            OpImmFunct::Add,
+        ))
+    } else {
+        // C.LUI: nzimm[17|16:12]
+        let imm = ci_imm(code);
+        // C.MOP.n (Zcmop) takes the place of c.lui x1, 0 to c.lui x15, 0, without writing rd
+        if imm == 0 && rd.index() % 2 == 1 && rd.index() < 16 {
+            return Some(Instr::Mop(Reg::zero()));
+        }
+        if imm == 0 {
+            return None;
+        }
+        Some(Instr::Lui(UType { rd, imm: imm << 12 }))
+    }
+}
+
+fn c_misc_alu(code: u16) -> Option<Instr> {
+    let rd = creg_hi(code);
+    let rs1 = creg_hi(code);
+    match bits(code, 11, 10) {
+        0b00 | 0b01 => {
+            // shamt[5] must be zero for RV32C
+            if bits(code, 12, 12) != 0 {
+                return None;
+            }
+            let imm = bits(code, 6, 2) as i32;
+            let f = if bits(code, 11, 10) == 0b00 {
+                OpImmFunct::Srl
+            } else {
+                OpImmFunct::Sra
+            };
+            Some(Instr::OpImm(IType { rd, rs1, imm }, f))
+        }
+        0b10 => Some(Instr::OpImm(
+            IType {
+                rd,
+                rs1,
+                imm: ci_imm(code),
+            },
+            OpImmFunct::And,
+        )),
+        _ => {
+            // C.SUBW and C.ADDW are RV64-only
+            if bits(code, 12, 12) != 0 {
+                return None;
+            }
+            let rs2 = creg_lo(code);
+            let f = match bits(code, 6, 5) {
+                0b00 => OpIFunct::Sub,
+                0b01 => OpIFunct::Xor,
+                0b10 => OpIFunct::Or,
+                _ => OpIFunct::And,
+            };
+            Some(Instr::Op(RType { rd, rs1, rs2 }, OpFunct::I(f)))
+        }
+    }
+}
+
+fn c_branch_btype(code: u16) -> BType {
+    // offset[8|4:3] in 12:10, offset[7:6|2:1|5] in 6:2
+    let imm = bits(code, 12, 12) << 8
+        | bits(code, 11, 10) << 3
+        | bits(code, 6, 5) << 6
+        | bits(code, 4, 3) << 1
+        | bits(code, 2, 2) << 5;
+    BType {
+        rs1: creg_hi(code),
+        rs2: Reg::zero(),
+        imm: sign_extend(imm, 9),
+    }
+}
+
+// ---------------- Quadrant 2 ------------------
+fn c_slli(code: u16) -> Option<Instr> {
+    // shamt[5] must be zero for RV32C
+    if bits(code, 12, 12) != 0 {
+        return None;
+    }
+    let rd = reg_rd(code);
+    let rs1 = reg_rd(code);
+    Some(Instr::OpImm(
+        IType {
+            rd,
+            rs1,
+            imm: bits(code, 6, 2) as i32,
+        },
+        OpImmFunct::Sll,
+    ))
+}
+
+fn c_lwsp(code: u16) -> Option<Instr> {
+    // Unlike C.FLWSP, rd must not be x0
+    if reg_rd(code).index() == 0 {
+        return None;
+    }
+    Some(Instr::Load(c_lwsp_itype(code), LoadFunct::W))
+}
+
+fn c_lwsp_itype(code: u16) -> IType {
+    // uimm[5] in 12, uimm[4:2|7:6] in 6:2
+    let imm = bits(code, 12, 12) << 5 | bits(code, 6, 4) << 2 | bits(code, 3, 2) << 6;
+    IType {
+        rd: reg_rd(code),
+        rs1: Reg::new(2),
+        imm: imm as i32,
+    }
+}
+
+fn c_ldsp_itype(code: u16) -> IType {
+    // uimm[5] in 12, uimm[4:3|8:6] in 6:2
+    let imm = bits(code, 12, 12) << 5 | bits(code, 6, 5) << 3 | bits(code, 4, 2) << 6;
+    IType {
+        rd: reg_rd(code),
+        rs1: Reg::new(2),
+        imm: imm as i32,
+    }
+}
+
+fn c_swsp_stype(code: u16) -> SType {
+    // uimm[5:2|7:6] in 12:7
+    let imm = bits(code, 12, 9) << 2 | bits(code, 8, 7) << 6;
+    SType {
+        rs1: Reg::new(2),
+        rs2: reg_rs2(code),
+        imm: imm as i32,
+    }
+}
+
+fn c_sdsp_stype(code: u16) -> SType {
+    // uimm[5:3|8:6] in 12:7
+    let imm = bits(code, 12, 10) << 3 | bits(code, 9, 7) << 6;
+    SType {
+        rs1: Reg::new(2),
+        rs2: reg_rs2(code),
+        imm: imm as i32,
+    }
+}
+
+fn c_jr_mv_add(code: u16) -> Option<Instr> {
+    let rs1 = reg_rd(code);
+    let rs2 = reg_rs2(code);
+    match (bits(code, 12, 12), rs1.index(), rs2.index()) {
+        // C.JR
+        (0, 0, 0) => None,
+        (0, _, 0) => Some(Instr::Jalr(IType {
+            rd: Reg::zero(),
+            rs1,
+            imm: 0,
+        })),
+        // C.MV
+        (0, _, _) => Some(Instr::Op(
+            RType {
+                rd: rs1,
+                rs1: Reg::zero(),
+                rs2,
+            },
+            OpFunct::I(OpIFunct::Add),
+        )),
+        // C.EBREAK
+        (1, 0, 0) => Some(Instr::Env(EnvFunct::Break)),
+        // C.JALR
+        (1, _, 0) => Some(Instr::Jalr(IType {
+            rd: Reg::new(1),
+            rs1,
+            imm: 0,
+        })),
+        // C.ADD
+        _ => Some(Instr::Op(
+            RType {
+                rd: reg_rd(code),
+                rs1,
+                rs2,
+            },
+            OpFunct::I(OpIFunct::Add),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reg(i: u8) -> Reg {
+        Reg::new(i)
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_quadrant0() {
+        assert_eq!(decode_compressed(0x0800).unwrap(), Instr::OpImm(IType { rd: reg( 8), rs1: reg( 2), imm: 16   }, OpImmFunct::Add)); // c.addi4spn s0, sp, 16
+        assert_eq!(decode_compressed(0x1fe4).unwrap(), Instr::OpImm(IType { rd: reg( 9), rs1: reg( 2), imm: 1020 }, OpImmFunct::Add)); // c.addi4spn s1, sp, 1020
+        assert_eq!(decode_compressed(0x4108).unwrap(), Instr::Load(IType  { rd: reg(10), rs1: reg(10), imm: 0    }, LoadFunct::W));    // c.lw a0, 0(a0)
+        assert_eq!(decode_compressed(0x5ffc).unwrap(), Instr::Load(IType  { rd: reg(15), rs1: reg(15), imm: 124  }, LoadFunct::W));    // c.lw a5, 124(a5)
+        assert_eq!(decode_compressed(0xc188).unwrap(), Instr::Store(SType { rs1: reg(11), rs2: reg(10), imm: 0   }, StoreFunct::W));   // c.sw a0, 0(a1)
+        assert_eq!(decode_compressed(0xc4e0).unwrap(), Instr::Store(SType { rs1: reg( 9), rs2: reg( 8), imm: 76  }, StoreFunct::W));   // c.sw s0, 76(s1)
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_quadrant1() {
+        assert_eq!(decode_compressed(0x0001).unwrap(), Instr::OpImm(IType { rd: reg( 0), rs1: reg( 0), imm: 0   }, OpImmFunct::Add)); // c.nop
+        assert_eq!(decode_compressed(0x1141).unwrap(), Instr::OpImm(IType { rd: reg( 2), rs1: reg( 2), imm: -16 }, OpImmFunct::Add)); // c.addi sp, -16
+        assert_eq!(decode_compressed(0x0785).unwrap(), Instr::OpImm(IType { rd: reg(15), rs1: reg(15), imm: 1   }, OpImmFunct::Add)); // c.addi a5, 1
+        assert_eq!(decode_compressed(0x4501).unwrap(), Instr::OpImm(IType { rd: reg(10), rs1: reg( 0), imm: 0   }, OpImmFunct::Add)); // c.li a0, 0
+        assert_eq!(decode_compressed(0x57fd).unwrap(), Instr::OpImm(IType { rd: reg(15), rs1: reg( 0), imm: -1  }, OpImmFunct::Add)); // c.li a5, -1
+        assert_eq!(decode_compressed(0x6505).unwrap(), Instr::Lui(UType { rd: reg(10), imm: 0x1000 }));                                // c.lui a0, 0x1
+        assert_eq!(decode_compressed(0x7501).unwrap(), Instr::Lui(UType { rd: reg(10), imm: 0xfffe0000_u32 as i32 }));                 // c.lui a0, 0xfffe0
+        assert_eq!(decode_compressed(0x6141).unwrap(), Instr::OpImm(IType { rd: reg( 2), rs1: reg( 2), imm: 16   }, OpImmFunct::Add)); // c.addi16sp sp, 16
+        assert_eq!(decode_compressed(0x7179).unwrap(), Instr::OpImm(IType { rd: reg( 2), rs1: reg( 2), imm: -48  }, OpImmFunct::Add)); // c.addi16sp sp, -48
+        assert_eq!(decode_compressed(0x8385).unwrap(), Instr::OpImm(IType { rd: reg(15), rs1: reg(15), imm: 1    }, OpImmFunct::Srl)); // c.srli a5, 1
+        assert_eq!(decode_compressed(0x847d).unwrap(), Instr::OpImm(IType { rd: reg( 8), rs1: reg( 8), imm: 31   }, OpImmFunct::Sra)); // c.srai s0, 31
+        assert_eq!(decode_compressed(0x8a3d).unwrap(), Instr::OpImm(IType { rd: reg(12), rs1: reg(12), imm: 15   }, OpImmFunct::And)); // c.andi a2, 15
+        assert_eq!(decode_compressed(0x9a01).unwrap(), Instr::OpImm(IType { rd: reg(12), rs1: reg(12), imm: -32  }, OpImmFunct::And)); // c.andi a2, -32
+        assert_eq!(decode_compressed(0x8d0d).unwrap(), Instr::Op(RType { rd: reg(10), rs1: reg(10), rs2: reg(11) }, OpFunct::I(OpIFunct::Sub))); // c.sub a0, a1
+        assert_eq!(decode_compressed(0x8d2d).unwrap(), Instr::Op(RType { rd: reg(10), rs1: reg(10), rs2: reg(11) }, OpFunct::I(OpIFunct::Xor))); // c.xor a0, a1
+        assert_eq!(decode_compressed(0x8d4d).unwrap(), Instr::Op(RType { rd: reg(10), rs1: reg(10), rs2: reg(11) }, OpFunct::I(OpIFunct::Or)));  // c.or a0, a1
+        assert_eq!(decode_compressed(0x8d6d).unwrap(), Instr::Op(RType { rd: reg(10), rs1: reg(10), rs2: reg(11) }, OpFunct::I(OpIFunct::And))); // c.and a0, a1
+        assert_eq!(decode_compressed(0x2011).unwrap(), Instr::Jal(JType { rd: reg(1), imm: 4     }));  // c.jal +4
+        assert_eq!(decode_compressed(0x3ffd).unwrap(), Instr::Jal(JType { rd: reg(1), imm: -2    }));  // c.jal -2
+        assert_eq!(decode_compressed(0xa001).unwrap(), Instr::Jal(JType { rd: reg(0), imm: 0     }));  // c.j +0
+        assert_eq!(decode_compressed(0xaffd).unwrap(), Instr::Jal(JType { rd: reg(0), imm: 2046  }));  // c.j +2046
+        assert_eq!(decode_compressed(0xb001).unwrap(), Instr::Jal(JType { rd: reg(0), imm: -2048 }));  // c.j -2048
+        assert_eq!(decode_compressed(0xc119).unwrap(), Instr::Branch(BType { rs1: reg(10), rs2: reg(0), imm: 6    }, BranchFunct::Eq)); // c.beqz a0, +6
+        assert_eq!(decode_compressed(0xfffd).unwrap(), Instr::Branch(BType { rs1: reg(15), rs2: reg(0), imm: -2   }, BranchFunct::Ne)); // c.bnez a5, -2
+        assert_eq!(decode_compressed(0xfc7d).unwrap(), Instr::Branch(BType { rs1: reg( 8), rs2: reg(0), imm: -2   }, BranchFunct::Ne)); // c.bnez s0, -2
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_quadrant2() {
+        assert_eq!(decode_compressed(0x050a).unwrap(), Instr::OpImm(IType { rd: reg(10), rs1: reg(10), imm: 2  }, OpImmFunct::Sll));    // c.slli a0, 2
+        assert_eq!(decode_compressed(0x40b2).unwrap(), Instr::Load(IType  { rd: reg( 1), rs1: reg( 2), imm: 12  }, LoadFunct::W));     // c.lwsp ra, 12(sp)
+        assert_eq!(decode_compressed(0x50fe).unwrap(), Instr::Load(IType  { rd: reg( 1), rs1: reg( 2), imm: 252 }, LoadFunct::W));     // c.lwsp ra, 252(sp)
+        assert_eq!(decode_compressed(0xc606).unwrap(), Instr::Store(SType { rs1: reg( 2), rs2: reg( 1), imm: 12  }, StoreFunct::W));   // c.swsp ra, 12(sp)
+        assert_eq!(decode_compressed(0xdf86).unwrap(), Instr::Store(SType { rs1: reg( 2), rs2: reg( 1), imm: 252 }, StoreFunct::W));   // c.swsp ra, 252(sp)
+        assert_eq!(decode_compressed(0x8082).unwrap(), Instr::Jalr(IType  { rd: reg( 0), rs1: reg( 1), imm: 0   }));                  // c.jr ra
+        assert_eq!(decode_compressed(0x9502).unwrap(), Instr::Jalr(IType  { rd: reg( 1), rs1: reg(10), imm: 0   }));                  // c.jalr a0
+        assert_eq!(decode_compressed(0x852e).unwrap(), Instr::Op(RType { rd: reg(10), rs1: reg( 0), rs2: reg(11) }, OpFunct::I(OpIFunct::Add))); // c.mv a0, a1
+        assert_eq!(decode_compressed(0x952e).unwrap(), Instr::Op(RType { rd: reg(10), rs1: reg(10), rs2: reg(11) }, OpFunct::I(OpIFunct::Add))); // c.add a0, a1
+        assert_eq!(decode_compressed(0x9002).unwrap(), Instr::Env(EnvFunct::Break));                                                       // c.ebreak
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_decode_float() {
+        assert_eq!(decode_compressed(0x2500).unwrap(), Instr::LoadFp(IType  { rd: reg( 8), rs1: reg(10), imm: 8   }, LoadFpFunct::D));  // c.fld fs0, 8(a0)
+        assert_eq!(decode_compressed(0xbcfc).unwrap(), Instr::StoreFp(SType { rs1: reg( 9), rs2: reg(15), imm: 248 }, StoreFpFunct::D)); // c.fsd fa5, 248(s1)
+        assert_eq!(decode_compressed(0x7fe4).unwrap(), Instr::LoadFp(IType  { rd: reg( 9), rs1: reg(15), imm: 124 }, LoadFpFunct::W));  // c.flw fs1, 124(a5)
+        assert_eq!(decode_compressed(0xe188).unwrap(), Instr::StoreFp(SType { rs1: reg(11), rs2: reg(10), imm: 0   }, StoreFpFunct::W)); // c.fsw fa0, 0(a1)
+        assert_eq!(decode_compressed(0x307e).unwrap(), Instr::LoadFp(IType  { rd: reg( 0), rs1: reg( 2), imm: 504 }, LoadFpFunct::D));  // c.fldsp ft0, 504(sp)
+        assert_eq!(decode_compressed(0xa46e).unwrap(), Instr::StoreFp(SType { rs1: reg( 2), rs2: reg(27), imm: 8   }, StoreFpFunct::D)); // c.fsdsp fs11, 8(sp)
+        assert_eq!(decode_compressed(0x707e).unwrap(), Instr::LoadFp(IType  { rd: reg( 0), rs1: reg( 2), imm: 252 }, LoadFpFunct::W));  // c.flwsp ft0, 252(sp)
+        assert_eq!(decode_compressed(0xe62a).unwrap(), Instr::StoreFp(SType { rs1: reg( 2), rs2: reg(10), imm: 12  }, StoreFpFunct::W)); // c.fswsp fa0, 12(sp)
+    }
+
+    #[test]
+    fn test_decode_compressed_illegal() {
+        assert_eq!(decode_compressed(0x0000), None); // All zeros
+        assert_eq!(decode_compressed(0x0010), None); // c.addi4spn with nzuimm = 0
+        assert_eq!(decode_compressed(0x6101), None); // c.addi16sp with nzimm = 0
+        assert_eq!(decode_compressed(0x6501), None); // c.lui with nzimm = 0
+        assert_eq!(decode_compressed(0x6081), Some(Instr::Mop(Reg::zero()))); // c.mop.1
+        assert_eq!(decode_compressed(0x6781), Some(Instr::Mop(Reg::zero()))); // c.mop.15
+        assert_eq!(decode_compressed(0x9385), None); // c.srli with shamt[5] = 1 (RV64 only)
+        assert_eq!(decode_compressed(0x1502), None); // c.slli with shamt[5] = 1 (RV64 only)
+        assert_eq!(decode_compressed(0x9d0d), None); // c.subw (RV64 only)
+        assert_eq!(decode_compressed(0x4002), None); // c.lwsp with rd = 0
+        assert_eq!(decode_compressed(0x8002), None); // c.jr with rs1 = 0
+        assert_eq!(decode_compressed(0x8000), None); // Reserved
+    }
+
+    #[test]
+    fn test_is_compressed() {
+        assert!(is_compressed(0x0001));
+        assert!(is_compressed(0x8082));
+        assert!(is_compressed(0x0000_4108));
+        assert!(!is_compressed(0x00150513));
+        assert!(!is_compressed(0xffff));
+    }
+}
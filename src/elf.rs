@@ -0,0 +1,421 @@
+use std::{
+    fmt::Debug,
+    io::{self, ErrorKind},
+};
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELF_CLASS_32: u8 = 1;
+const ELF_DATA_LE: u8 = 1;
+const ELF_MACHINE_RISCV: u16 = 243;
+
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+const SHDR_SIZE: usize = 40;
+const SYM_SIZE: usize = 16;
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+
+#[derive(Debug)]
+pub struct Elf {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    pub symbols: SymbolTable,
+    pub phdr: u32, // Address of the program headers once loaded (0 if not loaded)
+    pub phnum: u32,
+}
+
+pub struct Segment {
+    pub vaddr: u32,
+    pub paddr: u32,
+    pub mem_size: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    pub size: u32,
+}
+
+// Symbols sorted by address, used to describe a PC as function+offset
+#[derive(Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+pub fn is_elf(buf: &[u8]) -> bool {
+    buf.starts_with(&ELF_MAGIC)
+}
+
+impl Elf {
+    pub fn parse(buf: &[u8]) -> io::Result<Elf> {
+        if buf.len() < EHDR_SIZE || !is_elf(buf) {
+            return Err(invalid("not an ELF file"));
+        }
+        if buf[4] != ELF_CLASS_32 || buf[5] != ELF_DATA_LE {
+            return Err(invalid("not a little-endian ELF32 file"));
+        }
+        if read_u16(buf, 18)? != ELF_MACHINE_RISCV {
+            return Err(invalid("not a RISC-V ELF file"));
+        }
+
+        let entry = read_u32(buf, 24)?;
+        let phoff = read_u32(buf, 28)? as usize;
+        let shoff = read_u32(buf, 32)? as usize;
+        let phnum = read_u16(buf, 44)? as usize;
+        let shnum = read_u16(buf, 48)? as usize;
+
+        // Program headers
+        let mut segments = vec![];
+        let mut phdr = 0;
+        for i in 0..phnum {
+            let ph = phoff + i * PHDR_SIZE;
+            if read_u32(buf, ph)? != PT_LOAD {
+                continue;
+            }
+            let offset = read_u32(buf, ph + 4)? as usize;
+            let vaddr = read_u32(buf, ph + 8)?;
+            let paddr = read_u32(buf, ph + 12)?;
+            let file_size = read_u32(buf, ph + 16)? as usize;
+            let mem_size = read_u32(buf, ph + 20)?;
+            if (mem_size as usize) < file_size {
+                return Err(invalid("segment is larger in file than in memory"));
+            }
+            let data = slice(buf, offset, file_size)?.to_vec();
+            if (offset..offset + file_size).contains(&phoff) {
+                phdr = vaddr.wrapping_add((phoff - offset) as u32);
+            }
+            segments.push(Segment {
+                vaddr,
+                paddr,
+                mem_size,
+                data,
+            });
+        }
+
+        // Section headers (only the symbol table is of interest)
+        let mut symbols = vec![];
+        for i in 0..shnum {
+            let sh = shoff + i * SHDR_SIZE;
+            if read_u32(buf, sh + 4)? != SHT_SYMTAB {
+                continue;
+            }
+            let offset = read_u32(buf, sh + 16)? as usize;
+            let size = read_u32(buf, sh + 20)? as usize;
+            let link = read_u32(buf, sh + 24)? as usize;
+            let strtab_sh = shoff + link * SHDR_SIZE;
+            let strtab_offset = read_u32(buf, strtab_sh + 16)? as usize;
+            let strtab_size = read_u32(buf, strtab_sh + 20)? as usize;
+            let strtab = slice(buf, strtab_offset, strtab_size)?;
+
+            for sym in slice(buf, offset, size)?.chunks_exact(SYM_SIZE) {
+                let name = read_u32(sym, 0)? as usize;
+                let addr = read_u32(sym, 4)?;
+                let size = read_u32(sym, 8)?;
+                let kind = sym[12] & 0xf;
+                let shndx = read_u16(sym, 14)?;
+                if shndx == SHN_UNDEF || !matches!(kind, STT_NOTYPE | STT_OBJECT | STT_FUNC) {
+                    continue;
+                }
+                let name = read_str(strtab, name)?;
+                // Skip mapping symbols ($x, $d) and local assembler labels
+                if name.is_empty() || name.starts_with('$') || name.starts_with(".L") {
+                    continue;
+                }
+                symbols.push(Symbol { name, addr, size });
+            }
+        }
+
+        Ok(Elf {
+            entry,
+            segments,
+            symbols: SymbolTable::new(symbols),
+            phdr,
+            phnum: phnum as u32,
+        })
+    }
+}
+
+impl Debug for Segment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Segment (vaddr = 0x{:08x}, paddr = 0x{:08x}, {} of {} bytes)",
+            self.vaddr,
+            self.paddr,
+            self.data.len(),
+            self.mem_size
+        )
+    }
+}
+
+impl SymbolTable {
+    pub fn new(mut symbols: Vec<Symbol>) -> SymbolTable {
+        symbols.sort_by_key(|s| s.addr);
+        SymbolTable { symbols }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn extend(&mut self, other: SymbolTable) {
+        self.symbols.extend(other.symbols);
+        self.symbols.sort_by_key(|s| s.addr);
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    // The closest symbol at or before addr (and covering it, if it has a size)
+    pub fn lookup(&self, addr: u32) -> Option<(&Symbol, u32)> {
+        let idx = self.symbols.partition_point(|s| s.addr <= addr);
+        let sym = self.symbols[..idx]
+            .iter()
+            .rev()
+            .find(|s| s.size == 0 || addr - s.addr < s.size)?;
+        Some((sym, addr - sym.addr))
+    }
+
+    pub fn describe(&self, addr: u32) -> Option<String> {
+        let (sym, offset) = self.lookup(addr)?;
+        if offset == 0 {
+            Some(sym.name.clone())
+        } else {
+            Some(format!("{}+0x{:x}", sym.name, offset))
+        }
+    }
+}
+
+impl Debug for SymbolTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SymbolTable ({} symbols)", self.symbols.len())
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn slice(buf: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+    buf.get(offset..offset + len)
+        .ok_or_else(|| invalid("ELF file is truncated"))
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> io::Result<u16> {
+    let b = slice(buf, offset, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> io::Result<u32> {
+    let b = slice(buf, offset, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_str(buf: &[u8], offset: usize) -> io::Result<String> {
+    let s = buf
+        .get(offset..)
+        .ok_or_else(|| invalid("ELF file is truncated"))?;
+    let len = s.iter().position(|&c| c == 0).unwrap_or(s.len());
+    Ok(String::from_utf8_lossy(&s[..len]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a minimal ELF32 RISC-V executable with one PT_LOAD segment
+    // (text + bss) and a symbol table with the given symbols.
+    fn make_elf(
+        entry: u32,
+        addr: u32,
+        text: &[u8],
+        bss: u32,
+        syms: &[(&str, u32, u32)],
+    ) -> Vec<u8> {
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; SYM_SIZE];
+        for (name, value, size) in syms {
+            let name_off = strtab.len() as u32;
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            symtab.extend_from_slice(&name_off.to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&size.to_le_bytes());
+            symtab.push(0x10 | STT_FUNC); // Global function
+            symtab.push(0);
+            symtab.extend_from_slice(&1u16.to_le_bytes());
+        }
+
+        let text_off = EHDR_SIZE + PHDR_SIZE;
+        let symtab_off = text_off + text.len();
+        let strtab_off = symtab_off + symtab.len();
+        let shoff = strtab_off + strtab.len();
+
+        let mut buf = vec![];
+        // ELF header
+        buf.extend_from_slice(&ELF_MAGIC);
+        buf.extend_from_slice(&[ELF_CLASS_32, ELF_DATA_LE, 1, 0]);
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+        buf.extend_from_slice(&ELF_MACHINE_RISCV.to_le_bytes());
+        buf.extend_from_slice(&1u32.to_le_bytes());
+        buf.extend_from_slice(&entry.to_le_bytes());
+        buf.extend_from_slice(&(EHDR_SIZE as u32).to_le_bytes());
+        buf.extend_from_slice(&(shoff as u32).to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+        buf.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        buf.extend_from_slice(&1u16.to_le_bytes());
+        buf.extend_from_slice(&(SHDR_SIZE as u16).to_le_bytes());
+        buf.extend_from_slice(&3u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        // Program header
+        for v in [
+            PT_LOAD,
+            text_off as u32,
+            addr,
+            addr,
+            text.len() as u32,
+            text.len() as u32 + bss,
+            7,
+            4,
+        ] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        buf.extend_from_slice(text);
+        buf.extend_from_slice(&symtab);
+        buf.extend_from_slice(&strtab);
+        // Section headers: null, symtab, strtab
+        buf.extend_from_slice(&[0; SHDR_SIZE]);
+        for v in [
+            0,
+            SHT_SYMTAB,
+            0,
+            0,
+            symtab_off as u32,
+            symtab.len() as u32,
+            2,
+            1,
+            4,
+            SYM_SIZE as u32,
+        ] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [
+            0,
+            3,
+            0,
+            0,
+            strtab_off as u32,
+            strtab.len() as u32,
+            0,
+            0,
+            1,
+            0,
+        ] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        buf
+    }
+
+    #[test]
+    fn test_parse() {
+        let buf = make_elf(
+            0x8000_0004,
+            0x8000_0000,
+            &[1, 2, 3, 4, 5, 6, 7, 8],
+            0x10,
+            &[("_start", 0x8000_0004, 4)],
+        );
+        let elf = Elf::parse(&buf).unwrap();
+        assert_eq!(elf.entry, 0x8000_0004);
+        assert_eq!(elf.segments.len(), 1);
+        assert_eq!(elf.segments[0].paddr, 0x8000_0000);
+        assert_eq!(elf.segments[0].mem_size, 0x18);
+        assert_eq!(elf.segments[0].data, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(elf.symbols.find("_start").unwrap().addr, 0x8000_0004);
+        // The program headers are before the loaded segment
+        assert_eq!((elf.phdr, elf.phnum), (0, 1));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Elf::parse(&[0; 64]).is_err());
+        let buf = make_elf(0, 0, &[0; 4], 0, &[]);
+        assert!(Elf::parse(&buf[..40]).is_err());
+        let mut buf64 = buf.clone();
+        buf64[4] = 2;
+        assert!(Elf::parse(&buf64).is_err());
+    }
+
+    #[test]
+    fn test_symbol_lookup() {
+        let table = SymbolTable::new(vec![
+            Symbol {
+                name: "main".to_string(),
+                addr: 0x100,
+                size: 0x20,
+            },
+            Symbol {
+                name: "_start".to_string(),
+                addr: 0x0,
+                size: 0,
+            },
+            Symbol {
+                name: "helper".to_string(),
+                addr: 0x200,
+                size: 0x10,
+            },
+        ]);
+        assert_eq!(table.describe(0x0).unwrap(), "_start");
+        assert_eq!(table.describe(0x44).unwrap(), "_start+0x44");
+        assert_eq!(table.describe(0x100).unwrap(), "main");
+        assert_eq!(table.describe(0x11c).unwrap(), "main+0x1c");
+        // Past the end of main, fall back to the unsized _start label
+        assert_eq!(table.describe(0x124).unwrap(), "_start+0x124");
+        assert_eq!(table.describe(0x208).unwrap(), "helper+0x8");
+        assert!(SymbolTable::default().describe(0x208).is_none());
+    }
+
+    #[test]
+    fn test_load_into_system() {
+        use crate::{Config, System};
+        use bytesize::ByteSize;
+
+        // Segment placed in the middle of RAM, with 8 bytes of .bss
+        let buf = make_elf(
+            0x8000_1000,
+            0x8000_1000,
+            &[0x13, 0, 0, 0],
+            8,
+            &[("_start", 0x8000_1000, 4)],
+        );
+        let path = std::env::temp_dir().join(format!("riscv_sim_elf_{}.elf", std::process::id()));
+        std::fs::write(&path, buf).unwrap();
+
+        let mut cfg = Config::new();
+        cfg.binary = Some(path.clone());
+        cfg.size = ByteSize::kib(16);
+        cfg.base = 0x8000_0000;
+        let mut sys = System::from_config(cfg);
+        sys.mem.ram.as_u8_mut()[0x1004] = 0xff;
+
+        // Reloading the ELF re-zeros the .bss
+        crate::run::load_elf_from_file(&mut sys, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(sys.pc(), 0x8000_1000);
+        assert_eq!(sys.mem.ram.as_u32()[0x1000 / 4], 0x13);
+        assert_eq!(&sys.mem.ram.as_u8()[0x1004..0x100c], &[0; 8]);
+        assert_eq!(sys.symbols.describe(0x8000_1002).unwrap(), "_start+0x2");
+    }
+}
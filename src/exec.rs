@@ -1,13 +1,15 @@
 use crate::{
     instr::{format::*, Instr},
-    Result, System
+    Exception, Result, System, Trap,
 };
 
 mod atomic;
 mod auipc;
 mod branch;
-mod csr;
+pub mod csr;
 mod env;
+mod fence;
+pub mod float;
 mod jal;
 mod jalr;
 mod load;
@@ -15,6 +17,7 @@ mod lui;
 mod op;
 mod opimm;
 mod store;
+pub mod vector;
 
 pub fn execute(sys: &mut System, instr: &Instr) -> Result {
     match instr {
@@ -30,13 +33,57 @@ pub fn execute(sys: &mut System, instr: &Instr) -> Result {
             branch::execute_branch(sys, rs1, rs2, *imm, f)?
         }
         Instr::Atomic(RType { rd, rs1, rs2 }, f) => atomic::execute_atomic(sys, rd, rs1, rs2, f)?,
-        Instr::Fence => advance_pc(sys),
+        Instr::Fence(op, f) => fence::execute_fence(sys, op, f),
+        Instr::Pause => execute_pause(sys),
+        Instr::Mop(rd) => {
+            // May-be-operations which are not redefined by another extension write 0 to rd
+            *sys.reg_mut(rd) = 0;
+            advance_pc(sys)
+        }
         Instr::Env(f) => env::execute_env(sys, f)?,
         Instr::Csr(CsrType { rd, src, csr }, f) => csr::execute_csr(sys, rd, src, csr, f)?,
+        Instr::LoadFp(IType { rd, rs1, imm }, f) => float::execute_load_fp(sys, rd, rs1, *imm, f)?,
+        Instr::StoreFp(SType { rs1, rs2, imm }, f) => {
+            float::execute_store_fp(sys, rs1, rs2, *imm, f)?
+        }
+        Instr::Fma(R4Type { rd, rs1, rs2, rs3 }, fmt, f) => {
+            float::execute_fma(sys, rd, rs1, rs2, rs3, fmt, f)?
+        }
+        Instr::OpFp(RType { rd, rs1, rs2 }, fmt, f) => {
+            float::execute_op_fp(sys, rd, rs1, rs2, fmt, f)?
+        }
+        Instr::SetVl(VSetVlType { rd, avl, vtype }) => vector::execute_setvl(sys, rd, avl, vtype)?,
+        Instr::OpV(op, f) => vector::execute_op_v(sys, op, f)?,
+        Instr::LoadV(op, f) => vector::execute_load_v(sys, op, f)?,
+        Instr::StoreV(op, f) => vector::execute_store_v(sys, op, f)?,
     }
     Ok(())
 }
 
 fn advance_pc(sys: &mut System) {
-    *sys.pc_mut() = sys.pc().wrapping_add(4);
+    *sys.pc_mut() = sys.next_pc();
+}
+
+// A hint in spin-wait loops, which can skip ahead in time and yield to another hart
+fn execute_pause(sys: &mut System) {
+    let skip = sys.cfg.pause_skip;
+    if skip > 0 {
+        sys.mem.timer.time = sys.mem.timer.time.wrapping_add(skip);
+        if !sys.ctrl.mcycle_inhibit {
+            sys.ctrl.mcycle = sys.ctrl.mcycle.wrapping_add(skip);
+        }
+        sys.yield_hart();
+    }
+    advance_pc(sys);
+}
+
+fn check_jump_target(sys: &System, pc_jump: u32) -> Result {
+    // Jump targets must be 2-byte aligned with the C extension, 4-byte aligned otherwise
+    if pc_jump & !sys.ctrl.pc_mask() != 0 {
+        return Err(Trap::from_exception(
+            Exception::InstrAddrMisaligned,
+            pc_jump,
+        ));
+    }
+    Ok(())
 }
@@ -1,6 +1,7 @@
 use super::{advance_pc, Result};
 use crate::{
     instr::{funct::*, reg::Reg},
+    pmp::check_pmp,
     sys::mem_map::{AccessAttr, AccessType, AccessWidth},
     translate::translate,
     System, Trap,
@@ -28,8 +29,9 @@ fn load_reserved(sys: &mut System, rd: &Reg, rs1: &Reg) -> Result {
         lrsc: true,
         amo: false,
     };
+    check_pmp(sys, paddr, attr).map_err(make_trap)?;
     let data = sys.mem.read_u32(paddr, attr).map_err(make_trap)? as i32;
-    sys.mem.reserve(paddr);
+    sys.mem.reserve(sys.hart_id, paddr);
     *sys.reg_mut(rd) = data;
     Ok(())
 }
@@ -46,7 +48,8 @@ fn store_conditional(sys: &mut System, rd: &Reg, rs1: &Reg, rs2: &Reg) -> Result
         lrsc: true,
         amo: false,
     };
-    if sys.mem.is_reserved(paddr) {
+    check_pmp(sys, paddr, attr).map_err(make_trap)?;
+    if sys.mem.is_reserved(sys.hart_id, paddr) {
         // Only write when reservation is still valid
         let data = sys.reg(rs2);
         sys.mem
@@ -61,7 +64,7 @@ fn store_conditional(sys: &mut System, rd: &Reg, rs1: &Reg, rs2: &Reg) -> Result
         *sys.reg_mut(rd) = 1;
     }
     // Invalidate any reservation
-    sys.mem.clear_reservation();
+    sys.mem.clear_reservation(sys.hart_id);
     Ok(())
 }
 
@@ -80,6 +83,7 @@ fn execute_amo(sys: &mut System, rd: &Reg, rs1: &Reg, rs2: &Reg, f: &AmoFunct) -
         lrsc: false,
         amo: true,
     };
+    check_pmp(sys, paddr, attr).map_err(make_trap)?;
     let data = sys.mem.read_u32(paddr, attr).map_err(make_trap)? as i32;
 
     // Modify the data as store back at addr
@@ -166,7 +170,7 @@ mod tests {
         store_conditional(sys, 4, 1, 2).unwrap();
         assert_eq!(sys.mem.read_u32(TEST_ADDR, load_attr()).unwrap(), expect);
         assert_reg(&sys, 4, if success { 0 } else { 1 });
-        assert!(!sys.mem.is_reserved(TEST_ADDR));
+        assert!(!sys.mem.is_reserved(0, TEST_ADDR));
     }
 
     fn assert_amo(
@@ -217,7 +221,7 @@ mod tests {
         // Load reserved
         load_reserved(&mut sys, 3, 1).unwrap();
         assert_reg(&sys, 3, 0xbcfec832);
-        assert!(sys.mem.is_reserved(TEST_ADDR));
+        assert!(sys.mem.is_reserved(0, TEST_ADDR));
         // Store conditional (should succeed)
         sc_and_assert(&mut sys, 0x51290ce3, true);
         assert_eq!(sys.pc(), 8);
@@ -235,14 +239,14 @@ mod tests {
         // Load reserved
         load_reserved(&mut sys, 3, 1).unwrap();
         assert_reg(&sys, 3, 0xbcfec832);
-        assert!(sys.mem.is_reserved(TEST_ADDR));
+        assert!(sys.mem.is_reserved(0, TEST_ADDR));
         // Store conditional (other addr - should fail)
         store_conditional(&mut sys, 4, 6, 2).unwrap();
         assert_eq!(sys.mem.read_u32(TEST_ADDR, load_attr()).unwrap(), 0xbcfec832);
         assert_eq!(sys.mem.read_u32(OTHER_ADDR, load_attr()).unwrap(), 0x942a44b1);
         assert_reg(&sys, 4, 1);
-        assert!(!sys.mem.is_reserved(TEST_ADDR));
-        assert!(!sys.mem.is_reserved(OTHER_ADDR));
+        assert!(!sys.mem.is_reserved(0, TEST_ADDR));
+        assert!(!sys.mem.is_reserved(0, OTHER_ADDR));
 
         assert_eq!(sys.pc(), 8);
     }
@@ -1,7 +1,7 @@
-use super::Result;
+use super::{check_jump_target, Result};
 use crate::{
     instr::{funct::BranchFunct, reg::Reg},
-    Exception, System, Trap,
+    System,
 };
 
 pub fn execute_branch(sys: &mut System, rs1: &Reg, rs2: &Reg, imm: i32, f: &BranchFunct) -> Result {
@@ -16,13 +16,13 @@ pub fn execute_branch(sys: &mut System, rs1: &Reg, rs2: &Reg, imm: i32, f: &Bran
         BranchFunct::Ltu => (rs1 as u32) < (rs2 as u32),
         BranchFunct::Geu => (rs1 as u32) >= (rs2 as u32),
     };
-    let pc_next = pc.wrapping_add_signed(if branch_cond { imm } else { 4 });
-    if pc_next & 0b11 != 0 {
-        return Err(Trap::from_exception(
-            Exception::InstrAddrMisaligned,
-            pc_next,
-        ));
-    }
+    let pc_next = if branch_cond {
+        let pc_jump = pc.wrapping_add_signed(imm);
+        check_jump_target(sys, pc_jump)?;
+        pc_jump
+    } else {
+        sys.next_pc()
+    };
     *sys.pc_mut() = pc_next;
     Ok(())
 }
@@ -30,6 +30,7 @@ pub fn execute_branch(sys: &mut System, rs1: &Reg, rs2: &Reg, imm: i32, f: &Bran
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{Exception, Trap};
 
     fn assert_branch(
         sys: &mut System,
@@ -97,7 +98,9 @@ mod tests {
 
     #[test]
     fn test_execute_branch_misaligned() {
+        // Without the C extension, jump targets must be 4-byte aligned
         let mut sys = System::new();
+        sys.ctrl.ext_c = false;
         *sys.reg_mut(&Reg::new(1)) = 0xbcfec832_u32 as i32;
         *sys.reg_mut(&Reg::new(2)) = 0x51290ce3_u32 as i32;
         *sys.reg_mut(&Reg::new(4)) = 0xbcfec832_u32 as i32;
@@ -126,4 +129,15 @@ mod tests {
         assert_branch(&mut sys, 2, 1, 0x2, BranchFunct::Geu, 0, 0x4);
         assert_branch_failed(&mut sys, 1, 4, 0x2, BranchFunct::Geu, 0);
     }
+
+    #[test]
+    fn test_execute_branch_compressed() {
+        // With the C extension, 2-byte aligned targets are allowed
+        let mut sys = System::new();
+        *sys.reg_mut(&Reg::new(1)) = 0xbcfec832_u32 as i32;
+        *sys.reg_mut(&Reg::new(4)) = 0xbcfec832_u32 as i32;
+
+        assert_branch(&mut sys, 1, 4, 0x2, BranchFunct::Eq, 0, 0x2);
+        assert_branch(&mut sys, 1, 4, -0x6, BranchFunct::Geu, 0x8, 0x2);
+    }
 }
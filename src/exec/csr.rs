@@ -49,6 +49,23 @@ fn get_src(sys: &System, src: &CsrSrc) -> u32 {
     }
 }
 
+// Debugger access to a CSR, always with M-mode privilege
+pub fn csr_read_debug(sys: &mut System, csr: &CsrReg) -> Result32 {
+    let privilege = sys.ctrl.privilege;
+    sys.ctrl.privilege = MPriv::M;
+    let res = csr_read(sys, csr);
+    sys.ctrl.privilege = privilege;
+    res
+}
+
+pub fn csr_write_debug(sys: &mut System, csr: &CsrReg, val: u32) -> Result {
+    let privilege = sys.ctrl.privilege;
+    sys.ctrl.privilege = MPriv::M;
+    let res = csr_write(sys, csr, val);
+    sys.ctrl.privilege = privilege;
+    res
+}
+
 fn csr_read(sys: &mut System, csr: &CsrReg) -> Result32 {
     match csr {
         CsrReg::U(u) => csr_read_u(sys, u),
@@ -96,6 +113,7 @@ fn csr_write(sys: &mut System, csr: &CsrReg, val: u32) -> Result {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{proc::update_interrupt, Interrupt};
 
     fn assert_csr_reg(sys: &mut System, rd: u8, rs1: u8, csr: CsrReg, f: CsrFunct, expect: u32) {
         execute_csr(sys, &Reg::new(rd), &CsrSrc::Reg(Reg::new(rs1)), &csr, &f).unwrap();
@@ -157,4 +175,98 @@ mod tests {
 
         assert_eq!(sys.state.pc(), 8 * 4);
     }
+
+    #[test]
+    fn test_stimecmp() {
+        let mut sys = System::new();
+        let stimecmp = CsrReg::S(CsrRegS::STimeCmp);
+        let stimecmph = CsrReg::S(CsrRegS::STimeCmph);
+        let mip = CsrReg::M(CsrRegM::MIp);
+
+        // Not accessible in S-mode unless enabled by menvcfg.STCE and mcounteren.TM
+        sys.ctrl.privilege = MPriv::S;
+        assert!(csr_read(&mut sys, &stimecmp).is_err());
+        sys.ctrl.privilege = MPriv::M;
+        csr_write(&mut sys, &CsrReg::M(CsrRegM::MEnvCfgh), 1 << 31).unwrap();
+        sys.ctrl.privilege = MPriv::S;
+        assert!(csr_write(&mut sys, &stimecmp, 0).is_err());
+        sys.ctrl.mtime_en = true;
+        csr_write(&mut sys, &stimecmph, 0).unwrap();
+        csr_write(&mut sys, &stimecmp, 10).unwrap();
+        assert_eq!(sys.ctrl.stimecmp, 10);
+
+        // STIP follows time >= stimecmp, and is read-only in mip
+        sys.mem.timer.time = 9;
+        update_interrupt(&mut sys);
+        assert!(!sys.ctrl.ip.get(&Interrupt::STimer));
+        sys.mem.timer.time = 10;
+        update_interrupt(&mut sys);
+        assert!(sys.ctrl.ip.get(&Interrupt::STimer));
+        sys.ctrl.privilege = MPriv::M;
+        csr_write(&mut sys, &mip, 0).unwrap();
+        assert!(sys.ctrl.ip.get(&Interrupt::STimer));
+
+        // Without STCE, STIP is written by M-mode
+        csr_write(&mut sys, &CsrReg::M(CsrRegM::MEnvCfgh), 0).unwrap();
+        csr_write(&mut sys, &mip, 0).unwrap();
+        update_interrupt(&mut sys);
+        assert!(!sys.ctrl.ip.get(&Interrupt::STimer));
+    }
+
+    #[test]
+    fn test_hpm_csrs() {
+        let mut sys = System::new();
+        let hpmcounter4 = CsrReg::U(CsrRegU::HpmCounter(4));
+        let hpmcounterh4 = CsrReg::U(CsrRegU::HpmCounterh(4));
+        let scountovf = CsrReg::S(CsrRegS::SCountOvf);
+        csr_write(&mut sys, &CsrReg::M(CsrRegM::MHpmCounterh(4)), 0x1234).unwrap();
+        csr_write(&mut sys, &CsrReg::M(CsrRegM::MHpmEventh(4)), 1 << 31).unwrap();
+        csr_write(&mut sys, &CsrReg::M(CsrRegM::MCountInhibit), 0xffff_ffff).unwrap();
+        assert_eq!(sys.ctrl.hpm.inhibit, 0xffff_fff8);
+        assert_eq!(csr_read(&mut sys, &hpmcounterh4), Ok(0x1234));
+        assert_eq!(csr_read(&mut sys, &scountovf), Ok(1 << 4));
+
+        // Accessible in S-mode and U-mode if enabled by mcounteren and scounteren
+        sys.ctrl.privilege = MPriv::S;
+        assert!(csr_read(&mut sys, &hpmcounter4).is_err());
+        assert_eq!(csr_read(&mut sys, &scountovf), Ok(0));
+        sys.ctrl.hpm.en_m = 1 << 4;
+        assert_eq!(csr_read(&mut sys, &hpmcounter4), Ok(0));
+        assert_eq!(csr_read(&mut sys, &scountovf), Ok(1 << 4));
+        assert!(csr_write(&mut sys, &hpmcounter4, 0).is_err());
+        sys.ctrl.privilege = MPriv::U;
+        assert!(csr_read(&mut sys, &hpmcounter4).is_err());
+        sys.ctrl.hpm.en_s = 1 << 4;
+        assert_eq!(csr_read(&mut sys, &hpmcounter4), Ok(0));
+
+        // LCOFIP is writable in sip
+        sys.ctrl.privilege = MPriv::S;
+        sys.ctrl.ip.set(&Interrupt::STimer, true);
+        csr_write(&mut sys, &CsrReg::S(CsrRegS::SIp), 1 << 13).unwrap();
+        assert!(sys.ctrl.ip.get(&Interrupt::LocalCountOverflow));
+        assert!(sys.ctrl.ip.get(&Interrupt::STimer));
+    }
+
+    #[test]
+    fn test_vector_csrs() {
+        let mut sys = System::new();
+        let mstatus = CsrReg::M(CsrRegM::MStatus);
+        let vcsr = CsrReg::U(CsrRegU::VCsr);
+
+        // vcsr mirrors vxrm and vxsat, and writes make VS dirty (reflected in SD)
+        csr_write(&mut sys, &CsrReg::U(CsrRegU::VXRm), 0b110).unwrap();
+        csr_write(&mut sys, &CsrReg::U(CsrRegU::VXSat), 1).unwrap();
+        assert_eq!(csr_read(&mut sys, &vcsr), Ok(0b101));
+        let sd_vs = 0x8000_0600;
+        assert_eq!(csr_read(&mut sys, &mstatus).unwrap() & sd_vs, sd_vs);
+        assert_eq!(csr_read(&mut sys, &CsrReg::U(CsrRegU::VLenB)), Ok(16));
+        assert!(csr_write(&mut sys, &CsrReg::U(CsrRegU::Vl), 0).is_err());
+        csr_write(&mut sys, &CsrReg::U(CsrRegU::VStart), 0x1234).unwrap();
+        assert_eq!(sys.ctrl.vstart, 0x34);
+
+        // Not accessible while VS is off
+        csr_write(&mut sys, &mstatus, 0).unwrap();
+        assert!(csr_read(&mut sys, &vcsr).is_err());
+        assert!(csr_read(&mut sys, &CsrReg::U(CsrRegU::VType)).is_err());
+    }
 }
@@ -1,5 +1,6 @@
 use super::{Result, Result32};
 use crate::{
+    hpm::HPM_MASK,
     instr::csr::{CsrRegM::*, *},
     sys::{control::*, make_illegal},
     trap::TrapCause,
@@ -10,9 +11,19 @@ const MISA_MXL_32: u32 = 1 << 30;
 const MISA_EXT_I: u32 = 1 << 8;
 const MISA_EXT_M: u32 = 1 << 12;
 const MISA_EXT_A: u32 = 1 << 0;
+const MISA_EXT_C: u32 = 1 << 2;
+const MISA_EXT_F: u32 = 1 << 5;
+const MISA_EXT_D: u32 = 1 << 3;
 const MISA_EXT_U: u32 = 1 << 20;
 const MISA_EXT_S: u32 = 1 << 18;
-const MISA: u32 = MISA_MXL_32 | MISA_EXT_I | MISA_EXT_M | MISA_EXT_A | MISA_EXT_U | MISA_EXT_S;
+const MISA: u32 = MISA_MXL_32
+    | MISA_EXT_I
+    | MISA_EXT_M
+    | MISA_EXT_A
+    | MISA_EXT_F
+    | MISA_EXT_D
+    | MISA_EXT_U
+    | MISA_EXT_S;
 
 pub fn csr_read_m(sys: &mut System, csr: &CsrRegM) -> Result32 {
     match csr {
@@ -20,11 +31,11 @@ pub fn csr_read_m(sys: &mut System, csr: &CsrRegM) -> Result32 {
         MVendorId => Ok(0),
         MArchId => Ok(0),
         MImpId => Ok(0),
-        MHartId => Ok(0),
+        MHartId => Ok(sys.hart_id as u32),
         MConfigPtr => Ok(0),
         // Machine trap setup
         MStatus => Ok(read_mstatus(sys)),
-        MIsa => Ok(MISA),
+        MIsa => Ok(read_misa(sys)),
         MEdeleg => Ok(read_medeleg(sys)),
         MIdeleg => Ok(read_mideleg(sys)),
         MIe => Ok(read_mie(sys)),
@@ -40,21 +51,21 @@ pub fn csr_read_m(sys: &mut System, csr: &CsrRegM) -> Result32 {
         MIp => Ok(read_mip(sys)),
         // Machine configuration
         MEnvCfg => Ok(read_menvcfg(sys)),
-        MEnvCfgh => Ok(0),
-        // Machine memory protection (not supported)
-        PmpCfg(_) => Ok(0),
-        PmpAddr(_) => Ok(0),
+        MEnvCfgh => Ok(read_menvcfgh(sys)),
+        // Machine memory protection
+        PmpCfg(i) => Ok(sys.ctrl.pmp.read_cfg(*i as usize)),
+        PmpAddr(i) => Ok(sys.ctrl.pmp.read_addr(*i as usize)),
         // Machine counter/timer
         MCycle => Ok(read_mcycle(sys)),
         MInstRet => Ok(read_minstret(sys)),
-        MHpmCounter(_) => Ok(0),
+        MHpmCounter(i) => Ok(read_mhpmcounter(sys, *i)),
         MCycleh => Ok(read_mcycleh(sys)),
         MInstReth => Ok(read_minstreth(sys)),
-        MHpmCounterh(_) => Ok(0),
+        MHpmCounterh(i) => Ok(read_mhpmcounterh(sys, *i)),
         // Machine counter setup
         MCountInhibit => Ok(read_mcountinhibit(sys)),
-        MHpmEvent(_) => Ok(0),
-        MHpmEventh(_) => Ok(0),
+        MHpmEvent(i) => Ok(sys.ctrl.hpm.read_event(*i as usize)),
+        MHpmEventh(i) => Ok(sys.ctrl.hpm.read_eventh(*i as usize)),
     }
 }
 
@@ -68,7 +79,7 @@ pub fn csr_write_m(sys: &mut System, csr: &CsrRegM, val: u32) -> Result {
         MConfigPtr => Err(make_illegal(sys)),
         // Machine trap setup
         MStatus => Ok(write_mstatus(sys, val)),
-        MIsa => Ok(()),
+        MIsa => Ok(write_misa(sys, val)),
         MEdeleg => Ok(write_medeleg(sys, val)),
         MIdeleg => Ok(write_mideleg(sys, val)),
         MIe => Ok(write_mie(sys, val)),
@@ -84,21 +95,21 @@ pub fn csr_write_m(sys: &mut System, csr: &CsrRegM, val: u32) -> Result {
         MIp => Ok(write_mip(sys, val)),
         // Machine configuration
         MEnvCfg => Ok(write_menvcfg(sys, val)),
-        MEnvCfgh => Ok(()),
-        // Machine memory protection (not supported)
-        PmpCfg(_) => Ok(()),
-        PmpAddr(_) => Ok(()),
+        MEnvCfgh => Ok(write_menvcfgh(sys, val)),
+        // Machine memory protection
+        PmpCfg(i) => Ok(sys.ctrl.pmp.write_cfg(*i as usize, val)),
+        PmpAddr(i) => Ok(sys.ctrl.pmp.write_addr(*i as usize, val)),
         // Machine counter/timer
         MCycle => Ok(write_mcycle(sys, val)),
         MInstRet => Ok(write_minstret(sys, val)),
-        MHpmCounter(_) => Ok(()),
+        MHpmCounter(i) => Ok(write_mhpmcounter(sys, *i, val)),
         MCycleh => Ok(write_mcycleh(sys, val)),
         MInstReth => Ok(write_minstreth(sys, val)),
-        MHpmCounterh(_) => Ok(()),
+        MHpmCounterh(i) => Ok(write_mhpmcounterh(sys, *i, val)),
         // Machine counter setup
         MCountInhibit => Ok(write_mcountinhibit(sys, val)),
-        MHpmEvent(_) => Ok(()),
-        MHpmEventh(_) => Ok(()),
+        MHpmEvent(i) => Ok(sys.ctrl.hpm.write_event(*i as usize, val)),
+        MHpmEventh(i) => Ok(sys.ctrl.hpm.write_eventh(*i as usize, val)),
     }
 }
 
@@ -117,22 +128,28 @@ fn read_mstatus(sys: &System) -> u32 {
         tvm,
         tw,
         tsr,
+        fs,
+        vs,
         ..
     } = &sys.ctrl;
     let mpp = mpp.to_int();
     let spp = spp.to_int();
+    let sd = *fs == ExtStatus::Dirty || *vs == ExtStatus::Dirty;
     (*sie as u32) << 1
         | (*mie as u32) << 3
         | (*spie as u32) << 5
         | (*mpie as u32) << 7
         | (spp as u32) << 8
         | (mpp as u32) << 11
+        | vs.to_int() << 9
+        | fs.to_int() << 13
         | (*mprv as u32) << 17
         | (*sum as u32) << 18
         | (*mxr as u32) << 19
         | (*tvm as u32) << 20
         | (*tw as u32) << 21
         | (*tsr as u32) << 22
+        | (sd as u32) << 31
 }
 
 fn write_mstatus(sys: &mut System, val: u32) {
@@ -153,6 +170,30 @@ fn write_mstatus(sys: &mut System, val: u32) {
     if let Some(spp) = SPriv::from((val >> 8) & 0b1) {
         sys.ctrl.spp = spp;
     }
+    if let Some(vs) = ExtStatus::from((val >> 9) & 0b11) {
+        sys.ctrl.vs = vs;
+    }
+    if let Some(fs) = ExtStatus::from((val >> 13) & 0b11) {
+        sys.ctrl.fs = fs;
+    }
+}
+
+// ------------------- MISA ---------------------
+fn read_misa(sys: &System) -> u32 {
+    MISA | if sys.ctrl.ext_c { MISA_EXT_C } else { 0 }
+}
+
+fn write_misa(sys: &mut System, val: u32) {
+    // Only C can be toggled. Disabling it is ignored if the next
+    // instruction would not be 4-byte aligned.
+    let ext_c = val & MISA_EXT_C != 0;
+    if ext_c || sys.next_pc() & 0b11 == 0 {
+        // Compressed instructions in the decode cache become illegal or legal
+        if sys.ctrl.ext_c != ext_c {
+            sys.mem.icache.flush();
+        }
+        sys.ctrl.ext_c = ext_c;
+    }
 }
 
 // ------------------ MTVEC ---------------------
@@ -186,7 +227,7 @@ fn write_medeleg(sys: &mut System, val: u32) {
 }
 
 // ----------------- MIDELEG --------------------
-const IDELEG_MASK: u32 = 0x222; // All S-mode interrupta
+const IDELEG_MASK: u32 = 0x2222; // All S-mode interrupts and LCOFI
 
 fn read_mideleg(sys: &System) -> u32 {
     sys.ctrl.mideleg.0 & IDELEG_MASK
@@ -200,23 +241,25 @@ fn write_mideleg(sys: &mut System, val: u32) {
 
 // ------------------- MIP ----------------------
 fn read_mip(sys: &System) -> u32 {
-    sys.ctrl.ip.0 & 0xaaa
+    sys.ctrl.ip.0 & 0x2aaa
 }
 
 fn write_mip(sys: &mut System, val: u32) {
-    // meip, mtip, msip are read-only
-    sys.ctrl.ip.0 &= !0x222;
-    sys.ctrl.ip.0 |= val & 0x222;
+    // meip, mtip, msip are read-only, and so is stip if driven by stimecmp
+    let mask = if sys.ctrl.stce { 0x2202 } else { 0x2222 };
+    sys.ctrl.ip.0 &= !mask;
+    sys.ctrl.ip.0 |= val & mask;
+    sys.ctrl.seip = val & 0x200 != 0;
 }
 
 // ------------------- MIE ----------------------
 fn read_mie(sys: &System) -> u32 {
-    sys.ctrl.ie.0 & 0xaaa
+    sys.ctrl.ie.0 & 0x2aaa
 }
 
 fn write_mie(sys: &mut System, val: u32) {
-    sys.ctrl.ie.0 &= !0xaaa;
-    sys.ctrl.ie.0 = val & 0xaaa;
+    sys.ctrl.ie.0 &= !0x2aaa;
+    sys.ctrl.ie.0 = val & 0x2aaa;
 }
 
 // ---------------- MCOUNTEREN ------------------
@@ -227,13 +270,14 @@ fn read_mcounteren(sys: &System) -> u32 {
         minstret_en,
         ..
     } = &sys.ctrl;
-    (*mcycle_en as u32) | (*mtime_en as u32) << 1 | (*minstret_en as u32) << 2
+    (*mcycle_en as u32) | (*mtime_en as u32) << 1 | (*minstret_en as u32) << 2 | sys.ctrl.hpm.en_m
 }
 
 fn write_mcounteren(sys: &mut System, val: u32) {
     sys.ctrl.mcycle_en = (val & 1) != 0;
     sys.ctrl.mtime_en = (val & (1 << 1)) != 0;
     sys.ctrl.minstret_en = (val & (1 << 2)) != 0;
+    sys.ctrl.hpm.en_m = val & HPM_MASK;
 }
 
 // ----------------- MSCRATCH -------------------
@@ -247,11 +291,11 @@ fn write_mscratch(sys: &mut System, val: u32) {
 
 // ------------------- MEPC ---------------------
 fn read_mepc(sys: &System) -> u32 {
-    sys.ctrl.mepc & 0xffff_fffc
+    sys.ctrl.mepc & sys.ctrl.pc_mask()
 }
 
 fn write_mepc(sys: &mut System, val: u32) {
-    sys.ctrl.mepc = val & 0xffff_fffc;
+    sys.ctrl.mepc = val & 0xffff_fffe;
 }
 
 // ------------------ MCAUSE --------------------
@@ -286,6 +330,14 @@ fn write_menvcfg(sys: &mut System, val: u32) {
     sys.ctrl.mfiom = (val & 1) != 0;
 }
 
+fn read_menvcfgh(sys: &System) -> u32 {
+    (sys.ctrl.stce as u32) << 31
+}
+
+fn write_menvcfgh(sys: &mut System, val: u32) {
+    sys.ctrl.stce = (val & (1 << 31)) != 0;
+}
+
 // ----------------- MCYCLE ---------------------
 pub fn read_mcycle(sys: &System) -> u32 {
     sys.ctrl.mcycle as u32
@@ -324,6 +376,27 @@ fn write_minstreth(sys: &mut System, val: u32) {
     sys.ctrl.minstret |= (val as u64) << 32;
 }
 
+// -------------- MHPMCOUNTER -------------------
+pub fn read_mhpmcounter(sys: &System, i: u8) -> u32 {
+    sys.ctrl.hpm.read_counter(i as usize) as u32
+}
+
+pub fn read_mhpmcounterh(sys: &System, i: u8) -> u32 {
+    (sys.ctrl.hpm.read_counter(i as usize) >> 32) as u32
+}
+
+fn write_mhpmcounter(sys: &mut System, i: u8, val: u32) {
+    let counter = sys.ctrl.hpm.read_counter(i as usize);
+    let counter = counter & 0xffff_ffff_0000_0000 | val as u64;
+    sys.ctrl.hpm.write_counter(i as usize, counter);
+}
+
+fn write_mhpmcounterh(sys: &mut System, i: u8, val: u32) {
+    let counter = sys.ctrl.hpm.read_counter(i as usize);
+    let counter = counter & 0x0000_0000_ffff_ffff | (val as u64) << 32;
+    sys.ctrl.hpm.write_counter(i as usize, counter);
+}
+
 // -------------- MCOUNTINHIBIT -----------------
 fn read_mcountinhibit(sys: &System) -> u32 {
     let Control {
@@ -331,10 +404,11 @@ fn read_mcountinhibit(sys: &System) -> u32 {
         minstret_inhibit,
         ..
     } = &sys.ctrl;
-    (*mcycle_inhibit as u32) | (*minstret_inhibit as u32) << 2
+    (*mcycle_inhibit as u32) | (*minstret_inhibit as u32) << 2 | sys.ctrl.hpm.inhibit
 }
 
 fn write_mcountinhibit(sys: &mut System, val: u32) {
     sys.ctrl.mcycle_inhibit = (val & 1) != 0;
     sys.ctrl.minstret_inhibit = (val & (1 << 2)) != 0;
+    sys.ctrl.hpm.inhibit = val & HPM_MASK;
 }
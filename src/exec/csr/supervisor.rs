@@ -1,11 +1,15 @@
 use super::{Result, Result32};
 use crate::{
+    hpm::HPM_MASK,
     instr::csr::{CsrRegS::*, *},
     sys::{control::*, make_illegal},
     trap::TrapCause,
     System,
 };
 
+// ASIDLEN is 9 (the maximum for Sv32)
+pub const SATP_ASID_MASK: u32 = 0x1ff;
+
 pub fn csr_read_s(sys: &mut System, csr: &CsrRegS) -> Result32 {
     match csr {
         // Supervisor trap setup
@@ -21,6 +25,11 @@ pub fn csr_read_s(sys: &mut System, csr: &CsrRegS) -> Result32 {
         SCause => Ok(read_scause(sys)),
         STval => Ok(read_stval(sys)),
         SIp => Ok(read_sip(sys)),
+        // Supervisor timer compare
+        STimeCmp => read_stimecmp(sys),
+        STimeCmph => read_stimecmph(sys),
+        // Supervisor counter overflow
+        SCountOvf => Ok(read_scountovf(sys)),
         // Supervisor protection and translation
         SAtp => read_satp(sys),
     }
@@ -41,6 +50,11 @@ pub fn csr_write_s(sys: &mut System, csr: &CsrRegS, val: u32) -> Result {
         SCause => write_scause(sys, val),
         STval => Ok(write_stval(sys, val)),
         SIp => Ok(write_sip(sys, val)),
+        // Supervisor timer compare
+        STimeCmp => write_stimecmp(sys, val),
+        STimeCmph => write_stimecmph(sys, val),
+        // Supervisor counter overflow (read only)
+        SCountOvf => Err(make_illegal(sys)),
         // Supervisor protection and translation
         SAtp => write_satp(sys, val),
     }
@@ -54,14 +68,20 @@ fn read_sstatus(sys: &System) -> u32 {
         spp,
         sum,
         mxr,
+        fs,
+        vs,
         ..
     } = &sys.ctrl;
     let spp = spp.to_int();
+    let sd = *fs == ExtStatus::Dirty || *vs == ExtStatus::Dirty;
     (*sie as u32) << 1
         | (*spie as u32) << 5
         | (spp as u32) << 8
+        | vs.to_int() << 9
+        | fs.to_int() << 13
         | (*sum as u32) << 18
         | (*mxr as u32) << 19
+        | (sd as u32) << 31
 }
 
 fn write_sstatus(sys: &mut System, val: u32) {
@@ -73,6 +93,12 @@ fn write_sstatus(sys: &mut System, val: u32) {
     if let Some(spp) = SPriv::from((val >> 8) & 0b1) {
         sys.ctrl.spp = spp;
     }
+    if let Some(vs) = ExtStatus::from((val >> 9) & 0b11) {
+        sys.ctrl.vs = vs;
+    }
+    if let Some(fs) = ExtStatus::from((val >> 13) & 0b11) {
+        sys.ctrl.fs = fs;
+    }
 }
 
 // ------------------ STVEC ---------------------
@@ -94,23 +120,23 @@ fn write_stvec(sys: &mut System, val: u32) {
 
 // ------------------- SIP ----------------------
 fn read_sip(sys: &System) -> u32 {
-    sys.ctrl.ip.0 & 0x222
+    sys.ctrl.ip.0 & 0x2222
 }
 
 fn write_sip(sys: &mut System, val: u32) {
     // seip, stip are read-only
-    sys.ctrl.ip.0 &= !0x2;
-    sys.ctrl.ip.0 = val & 0x2
+    sys.ctrl.ip.0 &= !0x2002;
+    sys.ctrl.ip.0 |= val & 0x2002;
 }
 
 // ------------------- SIE ----------------------
 fn read_sie(sys: &System) -> u32 {
-    sys.ctrl.ie.0 & 0x222
+    sys.ctrl.ie.0 & 0x2222
 }
 
 fn write_sie(sys: &mut System, val: u32) {
-    sys.ctrl.ie.0 &= !0x222;
-    sys.ctrl.ie.0 |= val & 0x222;
+    sys.ctrl.ie.0 &= !0x2222;
+    sys.ctrl.ie.0 |= val & 0x2222;
 }
 
 // ---------------- SCOUNTEREN ------------------
@@ -121,13 +147,25 @@ fn read_scounteren(sys: &System) -> u32 {
         sinstret_en,
         ..
     } = &sys.ctrl;
-    (*scycle_en as u32) | (*stime_en as u32) << 1 | (*sinstret_en as u32) << 2
+    (*scycle_en as u32) | (*stime_en as u32) << 1 | (*sinstret_en as u32) << 2 | sys.ctrl.hpm.en_s
 }
 
 fn write_scounteren(sys: &mut System, val: u32) {
     sys.ctrl.scycle_en = (val & 1) != 0;
     sys.ctrl.stime_en = (val & (1 << 1)) != 0;
     sys.ctrl.sinstret_en = (val & (1 << 2)) != 0;
+    sys.ctrl.hpm.en_s = val & HPM_MASK;
+}
+
+// ---------------- SCOUNTOVF -------------------
+fn read_scountovf(sys: &System) -> u32 {
+    // Only the counters which S-mode may access (all of them in M-mode)
+    let ovf = sys.ctrl.hpm.overflows();
+    if sys.ctrl.privilege == MPriv::M {
+        ovf
+    } else {
+        ovf & sys.ctrl.hpm.en_m
+    }
 }
 
 // ----------------- SSCRATCH -------------------
@@ -141,11 +179,11 @@ fn write_sscratch(sys: &mut System, val: u32) {
 
 // ------------------- SEPC ---------------------
 fn read_sepc(sys: &System) -> u32 {
-    sys.ctrl.sepc & 0xffff_fffc
+    sys.ctrl.sepc & sys.ctrl.pc_mask()
 }
 
 fn write_sepc(sys: &mut System, val: u32) {
-    sys.ctrl.sepc = val & 0xffff_fffc;
+    sys.ctrl.sepc = val & 0xffff_fffe;
 }
 
 // ------------------ SCAUSE --------------------
@@ -180,19 +218,67 @@ fn write_senvcfg(sys: &mut System, val: u32) {
     sys.ctrl.sfiom = (val & 1) != 0;
 }
 
+// ----------------- STIMECMP -------------------
+fn check_stimecmp_access(sys: &System) -> Result {
+    // Accessible in S-mode only if enabled by menvcfg.STCE and mcounteren.TM
+    if sys.ctrl.privilege != MPriv::M && !(sys.ctrl.stce && sys.ctrl.mtime_en) {
+        Err(make_illegal(sys))
+    } else {
+        Ok(())
+    }
+}
+
+fn read_stimecmp(sys: &System) -> Result32 {
+    check_stimecmp_access(sys)?;
+    Ok(sys.ctrl.stimecmp as u32)
+}
+
+fn read_stimecmph(sys: &System) -> Result32 {
+    check_stimecmp_access(sys)?;
+    Ok((sys.ctrl.stimecmp >> 32) as u32)
+}
+
+fn write_stimecmp(sys: &mut System, val: u32) -> Result {
+    check_stimecmp_access(sys)?;
+    sys.ctrl.stimecmp &= 0xffff_ffff_0000_0000;
+    sys.ctrl.stimecmp |= val as u64;
+    Ok(())
+}
+
+fn write_stimecmph(sys: &mut System, val: u32) -> Result {
+    check_stimecmp_access(sys)?;
+    sys.ctrl.stimecmp &= 0x0000_0000_ffff_ffff;
+    sys.ctrl.stimecmp |= (val as u64) << 32;
+    Ok(())
+}
+
 // ------------------ SATP ----------------------
 fn read_satp(sys: &System) -> Result32 {
     if sys.ctrl.tvm {
         Err(make_illegal(sys))?
     }
-    Ok((sys.ctrl.satp_mode.to_int() << 31) | (sys.ctrl.satp_ppn & 0x3fffff))
+    let Control {
+        satp_mode,
+        satp_asid,
+        satp_ppn,
+        ..
+    } = &sys.ctrl;
+    Ok((satp_mode.to_int() << 31) | (satp_asid & SATP_ASID_MASK) << 22 | (satp_ppn & 0x3fffff))
 }
 
 fn write_satp(sys: &mut System, val: u32) -> Result {
     if sys.ctrl.tvm {
         Err(make_illegal(sys))?
     }
-    sys.ctrl.satp_mode = SatpMode::from(val >> 31).unwrap();
+    let mode = SatpMode::from(val >> 31).unwrap();
+    // Entries are tagged by ASID, but they mean nothing once translation is switched
+    if mode != sys.ctrl.satp_mode {
+        sys.ctrl.tlb.flush_all();
+    }
+    sys.ctrl.satp_mode = mode;
+    sys.ctrl.satp_asid = (val >> 22) & SATP_ASID_MASK;
     sys.ctrl.satp_ppn = val & 0x3fffff;
+    // The address space changed
+    sys.mem.icache.flush();
     Ok(())
 }
@@ -1,5 +1,9 @@
 use super::{machine::*, MPriv, Result, Result32};
 use crate::{
+    exec::{
+        float::{check_fs, set_dirty},
+        vector::{check_vs, set_vs_dirty},
+    },
     instr::csr::{CsrRegU::*, *},
     sys::make_illegal,
     System,
@@ -7,20 +11,44 @@ use crate::{
 
 pub fn csr_read_u(sys: &mut System, csr: &CsrRegU) -> Result32 {
     match csr {
+        // Unprivileged floating-point
+        FFlags => read_fflags(sys),
+        Frm => read_frm(sys),
+        FCsr => read_fcsr(sys),
+        // Unprivileged vector
+        VStart => read_vstart(sys),
+        VXSat => read_vxsat(sys),
+        VXRm => read_vxrm(sys),
+        VCsr => read_vcsr(sys),
+        Vl => read_vl(sys),
+        VType => read_vtype(sys),
+        VLenB => read_vlenb(sys),
         // Unprivileged counter/timer
         Cycle => read_cycle(sys),
         Time => read_time(sys),
         InstRet => read_instret(sys),
-        HpmCounter(_) => Err(make_illegal(sys)),
+        HpmCounter(i) => read_hpmcounter(sys, *i),
         Cycleh => read_cycleh(sys),
         Timeh => read_timeh(sys),
         InstReth => read_instreth(sys),
-        HpmCounterh(_) => Err(make_illegal(sys)),
+        HpmCounterh(i) => read_hpmcounterh(sys, *i),
     }
 }
 
-pub fn csr_write_u(sys: &mut System, csr: &CsrRegU, _val: u32) -> Result {
+pub fn csr_write_u(sys: &mut System, csr: &CsrRegU, val: u32) -> Result {
     match csr {
+        // Unprivileged floating-point
+        FFlags => write_fflags(sys, val),
+        Frm => write_frm(sys, val),
+        FCsr => write_fcsr(sys, val),
+        // Unprivileged vector
+        VStart => write_vstart(sys, val),
+        VXSat => write_vxsat(sys, val),
+        VXRm => write_vxrm(sys, val),
+        VCsr => write_vcsr(sys, val),
+        Vl => Err(make_illegal(sys)),
+        VType => Err(make_illegal(sys)),
+        VLenB => Err(make_illegal(sys)),
         // Unprivileged counter/timer (read only)
         Cycle => Err(make_illegal(sys)),
         Time => Err(make_illegal(sys)),
@@ -33,6 +61,117 @@ pub fn csr_write_u(sys: &mut System, csr: &CsrRegU, _val: u32) -> Result {
     }
 }
 
+// ----------------- FFLAGS ---------------------
+fn read_fflags(sys: &System) -> Result32 {
+    check_fs(sys)?;
+    Ok(sys.ctrl.fflags as u32)
+}
+
+fn write_fflags(sys: &mut System, val: u32) -> Result {
+    check_fs(sys)?;
+    sys.ctrl.fflags = (val & 0x1f) as u8;
+    set_dirty(sys);
+    Ok(())
+}
+
+// ------------------- FRM ----------------------
+fn read_frm(sys: &System) -> Result32 {
+    check_fs(sys)?;
+    Ok(sys.ctrl.frm as u32)
+}
+
+fn write_frm(sys: &mut System, val: u32) -> Result {
+    // Invalid rounding modes can be written, but trap when used
+    check_fs(sys)?;
+    sys.ctrl.frm = (val & 0b111) as u8;
+    set_dirty(sys);
+    Ok(())
+}
+
+// ------------------- FCSR ---------------------
+fn read_fcsr(sys: &System) -> Result32 {
+    check_fs(sys)?;
+    Ok((sys.ctrl.frm as u32) << 5 | sys.ctrl.fflags as u32)
+}
+
+fn write_fcsr(sys: &mut System, val: u32) -> Result {
+    check_fs(sys)?;
+    sys.ctrl.fflags = (val & 0x1f) as u8;
+    sys.ctrl.frm = ((val >> 5) & 0b111) as u8;
+    set_dirty(sys);
+    Ok(())
+}
+
+// ------------------ VSTART --------------------
+fn read_vstart(sys: &System) -> Result32 {
+    check_vs(sys)?;
+    Ok(sys.ctrl.vstart)
+}
+
+fn write_vstart(sys: &mut System, val: u32) -> Result {
+    // Only enough bits to hold the largest element index (VLEN - 1)
+    check_vs(sys)?;
+    sys.ctrl.vstart = val & (sys.state.vlenb() as u32 * 8 - 1);
+    set_vs_dirty(sys);
+    Ok(())
+}
+
+// ------------------ VXSAT ---------------------
+fn read_vxsat(sys: &System) -> Result32 {
+    check_vs(sys)?;
+    Ok(sys.ctrl.vxsat as u32)
+}
+
+fn write_vxsat(sys: &mut System, val: u32) -> Result {
+    check_vs(sys)?;
+    sys.ctrl.vxsat = val & 1 != 0;
+    set_vs_dirty(sys);
+    Ok(())
+}
+
+// ------------------- VXRM ---------------------
+fn read_vxrm(sys: &System) -> Result32 {
+    check_vs(sys)?;
+    Ok(sys.ctrl.vxrm as u32)
+}
+
+fn write_vxrm(sys: &mut System, val: u32) -> Result {
+    check_vs(sys)?;
+    sys.ctrl.vxrm = (val & 0b11) as u8;
+    set_vs_dirty(sys);
+    Ok(())
+}
+
+// ------------------- VCSR ---------------------
+fn read_vcsr(sys: &System) -> Result32 {
+    check_vs(sys)?;
+    Ok((sys.ctrl.vxrm as u32) << 1 | sys.ctrl.vxsat as u32)
+}
+
+fn write_vcsr(sys: &mut System, val: u32) -> Result {
+    check_vs(sys)?;
+    sys.ctrl.vxsat = val & 1 != 0;
+    sys.ctrl.vxrm = ((val >> 1) & 0b11) as u8;
+    set_vs_dirty(sys);
+    Ok(())
+}
+
+// -------------- VL, VTYPE, VLENB ---------------
+fn read_vl(sys: &System) -> Result32 {
+    check_vs(sys)?;
+    Ok(sys.ctrl.vl)
+}
+
+fn read_vtype(sys: &System) -> Result32 {
+    check_vs(sys)?;
+    Ok(sys.ctrl.vtype)
+}
+
+fn read_vlenb(sys: &System) -> Result32 {
+    check_vs(sys)?;
+    Ok(sys.state.vlenb() as u32)
+}
+
 // ------------------ CYCLE ---------------------
 fn read_cycle(sys: &System) -> Result32 {
     // Must take into account mcounteren and scounteren
@@ -101,3 +240,30 @@ fn read_instreth(sys: &System) -> Result32 {
         Err(make_illegal(sys))
     }
 }
+
+// ---------------- HPMCOUNTER ------------------
+fn read_hpmcounter(sys: &System, i: u8) -> Result32 {
+    // Must take into account mcounteren and scounteren
+    let bit = 1 << i;
+    if sys.ctrl.privilege == MPriv::M
+        || sys.ctrl.hpm.en_m & bit != 0
+            && (sys.ctrl.privilege == MPriv::S || sys.ctrl.hpm.en_s & bit != 0)
+    {
+        Ok(read_mhpmcounter(sys, i))
+    } else {
+        Err(make_illegal(sys))
+    }
+}
+
+fn read_hpmcounterh(sys: &System, i: u8) -> Result32 {
+    // Must take into account mcounteren and scounteren
+    let bit = 1 << i;
+    if sys.ctrl.privilege == MPriv::M
+        || sys.ctrl.hpm.en_m & bit != 0
+            && (sys.ctrl.privilege == MPriv::S || sys.ctrl.hpm.en_s & bit != 0)
+    {
+        Ok(read_mhpmcounterh(sys, i))
+    } else {
+        Err(make_illegal(sys))
+    }
+}
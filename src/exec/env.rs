@@ -1,6 +1,7 @@
 use super::{advance_pc, Result};
 use crate::{
-    instr::funct::EnvFunct,
+    exec::csr::supervisor::SATP_ASID_MASK,
+    instr::{funct::EnvFunct, reg::Reg},
     proc::{pop_trap_m, pop_trap_s},
     sys::{control::MPriv, make_illegal},
     Exception, System, Trap,
@@ -13,7 +14,7 @@ pub fn execute_env(sys: &mut System, f: &EnvFunct) -> Result {
         EnvFunct::Sret => execute_sret(sys),
         EnvFunct::Mret => execute_mret(sys),
         EnvFunct::Wfi => execute_wfi(sys),
-        EnvFunct::SfenceVma => execute_sfence(sys),
+        EnvFunct::SfenceVma(rs1, rs2) => execute_sfence(sys, rs1, rs2),
     }
 }
 
@@ -59,7 +60,7 @@ fn execute_wfi(sys: &mut System) -> Result {
     }
 }
 
-fn execute_sfence(sys: &mut System) -> Result {
+fn execute_sfence(sys: &mut System, rs1: &Reg, rs2: &Reg) -> Result {
     // Only available in S-mode and M-mode
     if sys.ctrl.privilege == MPriv::U {
         Err(make_illegal(sys))?
@@ -68,7 +69,11 @@ fn execute_sfence(sys: &mut System) -> Result {
     if sys.ctrl.tvm && sys.ctrl.privilege == MPriv::S {
         Err(make_illegal(sys))?
     }
-    // Otherwise, treated as a NOP (since the sim doesn't have a TLB to flush)
+    // x0 means all addresses or all ASIDs
+    let addr = (rs1.index() != 0).then(|| sys.reg(rs1) as u32);
+    let asid = (rs2.index() != 0).then(|| sys.reg(rs2) as u32 & SATP_ASID_MASK);
+    sys.ctrl.tlb.flush(addr, asid);
+    sys.mem.icache.flush();
     advance_pc(sys);
     Ok(())
 }
@@ -0,0 +1,86 @@
+use super::advance_pc;
+use crate::{
+    instr::{format::FenceType, funct::*},
+    sys::control::MPriv,
+    System,
+};
+
+// Accesses are performed one at a time and in order, so memory needs no fence.
+// What is left are the caches of the simulator and the devices.
+pub fn execute_fence(sys: &mut System, op: &FenceType, f: &FenceFunct) {
+    match f {
+        // Drop the decoded instructions, which are also invalidated by stores
+        FenceFunct::I => sys.mem.icache.flush(),
+        FenceFunct::Fence | FenceFunct::Tso => {
+            // Device I/O is ordered by letting the devices process their notified queues,
+            // so that their writes are visible to the accesses after the fence
+            let (pred, succ) = ordering_sets(sys, op);
+            if (pred | succ) & (FENCE_I | FENCE_O) != 0 {
+                sys.mem.poll_virtio();
+            }
+        }
+    }
+    advance_pc(sys);
+}
+
+// With FIOM, the memory reads and writes of fences below M-mode also order device input
+// and output respectively (menvcfg.FIOM for S-mode and U-mode, senvcfg.FIOM for U-mode)
+fn ordering_sets(sys: &System, op: &FenceType) -> (u8, u8) {
+    let fiom = match sys.ctrl.privilege {
+        MPriv::M => false,
+        MPriv::S => sys.ctrl.mfiom,
+        MPriv::U => sys.ctrl.mfiom || sys.ctrl.sfiom,
+    };
+    // R implies I, and W implies O
+    let io = |set: u8| set | (set & (FENCE_R | FENCE_W)) << 2;
+    if fiom {
+        (io(op.pred), io(op.succ))
+    } else {
+        (op.pred, op.succ)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instr::Instr;
+
+    #[test]
+    fn test_execute_fence() {
+        let mut sys = System::new();
+        let rw = FenceType {
+            pred: FENCE_R | FENCE_W,
+            succ: FENCE_W,
+        };
+        sys.mem.icache.insert(0x100, 0x0100000f, Instr::Pause);
+
+        // Only fence.i drops the decoded instructions
+        execute_fence(&mut sys, &rw, &FenceFunct::Fence);
+        execute_fence(&mut sys, &rw, &FenceFunct::Tso);
+        assert!(sys.mem.icache.get(0x100).is_some());
+        execute_fence(&mut sys, &FenceType { pred: 0, succ: 0 }, &FenceFunct::I);
+        assert!(sys.mem.icache.get(0x100).is_none());
+        assert_eq!(sys.state.pc(), 3 * 4);
+    }
+
+    #[test]
+    fn test_fiom() {
+        let mut sys = System::new();
+        let op = FenceType {
+            pred: FENCE_R,
+            succ: FENCE_W,
+        };
+        let (i, o) = (FENCE_I | FENCE_R, FENCE_O | FENCE_W);
+        sys.ctrl.mfiom = true;
+        assert_eq!(ordering_sets(&sys, &op), (FENCE_R, FENCE_W));
+
+        // menvcfg.FIOM applies to S-mode and U-mode, senvcfg.FIOM only to U-mode
+        sys.ctrl.privilege = MPriv::S;
+        assert_eq!(ordering_sets(&sys, &op), (i, o));
+        sys.ctrl.mfiom = false;
+        sys.ctrl.sfiom = true;
+        assert_eq!(ordering_sets(&sys, &op), (FENCE_R, FENCE_W));
+        sys.ctrl.privilege = MPriv::U;
+        assert_eq!(ordering_sets(&sys, &op), (i, o));
+    }
+}
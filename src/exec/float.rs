@@ -0,0 +1,431 @@
+use super::{advance_pc, Result};
+use crate::{
+    instr::{funct::*, reg::Reg},
+    pmp::check_pmp,
+    softfloat::{self, FmaOp, Format, RoundingMode},
+    sys::{
+        control::ExtStatus,
+        make_illegal,
+        mem_map::{AccessAttr, AccessType, AccessWidth},
+    },
+    translate::*,
+    System, Trap,
+};
+
+const NAN_BOX: u64 = 0xffff_ffff_0000_0000;
+
+pub fn check_fs(sys: &System) -> Result {
+    // FP instructions and CSRs are illegal while the FPU is off
+    if sys.ctrl.fs == ExtStatus::Off {
+        Err(make_illegal(sys))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn set_dirty(sys: &mut System) {
+    sys.ctrl.fs = ExtStatus::Dirty;
+}
+
+fn format(fmt: FpFmt) -> Format {
+    match fmt {
+        FpFmt::S => Format::S,
+        FpFmt::D => Format::D,
+    }
+}
+
+pub fn read_f(sys: &System, r: &Reg, fmt: FpFmt) -> u64 {
+    // Single-precision values must be NaN-boxed, otherwise they read as the canonical NaN
+    let val = sys.freg(r);
+    match fmt {
+        FpFmt::S if val & NAN_BOX == NAN_BOX => val & !NAN_BOX,
+        FpFmt::S => Format::S.canonical_nan(),
+        FpFmt::D => val,
+    }
+}
+
+pub fn write_f(sys: &mut System, r: &Reg, fmt: FpFmt, val: u64) {
+    *sys.freg_mut(r) = match fmt {
+        FpFmt::S => val | NAN_BOX,
+        FpFmt::D => val,
+    };
+    set_dirty(sys);
+}
+
+pub fn rounding_mode(sys: &System, rm: FpRm) -> core::result::Result<RoundingMode, Trap> {
+    // Reserved values in frm make dynamic rounding illegal
+    match rm {
+        FpRm::Rne => Ok(RoundingMode::Rne),
+        FpRm::Rtz => Ok(RoundingMode::Rtz),
+        FpRm::Rdn => Ok(RoundingMode::Rdn),
+        FpRm::Rup => Ok(RoundingMode::Rup),
+        FpRm::Rmm => Ok(RoundingMode::Rmm),
+        FpRm::Dyn => RoundingMode::from(sys.ctrl.frm).ok_or_else(|| make_illegal(sys)),
+    }
+}
+
+pub fn accrue_flags(sys: &mut System, flags: u8) {
+    if flags != 0 {
+        sys.ctrl.fflags |= flags;
+        set_dirty(sys);
+    }
+}
+
+// ---------------- Load/Store ------------------
+pub fn execute_load_fp(sys: &mut System, rd: &Reg, rs1: &Reg, imm: i32, f: &LoadFpFunct) -> Result {
+    check_fs(sys)?;
+    let vaddr = sys.reg(rs1).wrapping_add(imm) as u32;
+    let make_trap = |ex| Trap::from_exception(ex, vaddr);
+    // Translate virtual address
+    let paddr = translate(sys, vaddr, AccessType::Load).map_err(make_trap)?;
+    // Load data with physical address
+    let attr = AccessAttr {
+        atype: AccessType::Load,
+        lrsc: false,
+        amo: false,
+        width: match f {
+            LoadFpFunct::W => AccessWidth::Word,
+            LoadFpFunct::D => AccessWidth::DoubleWord,
+        },
+    };
+    check_pmp(sys, paddr, attr).map_err(make_trap)?;
+    match f {
+        LoadFpFunct::W => {
+            let data = sys.mem.read_u32(paddr, attr).map_err(make_trap)?;
+            write_f(sys, rd, FpFmt::S, data as u64);
+        }
+        LoadFpFunct::D => {
+            let data = sys.mem.read_u64(paddr, attr).map_err(make_trap)?;
+            write_f(sys, rd, FpFmt::D, data);
+        }
+    }
+    advance_pc(sys);
+    Ok(())
+}
+
+pub fn execute_store_fp(
+    sys: &mut System,
+    rs1: &Reg,
+    rs2: &Reg,
+    imm: i32,
+    f: &StoreFpFunct,
+) -> Result {
+    check_fs(sys)?;
+    let vaddr = sys.reg(rs1).wrapping_add(imm) as u32;
+    // Stores copy the raw bits, without checking NaN-boxing
+    let data = sys.freg(rs2);
+    let make_trap = |ex| Trap::from_exception(ex, vaddr);
+    // Translate virtual address
+    let paddr = translate(sys, vaddr, AccessType::Store).map_err(make_trap)?;
+    // Store data with physical address
+    let attr = AccessAttr {
+        atype: AccessType::Store,
+        lrsc: false,
+        amo: false,
+        width: match f {
+            StoreFpFunct::W => AccessWidth::Word,
+            StoreFpFunct::D => AccessWidth::DoubleWord,
+        },
+    };
+    check_pmp(sys, paddr, attr).map_err(make_trap)?;
+    match f {
+        StoreFpFunct::W => sys
+            .mem
+            .write_u32(paddr, data as u32, attr)
+            .map_err(make_trap)?,
+        StoreFpFunct::D => sys.mem.write_u64(paddr, data, attr).map_err(make_trap)?,
+    }
+    advance_pc(sys);
+    Ok(())
+}
+
+// ------------- Fused multiply-add -------------
+pub fn execute_fma(
+    sys: &mut System,
+    rd: &Reg,
+    rs1: &Reg,
+    rs2: &Reg,
+    rs3: &Reg,
+    fmt: &FpFmt,
+    f: &FmaFunct,
+) -> Result {
+    check_fs(sys)?;
+    let (op, rm) = match f {
+        FmaFunct::Madd(rm) => (FmaOp::Madd, rm),
+        FmaFunct::Msub(rm) => (FmaOp::Msub, rm),
+        FmaFunct::Nmsub(rm) => (FmaOp::Nmsub, rm),
+        FmaFunct::Nmadd(rm) => (FmaOp::Nmadd, rm),
+    };
+    let rm = rounding_mode(sys, *rm)?;
+    let a = read_f(sys, rs1, *fmt);
+    let b = read_f(sys, rs2, *fmt);
+    let c = read_f(sys, rs3, *fmt);
+    let mut flags = 0;
+    let val = softfloat::fma(format(*fmt), a, b, c, op, rm, &mut flags);
+    write_f(sys, rd, *fmt, val);
+    accrue_flags(sys, flags);
+    advance_pc(sys);
+    Ok(())
+}
+
+// ------------------- OP-FP --------------------
+pub fn execute_op_fp(
+    sys: &mut System,
+    rd: &Reg,
+    rs1: &Reg,
+    rs2: &Reg,
+    fmt: &FpFmt,
+    f: &OpFpFunct,
+) -> Result {
+    check_fs(sys)?;
+    let fmt = *fmt;
+    let ff = format(fmt);
+    let a = read_f(sys, rs1, fmt);
+    let b = read_f(sys, rs2, fmt);
+    let sign = match fmt {
+        FpFmt::S => 1 << 31,
+        FpFmt::D => 1 << 63,
+    };
+    let mut flags = 0;
+    match f {
+        // Arithmetic
+        OpFpFunct::Add(rm) => {
+            let rm = rounding_mode(sys, *rm)?;
+            write_f(sys, rd, fmt, softfloat::add(ff, a, b, rm, &mut flags));
+        }
+        OpFpFunct::Sub(rm) => {
+            let rm = rounding_mode(sys, *rm)?;
+            write_f(sys, rd, fmt, softfloat::sub(ff, a, b, rm, &mut flags));
+        }
+        OpFpFunct::Mul(rm) => {
+            let rm = rounding_mode(sys, *rm)?;
+            write_f(sys, rd, fmt, softfloat::mul(ff, a, b, rm, &mut flags));
+        }
+        OpFpFunct::Div(rm) => {
+            let rm = rounding_mode(sys, *rm)?;
+            write_f(sys, rd, fmt, softfloat::div(ff, a, b, rm, &mut flags));
+        }
+        OpFpFunct::Sqrt(rm) => {
+            let rm = rounding_mode(sys, *rm)?;
+            write_f(sys, rd, fmt, softfloat::sqrt(ff, a, rm, &mut flags));
+        }
+        // Sign injection
+        OpFpFunct::Sgnj => write_f(sys, rd, fmt, a & !sign | b & sign),
+        OpFpFunct::Sgnjn => write_f(sys, rd, fmt, a & !sign | !b & sign),
+        OpFpFunct::Sgnjx => write_f(sys, rd, fmt, a ^ b & sign),
+        // Min/max
+        OpFpFunct::Min => write_f(sys, rd, fmt, softfloat::min(ff, a, b, &mut flags)),
+        OpFpFunct::Max => write_f(sys, rd, fmt, softfloat::max(ff, a, b, &mut flags)),
+        // Conversion between precisions
+        OpFpFunct::CvtFF(rm) => {
+            let rm = rounding_mode(sys, *rm)?;
+            let (src, ff_src) = match fmt {
+                FpFmt::S => (FpFmt::D, Format::D),
+                FpFmt::D => (FpFmt::S, Format::S),
+            };
+            let a = read_f(sys, rs1, src);
+            write_f(
+                sys,
+                rd,
+                fmt,
+                softfloat::convert(ff_src, ff, a, rm, &mut flags),
+            );
+        }
+        // Conversion to and from integers
+        OpFpFunct::CvtWF(rm) => {
+            let rm = rounding_mode(sys, *rm)?;
+            *sys.reg_mut(rd) = softfloat::to_i32(ff, a, rm, &mut flags) as i32;
+        }
+        OpFpFunct::CvtWuF(rm) => {
+            let rm = rounding_mode(sys, *rm)?;
+            *sys.reg_mut(rd) = softfloat::to_u32(ff, a, rm, &mut flags) as i32;
+        }
+        OpFpFunct::CvtFW(rm) => {
+            let rm = rounding_mode(sys, *rm)?;
+            let x = sys.reg(rs1) as u32;
+            write_f(sys, rd, fmt, softfloat::from_i32(ff, x, rm, &mut flags));
+        }
+        OpFpFunct::CvtFWu(rm) => {
+            let rm = rounding_mode(sys, *rm)?;
+            let x = sys.reg(rs1) as u32;
+            write_f(sys, rd, fmt, softfloat::from_u32(ff, x, rm, &mut flags));
+        }
+        // Comparison
+        OpFpFunct::Eq => *sys.reg_mut(rd) = softfloat::eq(ff, a, b, &mut flags) as i32,
+        OpFpFunct::Lt => *sys.reg_mut(rd) = softfloat::lt(ff, a, b, &mut flags) as i32,
+        OpFpFunct::Le => *sys.reg_mut(rd) = softfloat::le(ff, a, b, &mut flags) as i32,
+        OpFpFunct::Class => *sys.reg_mut(rd) = softfloat::classify(ff, a) as i32,
+        // Moves copy the raw bits
+        OpFpFunct::MvXF => *sys.reg_mut(rd) = sys.freg(rs1) as u32 as i32,
+        OpFpFunct::MvFX => {
+            let x = sys.reg(rs1) as u32;
+            write_f(sys, rd, fmt, x as u64);
+        }
+    }
+    accrue_flags(sys, flags);
+    advance_pc(sys);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{softfloat::*, Exception};
+
+    fn s(x: f32) -> u64 {
+        x.to_bits() as u64 | NAN_BOX
+    }
+
+    fn d(x: f64) -> u64 {
+        x.to_bits()
+    }
+
+    fn op_fp(sys: &mut System, rd: u8, rs1: u8, rs2: u8, fmt: FpFmt, f: OpFpFunct) -> Result {
+        execute_op_fp(sys, &Reg::new(rd), &Reg::new(rs1), &Reg::new(rs2), &fmt, &f)
+    }
+
+    #[test]
+    fn test_load_store_fp() {
+        let mut sys = System::new();
+        sys.mem.ram.as_u8_mut()[0x100..0x108].copy_from_slice(&1.5f64.to_le_bytes());
+        sys.mem.ram.as_u8_mut()[0x108..0x10c].copy_from_slice(&(-2.0f32).to_le_bytes());
+        *sys.reg_mut(&Reg::new(1)) = 0x100;
+
+        // Single-precision loads are NaN-boxed
+        execute_load_fp(&mut sys, &Reg::new(2), &Reg::new(1), 0, &LoadFpFunct::D).unwrap();
+        execute_load_fp(&mut sys, &Reg::new(3), &Reg::new(1), 8, &LoadFpFunct::W).unwrap();
+        assert_eq!(sys.freg(&Reg::new(2)), d(1.5));
+        assert_eq!(sys.freg(&Reg::new(3)), s(-2.0));
+        assert_eq!(sys.ctrl.fs, ExtStatus::Dirty);
+
+        execute_store_fp(&mut sys, &Reg::new(1), &Reg::new(2), 0x10, &StoreFpFunct::D).unwrap();
+        execute_store_fp(&mut sys, &Reg::new(1), &Reg::new(3), 0x18, &StoreFpFunct::W).unwrap();
+        assert_eq!(sys.mem.ram.as_u8()[0x110..0x118], 1.5f64.to_le_bytes());
+        assert_eq!(sys.mem.ram.as_u8()[0x118..0x11c], (-2.0f32).to_le_bytes());
+        assert_eq!(sys.state.pc(), 4 * 4);
+
+        // Double-words must be 8-byte aligned
+        assert_eq!(
+            execute_load_fp(&mut sys, &Reg::new(2), &Reg::new(1), 4, &LoadFpFunct::D),
+            Err(Trap::from_exception(Exception::LoadAddrMisaligned, 0x104))
+        );
+    }
+
+    #[test]
+    fn test_nan_boxing() {
+        // Improperly boxed singles read as the canonical NaN
+        let mut sys = System::new();
+        *sys.freg_mut(&Reg::new(1)) = d(1.0);
+        *sys.freg_mut(&Reg::new(2)) = s(1.0);
+        op_fp(&mut sys, 3, 2, 1, FpFmt::S, OpFpFunct::Sgnj).unwrap();
+        assert_eq!(sys.freg(&Reg::new(3)), s(1.0));
+        op_fp(&mut sys, 3, 1, 2, FpFmt::S, OpFpFunct::Sgnj).unwrap();
+        assert_eq!(sys.freg(&Reg::new(3)), Format::S.canonical_nan() | NAN_BOX);
+
+        // Moves copy the raw bits in both directions
+        op_fp(&mut sys, 4, 1, 0, FpFmt::S, OpFpFunct::MvXF).unwrap();
+        assert_eq!(sys.reg(&Reg::new(4)), 0);
+        *sys.reg_mut(&Reg::new(5)) = 0x7f80_0001;
+        op_fp(&mut sys, 6, 5, 0, FpFmt::S, OpFpFunct::MvFX).unwrap();
+        assert_eq!(sys.freg(&Reg::new(6)), 0x7f80_0001 | NAN_BOX);
+    }
+
+    #[test]
+    fn test_arith_flags() {
+        let mut sys = System::new();
+        *sys.freg_mut(&Reg::new(1)) = d(1.0);
+        *sys.freg_mut(&Reg::new(2)) = d(3.0);
+        *sys.freg_mut(&Reg::new(3)) = d(0.0);
+
+        op_fp(&mut sys, 4, 1, 2, FpFmt::D, OpFpFunct::Add(FpRm::Rne)).unwrap();
+        assert_eq!(sys.freg(&Reg::new(4)), d(4.0));
+        assert_eq!(sys.ctrl.fflags, 0);
+
+        // Flags accrue until cleared
+        op_fp(&mut sys, 4, 1, 2, FpFmt::D, OpFpFunct::Div(FpRm::Rup)).unwrap();
+        assert_eq!(sys.freg(&Reg::new(4)), d(1.0 / 3.0) + 1);
+        op_fp(&mut sys, 4, 1, 3, FpFmt::D, OpFpFunct::Div(FpRm::Rne)).unwrap();
+        assert_eq!(sys.freg(&Reg::new(4)), d(f64::INFINITY));
+        assert_eq!(sys.ctrl.fflags, FLAG_NX | FLAG_DZ);
+
+        execute_fma(
+            &mut sys,
+            &Reg::new(5),
+            &Reg::new(2),
+            &Reg::new(2),
+            &Reg::new(1),
+            &FpFmt::D,
+            &FmaFunct::Nmsub(FpRm::Rne),
+        )
+        .unwrap();
+        assert_eq!(sys.freg(&Reg::new(5)), d(-8.0));
+    }
+
+    #[test]
+    fn test_dynamic_rounding() {
+        let mut sys = System::new();
+        *sys.freg_mut(&Reg::new(1)) = s(1.0);
+        *sys.freg_mut(&Reg::new(2)) = s(3.0);
+
+        sys.ctrl.frm = 0b011; // Round up
+        op_fp(&mut sys, 3, 1, 2, FpFmt::S, OpFpFunct::Div(FpRm::Dyn)).unwrap();
+        assert_eq!(sys.freg(&Reg::new(3)), s(1.0 / 3.0));
+        sys.ctrl.frm = 0b001; // Round towards zero
+        op_fp(&mut sys, 3, 1, 2, FpFmt::S, OpFpFunct::Div(FpRm::Dyn)).unwrap();
+        assert_eq!(sys.freg(&Reg::new(3)), s(1.0 / 3.0) - 1);
+
+        // Reserved rounding modes are illegal
+        sys.ctrl.frm = 0b101;
+        assert_eq!(
+            op_fp(&mut sys, 3, 1, 2, FpFmt::S, OpFpFunct::Div(FpRm::Dyn)),
+            Err(make_illegal(&sys))
+        );
+    }
+
+    #[test]
+    fn test_convert_compare() {
+        let mut sys = System::new();
+        *sys.freg_mut(&Reg::new(1)) = d(-2.75);
+        *sys.freg_mut(&Reg::new(2)) = s(0.5);
+
+        op_fp(&mut sys, 3, 1, 0, FpFmt::S, OpFpFunct::CvtFF(FpRm::Rne)).unwrap();
+        assert_eq!(sys.freg(&Reg::new(3)), s(-2.75));
+        op_fp(&mut sys, 4, 2, 0, FpFmt::D, OpFpFunct::CvtFF(FpRm::Rne)).unwrap();
+        assert_eq!(sys.freg(&Reg::new(4)), d(0.5));
+
+        op_fp(&mut sys, 5, 1, 0, FpFmt::D, OpFpFunct::CvtWF(FpRm::Rtz)).unwrap();
+        assert_eq!(sys.reg(&Reg::new(5)), -2);
+        op_fp(&mut sys, 6, 5, 0, FpFmt::S, OpFpFunct::CvtFWu(FpRm::Rne)).unwrap();
+        assert_eq!(sys.freg(&Reg::new(6)), s(4294967294.0));
+        assert_eq!(sys.ctrl.fflags, FLAG_NX);
+
+        op_fp(&mut sys, 7, 1, 4, FpFmt::D, OpFpFunct::Lt).unwrap();
+        assert_eq!(sys.reg(&Reg::new(7)), 1);
+        op_fp(&mut sys, 7, 4, 1, FpFmt::D, OpFpFunct::Le).unwrap();
+        assert_eq!(sys.reg(&Reg::new(7)), 0);
+        op_fp(&mut sys, 7, 1, 0, FpFmt::D, OpFpFunct::Class).unwrap();
+        assert_eq!(sys.reg(&Reg::new(7)), 1 << 1);
+    }
+
+    #[test]
+    fn test_fs_off() {
+        // Every FP instruction is illegal while mstatus.FS is Off
+        let mut sys = System::new();
+        sys.ctrl.fs = ExtStatus::Off;
+        assert_eq!(
+            op_fp(&mut sys, 1, 2, 3, FpFmt::S, OpFpFunct::Sgnj),
+            Err(make_illegal(&sys))
+        );
+        assert_eq!(
+            execute_load_fp(&mut sys, &Reg::new(1), &Reg::new(0), 0, &LoadFpFunct::W),
+            Err(make_illegal(&sys))
+        );
+        assert_eq!(sys.ctrl.fs, ExtStatus::Off);
+        assert_eq!(sys.state.pc(), 0);
+
+        // Writing an FP register marks the state dirty
+        sys.ctrl.fs = ExtStatus::Clean;
+        op_fp(&mut sys, 1, 2, 3, FpFmt::S, OpFpFunct::Sgnj).unwrap();
+        assert_eq!(sys.ctrl.fs, ExtStatus::Dirty);
+    }
+}
@@ -1,17 +1,13 @@
-use super::Result;
-use crate::{instr::reg::Reg, Exception, System, Trap};
+use super::{check_jump_target, Result};
+use crate::{instr::reg::Reg, System};
 
 pub fn execute_jal(sys: &mut System, rd: &Reg, imm: i32) -> Result {
     let pc = sys.pc();
-    let rd = sys.reg_mut(rd);
+    let pc_next = sys.next_pc();
     let pc_jump = pc.wrapping_add_signed(imm);
-    if pc_jump & 0b11 != 0 {
-        return Err(Trap::from_exception(
-            Exception::InstrAddrMisaligned,
-            pc_jump,
-        ));
-    }
-    *rd = pc.wrapping_add(4) as i32;
+    check_jump_target(sys, pc_jump)?;
+    let rd = sys.reg_mut(rd);
+    *rd = pc_next as i32;
     *sys.pc_mut() = pc_jump;
     Ok(())
 }
@@ -19,6 +15,7 @@ pub fn execute_jal(sys: &mut System, rd: &Reg, imm: i32) -> Result {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{Exception, Trap};
 
     #[test]
     fn test_execute_jal() {
@@ -31,7 +28,9 @@ mod tests {
 
     #[test]
     fn test_execute_jal_misaligned() {
+        // Without the C extension, jump targets must be 4-byte aligned
         let mut sys = System::new();
+        sys.ctrl.ext_c = false;
         sys.state.pc = 0xc496a1b4;
 
         assert_eq!(
@@ -63,4 +62,14 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn test_execute_jal_compressed() {
+        // With the C extension, 2-byte aligned targets are allowed
+        let mut sys = System::new();
+        sys.state.pc = 0xc496a1b4;
+        execute_jal(&mut sys, &Reg::new(1), 0x6).unwrap();
+        assert_eq!(sys.state.reg(&Reg::new(1)), 0xc496a1b8_u32 as i32);
+        assert_eq!(sys.state.pc(), 0xc496a1ba);
+    }
 }
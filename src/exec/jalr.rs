@@ -1,18 +1,13 @@
-use super::Result;
-use crate::{instr::reg::Reg, Exception, System, Trap};
+use super::{check_jump_target, Result};
+use crate::{instr::reg::Reg, System};
 
 pub fn execute_jalr(sys: &mut System, rd: &Reg, rs1: &Reg, imm: i32) -> Result {
-    let pc = sys.pc();
+    let pc_next = sys.next_pc();
     let rs1 = sys.reg(rs1);
-    let rd = sys.reg_mut(rd);
     let pc_jump = (rs1.wrapping_add(imm) as u32) & 0xfffffffe;
-    if pc_jump & 0b11 != 0 {
-        return Err(Trap::from_exception(
-            Exception::InstrAddrMisaligned,
-            pc_jump,
-        ));
-    }
-    *rd = pc.wrapping_add(4) as i32;
+    check_jump_target(sys, pc_jump)?;
+    let rd = sys.reg_mut(rd);
+    *rd = pc_next as i32;
     *sys.pc_mut() = pc_jump;
     Ok(())
 }
@@ -20,6 +15,7 @@ pub fn execute_jalr(sys: &mut System, rd: &Reg, rs1: &Reg, imm: i32) -> Result {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{Exception, Trap};
 
     #[test]
     fn test_execute_jalr() {
@@ -34,7 +30,9 @@ mod tests {
 
     #[test]
     fn test_execute_jalr_misaligned() {
+        // Without the C extension, jump targets must be 4-byte aligned
         let mut sys = System::new();
+        sys.ctrl.ext_c = false;
         sys.state.pc = 0xc496a1b4;
         *sys.reg_mut(&Reg::new(2)) = 0xbcfec832_u32 as i32;
 
@@ -67,4 +65,16 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn test_execute_jalr_compressed() {
+        // With the C extension, 2-byte aligned targets are allowed
+        let mut sys = System::new();
+        sys.state.pc = 0xc496a1b4;
+        *sys.reg_mut(&Reg::new(2)) = 0xbcfec832_u32 as i32;
+
+        execute_jalr(&mut sys, &Reg::new(1), &Reg::new(2), 0x1).unwrap();
+        assert_eq!(sys.state.reg(&Reg::new(1)), 0xc496a1b8_u32 as i32);
+        assert_eq!(sys.state.pc(), 0xbcfec832);
+    }
 }
@@ -1,6 +1,7 @@
 use super::{advance_pc, Result};
 use crate::{
     instr::{funct::LoadFunct, reg::Reg},
+    pmp::check_pmp,
     sys::mem_map::{AccessAttr, AccessType, AccessWidth},
     translate::*,
     System, Trap,
@@ -23,6 +24,7 @@ pub fn execute_load(sys: &mut System, rd: &Reg, rs1: &Reg, imm: i32, f: &LoadFun
             LoadFunct::W => AccessWidth::Word,
         }
     };
+    check_pmp(sys, paddr, attr).map_err(make_trap)?;
     let data = match f {
         LoadFunct::B => sign_extend_8(sys.mem.read_u8(paddr, attr).map_err(make_trap)?),
         LoadFunct::Bu => sys.mem.read_u8(paddr, attr).map_err(make_trap)? as i32,
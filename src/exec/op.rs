@@ -4,6 +4,8 @@ use crate::{
     System,
 };
 
+mod bit;
+mod cond;
 mod int;
 mod mul;
 
@@ -11,6 +13,8 @@ pub fn execute_op(sys: &mut System, rd: &Reg, rs1: &Reg, rs2: &Reg, f: &OpFunct)
     match f {
         OpFunct::I(fi) => int::execute_op_i(sys, rd, rs1, rs2, fi),
         OpFunct::M(fm) => mul::execute_op_m(sys, rd, rs1, rs2, fm),
+        OpFunct::B(fb) => bit::execute_op_b(sys, rd, rs1, rs2, fb),
+        OpFunct::Cond(fc) => cond::execute_op_cond(sys, rd, rs1, rs2, fc),
     }
     advance_pc(sys);
 }
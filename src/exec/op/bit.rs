@@ -0,0 +1,92 @@
+use crate::{
+    instr::{funct::*, reg::Reg},
+    System,
+};
+
+pub fn execute_op_b(sys: &mut System, rd: &Reg, rs1: &Reg, rs2: &Reg, f: &OpBFunct) {
+    let rs1 = sys.reg(rs1);
+    let rs2 = sys.reg(rs2);
+    let rd = sys.reg_mut(rd);
+    let (a, b) = (rs1 as u32, rs2 as u32);
+    let bit = 1 << (b & 31);
+    match f {
+        // Zba
+        OpBFunct::Sh1add => *rd = (rs1 << 1).wrapping_add(rs2),
+        OpBFunct::Sh2add => *rd = (rs1 << 2).wrapping_add(rs2),
+        OpBFunct::Sh3add => *rd = (rs1 << 3).wrapping_add(rs2),
+        // Zbb
+        OpBFunct::Andn => *rd = rs1 & !rs2,
+        OpBFunct::Orn => *rd = rs1 | !rs2,
+        OpBFunct::Xnor => *rd = !(rs1 ^ rs2),
+        OpBFunct::Max => *rd = rs1.max(rs2),
+        OpBFunct::Maxu => *rd = a.max(b) as i32,
+        OpBFunct::Min => *rd = rs1.min(rs2),
+        OpBFunct::Minu => *rd = a.min(b) as i32,
+        OpBFunct::Rol => *rd = a.rotate_left(b & 31) as i32,
+        OpBFunct::Ror => *rd = a.rotate_right(b & 31) as i32,
+        OpBFunct::ZextH => *rd = rs1 & 0xffff,
+        // Zbc
+        OpBFunct::Clmul => *rd = clmul(a, b) as i32,
+        OpBFunct::Clmulh => *rd = (clmul(a, b) >> 32) as i32,
+        OpBFunct::Clmulr => *rd = (clmul(a, b) >> 31) as i32,
+        // Zbs
+        OpBFunct::Bclr => *rd = (a & !bit) as i32,
+        OpBFunct::Bext => *rd = ((a >> (b & 31)) & 1) as i32,
+        OpBFunct::Binv => *rd = (a ^ bit) as i32,
+        OpBFunct::Bset => *rd = (a | bit) as i32,
+    };
+}
+
+// Carry-less product (the full 63 bits)
+fn clmul(a: u32, b: u32) -> u64 {
+    (0..32)
+        .filter(|i| (b >> i) & 1 != 0)
+        .fold(0, |acc, i| acc ^ ((a as u64) << i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute_op;
+    use super::*;
+
+    fn assert_op_b(sys: &mut System, rd: u8, rs1: u8, rs2: u8, f: OpBFunct, expect: u32) {
+        execute_op(
+            sys,
+            &Reg::new(rd),
+            &Reg::new(rs1),
+            &Reg::new(rs2),
+            &OpFunct::B(f),
+        );
+        assert_eq!(sys.state.reg(&Reg::new(rd)), expect as i32);
+    }
+
+    #[test]
+    fn test_execute_op_b() {
+        let mut sys = System::new();
+        *sys.state.reg_mut(&Reg::new(1)) = 0xbcfec832_u32 as i32;
+        *sys.state.reg_mut(&Reg::new(2)) = 0x51290ce3_u32 as i32;
+
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Sh1add, 0xcb269d47);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Sh2add, 0x45242dab);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Sh3add, 0x391f4e73);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Andn, 0xacd6c010);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Orn, 0xbefefb3e);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Xnor, 0x12283b2e);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Max, 0x51290ce3);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Maxu, 0xbcfec832);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Min, 0xbcfec832);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Minu, 0x51290ce3);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Rol, 0xe7f64195);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Ror, 0x579fd906);
+        assert_op_b(&mut sys, 3, 1, 0, OpBFunct::ZextH, 0x0000c832);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Clmul, 0x0a8f1396);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Clmulh, 0x245e63e5);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Clmulr, 0x48bcc7ca);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Bclr, 0xbcfec832);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Bext, 0);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Binv, 0xbcfec83a);
+        assert_op_b(&mut sys, 3, 1, 2, OpBFunct::Bset, 0xbcfec83a);
+
+        assert_eq!(sys.state.pc(), 20 * 4);
+    }
+}
@@ -0,0 +1,46 @@
+use crate::{
+    instr::{funct::*, reg::Reg},
+    System,
+};
+
+pub fn execute_op_cond(sys: &mut System, rd: &Reg, rs1: &Reg, rs2: &Reg, f: &OpCondFunct) {
+    let rs1 = sys.reg(rs1);
+    let cond = sys.reg(rs2) == 0;
+    // rd is zero if the condition holds, rs1 otherwise
+    let zero = match f {
+        OpCondFunct::CzeroEqz => cond,
+        OpCondFunct::CzeroNez => !cond,
+    };
+    *sys.reg_mut(rd) = if zero { 0 } else { rs1 };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute_op;
+    use super::*;
+
+    fn assert_op_cond(sys: &mut System, rd: u8, rs1: u8, rs2: u8, f: OpCondFunct, expect: u32) {
+        execute_op(
+            sys,
+            &Reg::new(rd),
+            &Reg::new(rs1),
+            &Reg::new(rs2),
+            &OpFunct::Cond(f),
+        );
+        assert_eq!(sys.state.reg(&Reg::new(rd)), expect as i32);
+    }
+
+    #[test]
+    fn test_execute_op_cond() {
+        let mut sys = System::new();
+        *sys.state.reg_mut(&Reg::new(1)) = 0xbcfec832_u32 as i32;
+        *sys.state.reg_mut(&Reg::new(2)) = 0x51290ce3_u32 as i32;
+
+        assert_op_cond(&mut sys, 3, 1, 2, OpCondFunct::CzeroEqz, 0xbcfec832);
+        assert_op_cond(&mut sys, 3, 1, 0, OpCondFunct::CzeroEqz, 0);
+        assert_op_cond(&mut sys, 3, 1, 2, OpCondFunct::CzeroNez, 0);
+        assert_op_cond(&mut sys, 3, 1, 0, OpCondFunct::CzeroNez, 0xbcfec832);
+
+        assert_eq!(sys.state.pc(), 4 * 4);
+    }
+}
@@ -17,10 +17,28 @@ pub fn execute_opimm(sys: &mut System, rd: &Reg, rs1: &Reg, imm: i32, f: &OpImmF
         OpImmFunct::Sll => *rd = rs1.wrapping_shl(imm as u32),
         OpImmFunct::Srl => *rd = (rs1 as u32).wrapping_shr(imm as u32) as i32,
         OpImmFunct::Sra => *rd = (rs1 as i32).wrapping_shr(imm as u32),
+        OpImmFunct::Clz => *rd = (rs1 as u32).leading_zeros() as i32,
+        OpImmFunct::Ctz => *rd = (rs1 as u32).trailing_zeros() as i32,
+        OpImmFunct::Cpop => *rd = (rs1 as u32).count_ones() as i32,
+        OpImmFunct::SextB => *rd = rs1 as i8 as i32,
+        OpImmFunct::SextH => *rd = rs1 as i16 as i32,
+        OpImmFunct::OrcB => *rd = orc_b(rs1 as u32) as i32,
+        OpImmFunct::Rev8 => *rd = rs1.swap_bytes(),
+        OpImmFunct::Rori => *rd = (rs1 as u32).rotate_right(imm as u32) as i32,
+        OpImmFunct::Bclri => *rd = rs1 & !(1 << imm),
+        OpImmFunct::Bexti => *rd = (rs1 >> imm) & 1,
+        OpImmFunct::Binvi => *rd = rs1 ^ (1 << imm),
+        OpImmFunct::Bseti => *rd = rs1 | (1 << imm),
     };
     advance_pc(sys);
 }
 
+// Each byte becomes 0xff if any of its bits is set, 0x00 otherwise
+fn orc_b(val: u32) -> u32 {
+    let bytes = val.to_le_bytes().map(|b| if b != 0 { 0xff } else { 0 });
+    u32::from_le_bytes(bytes)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,4 +82,33 @@ mod tests {
 
         assert_eq!(sys.state.pc(), 22 * 4);
     }
+
+    #[test]
+    fn test_execute_opimm_bitmanip() {
+        let mut sys = System::new();
+        *sys.state.reg_mut(&Reg::new(1)) = 0xbcfec832_u32 as i32;
+        *sys.state.reg_mut(&Reg::new(2)) = 0x51290ce3_u32 as i32;
+        *sys.state.reg_mut(&Reg::new(3)) = 0x00120300;
+
+        assert_opimm(&mut sys, 4, 1, 0, OpImmFunct::Clz, 0);
+        assert_opimm(&mut sys, 4, 2, 0, OpImmFunct::Clz, 1);
+        assert_opimm(&mut sys, 4, 0, 0, OpImmFunct::Clz, 32);
+        assert_opimm(&mut sys, 4, 1, 0, OpImmFunct::Ctz, 1);
+        assert_opimm(&mut sys, 4, 0, 0, OpImmFunct::Ctz, 32);
+        assert_opimm(&mut sys, 4, 1, 0, OpImmFunct::Cpop, 18);
+        assert_opimm(&mut sys, 4, 2, 0, OpImmFunct::Cpop, 13);
+        assert_opimm(&mut sys, 4, 1, 0, OpImmFunct::SextB, 0x00000032);
+        assert_opimm(&mut sys, 4, 2, 0, OpImmFunct::SextB, 0xffffffe3);
+        assert_opimm(&mut sys, 4, 1, 0, OpImmFunct::SextH, 0xffffc832);
+        assert_opimm(&mut sys, 4, 2, 0, OpImmFunct::SextH, 0x00000ce3);
+        assert_opimm(&mut sys, 4, 3, 0, OpImmFunct::OrcB, 0x00ffff00);
+        assert_opimm(&mut sys, 4, 1, 0, OpImmFunct::Rev8, 0x32c8febc);
+        assert_opimm(&mut sys, 4, 1, 11, OpImmFunct::Rori, 0x06579fd9);
+        assert_opimm(&mut sys, 4, 1, 5, OpImmFunct::Bclri, 0xbcfec812);
+        assert_opimm(&mut sys, 4, 1, 31, OpImmFunct::Bexti, 1);
+        assert_opimm(&mut sys, 4, 1, 0, OpImmFunct::Binvi, 0xbcfec833);
+        assert_opimm(&mut sys, 4, 2, 31, OpImmFunct::Bseti, 0xd1290ce3);
+
+        assert_eq!(sys.state.pc(), 18 * 4);
+    }
 }
@@ -1,6 +1,7 @@
 use super::{advance_pc, Result};
 use crate::{
     instr::{funct::StoreFunct, reg::Reg},
+    pmp::check_pmp,
     sys::mem_map::{AccessAttr, AccessType, AccessWidth},
     translate::*,
     System, Trap,
@@ -24,6 +25,7 @@ pub fn execute_store(sys: &mut System, rs1: &Reg, rs2: &Reg, imm: i32, f: &Store
             StoreFunct::W => AccessWidth::Word,
         },
     };
+    check_pmp(sys, paddr, attr).map_err(make_trap)?;
     match f {
         StoreFunct::B => sys.mem.write_u8(paddr, rs2 as u8, attr).map_err(make_trap)?,
         StoreFunct::H => sys.mem.write_u16(paddr, rs2 as u16, attr).map_err(make_trap)?,
@@ -0,0 +1,335 @@
+use super::{advance_pc, float::read_f, Result};
+use crate::{
+    instr::{format::*, funct::*, reg::Reg},
+    sys::{control::ExtStatus, make_illegal},
+    System, Trap,
+};
+
+mod float;
+mod int;
+mod mask;
+mod mem;
+mod permute;
+mod reduce;
+
+pub use mem::{execute_load_v, execute_store_v};
+
+pub fn check_vs(sys: &System) -> Result {
+    // Vector instructions and CSRs are illegal while the vector unit is off
+    if sys.ctrl.vs == ExtStatus::Off {
+        Err(make_illegal(sys))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn set_vs_dirty(sys: &mut System) {
+    sys.ctrl.vs = ExtStatus::Dirty;
+}
+
+// Configuration of the current vector instruction (from vtype, vl and vstart)
+#[derive(Debug, Clone, Copy)]
+pub struct VCfg {
+    pub sew: usize, // Element width in bytes
+    pub lmul: i32,  // log2 of LMUL
+    pub vl: usize,
+    pub vstart: usize,
+    pub vta: bool, // Tail agnostic
+    pub vma: bool, // Mask agnostic
+}
+
+impl VCfg {
+    // Number of registers in a group of EMUL (log2), fractional groups taking one register
+    pub fn regs(emul: i32) -> u8 {
+        1 << emul.max(0)
+    }
+
+    // Number of elements of eew bytes in a group of EMUL (log2)
+    pub fn vlmax(vlenb: usize, eew: usize, emul: i32) -> usize {
+        if emul >= 0 {
+            (vlenb << emul) / eew
+        } else {
+            (vlenb >> -emul) / eew
+        }
+    }
+
+    // EMUL (log2) of an operand of eew bytes, or None if out of range
+    pub fn emul(&self, eew: usize) -> Option<i32> {
+        let emul = self.lmul + eew.trailing_zeros() as i32 - self.sew.trailing_zeros() as i32;
+        (-3..=3).contains(&emul).then_some(emul)
+    }
+}
+
+// Decoded vtype: (SEW in bytes, log2 of LMUL, vta, vma), or None if unsupported
+pub fn parse_vtype(vtype: u32) -> Option<(usize, i32, bool, bool)> {
+    let lmul = match vtype & 0b111 {
+        i @ 0b000..=0b011 => i as i32,
+        0b100 => return None,
+        i => i as i32 - 8,
+    };
+    let vsew = (vtype >> 3) & 0b111;
+    // ELEN is 32, so SEW is at most 32 and LMUL at least SEW/ELEN
+    if vtype >> 8 != 0 || vsew > 2 || lmul < vsew as i32 - 2 {
+        return None;
+    }
+    Some((
+        1 << vsew,
+        lmul,
+        vtype & (1 << 6) != 0,
+        vtype & (1 << 7) != 0,
+    ))
+}
+
+pub fn vcfg(sys: &System) -> core::result::Result<VCfg, Trap> {
+    // Instructions depending on vtype are illegal while vill is set
+    let (sew, lmul, vta, vma) = parse_vtype(sys.ctrl.vtype).ok_or_else(|| make_illegal(sys))?;
+    Ok(VCfg {
+        sew,
+        lmul,
+        vl: sys.ctrl.vl as usize,
+        vstart: sys.ctrl.vstart as usize,
+        vta,
+        vma,
+    })
+}
+
+// The register must start a group of EMUL (log2) inside the register file
+pub fn check_group(sys: &System, r: u8, emul: i32) -> Result {
+    let regs = VCfg::regs(emul);
+    if !r.is_multiple_of(regs) || r as u32 + regs as u32 > 32 {
+        Err(make_illegal(sys))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn check_src(sys: &System, src: &VSrc, emul: i32) -> Result {
+    match src {
+        VSrc::V(vs1) => check_group(sys, vs1.index(), emul),
+        _ => Ok(()),
+    }
+}
+
+// Some instructions cannot have a destination group overlapping a source group
+pub fn check_overlap(sys: &System, vd: u8, dmul: i32, vs: u8, smul: i32) -> Result {
+    if vd < vs + VCfg::regs(smul) && vs < vd + VCfg::regs(dmul) {
+        Err(make_illegal(sys))
+    } else {
+        Ok(())
+    }
+}
+
+// A masked instruction cannot overwrite v0 (unless its result is a mask)
+pub fn check_masked_vd(sys: &System, vd: u8, vm: bool) -> Result {
+    if !vm && vd == 0 {
+        Err(make_illegal(sys))
+    } else {
+        Ok(())
+    }
+}
+
+// Element i of the second operand (vs1, rs1 or the immediate) truncated to eew bytes
+pub fn src_elem(sys: &System, src: &VSrc, i: usize, eew: usize) -> u64 {
+    match src {
+        VSrc::V(vs1) => sys.state.velem(vs1.index(), i, eew),
+        VSrc::X(rs1) => trunc(sys.reg(rs1) as i64 as u64, eew),
+        VSrc::F(rs1) => trunc(read_f(sys, rs1, FpFmt::S), eew),
+        VSrc::I(imm) => trunc(*imm as i64 as u64, eew),
+    }
+}
+
+// Element i is active if unmasked or if its bit of v0 is set
+pub fn is_active(sys: &System, vm: bool, i: usize) -> bool {
+    vm || sys.state.vmask(0, i)
+}
+
+// Write the body results (None for inactive elements) from vstart, then fill the tail.
+// Agnostic elements are overwritten with all 1s, the others are left undisturbed.
+pub fn write_elems(
+    sys: &mut System,
+    cfg: &VCfg,
+    vd: u8,
+    eew: usize,
+    emul: i32,
+    vals: &[Option<u64>],
+) {
+    // Nothing is written if vstart >= vl, not even the tail
+    if cfg.vstart >= cfg.vl {
+        return;
+    }
+    let ones = u64::MAX >> (64 - 8 * eew);
+    for (i, val) in (cfg.vstart..).zip(vals) {
+        match val {
+            Some(val) => sys.state.set_velem(vd, i, eew, *val),
+            None if cfg.vma => sys.state.set_velem(vd, i, eew, ones),
+            None => (),
+        }
+    }
+    if cfg.vta {
+        let end = VCfg::regs(emul) as usize * sys.state.vlenb() / eew;
+        for i in cfg.vl..end {
+            sys.state.set_velem(vd, i, eew, ones);
+        }
+    }
+}
+
+// Same as write_elems, for a mask destination
+pub fn write_mask(sys: &mut System, cfg: &VCfg, vd: u8, vals: &[Option<bool>]) {
+    if cfg.vstart >= cfg.vl {
+        return;
+    }
+    for (i, val) in (cfg.vstart..).zip(vals) {
+        match val {
+            Some(val) => sys.state.set_vmask(vd, i, *val),
+            None if cfg.vma => sys.state.set_vmask(vd, i, true),
+            None => (),
+        }
+    }
+    if cfg.vta {
+        for i in cfg.vl..sys.state.vlenb() * 8 {
+            sys.state.set_vmask(vd, i, true);
+        }
+    }
+}
+
+// Sign-extend an element of eew bytes
+pub fn sext(val: u64, eew: usize) -> i64 {
+    let shift = 64 - 8 * eew;
+    ((val << shift) as i64) >> shift
+}
+
+// Truncate a value to eew bytes
+pub fn trunc(val: u64, eew: usize) -> u64 {
+    val & (u64::MAX >> (64 - 8 * eew))
+}
+
+pub fn execute_setvl(sys: &mut System, rd: &Reg, avl: &VlSrc, vtype: &VTypeSrc) -> Result {
+    check_vs(sys)?;
+    let vtype = match vtype {
+        VTypeSrc::Reg(rs2) => sys.reg(rs2) as u32,
+        VTypeSrc::Imm(imm) => *imm,
+    };
+    let vl = match parse_vtype(vtype) {
+        Some((sew, lmul, ..)) => {
+            let vlmax = VCfg::vlmax(sys.state.vlenb(), sew, lmul) as u32;
+            let avl = match avl {
+                VlSrc::Imm(imm) => *imm as u32,
+                VlSrc::Reg(rs1) if rs1.index() != 0 => sys.reg(rs1) as u32,
+                // Set vl to VLMAX, or keep vl if rd is x0 too
+                VlSrc::Reg(_) if rd.index() != 0 => u32::MAX,
+                VlSrc::Reg(_) => sys.ctrl.vl,
+            };
+            sys.ctrl.vtype = vtype;
+            avl.min(vlmax)
+        }
+        None => {
+            sys.ctrl.vtype = 1 << 31;
+            0
+        }
+    };
+    sys.ctrl.vl = vl;
+    *sys.reg_mut(rd) = vl as i32;
+    sys.ctrl.vstart = 0;
+    set_vs_dirty(sys);
+    advance_pc(sys);
+    Ok(())
+}
+
+pub fn execute_op_v(sys: &mut System, op: &OpVType, f: &OpVFunct) -> Result {
+    use OpVFunct::*;
+    check_vs(sys)?;
+    match f {
+        Redsum | Redand | Redor | Redxor | Redminu | Redmin | Redmaxu | Redmax | Wredsumu
+        | Wredsum => reduce::execute_reduce(sys, op, f)?,
+        Merge | Mv | MvXS | MvSX | MvNr(_) | Slideup | Slidedown | Slide1up | Slide1down
+        | Rgather | Rgatherei16 | Compress => permute::execute_permute(sys, op, f)?,
+        Mand | Mnand | Mandn | Mxor | Mor | Mnor | Morn | Mxnor | Cpop | First | Msbf | Msif
+        | Msof | Iota | Id => mask::execute_mask(sys, op, f)?,
+        F(f) => float::execute_float(sys, op, f)?,
+        _ => int::execute_int(sys, op, f)?,
+    }
+    sys.ctrl.vstart = 0;
+    set_vs_dirty(sys);
+    advance_pc(sys);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instr::reg::Reg, Exception};
+
+    pub fn setvl(sys: &mut System, avl: u8, vtype: u32) {
+        let (rd, avl, vtype) = (Reg::new(10), VlSrc::Imm(avl), VTypeSrc::Imm(vtype));
+        execute_setvl(sys, &rd, &avl, &vtype).unwrap();
+    }
+
+    #[test]
+    fn test_execute_setvl() {
+        // VLEN is 128 by default
+        let mut sys = System::new();
+        let rd = Reg::new(10);
+        *sys.state.reg_mut(&Reg::new(1)) = 100;
+        *sys.state.reg_mut(&Reg::new(2)) = 0b11_010_001; // e32, m2, ta, ma
+
+        // vsetvli a0, x1, e8, m1
+        execute_setvl(&mut sys, &rd, &VlSrc::Reg(Reg::new(1)), &VTypeSrc::Imm(0)).unwrap();
+        assert_eq!(sys.state.reg(&rd), 16);
+        // vsetvl a0, x0, x2 (VLMAX)
+        execute_setvl(
+            &mut sys,
+            &rd,
+            &VlSrc::Reg(Reg::new(0)),
+            &VTypeSrc::Reg(Reg::new(2)),
+        )
+        .unwrap();
+        assert_eq!(sys.state.reg(&rd), 8);
+        assert_eq!(sys.ctrl.vtype, 0b11_010_001);
+        // vsetivli a0, 5, e16, mf2
+        execute_setvl(&mut sys, &rd, &VlSrc::Imm(5), &VTypeSrc::Imm(0b001_111)).unwrap();
+        assert_eq!(sys.state.reg(&rd), 4);
+        // vsetvli x0, x0, e32, m1 keeps vl
+        execute_setvl(
+            &mut sys,
+            &Reg::new(0),
+            &VlSrc::Reg(Reg::new(0)),
+            &VTypeSrc::Imm(0b010_000),
+        )
+        .unwrap();
+        assert_eq!(sys.ctrl.vl, 4);
+
+        // e64 (beyond ELEN), e32 with mf2 and a reserved LMUL set vill
+        for vtype in [0b011_000, 0b010_111, 0b000_100, 1 << 8] {
+            execute_setvl(&mut sys, &rd, &VlSrc::Imm(5), &VTypeSrc::Imm(vtype)).unwrap();
+            assert_eq!(sys.state.reg(&rd), 0);
+            assert_eq!(sys.ctrl.vtype, 1 << 31);
+        }
+        assert_eq!(sys.state.pc(), 8 * 4);
+    }
+
+    #[test]
+    fn test_vs_off() {
+        let mut sys = System::new();
+        sys.ctrl.vs = ExtStatus::Off;
+        let trap = Trap::from_exception(Exception::IllegalInstr, 0);
+        assert_eq!(
+            execute_setvl(&mut sys, &Reg::new(10), &VlSrc::Imm(1), &VTypeSrc::Imm(0)),
+            Err(trap)
+        );
+
+        // Executing an instruction makes the state dirty
+        sys.ctrl.vs = ExtStatus::Clean;
+        setvl(&mut sys, 1, 0);
+        assert_eq!(sys.ctrl.vs, ExtStatus::Dirty);
+
+        // Instructions depending on vtype are illegal when vill is set
+        sys.ctrl.vtype = 1 << 31;
+        let op = OpVType {
+            vd: Reg::new(1),
+            vs2: Reg::new(2),
+            src: VSrc::V(Reg::new(3)),
+            vm: true,
+        };
+        assert_eq!(execute_op_v(&mut sys, &op, &OpVFunct::Add), Err(trap));
+    }
+}
@@ -0,0 +1,496 @@
+use super::*;
+use crate::{
+    exec::float::{accrue_flags, check_fs, rounding_mode, write_f},
+    softfloat::{self, FmaOp, Format, RoundingMode, FLAG_DZ, FLAG_NV, FLAG_NX, FLAG_OF},
+    sys::make_illegal,
+};
+
+const S: Format = Format::S;
+const SIGN: u64 = 1 << 31;
+const INF: u64 = 0x7f80_0000;
+
+pub fn execute_float(sys: &mut System, op: &OpVType, f: &OpFVFunct) -> Result {
+    use OpFVFunct::*;
+    check_fs(sys)?;
+    let cfg = vcfg(sys)?;
+    // Elements are single-precision (or 16-bit integers converted from or to them)
+    let sew = match f {
+        WcvtFXu | WcvtFX | NcvtXuF | NcvtXF | NcvtRtzXuF | NcvtRtzXF => 2,
+        _ => 4,
+    };
+    if cfg.sew != sew {
+        return Err(make_illegal(sys));
+    }
+    // Only operations which round depend on frm (and are illegal if it is reserved)
+    let rm = match f {
+        CvtRtzXuF | CvtRtzXF | NcvtRtzXuF | NcvtRtzXF => RoundingMode::Rtz,
+        Add | Sub | Rsub | Mul | Div | Rdiv | Macc | Nmacc | Msac | Nmsac | Madd | Nmadd | Msub
+        | Nmsub | Sqrt | Rec7 | CvtXuF | CvtXF | CvtFXu | CvtFX | NcvtXuF | NcvtXF | Redusum
+        | Redosum => rounding_mode(sys, FpRm::Dyn)?,
+        _ => RoundingMode::Rne, // Exact
+    };
+    let mut flags = 0;
+    match f {
+        Merge | Mv | MvSF | Slide1up | Slide1down => {
+            let f = match f {
+                Merge => OpVFunct::Merge,
+                Mv => OpVFunct::Mv,
+                MvSF => OpVFunct::MvSX,
+                Slide1up => OpVFunct::Slide1up,
+                _ => OpVFunct::Slide1down,
+            };
+            permute::execute_permute(sys, op, &f)?;
+        }
+        MvFS => {
+            // Performed even if vstart >= vl
+            let val = sys.state.velem(op.vs2.index(), 0, 4);
+            write_f(sys, &op.vd, FpFmt::S, val);
+        }
+        Mfeq | Mfne | Mflt | Mfle | Mfgt | Mfge => execute_compare(sys, &cfg, op, f, &mut flags)?,
+        Redusum | Redosum | Redmin | Redmax => execute_reduce(sys, &cfg, op, f, rm, &mut flags)?,
+        WcvtFXu | WcvtFX => execute_widen(sys, &cfg, op, f, rm, &mut flags)?,
+        NcvtXuF | NcvtXF | NcvtRtzXuF | NcvtRtzXF => {
+            execute_narrow(sys, &cfg, op, f, rm, &mut flags)?
+        }
+        _ => execute_single(sys, &cfg, op, f, rm, &mut flags)?,
+    }
+    accrue_flags(sys, flags);
+    Ok(())
+}
+
+fn execute_single(
+    sys: &mut System,
+    cfg: &VCfg,
+    op: &OpVType,
+    f: &OpFVFunct,
+    rm: RoundingMode,
+    flags: &mut u8,
+) -> Result {
+    use OpFVFunct::*;
+    let OpVType { vd, vs2, src, vm } = op;
+    let (vd, vs2) = (vd.index(), vs2.index());
+    check_group(sys, vd, cfg.lmul)?;
+    check_group(sys, vs2, cfg.lmul)?;
+    // vs1 selects the unary operation
+    let unary = matches!(
+        f,
+        Sqrt | Rsqrt7 | Rec7 | Class | CvtXuF | CvtXF | CvtFXu | CvtFX | CvtRtzXuF | CvtRtzXF
+    );
+    if !unary {
+        check_src(sys, src, cfg.lmul)?;
+    }
+    check_masked_vd(sys, vd, *vm)?;
+    let vals: Vec<_> = (cfg.vstart..cfg.vl)
+        .map(|i| {
+            if !is_active(sys, *vm, i) {
+                return None;
+            }
+            let a = sys.state.velem(vs2, i, 4);
+            let b = src_elem(sys, src, i, 4);
+            let d = sys.state.velem(vd, i, 4);
+            Some(single_op(f, a, b, d, rm, flags))
+        })
+        .collect();
+    write_elems(sys, cfg, vd, 4, cfg.lmul, &vals);
+    Ok(())
+}
+
+// Element operation of vs2 (a) and vs1 or f[rs1] (b), with the old value of vd (d)
+fn single_op(f: &OpFVFunct, a: u64, b: u64, d: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    use OpFVFunct::*;
+    match f {
+        Add => softfloat::add(S, a, b, rm, flags),
+        Sub => softfloat::sub(S, a, b, rm, flags),
+        Rsub => softfloat::sub(S, b, a, rm, flags),
+        Mul => softfloat::mul(S, a, b, rm, flags),
+        Div => softfloat::div(S, a, b, rm, flags),
+        Rdiv => softfloat::div(S, b, a, rm, flags),
+        Min => softfloat::min(S, a, b, flags),
+        Max => softfloat::max(S, a, b, flags),
+        Sgnj => (a & !SIGN) | (b & SIGN),
+        Sgnjn => (a & !SIGN) | (!b & SIGN),
+        Sgnjx => a ^ (b & SIGN),
+        // vd = (vs1 * vs2) + vd, with the product and the addend negated as needed
+        Macc => softfloat::fma(S, b, a, d, FmaOp::Madd, rm, flags),
+        Nmacc => softfloat::fma(S, b, a, d, FmaOp::Nmadd, rm, flags),
+        Msac => softfloat::fma(S, b, a, d, FmaOp::Msub, rm, flags),
+        Nmsac => softfloat::fma(S, b, a, d, FmaOp::Nmsub, rm, flags),
+        // vd = (vs1 * vd) + vs2, likewise
+        Madd => softfloat::fma(S, b, d, a, FmaOp::Madd, rm, flags),
+        Nmadd => softfloat::fma(S, b, d, a, FmaOp::Nmadd, rm, flags),
+        Msub => softfloat::fma(S, b, d, a, FmaOp::Msub, rm, flags),
+        Nmsub => softfloat::fma(S, b, d, a, FmaOp::Nmsub, rm, flags),
+        Sqrt => softfloat::sqrt(S, a, rm, flags),
+        Rsqrt7 => rsqrt7(a, flags),
+        Rec7 => rec7(a, rm, flags),
+        Class => softfloat::classify(S, a) as u64,
+        CvtXuF | CvtRtzXuF => softfloat::to_u32(S, a, rm, flags) as u64,
+        CvtXF | CvtRtzXF => softfloat::to_i32(S, a, rm, flags) as u64,
+        CvtFXu => softfloat::from_u32(S, a as u32, rm, flags),
+        CvtFX => softfloat::from_i32(S, a as u32, rm, flags),
+        _ => unreachable!(),
+    }
+}
+
+fn execute_compare(
+    sys: &mut System,
+    cfg: &VCfg,
+    op: &OpVType,
+    f: &OpFVFunct,
+    flags: &mut u8,
+) -> Result {
+    use OpFVFunct::*;
+    let OpVType { vd, vs2, src, vm } = op;
+    let (vd, vs2) = (vd.index(), vs2.index());
+    check_group(sys, vs2, cfg.lmul)?;
+    check_src(sys, src, cfg.lmul)?;
+    // Equality is quiet, ordering raises invalid for any NaN
+    let vals: Vec<_> = (cfg.vstart..cfg.vl)
+        .map(|i| {
+            if !is_active(sys, *vm, i) {
+                return None;
+            }
+            let a = sys.state.velem(vs2, i, 4);
+            let b = src_elem(sys, src, i, 4);
+            Some(match f {
+                Mfeq => softfloat::eq(S, a, b, flags),
+                Mfne => !softfloat::eq(S, a, b, flags),
+                Mflt => softfloat::lt(S, a, b, flags),
+                Mfle => softfloat::le(S, a, b, flags),
+                Mfgt => softfloat::lt(S, b, a, flags),
+                Mfge => softfloat::le(S, b, a, flags),
+                _ => unreachable!(),
+            })
+        })
+        .collect();
+    write_mask(sys, cfg, vd, &vals);
+    Ok(())
+}
+
+fn execute_reduce(
+    sys: &mut System,
+    cfg: &VCfg,
+    op: &OpVType,
+    f: &OpFVFunct,
+    rm: RoundingMode,
+    flags: &mut u8,
+) -> Result {
+    use OpFVFunct::*;
+    let OpVType { vd, vs2, src, vm } = op;
+    let (vd, vs2) = (vd.index(), vs2.index());
+    // Reductions cannot be resumed, so vstart must be 0
+    if cfg.vstart != 0 {
+        return Err(make_illegal(sys));
+    }
+    check_group(sys, vs2, cfg.lmul)?;
+    if cfg.vl == 0 {
+        return Ok(());
+    }
+    // The unordered sum is also done in element order
+    let init = src_elem(sys, src, 0, 4);
+    let acc = (0..cfg.vl)
+        .filter(|i| is_active(sys, *vm, *i))
+        .map(|i| sys.state.velem(vs2, i, 4))
+        .fold(init, |acc, a| match f {
+            Redusum | Redosum => softfloat::add(S, acc, a, rm, flags),
+            Redmin => softfloat::min(S, acc, a, flags),
+            Redmax => softfloat::max(S, acc, a, flags),
+            _ => unreachable!(),
+        });
+    // Only the first element is written, the rest of vd is the tail
+    let one = VCfg { vl: 1, ..*cfg };
+    write_elems(sys, &one, vd, 4, 0, &[Some(acc)]);
+    Ok(())
+}
+
+fn execute_widen(
+    sys: &mut System,
+    cfg: &VCfg,
+    op: &OpVType,
+    f: &OpFVFunct,
+    rm: RoundingMode,
+    flags: &mut u8,
+) -> Result {
+    let OpVType { vd, vs2, vm, .. } = op;
+    let (vd, vs2) = (vd.index(), vs2.index());
+    // The destination has twice SEW and LMUL
+    let wmul = cfg.emul(4).ok_or_else(|| make_illegal(sys))?;
+    check_group(sys, vd, wmul)?;
+    check_group(sys, vs2, cfg.lmul)?;
+    check_masked_vd(sys, vd, *vm)?;
+    let vals: Vec<_> = (cfg.vstart..cfg.vl)
+        .map(|i| {
+            if !is_active(sys, *vm, i) {
+                return None;
+            }
+            let a = sys.state.velem(vs2, i, 2);
+            Some(match f {
+                OpFVFunct::WcvtFXu => softfloat::from_u32(S, a as u32, rm, flags),
+                _ => softfloat::from_i32(S, sext(a, 2) as u32, rm, flags),
+            })
+        })
+        .collect();
+    write_elems(sys, cfg, vd, 4, wmul, &vals);
+    Ok(())
+}
+
+fn execute_narrow(
+    sys: &mut System,
+    cfg: &VCfg,
+    op: &OpVType,
+    f: &OpFVFunct,
+    rm: RoundingMode,
+    flags: &mut u8,
+) -> Result {
+    use OpFVFunct::*;
+    let OpVType { vd, vs2, vm, .. } = op;
+    let (vd, vs2) = (vd.index(), vs2.index());
+    // The source vs2 has twice SEW and LMUL
+    let wmul = cfg.emul(4).ok_or_else(|| make_illegal(sys))?;
+    check_group(sys, vd, cfg.lmul)?;
+    check_group(sys, vs2, wmul)?;
+    check_masked_vd(sys, vd, *vm)?;
+    let vals: Vec<_> = (cfg.vstart..cfg.vl)
+        .map(|i| {
+            if !is_active(sys, *vm, i) {
+                return None;
+            }
+            let a = sys.state.velem(vs2, i, 4);
+            let signed = matches!(f, NcvtXF | NcvtRtzXF);
+            Some(trunc(to_int16(a, signed, rm, flags), 2))
+        })
+        .collect();
+    write_elems(sys, cfg, vd, 2, cfg.lmul, &vals);
+    Ok(())
+}
+
+// Out of range values saturate and only raise invalid, as for 32-bit integers
+fn to_int16(a: u64, signed: bool, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let mut int_flags = 0;
+    let (val, min, max) = if signed {
+        let val = softfloat::to_i32(S, a, rm, &mut int_flags) as i32;
+        (val as i64, i16::MIN as i64, i16::MAX as i64)
+    } else {
+        let val = softfloat::to_u32(S, a, rm, &mut int_flags);
+        (val as i64, 0, u16::MAX as i64)
+    };
+    if val < min || val > max {
+        *flags |= FLAG_NV;
+    } else {
+        *flags |= int_flags;
+    }
+    val.clamp(min, max) as u64
+}
+
+// Exponent and fraction of a finite non-zero value, normalizing subnormals
+// (their exponent is then 0 or less)
+fn normalize(exp: u64, frac: u64) -> (i64, u64) {
+    if exp != 0 {
+        return (exp as i64, frac);
+    }
+    // Shift out the leading 1
+    let shift = frac.leading_zeros() as i64 - 40;
+    (1 - shift, (frac << shift) & 0x7f_ffff)
+}
+
+// 7-bit estimate of 1/sqrt(a). The table of the specification is made of the
+// estimates at the middle of each interval, computed here instead.
+fn rsqrt7(a: u64, flags: &mut u8) -> u64 {
+    let (sign, exp, frac) = (a & SIGN != 0, (a >> 23) & 0xff, a & 0x7f_ffff);
+    match (sign, exp, frac) {
+        (_, 0xff, frac) if frac != 0 => {
+            if frac >> 22 == 0 {
+                *flags |= FLAG_NV;
+            }
+            S.canonical_nan()
+        }
+        (false, 0xff, _) => 0,
+        (_, 0, 0) => {
+            *flags |= FLAG_DZ;
+            a | INF
+        }
+        (true, ..) => {
+            *flags |= FLAG_NV;
+            S.canonical_nan()
+        }
+        _ => {
+            let (exp, frac) = normalize(exp, frac);
+            // Indexed by the LSB of the exponent and the 6 MSBs of the fraction
+            let index = ((exp as u64 & 1) << 6) | frac >> 17;
+            let x = match index {
+                0..=63 => 2.0 + (index as f64 + 0.5) / 32.0,
+                _ => 1.0 + (index as f64 - 63.5) / 64.0,
+            };
+            let est = (256.0 / x.sqrt()).round() as u64 - 128;
+            let out_exp = (3 * 127 - 1 - exp) / 2;
+            (out_exp as u64) << 23 | est << 16
+        }
+    }
+}
+
+// 7-bit estimate of 1/a, with a table computed as for rsqrt7
+fn rec7(a: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let (sign, exp, frac) = (a & SIGN, (a >> 23) & 0xff, a & 0x7f_ffff);
+    match (exp, frac) {
+        (0xff, 0) => sign,
+        (0xff, frac) => {
+            if frac >> 22 == 0 {
+                *flags |= FLAG_NV;
+            }
+            S.canonical_nan()
+        }
+        (0, 0) => {
+            *flags |= FLAG_DZ;
+            sign | INF
+        }
+        _ => {
+            let (exp, frac) = normalize(exp, frac);
+            let out_exp = 2 * 127 - 1 - exp;
+            // Subnormals below 2^-128 overflow
+            if out_exp > 254 {
+                *flags |= FLAG_OF | FLAG_NX;
+                let to_max = match rm {
+                    RoundingMode::Rtz => true,
+                    RoundingMode::Rdn => sign == 0,
+                    RoundingMode::Rup => sign != 0,
+                    _ => false,
+                };
+                return sign | if to_max { INF - 1 } else { INF };
+            }
+            // Indexed by the 7 MSBs of the fraction: round(2^15 / (128 + index + 0.5))
+            let index = frac >> 16;
+            let est = ((1 << 17) / (257 + 2 * index)).div_ceil(2) - 128;
+            let sig = est << 16;
+            match out_exp {
+                // The result is subnormal
+                0 | -1 => sign | ((1 << 23) | sig) >> (1 - out_exp),
+                _ => sign | (out_exp as u64) << 23 | sig,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::setvl;
+    use super::*;
+    use crate::{sys::control::ExtStatus, Exception, Trap};
+
+    fn s(x: f32) -> u64 {
+        x.to_bits() as u64
+    }
+
+    fn assert_float(sys: &mut System, vm: bool, src: VSrc, f: OpFVFunct, expect: &[u64]) {
+        let op = OpVType {
+            vd: Reg::new(4),
+            vs2: Reg::new(2),
+            src,
+            vm,
+        };
+        sys.state.vregs[64..96].fill(0xaa);
+        execute_op_v(sys, &op, &OpVFunct::F(f)).unwrap();
+        for (i, val) in expect.iter().enumerate() {
+            assert_eq!(sys.state.velem(4, i, 4), *val, "element {i}");
+        }
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_execute_float() {
+        let mut sys = System::new();
+        let v2 = [s(1.5), s(-2.0), s(f32::INFINITY), s(0.1)];
+        let v3 = [s(2.25), s(0.5), s(1.0), s(3.0)];
+        (0..4).for_each(|i| sys.state.set_velem(2, i, 4, v2[i]));
+        (0..4).for_each(|i| sys.state.set_velem(3, i, 4, v3[i]));
+        sys.state.set_velem(0, 0, 4, 0b0101);
+        *sys.freg_mut(&Reg::new(1)) = s(4.0) | 0xffff_ffff_0000_0000;
+        let (v3, f1) = (VSrc::V(Reg::new(3)), VSrc::F(Reg::new(1)));
+        let u = 0xaaaa_aaaa;
+        let nan = S.canonical_nan();
+
+        use OpFVFunct::*;
+        setvl(&mut sys, 4, 0b010_000); // e32, m1
+        assert_float(&mut sys, true, v3.clone(), Add, &[s(3.75), s(-1.5), s(f32::INFINITY), s(0.1 + 3.0)]);
+        assert_float(&mut sys, true, f1.clone(), Rdiv, &[s(4.0 / 1.5), s(-2.0), 0, s(4.0 / 0.1)]);
+        assert_float(&mut sys, true, v3.clone(), Sgnjn, &[s(-1.5), s(-2.0), s(-f32::INFINITY), s(-0.1)]);
+        assert_float(&mut sys, true, f1.clone(), Macc, &[s(6.0), s(-8.0), s(f32::INFINITY), s(4.0 * 0.1)]);
+        assert_float(&mut sys, false, v3.clone(), Min, &[s(1.5), u, s(1.0), u]);
+        assert_eq!(sys.ctrl.fflags, FLAG_NX);
+
+        // Infinity minus infinity is invalid
+        sys.ctrl.fflags = 0;
+        assert_float(&mut sys, true, VSrc::V(Reg::new(2)), Sub, &[0, 0, nan, 0]);
+        assert_eq!(sys.ctrl.fflags, FLAG_NV);
+
+        // The rounding mode is taken from frm
+        sys.ctrl.frm = 0b010; // Round down
+        assert_float(&mut sys, true, v3.clone(), CvtXF, &[1, (-2i32) as u32 as u64, 0x7fff_ffff, 0]);
+        assert_float(&mut sys, true, v3.clone(), CvtRtzXuF, &[1, 0, 0xffff_ffff, 0]);
+        sys.ctrl.frm = 0b101;
+        let op = OpVType { vd: Reg::new(4), vs2: Reg::new(2), src: v3.clone(), vm: true };
+        assert!(execute_op_v(&mut sys, &op, &OpVFunct::F(Add)).is_err());
+        assert_float(&mut sys, true, v3.clone(), Max, &[s(2.25), s(0.5), s(f32::INFINITY), s(3.0)]);
+        sys.ctrl.frm = 0;
+
+        // Estimates (1/1.5 is about 0.664, 1/sqrt(1.5) about 0.8125)
+        assert_float(&mut sys, true, v3.clone(), Rec7, &[0x3f2a0000, 0xbeff0000, 0, s(10.0)]);
+        assert_float(&mut sys, true, v3.clone(), Rsqrt7, &[0x3f500000, nan, 0, 0x404a0000]);
+
+        // Compares write a mask
+        assert_float(&mut sys, true, v3.clone(), Mflt, &[0xaaaa_aaab]);
+        assert_float(&mut sys, true, f1.clone(), Mfge, &[0xaaaa_aaa4]);
+
+        // Reductions
+        let op = |src| OpVType { vd: Reg::new(4), vs2: Reg::new(3), src, vm: true };
+        sys.state.set_velem(5, 0, 4, s(10.0));
+        execute_op_v(&mut sys, &op(VSrc::V(Reg::new(5))), &OpVFunct::F(Redosum)).unwrap();
+        assert_eq!(sys.state.velem(4, 0, 4), s(16.75));
+        execute_op_v(&mut sys, &op(VSrc::V(Reg::new(5))), &OpVFunct::F(Redmin)).unwrap();
+        assert_eq!(sys.state.velem(4, 0, 4), s(0.5));
+
+        // Permutations take the scalar from f[rs1], vfmv.f.s NaN-boxes
+        assert_float(&mut sys, false, f1.clone(), Merge, &[s(4.0), s(-2.0), s(4.0), s(0.1)]);
+        assert_float(&mut sys, true, f1.clone(), Slide1up, &[s(4.0), s(1.5), s(-2.0), s(f32::INFINITY)]);
+        execute_op_v(&mut sys, &op(VSrc::V(Reg::new(0))), &OpVFunct::F(MvFS)).unwrap();
+        assert_eq!(sys.freg(&Reg::new(4)), s(2.25) | 0xffff_ffff_0000_0000);
+
+        // Conversions from and to 16-bit integers (e16)
+        setvl(&mut sys, 4, 0b001_000);
+        sys.state.set_velem(2, 0, 4, 0xffff_0003);
+        assert_float(&mut sys, true, v3.clone(), WcvtFX, &[s(3.0), s(-1.0)]);
+        // Out of range and NaN saturate
+        assert_float(&mut sys, true, v3.clone(), NcvtXF, &[0xfffe_7fff, 0x0000_7fff]);
+    }
+
+    #[test]
+    fn test_float_checks() {
+        let mut sys = System::new();
+        let trap = Trap::from_exception(Exception::IllegalInstr, 0);
+        let op = OpVType {
+            vd: Reg::new(4),
+            vs2: Reg::new(2),
+            src: VSrc::F(Reg::new(1)),
+            vm: true,
+        };
+        let add = OpVFunct::F(OpFVFunct::Add);
+
+        // Only single precision
+        setvl(&mut sys, 4, 0b001_000); // e16
+        assert_eq!(execute_op_v(&mut sys, &op, &add), Err(trap));
+
+        // The FPU must be on, the state becomes dirty
+        setvl(&mut sys, 4, 0b010_000); // e32
+        sys.ctrl.fs = ExtStatus::Off;
+        assert_eq!(execute_op_v(&mut sys, &op, &add), Err(trap));
+        sys.ctrl.fs = ExtStatus::Clean;
+        sys.ctrl.vs = ExtStatus::Off;
+        assert_eq!(execute_op_v(&mut sys, &op, &add), Err(trap));
+        sys.ctrl.vs = ExtStatus::Clean;
+        let mv_fs = OpVFunct::F(OpFVFunct::MvFS);
+        execute_op_v(&mut sys, &op, &mv_fs).unwrap();
+        assert_eq!(
+            (sys.ctrl.fs, sys.ctrl.vs),
+            (ExtStatus::Dirty, ExtStatus::Dirty)
+        );
+    }
+}
@@ -0,0 +1,428 @@
+use super::*;
+use crate::sys::make_illegal;
+
+pub fn execute_int(sys: &mut System, op: &OpVType, f: &OpVFunct) -> Result {
+    use OpVFunct::*;
+    let cfg = vcfg(sys)?;
+    match f {
+        Mseq | Msne | Msltu | Mslt | Msleu | Msle | Msgtu | Msgt | Madc | Msbc => {
+            execute_compare(sys, &cfg, op, f)
+        }
+        Waddu | Wadd | Wsubu | Wsub | WadduW | WaddW | WsubuW | WsubW | Wmulu | Wmulsu | Wmul
+        | Wmaccu | Wmacc | Wmaccsu | Wmaccus => execute_widen(sys, &cfg, op, f),
+        Nsrl | Nsra | Nclipu | Nclip => execute_narrow(sys, &cfg, op, f),
+        Zext(frac) | Sext(frac) => execute_extend(sys, &cfg, op, f, *frac as usize),
+        _ => execute_single(sys, &cfg, op, f),
+    }
+}
+
+fn execute_single(sys: &mut System, cfg: &VCfg, op: &OpVType, f: &OpVFunct) -> Result {
+    let OpVType { vd, vs2, src, vm } = op;
+    let (vd, vs2, sew) = (vd.index(), vs2.index(), cfg.sew);
+    check_group(sys, vd, cfg.lmul)?;
+    check_group(sys, vs2, cfg.lmul)?;
+    check_src(sys, src, cfg.lmul)?;
+    check_masked_vd(sys, vd, *vm)?;
+    // v0 holds the carries (or borrows) instead of the mask
+    let carry = matches!(f, OpVFunct::Adc | OpVFunct::Sbc);
+    let mut sat = false;
+    let vals: Vec<_> = (cfg.vstart..cfg.vl)
+        .map(|i| {
+            if !carry && !is_active(sys, *vm, i) {
+                return None;
+            }
+            let a = sys.state.velem(vs2, i, sew);
+            // The carry-in is added to the second operand
+            let c = (carry && sys.state.vmask(0, i)) as u64;
+            let b = src_elem(sys, src, i, sew) + c;
+            let d = sys.state.velem(vd, i, sew);
+            let val = single_op(f, a, b, d, sew, sys.ctrl.vxrm, &mut sat);
+            Some(trunc(val, sew))
+        })
+        .collect();
+    sys.ctrl.vxsat |= sat;
+    write_elems(sys, cfg, vd, sew, cfg.lmul, &vals);
+    Ok(())
+}
+
+// Element operation of vs2 (a) and vs1, rs1 or imm (b), with the old value of vd (d)
+fn single_op(f: &OpVFunct, a: u64, b: u64, d: u64, sew: usize, vxrm: u8, sat: &mut bool) -> u64 {
+    use OpVFunct::*;
+    let (sa, sb) = (sext(a, sew), sext(b, sew));
+    let bits = 8 * sew as u32;
+    let shamt = b as u32 & (bits - 1);
+    match f {
+        Add => a.wrapping_add(b),
+        Sub => a.wrapping_sub(b),
+        Rsub => b.wrapping_sub(a),
+        Adc => a + b,
+        Sbc => a.wrapping_sub(b),
+        Minu => a.min(b),
+        Min => sa.min(sb) as u64,
+        Maxu => a.max(b),
+        Max => sa.max(sb) as u64,
+        And => a & b,
+        Or => a | b,
+        Xor => a ^ b,
+        Sll => a << shamt,
+        Srl => a >> shamt,
+        Sra => (sa >> shamt) as u64,
+        Mul => a.wrapping_mul(b),
+        Mulh => ((sa * sb) >> bits) as u64,
+        Mulhu => (a * b) >> bits,
+        Mulhsu => ((sa * b as i64) >> bits) as u64,
+        // Division by zero and overflow as in the M extension
+        Divu => a.checked_div(b).unwrap_or(u64::MAX),
+        Div => sa.checked_div(sb).unwrap_or(-1) as u64,
+        Remu => a.checked_rem(b).unwrap_or(a),
+        Rem => sa.checked_rem(sb).unwrap_or(sa) as u64,
+        Macc => d.wrapping_add(a.wrapping_mul(b)),
+        Nmsac => d.wrapping_sub(a.wrapping_mul(b)),
+        Madd => a.wrapping_add(d.wrapping_mul(b)),
+        Nmsub => a.wrapping_sub(d.wrapping_mul(b)),
+        Saddu => clip_u(a + b, sew, sat),
+        Sadd => clip_s(sa + sb, sew, sat),
+        Ssubu => {
+            *sat |= a < b;
+            a.saturating_sub(b)
+        }
+        Ssub => clip_s(sa - sb, sew, sat),
+        Aaddu => roundoff_u(a + b, 1, vxrm),
+        Aadd => roundoff_s(sa + sb, 1, vxrm) as u64,
+        Asubu => roundoff_s(a as i64 - b as i64, 1, vxrm) as u64,
+        Asub => roundoff_s(sa - sb, 1, vxrm) as u64,
+        Smul => clip_s(roundoff_s(sa * sb, bits - 1, vxrm), sew, sat),
+        Ssrl => roundoff_u(a, shamt, vxrm),
+        Ssra => roundoff_s(sa, shamt, vxrm) as u64,
+        _ => unreachable!(),
+    }
+}
+
+fn execute_compare(sys: &mut System, cfg: &VCfg, op: &OpVType, f: &OpVFunct) -> Result {
+    use OpVFunct::*;
+    let OpVType { vd, vs2, src, vm } = op;
+    let (vd, vs2, sew) = (vd.index(), vs2.index(), cfg.sew);
+    check_group(sys, vs2, cfg.lmul)?;
+    check_src(sys, src, cfg.lmul)?;
+    // For vmadc and vmsbc, v0 holds the carries (or borrows) if masked
+    let carry = matches!(f, Madc | Msbc);
+    let vals: Vec<_> = (cfg.vstart..cfg.vl)
+        .map(|i| {
+            if !carry && !is_active(sys, *vm, i) {
+                return None;
+            }
+            let a = sys.state.velem(vs2, i, sew);
+            let b = src_elem(sys, src, i, sew);
+            let c = (carry && !vm && sys.state.vmask(0, i)) as u64;
+            let (sa, sb) = (sext(a, sew), sext(b, sew));
+            Some(match f {
+                Mseq => a == b,
+                Msne => a != b,
+                Msltu => a < b,
+                Mslt => sa < sb,
+                Msleu => a <= b,
+                Msle => sa <= sb,
+                Msgtu => a > b,
+                Msgt => sa > sb,
+                Madc => (a + b + c) >> (8 * sew) != 0,
+                Msbc => a < b + c,
+                _ => unreachable!(),
+            })
+        })
+        .collect();
+    write_mask(sys, cfg, vd, &vals);
+    Ok(())
+}
+
+fn execute_widen(sys: &mut System, cfg: &VCfg, op: &OpVType, f: &OpVFunct) -> Result {
+    use OpVFunct::*;
+    let OpVType { vd, vs2, src, vm } = op;
+    let (vd, vs2, sew) = (vd.index(), vs2.index(), cfg.sew);
+    // The destination has twice SEW and LMUL, which must not exceed ELEN and 8
+    let wide = 2 * sew;
+    let wmul = cfg
+        .emul(wide)
+        .filter(|_| wide <= 4)
+        .ok_or_else(|| make_illegal(sys))?;
+    let wide_vs2 = matches!(f, WadduW | WaddW | WsubuW | WsubW);
+    check_group(sys, vd, wmul)?;
+    check_group(sys, vs2, if wide_vs2 { wmul } else { cfg.lmul })?;
+    check_src(sys, src, cfg.lmul)?;
+    check_masked_vd(sys, vd, *vm)?;
+    let vals: Vec<_> = (cfg.vstart..cfg.vl)
+        .map(|i| {
+            if !is_active(sys, *vm, i) {
+                return None;
+            }
+            let a = sys.state.velem(vs2, i, if wide_vs2 { wide } else { sew });
+            let b = src_elem(sys, src, i, sew);
+            let d = sys.state.velem(vd, i, wide);
+            let (sa, sb) = (sext(a, if wide_vs2 { wide } else { sew }), sext(b, sew));
+            let val = match f {
+                Waddu | WadduW => a + b,
+                Wadd | WaddW => (sa + sb) as u64,
+                Wsubu | WsubuW => a.wrapping_sub(b),
+                Wsub | WsubW => (sa - sb) as u64,
+                Wmulu => a * b,
+                Wmulsu => (sa * b as i64) as u64,
+                Wmul => (sa * sb) as u64,
+                Wmaccu => d.wrapping_add(a * b),
+                Wmacc => d.wrapping_add((sa * sb) as u64),
+                Wmaccsu => d.wrapping_add((sb * a as i64) as u64),
+                Wmaccus => d.wrapping_add((b as i64 * sa) as u64),
+                _ => unreachable!(),
+            };
+            Some(trunc(val, wide))
+        })
+        .collect();
+    write_elems(sys, cfg, vd, wide, wmul, &vals);
+    Ok(())
+}
+
+fn execute_narrow(sys: &mut System, cfg: &VCfg, op: &OpVType, f: &OpVFunct) -> Result {
+    use OpVFunct::*;
+    let OpVType { vd, vs2, src, vm } = op;
+    let (vd, vs2, sew) = (vd.index(), vs2.index(), cfg.sew);
+    // The source vs2 has twice SEW and LMUL
+    let wide = 2 * sew;
+    let wmul = cfg
+        .emul(wide)
+        .filter(|_| wide <= 4)
+        .ok_or_else(|| make_illegal(sys))?;
+    check_group(sys, vd, cfg.lmul)?;
+    check_group(sys, vs2, wmul)?;
+    check_src(sys, src, cfg.lmul)?;
+    check_masked_vd(sys, vd, *vm)?;
+    let mut sat = false;
+    let vals: Vec<_> = (cfg.vstart..cfg.vl)
+        .map(|i| {
+            if !is_active(sys, *vm, i) {
+                return None;
+            }
+            let a = sys.state.velem(vs2, i, wide);
+            let shamt = src_elem(sys, src, i, sew) as u32 & (16 * sew as u32 - 1);
+            let vxrm = sys.ctrl.vxrm;
+            let val = match f {
+                Nsrl => a >> shamt,
+                Nsra => (sext(a, wide) >> shamt) as u64,
+                Nclipu => clip_u(roundoff_u(a, shamt, vxrm), sew, &mut sat),
+                Nclip => clip_s(roundoff_s(sext(a, wide), shamt, vxrm), sew, &mut sat),
+                _ => unreachable!(),
+            };
+            Some(trunc(val, sew))
+        })
+        .collect();
+    sys.ctrl.vxsat |= sat;
+    write_elems(sys, cfg, vd, sew, cfg.lmul, &vals);
+    Ok(())
+}
+
+fn execute_extend(sys: &mut System, cfg: &VCfg, op: &OpVType, f: &OpVFunct, frac: usize) -> Result {
+    let OpVType { vd, vs2, vm, .. } = op;
+    let (vd, vs2, sew) = (vd.index(), vs2.index(), cfg.sew);
+    // The source has SEW/frac and LMUL/frac
+    let eew = sew / frac;
+    let emul = cfg
+        .emul(eew)
+        .filter(|_| eew > 0)
+        .ok_or_else(|| make_illegal(sys))?;
+    check_group(sys, vd, cfg.lmul)?;
+    check_group(sys, vs2, emul)?;
+    check_masked_vd(sys, vd, *vm)?;
+    let vals: Vec<_> = (cfg.vstart..cfg.vl)
+        .map(|i| {
+            if !is_active(sys, *vm, i) {
+                return None;
+            }
+            let a = sys.state.velem(vs2, i, eew);
+            match f {
+                OpVFunct::Sext(_) => Some(trunc(sext(a, eew) as u64, sew)),
+                _ => Some(a),
+            }
+        })
+        .collect();
+    write_elems(sys, cfg, vd, sew, cfg.lmul, &vals);
+    Ok(())
+}
+
+// Rounding increment when shifting v right by d bits, with the fixed-point rounding mode
+fn round_inc(v: u64, d: u32, vxrm: u8) -> u64 {
+    if d == 0 {
+        return 0;
+    }
+    let bit = |i: u32| (v >> i) & 1;
+    let below = |i: u32| v & ((1 << i) - 1) != 0;
+    match vxrm {
+        0b00 => bit(d - 1),                                  // Round to nearest, ties up
+        0b01 => bit(d - 1) & (below(d - 1) as u64 | bit(d)), // Round to nearest, ties to even
+        0b10 => 0,                                           // Round down (truncate)
+        _ => (bit(d) == 0 && below(d)) as u64,               // Round to odd (jam)
+    }
+}
+
+fn roundoff_u(v: u64, d: u32, vxrm: u8) -> u64 {
+    (v >> d) + round_inc(v, d, vxrm)
+}
+
+fn roundoff_s(v: i64, d: u32, vxrm: u8) -> i64 {
+    (v >> d) + round_inc(v as u64, d, vxrm) as i64
+}
+
+// Saturate to the unsigned range of SEW
+fn clip_u(v: u64, sew: usize, sat: &mut bool) -> u64 {
+    let max = trunc(u64::MAX, sew);
+    if v > max {
+        *sat = true;
+        max
+    } else {
+        v
+    }
+}
+
+// Saturate to the signed range of SEW
+fn clip_s(v: i64, sew: usize, sat: &mut bool) -> u64 {
+    let max = (1 << (8 * sew - 1)) - 1;
+    let min = -max - 1;
+    if v > max || v < min {
+        *sat = true;
+    }
+    v.clamp(min, max) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::setvl;
+    use super::*;
+
+    fn assert_int(sys: &mut System, vm: bool, src: VSrc, f: OpVFunct, expect: &[u64]) {
+        let op = OpVType {
+            vd: Reg::new(4),
+            vs2: Reg::new(2),
+            src,
+            vm,
+        };
+        sys.state.vregs[64..96].fill(0xaa);
+        execute_op_v(sys, &op, &f).unwrap();
+        for (i, val) in expect.iter().enumerate() {
+            assert_eq!(sys.state.velem(4, i, 4), *val, "element {i}");
+        }
+    }
+
+    #[test]
+    fn test_execute_int() {
+        let mut sys = System::new();
+        let v2 = [0xbcfec832, 0x51290ce3, 0x7fffffff, 5];
+        let v3 = [0x51290ce3, 0xbcfec832, 1, 0xfffffffe];
+        (0..4).for_each(|i| sys.state.set_velem(2, i, 4, v2[i]));
+        (0..4).for_each(|i| sys.state.set_velem(3, i, 4, v3[i]));
+        sys.state.set_velem(0, 0, 4, 0b0101);
+        *sys.state.reg_mut(&Reg::new(1)) = -1;
+        let (v3, x1) = (VSrc::V(Reg::new(3)), VSrc::X(Reg::new(1)));
+        let u = 0xaaaa_aaaa;
+
+        setvl(&mut sys, 4, 0b010_000); // e32, m1
+        assert_int(
+            &mut sys,
+            true,
+            v3.clone(),
+            OpVFunct::Add,
+            &[0x0e27d515, 0x0e27d515, 0x80000000, 3],
+        );
+        assert_int(
+            &mut sys,
+            true,
+            x1.clone(),
+            OpVFunct::Sub,
+            &[0xbcfec833, 0x51290ce4, 0x80000000, 6],
+        );
+        assert_int(
+            &mut sys,
+            true,
+            v3.clone(),
+            OpVFunct::Mulh,
+            &[0xeac1dec6, 0xeac1dec6, 0, 0xffffffff],
+        );
+        assert_int(
+            &mut sys,
+            true,
+            v3.clone(),
+            OpVFunct::Divu,
+            &[2, 0, 0x7fffffff, 0],
+        );
+        assert_int(
+            &mut sys,
+            true,
+            v3.clone(),
+            OpVFunct::Rem,
+            &[0xbcfec832, 0x0e27d515, 0, 1],
+        );
+        assert_int(
+            &mut sys,
+            true,
+            v3.clone(),
+            OpVFunct::Aadd,
+            &[0x0713ea8b, 0x0713ea8b, 0x40000000, 2],
+        );
+        assert_int(
+            &mut sys,
+            true,
+            VSrc::I(4),
+            OpVFunct::Ssrl,
+            &[0x0bcfec83, 0x051290ce, 0x08000000, 0],
+        );
+        assert!(!sys.ctrl.vxsat);
+        assert_int(
+            &mut sys,
+            true,
+            v3.clone(),
+            OpVFunct::Sadd,
+            &[0x0e27d515, 0x0e27d515, 0x7fffffff, 3],
+        );
+        assert!(sys.ctrl.vxsat);
+
+        // Masked and tail elements are undisturbed, unless agnostic
+        setvl(&mut sys, 3, 0b010_000); // e32, m1, tu, mu
+        assert_int(
+            &mut sys,
+            false,
+            v3.clone(),
+            OpVFunct::Add,
+            &[0x0e27d515, u, 0x80000000, u],
+        );
+        setvl(&mut sys, 3, 0b11_010_000); // e32, m1, ta, ma
+        let ones = 0xffffffff;
+        assert_int(
+            &mut sys,
+            false,
+            v3.clone(),
+            OpVFunct::Add,
+            &[0x0e27d515, ones, 0x80000000, ones],
+        );
+
+        // Compares write a mask
+        setvl(&mut sys, 4, 0b010_000);
+        assert_int(&mut sys, true, v3.clone(), OpVFunct::Mslt, &[0xaaaa_aaa1]);
+        assert_int(&mut sys, true, v3.clone(), OpVFunct::Madc, &[0xaaaa_aaab]);
+
+        // Widening and narrowing (e16)
+        setvl(&mut sys, 4, 0b001_000);
+        let wmul = [0xfd30dc56, 0xeac1a2ae, 0xfd30dc56, 0xeac1a2ae];
+        assert_int(&mut sys, true, v3.clone(), OpVFunct::Wmul, &wmul);
+        assert_int(
+            &mut sys,
+            true,
+            VSrc::I(8),
+            OpVFunct::Nclipu,
+            &[0xffff_ffff, 0x0000_ffff, 0xaaaa_aaaa],
+        );
+
+        // The widened destination must be aligned to 2 registers
+        let op = OpVType {
+            vd: Reg::new(5),
+            vs2: Reg::new(2),
+            src: v3,
+            vm: true,
+        };
+        assert!(execute_op_v(&mut sys, &op, &OpVFunct::Wmul).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use super::*;
+use crate::sys::make_illegal;
+
+pub fn execute_mask(sys: &mut System, op: &OpVType, f: &OpVFunct) -> Result {
+    use OpVFunct::*;
+    let cfg = vcfg(sys)?;
+    let OpVType { vd, vs2, src, vm } = op;
+    let (vd, vs2) = (vd.index(), vs2.index());
+    // Mask logical operations are unmasked, vs1 being the other mask
+    if let (VSrc::V(vs1), Mand | Mnand | Mandn | Mxor | Mor | Mnor | Morn | Mxnor) = (src, f) {
+        let vs1 = vs1.index();
+        let vals: Vec<_> = (cfg.vstart..cfg.vl)
+            .map(|i| {
+                let (a, b) = (sys.state.vmask(vs2, i), sys.state.vmask(vs1, i));
+                Some(match f {
+                    Mand => a & b,
+                    Mnand => !(a & b),
+                    Mandn => a & !b,
+                    Mxor => a ^ b,
+                    Mor => a | b,
+                    Mnor => !(a | b),
+                    Morn => a | !b,
+                    Mxnor => !(a ^ b),
+                    _ => unreachable!(),
+                })
+            })
+            .collect();
+        write_mask(sys, &cfg, vd, &vals);
+        return Ok(());
+    }
+    // The others scan the elements from the start
+    if cfg.vstart != 0 {
+        return Err(make_illegal(sys));
+    }
+    let set_bits = (0..cfg.vl).filter(|i| is_active(sys, *vm, *i) && sys.state.vmask(vs2, *i));
+    match f {
+        Cpop => *sys.reg_mut(&op.vd) = set_bits.count() as i32,
+        First => *sys.reg_mut(&op.vd) = set_bits.min().map_or(-1, |i| i as i32),
+        Msbf | Msif | Msof => {
+            check_masked_vd(sys, vd, *vm)?;
+            check_overlap(sys, vd, 0, vs2, 0)?;
+            let first = set_bits.min().unwrap_or(cfg.vl);
+            let vals: Vec<_> = (0..cfg.vl)
+                .map(|i| {
+                    let val = match f {
+                        Msbf => i < first,
+                        Msif => i <= first,
+                        _ => i == first,
+                    };
+                    is_active(sys, *vm, i).then_some(val)
+                })
+                .collect();
+            write_mask(sys, &cfg, vd, &vals);
+        }
+        Iota | Id => {
+            check_group(sys, vd, cfg.lmul)?;
+            check_masked_vd(sys, vd, *vm)?;
+            if matches!(f, Iota) {
+                check_overlap(sys, vd, cfg.lmul, vs2, 0)?;
+            }
+            // Prefix sum of the active bits of vs2, or the index for vid
+            let mut count = 0;
+            let vals: Vec<_> = (0..cfg.vl)
+                .map(|i| {
+                    if !is_active(sys, *vm, i) {
+                        return None;
+                    }
+                    let val = if matches!(f, Id) { i as u64 } else { count };
+                    count += sys.state.vmask(vs2, i) as u64;
+                    Some(trunc(val, cfg.sew))
+                })
+                .collect();
+            write_elems(sys, &cfg, vd, cfg.sew, cfg.lmul, &vals);
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::setvl;
+    use super::*;
+
+    fn mask_op(vd: u8, vs2: u8, vs1: u8, vm: bool) -> OpVType {
+        OpVType {
+            vd: Reg::new(vd),
+            vs2: Reg::new(vs2),
+            src: VSrc::V(Reg::new(vs1)),
+            vm,
+        }
+    }
+
+    #[test]
+    fn test_execute_mask() {
+        let mut sys = System::new();
+        setvl(&mut sys, 10, 0b000_000); // e8, m1
+        sys.state.set_velem(0, 0, 2, 0b11_1110_1011);
+        sys.state.set_velem(1, 0, 2, 0b01_0101_1000);
+        sys.state.set_velem(2, 0, 2, 0b00_1100_1100);
+
+        let mut assert_mask = |f, vd, vs2, vs1, vm, expect| {
+            sys.state.set_velem(vd, 0, 2, 0xfc00);
+            execute_op_v(&mut sys, &mask_op(vd, vs2, vs1, vm), &f).unwrap();
+            assert_eq!(sys.state.velem(vd, 0, 2), expect);
+        };
+        assert_mask(OpVFunct::Mand, 3, 1, 2, true, 0xfc00 | 0b00_0100_1000);
+        assert_mask(OpVFunct::Mandn, 3, 1, 2, true, 0xfc00 | 0b01_0001_0000);
+        assert_mask(OpVFunct::Mxnor, 3, 1, 2, true, 0xfc00 | 0b10_0110_1011);
+        assert_mask(OpVFunct::Msbf, 3, 1, 0, true, 0xfc00 | 0b00_0000_0111);
+        assert_mask(OpVFunct::Msif, 3, 1, 0, true, 0xfc00 | 0b00_0000_1111);
+        assert_mask(OpVFunct::Msof, 3, 1, 0, true, 0xfc00 | 0b00_0000_1000);
+        // Masked, only active bits of vs2 count (bit 2 is inactive)
+        assert_mask(OpVFunct::Msof, 3, 2, 0, false, 0xfc00 | 0b00_0000_1000);
+
+        execute_op_v(&mut sys, &mask_op(5, 1, 0b10000, true), &OpVFunct::Cpop).unwrap();
+        assert_eq!(sys.state.reg(&Reg::new(5)), 4);
+        execute_op_v(&mut sys, &mask_op(5, 2, 0b10001, false), &OpVFunct::First).unwrap();
+        assert_eq!(sys.state.reg(&Reg::new(5)), 3);
+        execute_op_v(&mut sys, &mask_op(5, 2, 0b10001, true), &OpVFunct::First).unwrap();
+        assert_eq!(sys.state.reg(&Reg::new(5)), 2);
+
+        // viota.m and vid.v
+        sys.state.vregs[64..80].fill(0xaa);
+        execute_op_v(&mut sys, &mask_op(4, 1, 0b10000, false), &OpVFunct::Iota).unwrap();
+        assert_eq!(sys.state.velem(4, 0, 8), 0x02_01_01_aa_00_aa_00_00);
+        assert_eq!(sys.state.velem(4, 1, 8), 0xaa_aa_aa_aa_aa_aa_03_02);
+        execute_op_v(&mut sys, &mask_op(4, 0, 0b10001, true), &OpVFunct::Id).unwrap();
+        assert_eq!(sys.state.velem(4, 0, 8), 0x07_06_05_04_03_02_01_00);
+    }
+}
@@ -0,0 +1,275 @@
+use super::*;
+use crate::{
+    pmp::check_pmp,
+    sys::{
+        make_illegal,
+        mem_map::{AccessAttr, AccessType, AccessWidth},
+    },
+    translate::*,
+};
+
+pub fn execute_load_v(sys: &mut System, op: &VMemType, f: &VMemFunct) -> Result {
+    check_vs(sys)?;
+    execute_vmem(sys, op, f, AccessType::Load)?;
+    sys.ctrl.vstart = 0;
+    set_vs_dirty(sys);
+    advance_pc(sys);
+    Ok(())
+}
+
+pub fn execute_store_v(sys: &mut System, op: &VMemType, f: &VMemFunct) -> Result {
+    check_vs(sys)?;
+    execute_vmem(sys, op, f, AccessType::Store)?;
+    sys.ctrl.vstart = 0;
+    advance_pc(sys);
+    Ok(())
+}
+
+fn execute_vmem(sys: &mut System, op: &VMemType, f: &VMemFunct, atype: AccessType) -> Result {
+    use VMemFunct::*;
+    let VMemType { vd, rs1, rs2, vm } = op;
+    let vd = vd.index();
+    let is_load = atype == AccessType::Load;
+    let vlenb = sys.state.vlenb();
+    let vstart = sys.ctrl.vstart as usize;
+    // Whole register accesses do not depend on vtype and vl
+    let (eew, emul, nf, evl, vta, vma) = match f {
+        Whole(eew, nr) => {
+            let eew = eew.bytes();
+            (
+                eew,
+                nr.trailing_zeros() as i32,
+                1,
+                *nr as usize * vlenb / eew,
+                false,
+                false,
+            )
+        }
+        _ => {
+            let cfg = vcfg(sys)?;
+            let (eew, emul, nf, evl) = match f {
+                Unit(eew, nf) | FaultFirst(eew, nf) | Strided(eew, nf) => {
+                    let emul = cfg.emul(eew.bytes()).ok_or_else(|| make_illegal(sys))?;
+                    (eew.bytes(), emul, *nf, cfg.vl)
+                }
+                // The data has SEW, the indices have EEW
+                Indexed(ieew, nf) => {
+                    let iemul = cfg.emul(ieew.bytes()).ok_or_else(|| make_illegal(sys))?;
+                    check_group(sys, rs2.index(), iemul)?;
+                    (cfg.sew, cfg.lmul, *nf, cfg.vl)
+                }
+                Mask => (1, 0, 1, cfg.vl.div_ceil(8)),
+                Whole(..) => unreachable!(),
+            };
+            (eew, emul, nf, evl, cfg.vta, cfg.vma)
+        }
+    };
+    // All the fields must fit in 8 registers
+    let regs = VCfg::regs(emul);
+    if nf as u32 * regs as u32 > 8 || vd % regs != 0 || vd as u32 + (nf * regs) as u32 > 32 {
+        return Err(make_illegal(sys));
+    }
+    if is_load {
+        check_masked_vd(sys, vd, *vm)?;
+    }
+
+    let base = sys.reg(rs1) as u32;
+    let stride = sys.reg(rs2) as u32;
+    // The indices are read up front as the loads might overwrite them
+    let offsets: Vec<_> = (0..evl)
+        .map(|i| match f {
+            Strided(..) => i as u32 * stride,
+            Indexed(ieew, _) => sys.state.velem(rs2.index(), i, ieew.bytes()) as u32,
+            _ => (i * nf as usize) as u32 * eew as u32,
+        })
+        .collect();
+    let ones = trunc(u64::MAX, eew);
+    for (i, offset) in offsets.into_iter().enumerate().skip(vstart) {
+        let active = is_active(sys, *vm, i);
+        for field in 0..nf {
+            let reg = vd + field * regs;
+            if !active {
+                if is_load && vma {
+                    sys.state.set_velem(reg, i, eew, ones);
+                }
+                continue;
+            }
+            let vaddr = base
+                .wrapping_add(offset)
+                .wrapping_add(field as u32 * eew as u32);
+            let res = if is_load {
+                load_elem(sys, vaddr, eew).map(|val| sys.state.set_velem(reg, i, eew, val))
+            } else {
+                let val = sys.state.velem(reg, i, eew);
+                store_elem(sys, vaddr, eew, val)
+            };
+            match res {
+                Ok(()) => (),
+                // Fault-only-first loads only trap on the first element, otherwise they trim vl
+                Err(_) if matches!(f, FaultFirst(..)) && i > 0 => {
+                    sys.ctrl.vl = i as u32;
+                    fill_tail(sys, vd, nf, emul, eew, i, vta);
+                    return Ok(());
+                }
+                Err(trap) => {
+                    // Resume from the faulting element
+                    sys.ctrl.vstart = i as u32;
+                    return Err(trap);
+                }
+            }
+        }
+    }
+    if is_load && vstart < evl {
+        fill_tail(sys, vd, nf, emul, eew, evl, vta);
+    }
+    Ok(())
+}
+
+// Fill the tail of each field with all 1s if tail agnostic
+fn fill_tail(sys: &mut System, vd: u8, nf: u8, emul: i32, eew: usize, vl: usize, vta: bool) {
+    if !vta {
+        return;
+    }
+    let regs = VCfg::regs(emul);
+    let end = regs as usize * sys.state.vlenb() / eew;
+    for field in 0..nf {
+        for i in vl..end {
+            sys.state
+                .set_velem(vd + field * regs, i, eew, trunc(u64::MAX, eew));
+        }
+    }
+}
+
+fn attr(atype: AccessType, eew: usize) -> AccessAttr {
+    AccessAttr {
+        atype,
+        lrsc: false,
+        amo: false,
+        width: match eew {
+            1 => AccessWidth::Byte,
+            2 => AccessWidth::HalfWord,
+            _ => AccessWidth::Word,
+        },
+    }
+}
+
+fn load_elem(sys: &mut System, vaddr: u32, eew: usize) -> core::result::Result<u64, Trap> {
+    let make_trap = |ex| Trap::from_exception(ex, vaddr);
+    // Translate virtual address
+    let paddr = translate(sys, vaddr, AccessType::Load).map_err(make_trap)?;
+    // Load data with physical address
+    let attr = attr(AccessType::Load, eew);
+    check_pmp(sys, paddr, attr).map_err(make_trap)?;
+    Ok(match eew {
+        1 => sys.mem.read_u8(paddr, attr).map_err(make_trap)? as u64,
+        2 => sys.mem.read_u16(paddr, attr).map_err(make_trap)? as u64,
+        _ => sys.mem.read_u32(paddr, attr).map_err(make_trap)? as u64,
+    })
+}
+
+fn store_elem(sys: &mut System, vaddr: u32, eew: usize, val: u64) -> Result {
+    let make_trap = |ex| Trap::from_exception(ex, vaddr);
+    // Translate virtual address
+    let paddr = translate(sys, vaddr, AccessType::Store).map_err(make_trap)?;
+    // Store data with physical address
+    let attr = attr(AccessType::Store, eew);
+    check_pmp(sys, paddr, attr).map_err(make_trap)?;
+    match eew {
+        1 => sys
+            .mem
+            .write_u8(paddr, val as u8, attr)
+            .map_err(make_trap)?,
+        2 => sys
+            .mem
+            .write_u16(paddr, val as u16, attr)
+            .map_err(make_trap)?,
+        _ => sys
+            .mem
+            .write_u32(paddr, val as u32, attr)
+            .map_err(make_trap)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::setvl;
+    use super::*;
+    use crate::Exception;
+
+    fn mem_op(vd: u8, rs2: u8, vm: bool) -> VMemType {
+        VMemType {
+            vd: Reg::new(vd),
+            rs1: Reg::new(1),
+            rs2: Reg::new(rs2),
+            vm,
+        }
+    }
+
+    #[test]
+    fn test_execute_vmem() {
+        use {VEew::*, VMemFunct::*};
+        let mut sys = System::new();
+        let ram = |sys: &System, i: usize| sys.mem.ram.as_u8()[0x100 + i];
+        (0..32).for_each(|i| sys.mem.ram.as_u8_mut()[0x100 + i] = i as u8);
+        *sys.state.reg_mut(&Reg::new(1)) = 0x100;
+        *sys.state.reg_mut(&Reg::new(2)) = 6;
+        setvl(&mut sys, 3, 0b001_000); // e16, m1
+
+        // vle16.v, vlse16.v and vluxei8.v
+        execute_load_v(&mut sys, &mem_op(4, 0, true), &Unit(E16, 1)).unwrap();
+        assert_eq!(sys.state.velem(4, 0, 8), 0x0000_0504_0302_0100);
+        execute_load_v(&mut sys, &mem_op(4, 2, true), &Strided(E16, 1)).unwrap();
+        assert_eq!(sys.state.velem(4, 0, 8), 0x0000_0d0c_0706_0100);
+        sys.state.set_velem(8, 0, 4, 0x00_02_1e_08);
+        execute_load_v(&mut sys, &mem_op(4, 8, true), &Indexed(E8, 1)).unwrap();
+        assert_eq!(sys.state.velem(4, 0, 8), 0x0000_0302_1f1e_0908);
+
+        // vlseg2e8.v with a mask
+        setvl(&mut sys, 3, 0b000_000); // e8, m1
+        sys.state.set_velem(0, 0, 1, 0b101);
+        execute_load_v(&mut sys, &mem_op(4, 0, false), &Unit(E8, 2)).unwrap();
+        assert_eq!(sys.state.velem(4, 0, 4) & 0xffffff, 0x04_09_00);
+        assert_eq!(sys.state.velem(5, 0, 4) & 0xffffff, 0x05_00_01);
+
+        // vse8.v
+        sys.state.set_velem(6, 0, 4, 0xccbbaa);
+        execute_store_v(&mut sys, &mem_op(6, 0, true), &Unit(E8, 1)).unwrap();
+        assert_eq!(
+            (0..4).map(|i| ram(&sys, i)).collect::<Vec<_>>(),
+            [0xaa, 0xbb, 0xcc, 3]
+        );
+        // vs1r.v
+        execute_store_v(&mut sys, &mem_op(8, 8, true), &Whole(E8, 1)).unwrap();
+        assert_eq!(
+            (0..5).map(|i| ram(&sys, i)).collect::<Vec<_>>(),
+            [0x08, 0x1e, 0x02, 0, 0]
+        );
+        assert_eq!(sys.state.pc(), 8 * 4);
+    }
+
+    #[test]
+    fn test_execute_vmem_fault() {
+        use {VEew::*, VMemFunct::*};
+        let mut sys = System::new();
+        let size = sys.mem.ram.as_u8().len() as i32;
+        *sys.state.reg_mut(&Reg::new(1)) = size - 4;
+        setvl(&mut sys, 4, 0b010_000); // e32, m1
+
+        // Faults on the second element set vstart
+        let trap = Trap::from_exception(Exception::LoadAccessFault, size as u32);
+        let load = Unit(E32, 1);
+        assert_eq!(
+            execute_load_v(&mut sys, &mem_op(4, 0, true), &load),
+            Err(trap)
+        );
+        assert_eq!(sys.ctrl.vstart, 1);
+
+        // Fault-only-first loads trim vl instead
+        sys.ctrl.vstart = 0;
+        let load = FaultFirst(E32, 1);
+        execute_load_v(&mut sys, &mem_op(4, 0, true), &load).unwrap();
+        assert_eq!(sys.ctrl.vl, 1);
+        assert_eq!(sys.ctrl.vstart, 0);
+    }
+}
@@ -0,0 +1,230 @@
+use super::*;
+use crate::sys::make_illegal;
+
+pub fn execute_permute(sys: &mut System, op: &OpVType, f: &OpVFunct) -> Result {
+    use OpVFunct::*;
+    // Whole register moves do not depend on vtype
+    if let MvNr(nr) = f {
+        return execute_mv_nr(sys, op, *nr);
+    }
+    let cfg = vcfg(sys)?;
+    let OpVType { vd, vs2, src, vm } = op;
+    let (vd, vs2, sew) = (vd.index(), vs2.index(), cfg.sew);
+    let vlmax = VCfg::vlmax(sys.state.vlenb(), sew, cfg.lmul);
+    match f {
+        MvXS => {
+            // Performed even if vstart >= vl
+            let val = sext(sys.state.velem(vs2, 0, sew), sew) as i32;
+            *sys.reg_mut(&op.vd) = val;
+            return Ok(());
+        }
+        MvSX => {
+            let vl = cfg.vl.min(1);
+            let one = VCfg { vl, ..cfg };
+            let val = src_elem(sys, src, 0, sew);
+            write_elems(sys, &one, vd, sew, 0, &[Some(val)]);
+            return Ok(());
+        }
+        _ => (),
+    }
+    check_group(sys, vd, cfg.lmul)?;
+    check_masked_vd(sys, vd, *vm)?;
+    if !matches!(f, Mv) {
+        check_group(sys, vs2, cfg.lmul)?;
+    }
+    match f {
+        Merge | Mv => check_src(sys, src, cfg.lmul)?,
+        Slideup | Slide1up | Rgather => check_overlap(sys, vd, cfg.lmul, vs2, cfg.lmul)?,
+        _ => (),
+    }
+    let offset = scalar(sys, src);
+    let vals: Vec<_> = match f {
+        Merge | Mv => (cfg.vstart..cfg.vl)
+            .map(|i| match f {
+                Merge if !sys.state.vmask(0, i) => Some(sys.state.velem(vs2, i, sew)),
+                _ => Some(src_elem(sys, src, i, sew)),
+            })
+            .collect(),
+        Slideup => {
+            // Elements below the offset are left unchanged
+            let vstart = cfg.vstart.max(offset.min(cfg.vl as u64) as usize);
+            let vals = (vstart..cfg.vl)
+                .map(|i| {
+                    is_active(sys, *vm, i).then(|| sys.state.velem(vs2, i - offset as usize, sew))
+                })
+                .collect::<Vec<_>>();
+            let cfg = VCfg { vstart, ..cfg };
+            write_elems(sys, &cfg, vd, sew, cfg.lmul, &vals);
+            return Ok(());
+        }
+        Slidedown => (cfg.vstart..cfg.vl)
+            .map(|i| {
+                let j = i as u64 + offset;
+                let val = if j < vlmax as u64 {
+                    sys.state.velem(vs2, j as usize, sew)
+                } else {
+                    0
+                };
+                is_active(sys, *vm, i).then_some(val)
+            })
+            .collect(),
+        Slide1up => (cfg.vstart..cfg.vl)
+            .map(|i| {
+                let val = match i {
+                    0 => src_elem(sys, src, 0, sew),
+                    _ => sys.state.velem(vs2, i - 1, sew),
+                };
+                is_active(sys, *vm, i).then_some(val)
+            })
+            .collect(),
+        Slide1down => (cfg.vstart..cfg.vl)
+            .map(|i| {
+                let val = match i + 1 < cfg.vl {
+                    true => sys.state.velem(vs2, i + 1, sew),
+                    false => src_elem(sys, src, 0, sew),
+                };
+                is_active(sys, *vm, i).then_some(val)
+            })
+            .collect(),
+        Rgather | Rgatherei16 => {
+            // The indices have SEW bits, or 16 bits for vrgatherei16
+            let (eew, emul) = match f {
+                Rgatherei16 => (2, cfg.emul(2).ok_or_else(|| make_illegal(sys))?),
+                _ => (sew, cfg.lmul),
+            };
+            if let VSrc::V(vs1) = src {
+                check_group(sys, vs1.index(), emul)?;
+                check_overlap(sys, vd, cfg.lmul, vs1.index(), emul)?;
+            }
+            check_overlap(sys, vd, cfg.lmul, vs2, cfg.lmul)?;
+            (cfg.vstart..cfg.vl)
+                .map(|i| {
+                    let index = match src {
+                        VSrc::V(vs1) => sys.state.velem(vs1.index(), i, eew),
+                        _ => offset,
+                    };
+                    let val = match index < vlmax as u64 {
+                        true => sys.state.velem(vs2, index as usize, sew),
+                        false => 0,
+                    };
+                    is_active(sys, *vm, i).then_some(val)
+                })
+                .collect()
+        }
+        Compress => {
+            // The selected elements are packed, the rest is the tail
+            let VSrc::V(vs1) = src else { unreachable!() };
+            if cfg.vstart != 0 {
+                return Err(make_illegal(sys));
+            }
+            check_overlap(sys, vd, cfg.lmul, vs2, cfg.lmul)?;
+            check_overlap(sys, vd, cfg.lmul, vs1.index(), 0)?;
+            let vals: Vec<_> = (0..cfg.vl)
+                .filter(|i| sys.state.vmask(vs1.index(), *i))
+                .map(|i| Some(sys.state.velem(vs2, i, sew)))
+                .collect();
+            let vl = vals.len();
+            let cfg = VCfg { vl, ..cfg };
+            write_elems(sys, &cfg, vd, sew, cfg.lmul, &vals);
+            return Ok(());
+        }
+        _ => unreachable!(),
+    };
+    write_elems(sys, &cfg, vd, sew, cfg.lmul, &vals);
+    Ok(())
+}
+
+// Offset of slides and index of gathers (rs1 or the unsigned immediate)
+fn scalar(sys: &System, src: &VSrc) -> u64 {
+    match src {
+        VSrc::X(rs1) => sys.reg(rs1) as u32 as u64,
+        VSrc::I(imm) => (*imm & 0x1f) as u64,
+        VSrc::V(_) | VSrc::F(_) => 0,
+    }
+}
+
+fn execute_mv_nr(sys: &mut System, op: &OpVType, nr: u8) -> Result {
+    let (vd, vs2) = (op.vd.index(), op.vs2.index());
+    let emul = nr.trailing_zeros() as i32;
+    check_group(sys, vd, emul)?;
+    check_group(sys, vs2, emul)?;
+    // Elements have SEW bits (bytes if vill is set) for the purpose of vstart
+    let sew = parse_vtype(sys.ctrl.vtype).map_or(1, |(sew, ..)| sew);
+    let evl = nr as usize * sys.state.vlenb() / sew;
+    for i in sys.ctrl.vstart as usize..evl {
+        let val = sys.state.velem(vs2, i, sew);
+        sys.state.set_velem(vd, i, sew, val);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::setvl;
+    use super::*;
+
+    fn assert_permute(sys: &mut System, vm: bool, src: VSrc, f: OpVFunct, expect: &[u64]) {
+        let op = OpVType {
+            vd: Reg::new(4),
+            vs2: Reg::new(2),
+            src,
+            vm,
+        };
+        sys.state.vregs[64..80].fill(0xaa);
+        execute_op_v(sys, &op, &f).unwrap();
+        for (i, val) in expect.iter().enumerate() {
+            assert_eq!(sys.state.velem(4, i, 4), *val, "element {i}");
+        }
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_execute_permute() {
+        let mut sys = System::new();
+        setvl(&mut sys, 3, 0b010_000); // e32, m1, tu, mu
+        (0..4).for_each(|i| sys.state.set_velem(2, i, 4, 0x10 + i as u64));
+        (0..4).for_each(|i| sys.state.set_velem(6, i, 4, [2, 0, 7, 1][i]));
+        sys.state.set_velem(0, 0, 4, 0b0101);
+        *sys.state.reg_mut(&Reg::new(1)) = -1;
+        let x1 = VSrc::X(Reg::new(1));
+        let x = 0xffff_ffff;
+        let u = 0xaaaa_aaaa;
+
+        use OpVFunct::*;
+        assert_permute(&mut sys, true, VSrc::I(1), Slideup, &[u, 0x10, 0x11, u]);
+        assert_permute(&mut sys, true, VSrc::I(1), Slidedown, &[0x11, 0x12, 0x13, u]);
+        assert_permute(&mut sys, true, VSrc::I(2), Slidedown, &[0x12, 0x13, 0, u]);
+        assert_permute(&mut sys, false, VSrc::I(2), Slidedown, &[0x12, u, 0, u]);
+        assert_permute(&mut sys, true, x1.clone(), Slide1up, &[x, 0x10, 0x11, u]);
+        assert_permute(&mut sys, true, x1.clone(), Slide1down, &[0x11, 0x12, x, u]);
+        assert_permute(&mut sys, false, VSrc::I(5), Merge, &[5, 0x11, 5, u]);
+        assert_permute(&mut sys, true, VSrc::V(Reg::new(6)), Rgather, &[0x12, 0x10, 0, u]);
+        assert_permute(&mut sys, true, x1.clone(), Rgather, &[0, 0, 0, u]);
+        assert_permute(&mut sys, true, VSrc::V(Reg::new(0)), Compress, &[0x10, 0x12, u, u]);
+
+        // Tail agnostic
+        setvl(&mut sys, 3, 0b01_010_000);
+        assert_permute(&mut sys, true, VSrc::I(0), Slideup, &[0x10, 0x11, 0x12, x]);
+
+        // The destination cannot overlap the source for slideup
+        let op = OpVType {
+            vd: Reg::new(2),
+            vs2: Reg::new(2),
+            src: VSrc::I(1),
+            vm: true,
+        };
+        assert!(execute_op_v(&mut sys, &op, &Slideup).is_err());
+
+        // vmv.x.s sign-extends, vmv.s.x only writes the first element
+        let op = OpVType {
+            vd: Reg::new(5),
+            vs2: Reg::new(6),
+            src: VSrc::V(Reg::new(0)),
+            vm: true,
+        };
+        execute_op_v(&mut sys, &op, &MvXS).unwrap();
+        assert_eq!(sys.state.reg(&Reg::new(5)), 2);
+        setvl(&mut sys, 3, 0b001_000); // e16
+        assert_permute(&mut sys, true, x1, MvSX, &[0xaaaa_ffff, u]);
+    }
+}
@@ -0,0 +1,83 @@
+use super::*;
+use crate::sys::make_illegal;
+
+pub fn execute_reduce(sys: &mut System, op: &OpVType, f: &OpVFunct) -> Result {
+    use OpVFunct::*;
+    let cfg = vcfg(sys)?;
+    let OpVType { vd, vs2, src, vm } = op;
+    let (vd, vs2, sew) = (vd.index(), vs2.index(), cfg.sew);
+    // The scalar operand vs1[0] and the result vd[0] have twice SEW for widening reductions
+    let widen = matches!(f, Wredsumu | Wredsum);
+    let eew = if widen { 2 * sew } else { sew };
+    // Reductions cannot be resumed, so vstart must be 0
+    if cfg.vstart != 0 || eew > 4 {
+        return Err(make_illegal(sys));
+    }
+    check_group(sys, vs2, cfg.lmul)?;
+    if cfg.vl == 0 {
+        return Ok(());
+    }
+    let init = src_elem(sys, src, 0, eew);
+    let acc = (0..cfg.vl)
+        .filter(|i| is_active(sys, *vm, *i))
+        .map(|i| sys.state.velem(vs2, i, sew))
+        .fold(init, |acc, a| {
+            let (sacc, sa) = (sext(acc, eew), sext(a, sew));
+            match f {
+                Redsum => acc.wrapping_add(a),
+                Redand => acc & a,
+                Redor => acc | a,
+                Redxor => acc ^ a,
+                Redminu => acc.min(a),
+                Redmin => sacc.min(sa) as u64,
+                Redmaxu => acc.max(a),
+                Redmax => sacc.max(sa) as u64,
+                Wredsumu => acc.wrapping_add(a),
+                Wredsum => (sacc + sa) as u64,
+                _ => unreachable!(),
+            }
+        });
+    // Only the first element is written, the rest of vd is the tail
+    let one = VCfg { vl: 1, ..cfg };
+    write_elems(sys, &one, vd, eew, 0, &[Some(trunc(acc, eew))]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::setvl;
+    use super::*;
+
+    #[test]
+    fn test_execute_reduce() {
+        let mut sys = System::new();
+        setvl(&mut sys, 4, 0b001_000); // e16, m1
+        [3, 0xfffe, 7, 0x8000]
+            .iter()
+            .enumerate()
+            .for_each(|(i, v)| sys.state.set_velem(2, i, 2, *v));
+        sys.state.set_velem(1, 0, 4, 0x0001_0010);
+        sys.state.set_velem(0, 0, 2, 0b1011);
+
+        let op = |vm| OpVType {
+            vd: Reg::new(3),
+            vs2: Reg::new(2),
+            src: VSrc::V(Reg::new(1)),
+            vm,
+        };
+        let reduce = |sys: &mut System, vm, f| {
+            execute_op_v(sys, &op(vm), &f).unwrap();
+            sys.state.velem(3, 0, 4)
+        };
+        assert_eq!(reduce(&mut sys, true, OpVFunct::Redsum) & 0xffff, 0x8018);
+        assert_eq!(reduce(&mut sys, true, OpVFunct::Redmaxu) & 0xffff, 0xfffe);
+        assert_eq!(reduce(&mut sys, true, OpVFunct::Redmin) & 0xffff, 0x8000);
+        assert_eq!(reduce(&mut sys, false, OpVFunct::Redmax) & 0xffff, 0x0010);
+        assert_eq!(reduce(&mut sys, true, OpVFunct::Wredsum), 0x0000_8018);
+        assert_eq!(reduce(&mut sys, false, OpVFunct::Wredsumu), 0x0002_8011);
+
+        // Not resumable
+        sys.ctrl.vstart = 1;
+        assert!(execute_op_v(&mut sys, &op(true), &OpVFunct::Redsum).is_err());
+    }
+}
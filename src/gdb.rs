@@ -0,0 +1,801 @@
+use crate::{
+    exec::csr::{csr_read_debug, csr_write_debug},
+    instr::csr::CsrReg,
+    sys::{control::MPriv, log_with_pc, mem_map::AccessType, StopReason},
+    translate::translate_debug,
+    Reg, System,
+};
+use colored::*;
+use std::{
+    collections::HashSet,
+    io::{self, Read, Write},
+    net::TcpListener,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+// Register numbers as used by GDB for RISC-V
+const REG_PC: usize = 32;
+const REG_F0: usize = 33;
+const REG_CSR0: usize = 65;
+const REG_PRIV: usize = REG_CSR0 + 0x1000;
+
+const CTRL_C: u8 = 0x03;
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+// How many instructions to run between checks for Ctrl-C
+const POLL_INTERVAL: usize = 1024;
+
+// CSRs exposed through the target description (fflags, frm and fcsr are in the fpu feature)
+#[rustfmt::skip]
+const CSRS: [(&str, u32); 40] = [
+    ("cycle", 0xc00), ("time", 0xc01), ("instret", 0xc02),
+    ("cycleh", 0xc80), ("timeh", 0xc81), ("instreth", 0xc82),
+    ("sstatus", 0x100), ("sie", 0x104), ("stvec", 0x105), ("scounteren", 0x106),
+    ("senvcfg", 0x10a), ("sscratch", 0x140), ("sepc", 0x141), ("scause", 0x142),
+    ("stval", 0x143), ("sip", 0x144), ("stimecmp", 0x14d), ("stimecmph", 0x15d),
+    ("satp", 0x180),
+    ("mvendorid", 0xf11), ("marchid", 0xf12), ("mimpid", 0xf13), ("mhartid", 0xf14),
+    ("mstatus", 0x300), ("misa", 0x301), ("medeleg", 0x302), ("mideleg", 0x303),
+    ("mie", 0x304), ("mtvec", 0x305), ("mcounteren", 0x306), ("menvcfg", 0x30a),
+    ("mcountinhibit", 0x320), ("mscratch", 0x340), ("mepc", 0x341), ("mcause", 0x342),
+    ("mtval", 0x343), ("mip", 0x344),
+    ("mcycle", 0xb00), ("minstret", 0xb02), ("mcycleh", 0xb80),
+];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GdbExit {
+    Detach,
+    Kill,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Reply(String),
+    Step,
+    Continue,
+    Detach,
+    Kill,
+}
+
+pub struct GdbStub<W: Write> {
+    rx: Receiver<u8>,
+    tx: W,
+    sw_breakpoints: HashSet<u32>,
+    hw_breakpoints: HashSet<u32>,
+    no_ack: bool,
+    translate: bool,
+}
+
+// Wait for a single GDB connection on localhost and serve it until it detaches or kills the target
+pub fn serve(sys: &mut System, port: u16) -> io::Result<GdbExit> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    log_with_pc(
+        sys,
+        &format!("{} on 127.0.0.1:{port}", "Wait for gdb".blue()),
+        false,
+    );
+    let (stream, peer) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    log_with_pc(
+        sys,
+        &format!("{} from {peer}", "Gdb connected".blue()),
+        false,
+    );
+
+    // Bytes are forwarded by a thread, so that Ctrl-C can be polled while running
+    let (tx, rx) = mpsc::channel();
+    let mut reader = stream.try_clone()?;
+    thread::spawn(move || {
+        let mut buf = [0; 1024];
+        while let Ok(len @ 1..) = reader.read(&mut buf) {
+            if buf[..len].iter().any(|b| tx.send(*b).is_err()) {
+                break;
+            }
+        }
+    });
+
+    GdbStub::new(rx, stream).run(sys)
+}
+
+impl<W: Write> GdbStub<W> {
+    pub fn new(rx: Receiver<u8>, tx: W) -> GdbStub<W> {
+        GdbStub {
+            rx,
+            tx,
+            sw_breakpoints: HashSet::new(),
+            hw_breakpoints: HashSet::new(),
+            no_ack: false,
+            translate: true,
+        }
+    }
+
+    pub fn run(&mut self, sys: &mut System) -> io::Result<GdbExit> {
+        // A closed connection is treated as a detach
+        while let Some(packet) = self.recv_packet()? {
+            let reply = match self.handle_packet(sys, &packet) {
+                Action::Reply(reply) => reply,
+                Action::Step => stopped(sys).unwrap_or_else(|| {
+                    let _ = sys.step();
+                    stopped(sys).unwrap_or_else(|| stop_reply(SIGTRAP))
+                }),
+                Action::Continue => self.resume(sys),
+                Action::Detach => {
+                    self.send_packet("OK")?;
+                    return Ok(GdbExit::Detach);
+                }
+                Action::Kill => return Ok(GdbExit::Kill),
+            };
+            self.send_packet(&reply)?;
+        }
+        Ok(GdbExit::Detach)
+    }
+
+    // Run until a breakpoint is hit, GDB interrupts or the machine stops, returning the stop reply
+    fn resume(&mut self, sys: &mut System) -> String {
+        let mut count = 0;
+        loop {
+            if let Some(reply) = stopped(sys) {
+                return reply;
+            }
+            // Always step once, so that we can continue from a breakpoint
+            if count > 0 {
+                if self.is_breakpoint(sys.pc()) {
+                    return stop_reply(SIGTRAP);
+                }
+                if count % POLL_INTERVAL == 0 && self.interrupted() {
+                    return stop_reply(SIGINT);
+                }
+            }
+            let _ = sys.step();
+            count += 1;
+        }
+    }
+
+    fn is_breakpoint(&self, addr: u32) -> bool {
+        self.sw_breakpoints.contains(&addr) || self.hw_breakpoints.contains(&addr)
+    }
+
+    fn interrupted(&mut self) -> bool {
+        loop {
+            match self.rx.try_recv() {
+                Ok(CTRL_C) | Err(TryRecvError::Disconnected) => return true,
+                Ok(_) => continue,
+                Err(TryRecvError::Empty) => return false,
+            }
+        }
+    }
+
+    // ---- Packet layer ----
+    fn recv_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acks and stray Ctrl-C until the start of a packet
+            loop {
+                match self.rx.recv() {
+                    Ok(b'$') => break,
+                    Ok(_) => continue,
+                    Err(_) => return Ok(None),
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.rx.recv() {
+                    Ok(b'#') => break,
+                    Ok(b) => data.push(b),
+                    Err(_) => return Ok(None),
+                }
+            }
+            let mut sum = [0; 2];
+            for s in sum.iter_mut() {
+                match self.rx.recv() {
+                    Ok(b) => *s = b,
+                    Err(_) => return Ok(None),
+                }
+            }
+            let valid = parse_hex(&String::from_utf8_lossy(&sum)) == Some(checksum(&data) as u32);
+            if !self.no_ack {
+                self.tx.write_all(if valid { b"+" } else { b"-" })?;
+                self.tx.flush()?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        loop {
+            write!(self.tx, "${data}#{:02x}", checksum(data.as_bytes()))?;
+            self.tx.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+            // Resend until acknowledged
+            loop {
+                match self.rx.recv() {
+                    Ok(b'+') => return Ok(()),
+                    Ok(b'-') => break,
+                    Ok(_) => continue,
+                    Err(_) => return Ok(()),
+                }
+            }
+        }
+    }
+
+    // ---- Command layer ----
+    fn handle_packet(&mut self, sys: &mut System, packet: &str) -> Action {
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+        let reply = match cmd {
+            "?" => Some(stop_reply(SIGTRAP)),
+            "g" => Some((0..=REG_PC).map(|n| read_reg(sys, n).unwrap()).collect()),
+            "G" => self.write_regs(sys, args),
+            "p" => parse_hex(args).and_then(|n| read_reg(sys, n as usize)),
+            "P" => args
+                .split_once('=')
+                .and_then(|(n, val)| Some((parse_hex(n)?, from_hex(val)?)))
+                .and_then(|(n, val)| write_reg(sys, n as usize, &val))
+                .map(|_| "OK".to_string()),
+            "m" => parse_addr_len(args).and_then(|(addr, len)| self.read_mem(sys, addr, len)),
+            "M" => args
+                .split_once(':')
+                .and_then(|(range, data)| Some((parse_addr_len(range)?, from_hex(data)?)))
+                .and_then(|((addr, len), data)| {
+                    (data.len() == len).then_some(())?;
+                    self.write_mem(sys, addr, &data)
+                })
+                .map(|_| "OK".to_string()),
+            "Z" | "z" => self.set_breakpoint(args, cmd == "Z"),
+            "s" | "c" => {
+                // An optional address to resume at
+                if !args.is_empty() {
+                    match parse_hex(args) {
+                        Some(addr) => *sys.pc_mut() = addr,
+                        None => return Action::Reply(error_reply()),
+                    }
+                }
+                return if cmd == "s" {
+                    Action::Step
+                } else {
+                    Action::Continue
+                };
+            }
+            "D" => return Action::Detach,
+            "k" => return Action::Kill,
+            "H" => Some("OK".to_string()),
+            "T" => Some("OK".to_string()),
+            "q" | "Q" => self.query(sys, packet),
+            // Unsupported (including vCont and X), GDB falls back to the basic packets
+            _ => Some(String::new()),
+        };
+        Action::Reply(reply.unwrap_or_else(error_reply))
+    }
+
+    fn query(&mut self, sys: &mut System, packet: &str) -> Option<String> {
+        let (name, args) = packet.split_once([':', ',']).unwrap_or((packet, ""));
+        match name {
+            "qSupported" => {
+                Some("PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string())
+            }
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                Some("OK".to_string())
+            }
+            "qAttached" => Some("1".to_string()),
+            "qC" => Some("QC1".to_string()),
+            "qfThreadInfo" => Some("m1".to_string()),
+            "qsThreadInfo" => Some("l".to_string()),
+            "qXfer" => {
+                let args = args.strip_prefix("features:read:target.xml:")?;
+                let (offset, len) = parse_addr_len(args)?;
+                Some(xfer_chunk(&target_xml(), offset as usize, len))
+            }
+            "qRcmd" => {
+                let cmd = String::from_utf8(from_hex(args)?).ok()?;
+                self.monitor(sys, cmd.trim())
+            }
+            _ => Some(String::new()),
+        }
+    }
+
+    // Commands sent with "monitor <cmd>"
+    fn monitor(&mut self, sys: &mut System, cmd: &str) -> Option<String> {
+        let words: Vec<_> = cmd.split_whitespace().collect();
+        match cmd {
+            "translate on" => self.translate = true,
+            "translate off" => self.translate = false,
+            "tlb" => {
+                let tlb = &sys.ctrl.tlb;
+                let stats = format!(
+                    "TLB hits: {}, misses: {}, flushes: {}\n",
+                    tlb.hits, tlb.misses, tlb.flushes
+                );
+                return Some(to_hex(stats.as_bytes()));
+            }
+            // Link status of the network device in a virtio slot ("link <slot> up|down")
+            _ if words.len() == 3 && words[0] == "link" => {
+                let virtio = sys.mem.virtio.get_mut(words[1].parse::<usize>().ok()?)?;
+                let up = match words[2] {
+                    "up" => true,
+                    "down" => false,
+                    _ => return None,
+                };
+                if !virtio.set_link_up(up) {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+        log_with_pc(sys, &format!("{} {cmd}", "Gdb monitor".blue()), false);
+        Some("OK".to_string())
+    }
+
+    fn write_regs(&mut self, sys: &mut System, args: &str) -> Option<String> {
+        let data = from_hex(args)?;
+        for (n, val) in data.chunks(4).take(REG_PC + 1).enumerate() {
+            write_reg(sys, n, val)?;
+        }
+        Some("OK".to_string())
+    }
+
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> Option<String> {
+        let mut args = args.split(',');
+        let kind = args.next()?;
+        let addr = parse_hex(args.next()?)?;
+        let set = match kind {
+            "0" => &mut self.sw_breakpoints,
+            "1" => &mut self.hw_breakpoints,
+            // Watchpoints are not supported
+            _ => return Some(String::new()),
+        };
+        if insert {
+            set.insert(addr);
+        } else {
+            set.remove(&addr);
+        }
+        Some("OK".to_string())
+    }
+
+    // ---- Memory access ----
+    // Only RAM and the device tree are accessed, and the translation leaves no trace
+    fn debug_addr(&self, sys: &System, addr: u32, atype: AccessType) -> Option<u64> {
+        if self.translate {
+            translate_debug(sys, addr, atype).ok()
+        } else {
+            Some(addr as u64)
+        }
+    }
+
+    fn read_mem(&self, sys: &System, addr: u32, len: usize) -> Option<String> {
+        let mut data = Vec::with_capacity(len);
+        for i in 0..len {
+            let vaddr = addr.wrapping_add(i as u32);
+            let byte = self
+                .debug_addr(sys, vaddr, AccessType::Load)
+                .and_then(|paddr| sys.mem.peek_u8(paddr));
+            match byte {
+                Some(b) => data.push(b),
+                None => break,
+            }
+        }
+        // A partial read is fine, as long as the first byte can be read
+        (len == 0 || !data.is_empty()).then(|| to_hex(&data))
+    }
+
+    fn write_mem(&self, sys: &mut System, addr: u32, data: &[u8]) -> Option<()> {
+        for (i, b) in data.iter().enumerate() {
+            let vaddr = addr.wrapping_add(i as u32);
+            let paddr = self.debug_addr(sys, vaddr, AccessType::Store)?;
+            sys.mem.poke_u8(paddr, *b)?;
+        }
+        Some(())
+    }
+}
+
+// ---- Registers ----
+fn read_reg(sys: &mut System, n: usize) -> Option<String> {
+    match n {
+        0..=31 => Some(to_hex(&sys.reg(&Reg::new(n as u8)).to_le_bytes())),
+        REG_PC => Some(to_hex(&sys.pc().to_le_bytes())),
+        REG_F0..=64 => Some(to_hex(
+            &sys.freg(&Reg::new((n - REG_F0) as u8)).to_le_bytes(),
+        )),
+        REG_PRIV => Some(to_hex(&[sys.ctrl.privilege.to_int() as u8])),
+        _ => {
+            let csr = csr_from_regnum(n)?;
+            let val = csr_read_debug(sys, &csr).ok()?;
+            Some(to_hex(&val.to_le_bytes()))
+        }
+    }
+}
+
+fn write_reg(sys: &mut System, n: usize, val: &[u8]) -> Option<()> {
+    let mut bytes = [0; 8];
+    bytes.get_mut(..val.len())?.copy_from_slice(val);
+    let val = u64::from_le_bytes(bytes);
+    match n {
+        // x0 is hardwired to zero
+        0 => {}
+        1..=31 => *sys.reg_mut(&Reg::new(n as u8)) = val as i32,
+        REG_PC => *sys.pc_mut() = val as u32,
+        REG_F0..=64 => *sys.freg_mut(&Reg::new((n - REG_F0) as u8)) = val,
+        REG_PRIV => sys.ctrl.privilege = MPriv::from(val as u32)?,
+        _ => {
+            let csr = csr_from_regnum(n)?;
+            csr_write_debug(sys, &csr, val as u32).ok()?;
+        }
+    }
+    Some(())
+}
+
+fn csr_from_regnum(n: usize) -> Option<CsrReg> {
+    let addr = n.checked_sub(REG_CSR0).filter(|addr| *addr < 0x1000)?;
+    CsrReg::from(addr as i32)
+}
+
+// ---- Target description ----
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><architecture>riscv:rv32</architecture>",
+    );
+    xml += "<feature name=\"org.gnu.gdb.riscv.cpu\">";
+    let abi_names = [
+        "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+        "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+        "t5", "t6",
+    ];
+    for (n, name) in abi_names.iter().enumerate() {
+        let ty = match n {
+            1 => "code_ptr",
+            2 | 8 => "data_ptr",
+            _ => "int",
+        };
+        xml += &format!("<reg name=\"{name}\" bitsize=\"32\" type=\"{ty}\" regnum=\"{n}\"/>");
+    }
+    xml += &format!("<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"{REG_PC}\"/>");
+    xml += "</feature><feature name=\"org.gnu.gdb.riscv.fpu\">";
+    for n in 0..32 {
+        let regnum = REG_F0 + n;
+        xml += &format!(
+            "<reg name=\"f{n}\" bitsize=\"64\" type=\"ieee_double\" regnum=\"{regnum}\"/>"
+        );
+    }
+    for (name, addr) in [("fflags", 0x001), ("frm", 0x002), ("fcsr", 0x003)] {
+        let regnum = REG_CSR0 + addr;
+        xml += &format!("<reg name=\"{name}\" bitsize=\"32\" type=\"int\" regnum=\"{regnum}\" group=\"float\"/>");
+    }
+    xml += "</feature><feature name=\"org.gnu.gdb.riscv.csr\">";
+    for (name, addr) in CSRS {
+        let regnum = REG_CSR0 + addr as usize;
+        xml += &format!(
+            "<reg name=\"{name}\" bitsize=\"32\" type=\"int\" regnum=\"{regnum}\" group=\"csr\"/>"
+        );
+    }
+    xml += "</feature><feature name=\"org.gnu.gdb.riscv.virtual\">";
+    xml += &format!(
+        "<reg name=\"priv\" bitsize=\"8\" type=\"int\" regnum=\"{REG_PRIV}\" group=\"system\"/>"
+    );
+    xml += "</feature></target>";
+    xml
+}
+
+// A chunk of a qXfer object, 'm' if there's more to read and 'l' for the last one
+fn xfer_chunk(data: &str, offset: usize, len: usize) -> String {
+    let start = offset.min(data.len());
+    let end = start.saturating_add(len).min(data.len());
+    let prefix = if end < data.len() { 'm' } else { 'l' };
+    format!("{prefix}{}", &data[start..end])
+}
+
+// ---- Helpers ----
+fn stop_reply(signal: u8) -> String {
+    format!("S{signal:02x}")
+}
+
+// An exit ends the process for GDB, a reset starts it again and stops at the first instruction
+fn stopped(sys: &mut System) -> Option<String> {
+    match sys.stop? {
+        StopReason::Exit(code) => Some(format!("W{:02x}", code as u8)),
+        StopReason::Reset => {
+            sys.reset();
+            Some(stop_reply(SIGTRAP))
+        }
+    }
+}
+
+fn error_reply() -> String {
+    "E01".to_string()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+fn parse_addr_len(s: &str) -> Option<(u32, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)? as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Nic, NicBackend},
+        sys::control::SatpMode,
+        Config,
+    };
+    use bytesize::ByteSize;
+    use mpsc::Sender;
+
+    fn make_stub() -> (GdbStub<Vec<u8>>, Sender<u8>) {
+        let (tx, rx) = mpsc::channel();
+        let mut stub = GdbStub::new(rx, Vec::new());
+        stub.no_ack = true;
+        (stub, tx)
+    }
+
+    fn reply(stub: &mut GdbStub<Vec<u8>>, sys: &mut System, packet: &str) -> String {
+        match stub.handle_packet(sys, packet) {
+            Action::Reply(r) => r,
+            a => panic!("Unexpected action {:?}", a),
+        }
+    }
+
+    fn write_u32(sys: &mut System, addr: usize, val: u32) {
+        sys.mem.ram.as_u8_mut()[addr..addr + 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    #[test]
+    fn test_packet_framing() {
+        let (mut stub, tx) = make_stub();
+        stub.no_ack = false;
+        for b in b"+$qAttached#8f$m0,4#00$m0,4#fd" {
+            tx.send(*b).unwrap();
+        }
+        assert_eq!(stub.recv_packet().unwrap(), Some("qAttached".to_string()));
+        // The bad checksum is rejected and the packet is received again
+        assert_eq!(stub.recv_packet().unwrap(), Some("m0,4".to_string()));
+        assert_eq!(stub.tx, b"+-+");
+
+        tx.send(b'+').unwrap();
+        stub.send_packet("OK").unwrap();
+        assert_eq!(&stub.tx[3..], b"$OK#9a");
+
+        drop(tx);
+        assert_eq!(stub.recv_packet().unwrap(), None);
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut sys = System::new();
+        let (mut stub, _tx) = make_stub();
+        *sys.reg_mut(&Reg::new(1)) = 0x12345678;
+        *sys.pc_mut() = 0x100;
+
+        let g = reply(&mut stub, &mut sys, "g");
+        assert_eq!(g.len(), 33 * 8);
+        assert_eq!(&g[..16], "0000000078563412");
+        assert_eq!(&g[256..], "00010000");
+
+        assert_eq!(reply(&mut stub, &mut sys, "P2=efbeadde"), "OK");
+        assert_eq!(sys.reg(&Reg::new(2)), 0xdeadbeef_u32 as i32);
+        assert_eq!(reply(&mut stub, &mut sys, "P0=01000000"), "OK");
+        assert_eq!(reply(&mut stub, &mut sys, "p0"), "00000000");
+        assert_eq!(reply(&mut stub, &mut sys, "P20=04010000"), "OK");
+        assert_eq!(sys.pc(), 0x104);
+
+        // Floating-point registers are 64 bits
+        assert_eq!(reply(&mut stub, &mut sys, "P22=0000000000000840"), "OK");
+        assert_eq!(sys.freg(&Reg::new(1)), 3.0_f64.to_bits());
+        assert_eq!(reply(&mut stub, &mut sys, "p22"), "0000000000000840");
+
+        let mut regs = String::new();
+        for i in 0..33 {
+            regs += &to_hex(&(i as u32).to_le_bytes());
+        }
+        assert_eq!(reply(&mut stub, &mut sys, &format!("G{regs}")), "OK");
+        assert_eq!(sys.reg(&Reg::new(31)), 31);
+        assert_eq!(sys.pc(), 32);
+    }
+
+    #[test]
+    fn test_csr_registers() {
+        let mut sys = System::new();
+        let (mut stub, _tx) = make_stub();
+        sys.ctrl.privilege = MPriv::U;
+        sys.ctrl.mscratch = 0xcafe;
+
+        // mscratch (0x340) is readable from the debugger in any privilege mode
+        let regnum = format!("{:x}", REG_CSR0 + 0x340);
+        assert_eq!(
+            reply(&mut stub, &mut sys, &format!("p{regnum}")),
+            "feca0000"
+        );
+        assert_eq!(
+            reply(&mut stub, &mut sys, &format!("P{regnum}=0d000000")),
+            "OK"
+        );
+        assert_eq!(sys.ctrl.mscratch, 0xd);
+        assert_eq!(sys.ctrl.privilege, MPriv::U);
+
+        let regnum = format!("{:x}", REG_PRIV);
+        assert_eq!(reply(&mut stub, &mut sys, &format!("p{regnum}")), "00");
+        assert_eq!(reply(&mut stub, &mut sys, &format!("P{regnum}=03")), "OK");
+        assert_eq!(sys.ctrl.privilege, MPriv::M);
+
+        // Unknown CSR
+        let regnum = format!("{:x}", REG_CSR0 + 0x7ff);
+        assert_eq!(reply(&mut stub, &mut sys, &format!("p{regnum}")), "E01");
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut cfg = Config::new();
+        cfg.size = ByteSize::kib(64);
+        let mut sys = System::from_config(cfg);
+        let (mut stub, _tx) = make_stub();
+
+        assert_eq!(reply(&mut stub, &mut sys, "M10,4:01020304"), "OK");
+        assert_eq!(reply(&mut stub, &mut sys, "m10,6"), "010203040000");
+        assert_eq!(reply(&mut stub, &mut sys, "M10,4:0102"), "E01");
+        // A read past the end of RAM is cut short
+        assert_eq!(reply(&mut stub, &mut sys, "mfffe,4"), "0000");
+        assert_eq!(reply(&mut stub, &mut sys, "m10000,4"), "E01");
+
+        // VA page 1 maps to PA page 5
+        write_u32(&mut sys, 0x8004, (9 << 10) | 0x01);
+        write_u32(&mut sys, 0x9004, (5 << 10) | 0xcf);
+        sys.ctrl.privilege = MPriv::S;
+        sys.ctrl.satp_mode = SatpMode::Sv32;
+        sys.ctrl.satp_ppn = 8;
+        write_u32(&mut sys, 0x5010, 0xdeadbeef);
+        assert_eq!(reply(&mut stub, &mut sys, "m401010,4"), "efbeadde");
+        assert_eq!(reply(&mut stub, &mut sys, "m10,4"), "E01");
+
+        // Read-only without A/D: readable but not writable, and the PTE stays untouched
+        write_u32(&mut sys, 0x9004, (5 << 10) | 0x03);
+        assert_eq!(reply(&mut stub, &mut sys, "m401010,4"), "efbeadde");
+        assert_eq!(reply(&mut stub, &mut sys, "M401010,1:00"), "E01");
+        assert_eq!(sys.mem.peek_u32(0x9004), Some((5 << 10) | 0x03));
+        assert_eq!(sys.ctrl.tlb.misses, 0);
+
+        let cmd = to_hex(b"translate off");
+        assert_eq!(reply(&mut stub, &mut sys, &format!("qRcmd,{cmd}")), "OK");
+        assert_eq!(reply(&mut stub, &mut sys, "m10,4"), "01020304");
+
+        // Devices are not accessed
+        assert_eq!(reply(&mut stub, &mut sys, "mc0000000,1"), "E01");
+        assert_eq!(reply(&mut stub, &mut sys, "Mc0000000,1:41"), "E01");
+    }
+
+    #[test]
+    fn test_step_and_breakpoints() {
+        let mut sys = System::new();
+        let (mut stub, tx) = make_stub();
+        for i in 0..8 {
+            write_u32(&mut sys, i * 4, 0x00150513); // addi a0, a0, 1
+        }
+
+        assert_eq!(stub.handle_packet(&mut sys, "s"), Action::Step);
+        assert_eq!(reply(&mut stub, &mut sys, "Z0,10,4"), "OK");
+        assert_eq!(reply(&mut stub, &mut sys, "Z1,18,4"), "OK");
+        assert_eq!(stub.handle_packet(&mut sys, "c"), Action::Continue);
+        assert_eq!(stub.resume(&mut sys), stop_reply(SIGTRAP));
+        assert_eq!(sys.pc(), 0x10);
+        assert_eq!(sys.reg(&Reg::new(10)), 4);
+
+        // Continue from a breakpoint
+        assert_eq!(reply(&mut stub, &mut sys, "z0,10,4"), "OK");
+        assert_eq!(stub.resume(&mut sys), stop_reply(SIGTRAP));
+        assert_eq!(sys.pc(), 0x18);
+
+        // Interrupted by Ctrl-C while spinning on "j ."
+        assert_eq!(reply(&mut stub, &mut sys, "z1,18,4"), "OK");
+        write_u32(&mut sys, 0x18, 0x0000006f);
+        tx.send(CTRL_C).unwrap();
+        assert_eq!(stub.resume(&mut sys), stop_reply(SIGINT));
+        assert_eq!(sys.pc(), 0x18);
+
+        assert_eq!(stub.handle_packet(&mut sys, "c20"), Action::Continue);
+        assert_eq!(sys.pc(), 0x20);
+    }
+
+    #[test]
+    fn test_stop() {
+        let mut sys = System::new();
+        let (mut stub, _tx) = make_stub();
+        write_u32(&mut sys, 0x0, 0xc00012b7); // lui t0, 0xc0001
+        write_u32(&mut sys, 0x4, 0x00033337); // lui t1, 0x33
+        write_u32(&mut sys, 0x8, 0x33330313); // addi t1, t1, 0x333
+        write_u32(&mut sys, 0xc, 0x0062a023); // sw t1, 0(t0)
+        write_u32(&mut sys, 0x10, 0x0000006f); // j .
+
+        // The finisher fails with code 3, also when GDB tries to go on
+        assert_eq!(stub.resume(&mut sys), "W03");
+        assert_eq!(sys.pc(), 0x10);
+        assert_eq!(stub.resume(&mut sys), "W03");
+        assert_eq!(sys.stop, Some(StopReason::Exit(3)));
+
+        // A reset stops at the first instruction of the fresh machine
+        sys.stop = Some(StopReason::Reset);
+        *sys.pc_mut() = 0x10;
+        assert_eq!(stub.resume(&mut sys), stop_reply(SIGTRAP));
+        assert_eq!((sys.stop, sys.pc()), (None, 0));
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut sys = System::new();
+        let (mut stub, _tx) = make_stub();
+        stub.no_ack = false;
+
+        assert!(
+            reply(&mut stub, &mut sys, "qSupported:multiprocess+;swbreak+")
+                .contains("qXfer:features:read+")
+        );
+        assert_eq!(reply(&mut stub, &mut sys, "QStartNoAckMode"), "OK");
+        assert!(stub.no_ack);
+        assert_eq!(reply(&mut stub, &mut sys, "?"), "S05");
+        assert_eq!(reply(&mut stub, &mut sys, "vMustReplyEmpty"), "");
+        assert_eq!(reply(&mut stub, &mut sys, "qRcmd,666f6f"), "E01");
+        let stats = reply(&mut stub, &mut sys, &format!("qRcmd,{}", to_hex(b"tlb")));
+        assert_eq!(
+            from_hex(&stats).unwrap(),
+            b"TLB hits: 0, misses: 0, flushes: 0\n"
+        );
+        // No network device in slot 0
+        let cmd = to_hex(b"link 0 down");
+        assert_eq!(reply(&mut stub, &mut sys, &format!("qRcmd,{cmd}")), "E01");
+        let mut cfg = Config::new();
+        cfg.size = ByteSize::mib(1);
+        cfg.nic = vec![Nic {
+            backend: NicBackend::Echo,
+            mac: None,
+            link_up: true,
+        }];
+        let mut sys_nic = System::from_config(cfg);
+        let reply_nic = reply(&mut stub, &mut sys_nic, &format!("qRcmd,{cmd}"));
+        assert_eq!(reply_nic, "OK");
+        assert!(sys_nic.mem.virtio[0].is_interrupt_set());
+
+        // Read the target description in chunks
+        let mut xml = String::new();
+        loop {
+            let chunk = reply(
+                &mut stub,
+                &mut sys,
+                &format!("qXfer:features:read:target.xml:{:x},100", xml.len()),
+            );
+            xml += &chunk[1..];
+            if chunk.starts_with('l') {
+                break;
+            }
+        }
+        assert_eq!(xml, target_xml());
+        assert!(xml.contains(
+            "<reg name=\"mstatus\" bitsize=\"32\" type=\"int\" regnum=\"833\" group=\"csr\"/>"
+        ));
+
+        assert_eq!(stub.handle_packet(&mut sys, "D"), Action::Detach);
+        assert_eq!(stub.handle_packet(&mut sys, "k"), Action::Kill);
+    }
+}
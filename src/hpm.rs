@@ -0,0 +1,376 @@
+use crate::{
+    instr::Instr,
+    snapshot::{SnapshotReader, SnapshotWriter},
+    sys::control::MPriv,
+    tlb::Tlb,
+    trap::TrapCause,
+    Exception, Interrupt, Result,
+};
+use std::io;
+
+// mhpmcounter3-31
+pub const HPM_COUNTERS: usize = 29;
+const HPM_FIRST: usize = 3;
+
+// Bits of the counters in mcounteren, scounteren, mcountinhibit and scountovf
+pub const HPM_MASK: u32 = 0xffff_fff8;
+
+// mhpmeventh (Sscofpmf): overflow and mode inhibit bits (VSINH and VUINH are zero without H)
+const EVENT_OF: u32 = 1 << 31;
+const EVENT_MINH: u32 = 1 << 30;
+const EVENT_SINH: u32 = 1 << 29;
+const EVENT_UINH: u32 = 1 << 28;
+const EVENTH_MASK: u32 = EVENT_OF | EVENT_MINH | EVENT_SINH | EVENT_UINH;
+
+// Selectors of mhpmevent (unknown ones are not counted and read as 0)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HpmEvent {
+    Cycles,
+    Instrs,        // Retired instructions
+    Loads,         // Scalar and vector loads
+    Stores,        // Scalar and vector stores
+    Atomics,       // AMOs, LR and SC
+    Branches,      // Conditional branches
+    TakenBranches, // Conditional branches which are taken
+    Jumps,         // jal and jalr
+    FpInstrs,      // Floating-point computations
+    VectorInstrs,  // Vector instructions, including loads and stores
+    Exceptions,    // Exceptions taken
+    Interrupts,    // Interrupts taken
+    TlbMisses,     // Instruction and data TLB misses
+    TlbFlushes,    // sfence.vma
+    MCycles,       // Cycles in M-mode
+    SCycles,       // Cycles in S-mode
+    UCycles,       // Cycles in U-mode
+    // Traps of a given cause (0x100 + exception code, 0x200 + interrupt code)
+    Trap(TrapCause),
+}
+
+// What the current step did, counted when it retires
+#[derive(Debug)]
+struct HpmStep {
+    privilege: MPriv, // Privilege mode the step started in
+    events: u32,      // Events of the executed instruction (bit per selector)
+    tlb_misses: u64,  // TLB statistics at the start
+    tlb_flushes: u64,
+}
+
+// Programmable hardware performance monitor counters
+#[derive(Debug)]
+pub struct Hpm {
+    counters: [u64; HPM_COUNTERS],
+    events: [Option<HpmEvent>; HPM_COUNTERS], // mhpmevent
+    flags: [u32; HPM_COUNTERS],               // mhpmeventh
+    pub en_m: u32,                            // mcounteren (bits 3-31)
+    pub en_s: u32,                            // scounteren (bits 3-31)
+    pub inhibit: u32,                         // mcountinhibit (bits 3-31)
+    step: HpmStep,
+}
+
+impl HpmEvent {
+    pub fn from(code: u32) -> Option<HpmEvent> {
+        match code {
+            0x01 => Some(HpmEvent::Cycles),
+            0x02 => Some(HpmEvent::Instrs),
+            0x03 => Some(HpmEvent::Loads),
+            0x04 => Some(HpmEvent::Stores),
+            0x05 => Some(HpmEvent::Atomics),
+            0x06 => Some(HpmEvent::Branches),
+            0x07 => Some(HpmEvent::TakenBranches),
+            0x08 => Some(HpmEvent::Jumps),
+            0x09 => Some(HpmEvent::FpInstrs),
+            0x0a => Some(HpmEvent::VectorInstrs),
+            0x0b => Some(HpmEvent::Exceptions),
+            0x0c => Some(HpmEvent::Interrupts),
+            0x0d => Some(HpmEvent::TlbMisses),
+            0x0e => Some(HpmEvent::TlbFlushes),
+            0x0f => Some(HpmEvent::MCycles),
+            0x10 => Some(HpmEvent::SCycles),
+            0x11 => Some(HpmEvent::UCycles),
+            0x100..=0x1ff => Some(HpmEvent::Trap(TrapCause::Exception(Exception::from(
+                code & 0xff,
+            )?))),
+            0x200..=0x2ff => Some(HpmEvent::Trap(TrapCause::Interrupt(Interrupt::from(
+                code & 0xff,
+            )?))),
+            _ => None,
+        }
+    }
+
+    pub fn to_int(&self) -> u32 {
+        match self {
+            HpmEvent::Cycles => 0x01,
+            HpmEvent::Instrs => 0x02,
+            HpmEvent::Loads => 0x03,
+            HpmEvent::Stores => 0x04,
+            HpmEvent::Atomics => 0x05,
+            HpmEvent::Branches => 0x06,
+            HpmEvent::TakenBranches => 0x07,
+            HpmEvent::Jumps => 0x08,
+            HpmEvent::FpInstrs => 0x09,
+            HpmEvent::VectorInstrs => 0x0a,
+            HpmEvent::Exceptions => 0x0b,
+            HpmEvent::Interrupts => 0x0c,
+            HpmEvent::TlbMisses => 0x0d,
+            HpmEvent::TlbFlushes => 0x0e,
+            HpmEvent::MCycles => 0x0f,
+            HpmEvent::SCycles => 0x10,
+            HpmEvent::UCycles => 0x11,
+            HpmEvent::Trap(TrapCause::Exception(e)) => 0x100 + e.to_int(),
+            HpmEvent::Trap(TrapCause::Interrupt(i)) => 0x200 + i.to_int(),
+        }
+    }
+
+    // Bit in the events of a step (not for TLB and trap cause events)
+    fn bit(&self) -> u32 {
+        1 << self.to_int()
+    }
+}
+
+impl Default for Hpm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hpm {
+    pub fn new() -> Hpm {
+        Hpm {
+            counters: [0; HPM_COUNTERS],
+            events: [None; HPM_COUNTERS],
+            flags: [0; HPM_COUNTERS],
+            en_m: 0,
+            en_s: 0,
+            inhibit: 0,
+            step: HpmStep {
+                privilege: MPriv::M,
+                events: 0,
+                tlb_misses: 0,
+                tlb_flushes: 0,
+            },
+        }
+    }
+
+    // Counters are accessed by their CSR number (3-31)
+    pub fn read_counter(&self, i: usize) -> u64 {
+        self.counters[i - HPM_FIRST]
+    }
+
+    pub fn write_counter(&mut self, i: usize, val: u64) {
+        self.counters[i - HPM_FIRST] = val;
+    }
+
+    pub fn read_event(&self, i: usize) -> u32 {
+        self.events[i - HPM_FIRST].map_or(0, |e| e.to_int())
+    }
+
+    pub fn write_event(&mut self, i: usize, val: u32) {
+        self.events[i - HPM_FIRST] = HpmEvent::from(val);
+    }
+
+    pub fn read_eventh(&self, i: usize) -> u32 {
+        self.flags[i - HPM_FIRST]
+    }
+
+    pub fn write_eventh(&mut self, i: usize, val: u32) {
+        self.flags[i - HPM_FIRST] = val & EVENTH_MASK;
+    }
+
+    // scountovf: the OF bits of all the counters
+    pub fn overflows(&self) -> u32 {
+        self.flags.iter().enumerate().fold(0, |ovf, (i, f)| {
+            ovf | ((f & EVENT_OF != 0) as u32) << (i + HPM_FIRST)
+        })
+    }
+
+    pub fn begin(&mut self, privilege: MPriv, tlb: &Tlb) {
+        self.step = HpmStep {
+            privilege,
+            events: 0,
+            tlb_misses: tlb.misses,
+            tlb_flushes: tlb.flushes,
+        };
+    }
+
+    // Record the kind of an instruction which was executed without trapping
+    pub fn executed(&mut self, instr: &Instr, taken: bool) {
+        use HpmEvent::*;
+        let events: &[HpmEvent] = match instr {
+            Instr::Load(..) | Instr::LoadFp(..) => &[Loads],
+            Instr::Store(..) | Instr::StoreFp(..) => &[Stores],
+            Instr::Atomic(..) => &[Atomics],
+            Instr::Branch(..) if taken => &[Branches, TakenBranches],
+            Instr::Branch(..) => &[Branches],
+            Instr::Jal(_) | Instr::Jalr(_) => &[Jumps],
+            Instr::Fma(..) | Instr::OpFp(..) => &[FpInstrs],
+            Instr::LoadV(..) => &[Loads, VectorInstrs],
+            Instr::StoreV(..) => &[Stores, VectorInstrs],
+            Instr::SetVl(_) | Instr::OpV(..) => &[VectorInstrs],
+            _ => &[],
+        };
+        self.step.events = events.iter().fold(0, |bits, e| bits | e.bit());
+    }
+
+    // Count the events of the step. Returns true if a counter overflowed while its OF
+    // bit was clear, which raises a local counter overflow interrupt.
+    pub fn retire(&mut self, res: &Result, tlb: &Tlb) -> bool {
+        if self.events.iter().all(Option::is_none) {
+            return false;
+        }
+        let HpmStep {
+            privilege,
+            mut events,
+            tlb_misses,
+            tlb_flushes,
+        } = self.step;
+        let (mode_cycles, mode_inhibit) = match privilege {
+            MPriv::M => (HpmEvent::MCycles, EVENT_MINH),
+            MPriv::S => (HpmEvent::SCycles, EVENT_SINH),
+            MPriv::U => (HpmEvent::UCycles, EVENT_UINH),
+        };
+        events |= HpmEvent::Cycles.bit() | mode_cycles.bit();
+        events |= match res.map_err(|trap| trap.cause) {
+            Ok(()) => HpmEvent::Instrs.bit(),
+            Err(TrapCause::Exception(_)) => HpmEvent::Exceptions.bit(),
+            Err(TrapCause::Interrupt(_)) => HpmEvent::Interrupts.bit(),
+        };
+
+        let mut raised = false;
+        for (i, event) in self.events.iter().enumerate() {
+            let Some(event) = event else { continue };
+            let flags = &mut self.flags[i];
+            if self.inhibit & 1 << (i + HPM_FIRST) != 0 || *flags & mode_inhibit != 0 {
+                continue;
+            }
+            let n = match event {
+                HpmEvent::TlbMisses => tlb.misses.wrapping_sub(tlb_misses),
+                HpmEvent::TlbFlushes => tlb.flushes.wrapping_sub(tlb_flushes),
+                HpmEvent::Trap(cause) => res.is_err_and(|trap| trap.cause == *cause) as u64,
+                _ => (events & event.bit() != 0) as u64,
+            };
+            let (val, overflow) = self.counters[i].overflowing_add(n);
+            self.counters[i] = val;
+            if overflow && *flags & EVENT_OF == 0 {
+                *flags |= EVENT_OF;
+                raised = true;
+            }
+        }
+        raised
+    }
+
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        self.counters.iter().for_each(|c| w.put_u64(*c));
+        self.events
+            .iter()
+            .for_each(|e| w.put_u32(e.map_or(0, |e| e.to_int())));
+        self.flags.iter().for_each(|f| w.put_u32(*f));
+        w.put_u32(self.en_m);
+        w.put_u32(self.en_s);
+        w.put_u32(self.inhibit);
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        for c in self.counters.iter_mut() {
+            *c = r.get_u64()?;
+        }
+        for e in self.events.iter_mut() {
+            *e = HpmEvent::from(r.get_u32()?);
+        }
+        for f in self.flags.iter_mut() {
+            *f = r.get_u32()? & EVENTH_MASK;
+        }
+        self.en_m = r.get_u32()? & HPM_MASK;
+        self.en_s = r.get_u32()? & HPM_MASK;
+        self.inhibit = r.get_u32()? & HPM_MASK;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instr::{format::BType, funct::BranchFunct, reg::Reg},
+        Trap,
+    };
+
+    fn branch() -> Instr {
+        let op = BType {
+            rs1: Reg::new(1),
+            rs2: Reg::new(2),
+            imm: 8,
+        };
+        Instr::Branch(op, BranchFunct::Eq)
+    }
+
+    #[test]
+    fn test_event_codes() {
+        for code in (0..0x300).filter(|c| HpmEvent::from(*c).is_some()) {
+            assert_eq!(HpmEvent::from(code).unwrap().to_int(), code);
+        }
+        let page_fault = TrapCause::Exception(Exception::LoadPageFault);
+        assert_eq!(HpmEvent::from(0x10d), Some(HpmEvent::Trap(page_fault)));
+        assert_eq!(HpmEvent::from(0x10a), None);
+
+        // Unknown selectors read as 0
+        let mut hpm = Hpm::new();
+        hpm.write_event(3, 0xdead);
+        assert_eq!(hpm.read_event(3), 0);
+        hpm.write_eventh(3, 0xffff_ffff);
+        assert_eq!(hpm.read_eventh(3), 0xf000_0000);
+    }
+
+    #[test]
+    fn test_count() {
+        let mut hpm = Hpm::new();
+        let tlb = Tlb::new();
+        hpm.write_event(3, 0x06); // Branches
+        hpm.write_event(4, 0x07); // Taken branches
+        hpm.write_event(5, 0x10); // S-mode cycles
+        hpm.write_event(6, 0x102); // Illegal instructions
+        hpm.write_event(31, 0x02); // Instructions
+        let step = |hpm: &mut Hpm, privilege, taken, res: Result| {
+            hpm.begin(privilege, &tlb);
+            if res.is_ok() {
+                hpm.executed(&branch(), taken);
+            }
+            hpm.retire(&res, &tlb)
+        };
+
+        step(&mut hpm, MPriv::S, false, Ok(()));
+        step(&mut hpm, MPriv::S, true, Ok(()));
+        let illegal = Trap::from_exception(Exception::IllegalInstr, 0);
+        step(&mut hpm, MPriv::M, false, Err(illegal));
+        let counts: Vec<_> = [3, 4, 5, 6, 31].map(|i| hpm.read_counter(i)).into();
+        assert_eq!(counts, [2, 1, 2, 1, 2]);
+
+        // Inhibited by mcountinhibit or by the mode
+        hpm.inhibit = 1 << 3;
+        hpm.write_eventh(4, EVENT_SINH);
+        step(&mut hpm, MPriv::S, true, Ok(()));
+        assert_eq!((hpm.read_counter(3), hpm.read_counter(4)), (2, 1));
+        step(&mut hpm, MPriv::U, true, Ok(()));
+        assert_eq!((hpm.read_counter(3), hpm.read_counter(4)), (2, 2));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut hpm = Hpm::new();
+        let tlb = Tlb::new();
+        hpm.write_event(7, 0x01); // Cycles
+        hpm.write_counter(7, u64::MAX - 1);
+
+        // Only the overflow which sets OF raises the interrupt
+        hpm.begin(MPriv::M, &tlb);
+        assert!(!hpm.retire(&Ok(()), &tlb));
+        assert!(hpm.retire(&Ok(()), &tlb));
+        assert_eq!(hpm.read_counter(7), 0);
+        assert_eq!(hpm.overflows(), 1 << 7);
+        hpm.write_counter(7, u64::MAX);
+        assert!(!hpm.retire(&Ok(()), &tlb));
+
+        hpm.write_eventh(7, 0);
+        hpm.write_counter(7, u64::MAX);
+        assert!(hpm.retire(&Ok(()), &tlb));
+    }
+}
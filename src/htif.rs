@@ -0,0 +1,220 @@
+use crate::{
+    elf::SymbolTable,
+    linux::{guest_range, read_guest, write_guest},
+    sys::{log_with_pc, StopReason},
+    System,
+};
+use colored::*;
+
+// Host-target interface (as Spike and the riscv-tests):
+// - tohost and fromhost are 64-bit words in RAM, polled after each step
+// - A command is device (bits 63:56), command (bits 55:48) and payload (bits 47:0)
+// - Device 0 (syscalls): an odd payload exits with payload >> 1, otherwise it points to
+//   a proxy kernel syscall (number and arguments as 64-bit words, result in the first one)
+// - Device 1 (console): command 0 reads a character, and command 1 writes one
+// - The host clears tohost once a command is handled, and replies in fromhost
+
+// Offset of fromhost when only tohost is known
+const FROMHOST_OFFSET: u32 = 0x40;
+
+const DEV_SYSCALL: u64 = 0;
+const DEV_CONSOLE: u64 = 1;
+const CMD_GETCHAR: u64 = 0;
+const CMD_PUTCHAR: u64 = 1;
+
+// Proxy kernel syscalls
+const SYS_WRITE: u64 = 64;
+const SYS_EXIT: u64 = 93;
+const ENOSYS: i64 = 38;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Htif {
+    pub tohost: u32,
+    pub fromhost: u32,
+}
+
+impl Htif {
+    // Use the configured address, or the tohost and fromhost symbols
+    pub fn new(tohost: Option<u32>, symbols: &SymbolTable) -> Option<Htif> {
+        let tohost = tohost.or_else(|| symbols.find("tohost").map(|s| s.addr))?;
+        let fromhost = symbols
+            .find("fromhost")
+            .map_or(tohost.wrapping_add(FROMHOST_OFFSET), |s| s.addr);
+        Some(Htif { tohost, fromhost })
+    }
+
+    pub fn poll(&self, sys: &mut System) {
+        let cmd = match read_u64(sys, self.tohost) {
+            Some(cmd) if cmd != 0 => cmd,
+            _ => return,
+        };
+        let (dev, op, payload) = (cmd >> 56, (cmd >> 48) & 0xff, cmd & 0xffff_ffff_ffff);
+        let reply = match (dev, op) {
+            (DEV_SYSCALL, _) if payload & 1 != 0 => {
+                let code = (payload >> 1) as i32;
+                log_with_pc(sys, &format!("{} with code {code}", "Exit".blue()), true);
+                sys.stop = Some(StopReason::Exit(code));
+                None
+            }
+            (DEV_SYSCALL, _) => {
+                syscall(sys, payload as u32);
+                Some(1)
+            }
+            (DEV_CONSOLE, CMD_GETCHAR) => {
+                // Wait until a character is received
+                let Some(c) = sys.mem.uart.console_read() else {
+                    return;
+                };
+                Some(DEV_CONSOLE << 56 | c as u64)
+            }
+            (DEV_CONSOLE, CMD_PUTCHAR) => {
+                sys.mem.uart.console_write(&[payload as u8]);
+                Some(DEV_CONSOLE << 56 | CMD_PUTCHAR << 48)
+            }
+            _ => {
+                log_with_pc(
+                    sys,
+                    &format!("{} 0x{cmd:016x}", "Unsupported HTIF command".yellow()),
+                    true,
+                );
+                None
+            }
+        };
+        write_u64(sys, self.tohost, 0);
+        if let Some(reply) = reply {
+            write_u64(sys, self.fromhost, reply);
+        }
+    }
+}
+
+fn syscall(sys: &mut System, addr: u32) {
+    let Some(args) = (0..4)
+        .map(|i| read_u64(sys, addr.wrapping_add(8 * i)))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return;
+    };
+    let res = match args[0] {
+        SYS_WRITE if matches!(args[1], 1 | 2) => {
+            match read_guest(sys, args[2] as u32, args[3] as u32) {
+                Ok(buf) => {
+                    sys.mem.uart.console_write(&buf);
+                    args[3] as i64
+                }
+                Err(errno) => -errno as i64,
+            }
+        }
+        SYS_EXIT => {
+            sys.stop = Some(StopReason::Exit(args[1] as i32));
+            0
+        }
+        _ => -ENOSYS,
+    };
+    write_u64(sys, addr, res as u64);
+}
+
+// Polled after each step, so straight from RAM without allocating
+fn read_u64(sys: &System, addr: u32) -> Option<u64> {
+    let range = guest_range(sys, addr, 8).ok()?;
+    let bytes: [u8; 8] = sys.mem.ram.as_u8()[range].try_into().unwrap();
+    Some(u64::from_le_bytes(bytes))
+}
+
+fn write_u64(sys: &mut System, addr: u32, val: u64) {
+    let _ = write_guest(sys, addr, &val.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{elf::Symbol, Config};
+    use bytesize::ByteSize;
+
+    const TOHOST: u32 = 0x100;
+    const FROMHOST: u32 = 0x140;
+
+    fn make_sys() -> System {
+        let mut cfg = Config::new();
+        cfg.size = ByteSize::mib(1);
+        cfg.tohost = Some(TOHOST);
+        System::from_config(cfg)
+    }
+
+    fn send(sys: &mut System, cmd: u64) -> u64 {
+        write_u64(sys, TOHOST, cmd);
+        sys.htif.unwrap().poll(sys);
+        assert_eq!(read_u64(sys, TOHOST), Some(0));
+        read_u64(sys, FROMHOST).unwrap()
+    }
+
+    #[test]
+    fn test_new() {
+        let symbols = |names: &[&str]| {
+            SymbolTable::new(
+                names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| Symbol {
+                        name: name.to_string(),
+                        addr: 0x8000_1000 + 0x100 * i as u32,
+                        size: 8,
+                    })
+                    .collect(),
+            )
+        };
+        assert_eq!(Htif::new(None, &symbols(&["main"])), None);
+        assert_eq!(
+            Htif::new(None, &symbols(&["tohost", "fromhost"])),
+            Some(Htif {
+                tohost: 0x8000_1000,
+                fromhost: 0x8000_1100
+            })
+        );
+        assert_eq!(
+            Htif::new(Some(0x1000), &SymbolTable::default()),
+            Some(Htif {
+                tohost: 0x1000,
+                fromhost: 0x1040
+            })
+        );
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut sys = make_sys();
+        // Console putchar
+        assert_eq!(
+            send(&mut sys, 1 << 56 | 1 << 48 | b'\n' as u64),
+            1 << 56 | 1 << 48
+        );
+
+        // Proxy kernel write to stdout
+        for (i, arg) in [SYS_WRITE, 1, 0x300, 1].iter().enumerate() {
+            write_u64(&mut sys, 0x200 + 8 * i as u32, *arg);
+        }
+        write_guest(&mut sys, 0x300, b"\n").unwrap();
+        assert_eq!(send(&mut sys, 0x200), 1);
+        assert_eq!(read_u64(&sys, 0x200), Some(1));
+        write_u64(&mut sys, 0x200, 1234);
+        send(&mut sys, 0x200);
+        assert_eq!(read_u64(&sys, 0x200), Some(-ENOSYS as u64));
+        assert_eq!(sys.stop, None);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut sys = make_sys();
+        // li t0, 7; sw t0, 0x100(zero)
+        write_guest(
+            &mut sys,
+            0,
+            &[0x93, 0x02, 0x70, 0x00, 0x23, 0x20, 0x50, 0x10],
+        )
+        .unwrap();
+        assert_eq!(
+            crate::run_for_or_until_stop(&mut sys, 10),
+            Some(StopReason::Exit(3))
+        );
+        assert_eq!(sys.pc(), 8);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::Instr;
+
+// Number of entries, indexed by bits of the physical PC
+const ICACHE_ENTRIES: usize = 1 << 14;
+const INDEX_MASK: u64 = ICACHE_ENTRIES as u64 - 1;
+
+// Decoded instructions keyed by physical PC (direct-mapped).
+// Only instructions in RAM that don't cross a page boundary are cached,
+// so a store to RAM is the only way to change the code of an entry.
+#[derive(Debug)]
+pub struct DecodeCache {
+    entries: Vec<Option<Entry>>,
+    generation: u32, // Entries of an older generation are invalid
+}
+
+#[derive(Debug)]
+struct Entry {
+    paddr: u64,
+    generation: u32,
+    code: u32,
+    instr: Instr,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache {
+            entries: (0..ICACHE_ENTRIES).map(|_| None).collect(),
+            generation: 0,
+        }
+    }
+
+    pub fn get(&self, paddr: u64) -> Option<(u32, &Instr)> {
+        match &self.entries[index(paddr)] {
+            Some(e) if e.paddr == paddr && e.generation == self.generation => {
+                Some((e.code, &e.instr))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, paddr: u64, code: u32, instr: Instr) {
+        let generation = self.generation;
+        self.entries[index(paddr)] = Some(Entry {
+            paddr,
+            generation,
+            code,
+            instr,
+        });
+    }
+
+    // Invalidate the instructions overlapping a store of len bytes
+    pub fn invalidate(&mut self, paddr: u64, len: u64) {
+        // A 4-byte instruction may start 2 bytes before the store
+        let start = (paddr & !1).saturating_sub(2);
+        for addr in (start..paddr + len).step_by(2) {
+            let entry = &mut self.entries[index(addr)];
+            if entry.as_ref().is_some_and(|e| e.paddr == addr) {
+                *entry = None;
+            }
+        }
+    }
+
+    // Invalidate everything
+    pub fn flush(&mut self) {
+        if self.generation == u32::MAX {
+            self.entries.iter_mut().for_each(|e| *e = None);
+            self.generation = 0;
+        } else {
+            self.generation += 1;
+        }
+    }
+}
+
+fn index(paddr: u64) -> usize {
+    ((paddr >> 1) & INDEX_MASK) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalidate() {
+        let mut cache = DecodeCache::new();
+        for addr in [0x100, 0x104, 0x108, 0x10a] {
+            cache.insert(addr, 0x0100000f, Instr::Pause);
+        }
+
+        // A store to 0x106 hits the instruction at 0x104 (but not 0x108)
+        cache.invalidate(0x106, 2);
+        assert!(cache.get(0x100).is_some());
+        assert!(cache.get(0x104).is_none());
+        assert!(cache.get(0x108).is_some());
+
+        cache.invalidate(0x10b, 1);
+        assert!(cache.get(0x108).is_none());
+        assert!(cache.get(0x10a).is_none());
+
+        // Same index, different address
+        assert!(cache.get(0x100 + 2 * ICACHE_ENTRIES as u64).is_none());
+        cache.invalidate(0x100 + 2 * ICACHE_ENTRIES as u64, 4);
+        assert_eq!(cache.get(0x100), Some((0x0100000f, &Instr::Pause)));
+    }
+
+    #[test]
+    fn test_flush() {
+        let mut cache = DecodeCache::new();
+        cache.insert(0x100, 0x0100000f, Instr::Pause);
+        cache.flush();
+        assert!(cache.get(0x100).is_none());
+        cache.insert(0x100, 0x0100000f, Instr::Pause);
+        assert!(cache.get(0x100).is_some());
+
+        cache.generation = u32::MAX;
+        cache.insert(0x104, 0x0100000f, Instr::Pause);
+        cache.flush();
+        assert!(cache.get(0x104).is_none());
+    }
+}
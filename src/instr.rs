@@ -5,8 +5,9 @@ pub mod reg;
 
 use format::*;
 use funct::*;
+use reg::Reg;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Instr {
     Op(RType, OpFunct),
     OpImm(IType, OpImmFunct),
@@ -18,7 +19,39 @@ pub enum Instr {
     Jalr(IType),
     Branch(BType, BranchFunct),
     Atomic(RType, AtomicFunct),
-    Fence,
+    Fence(FenceType, FenceFunct),
+    Pause,
+    Mop(Reg),
     Env(EnvFunct),
     Csr(CsrType, CsrFunct),
+    LoadFp(IType, LoadFpFunct),
+    StoreFp(SType, StoreFpFunct),
+    Fma(R4Type, FpFmt, FmaFunct),
+    OpFp(RType, FpFmt, OpFpFunct),
+    SetVl(VSetVlType),
+    OpV(OpVType, OpVFunct),
+    LoadV(VMemType, VMemFunct),
+    StoreV(VMemType, VMemFunct),
+}
+
+impl Instr {
+    // Instructions of the bitmanip extensions (Zba, Zbb, Zbc and Zbs)
+    pub fn is_bitmanip(&self) -> bool {
+        match self {
+            Instr::Op(_, OpFunct::B(_)) => true,
+            Instr::OpImm(_, f) => !matches!(
+                f,
+                OpImmFunct::Add
+                    | OpImmFunct::Slt
+                    | OpImmFunct::Sltu
+                    | OpImmFunct::And
+                    | OpImmFunct::Or
+                    | OpImmFunct::Xor
+                    | OpImmFunct::Sll
+                    | OpImmFunct::Srl
+                    | OpImmFunct::Sra
+            ),
+            _ => false,
+        }
+    }
 }
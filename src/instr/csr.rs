@@ -1,12 +1,25 @@
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CsrReg {
     U(CsrRegU),
     S(CsrRegS),
     M(CsrRegM),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CsrRegU {
+    // Unprivileged floating-point
+    FFlags,
+    Frm,
+    FCsr,
+    // Unprivileged vector
+    VStart,
+    VXSat,
+    VXRm,
+    VCsr,
+    Vl,
+    VType,
+    VLenB,
+    // Unprivileged counter/timer
     Cycle,
     Time,
     InstRet,
@@ -17,7 +30,7 @@ pub enum CsrRegU {
     HpmCounterh(u8),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CsrRegS {
     // Supervisor trap setup
     SStatus,
@@ -32,11 +45,16 @@ pub enum CsrRegS {
     SCause,
     STval,
     SIp,
+    // Supervisor timer compare (Sstc)
+    STimeCmp,
+    STimeCmph,
+    // Supervisor counter overflow (Sscofpmf)
+    SCountOvf,
     // Supervisor protection and translation
     SAtp,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CsrRegM {
     // Machine information
     MVendorId,
@@ -83,6 +101,18 @@ impl CsrReg {
     pub fn from(imm: i32) -> Option<CsrReg> {
         let imm = imm & 0xfff;
         match imm {
+            // Unprivileged floating-point
+            0x001 => Some(Self::U(CsrRegU::FFlags)),
+            0x002 => Some(Self::U(CsrRegU::Frm)),
+            0x003 => Some(Self::U(CsrRegU::FCsr)),
+            // Unprivileged vector
+            0x008 => Some(Self::U(CsrRegU::VStart)),
+            0x009 => Some(Self::U(CsrRegU::VXSat)),
+            0x00a => Some(Self::U(CsrRegU::VXRm)),
+            0x00f => Some(Self::U(CsrRegU::VCsr)),
+            0xc20 => Some(Self::U(CsrRegU::Vl)),
+            0xc21 => Some(Self::U(CsrRegU::VType)),
+            0xc22 => Some(Self::U(CsrRegU::VLenB)),
             // Unprivileged counter/timer
             0xc00 => Some(Self::U(CsrRegU::Cycle)),
             0xc01 => Some(Self::U(CsrRegU::Time)),
@@ -105,6 +135,11 @@ impl CsrReg {
             0x142 => Some(Self::S(CsrRegS::SCause)),
             0x143 => Some(Self::S(CsrRegS::STval)),
             0x144 => Some(Self::S(CsrRegS::SIp)),
+            // Supervisor timer compare (Sstc)
+            0x14d => Some(Self::S(CsrRegS::STimeCmp)),
+            0x15d => Some(Self::S(CsrRegS::STimeCmph)),
+            // Supervisor counter overflow (Sscofpmf)
+            0xda0 => Some(Self::S(CsrRegS::SCountOvf)),
             // Supervisor protection and translation
             0x180 => Some(Self::S(CsrRegS::SAtp)),
             // Machine information
@@ -145,7 +180,7 @@ impl CsrReg {
             // Machine counter setup
             0x320 => Some(Self::M(CsrRegM::MCountInhibit)),
             i @ 0x323..=0x33f => Some(Self::M(CsrRegM::MHpmEvent((i - 0x320) as u8))),
-            i @ 0x723..=0x73f => Some(Self::M(CsrRegM::MHpmEventh((i - 0x3720) as u8))),
+            i @ 0x723..=0x73f => Some(Self::M(CsrRegM::MHpmEventh((i - 0x720) as u8))),
             _ => None,
         }
     }
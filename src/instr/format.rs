@@ -1,54 +1,96 @@
 use super::reg::Reg;
 use super::csr::CsrReg;
+use super::funct::{funct3, VSrc, VTypeSrc, VlSrc};
 use super::CsrSrc;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct RType {
     pub rd: Reg,
     pub rs1: Reg,
     pub rs2: Reg,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct R4Type {
+    pub rd: Reg,
+    pub rs1: Reg,
+    pub rs2: Reg,
+    pub rs3: Reg,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct IType {
     pub rd: Reg,
     pub rs1: Reg,
     pub imm: i32,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct SType {
     pub rs1: Reg,
     pub rs2: Reg,
     pub imm: i32,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+// Predecessor and successor sets of a fence (see the FENCE_* bits)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FenceType {
+    pub pred: u8,
+    pub succ: u8,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct BType {
     pub rs1: Reg,
     pub rs2: Reg,
     pub imm: i32,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct UType {
     pub rd: Reg,
     pub imm: i32,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct JType {
     pub rd: Reg,
     pub imm: i32,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct CsrType {
     pub rd: Reg,
     pub src: CsrSrc,
     pub csr: CsrReg,
 }
 
+// Vector arithmetic (vm is set if unmasked)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OpVType {
+    pub vd: Reg,
+    pub vs2: Reg,
+    pub src: VSrc,
+    pub vm: bool,
+}
+
+// Vector loads and stores (vd is vs3 for stores, rs2 is the stride or the index register vs2)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VMemType {
+    pub vd: Reg,
+    pub rs1: Reg,
+    pub rs2: Reg,
+    pub vm: bool,
+}
+
+// vsetvli, vsetivli and vsetvl
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VSetVlType {
+    pub rd: Reg,
+    pub avl: VlSrc,
+    pub vtype: VTypeSrc,
+}
+
 const I_MASK: u32 = (1 << 12) - 1;
 const I_SHAMT_MASK: u32 = (1 << 5) - 1;
 
@@ -91,6 +133,16 @@ impl RType {
     }
 }
 
+impl R4Type {
+    pub fn from(code: u32) -> R4Type {
+        let rd = Reg::extract_rd(code);
+        let rs1 = Reg::extract_rs1(code);
+        let rs2 = Reg::extract_rs2(code);
+        let rs3 = Reg::extract_rs3(code);
+        R4Type { rd, rs1, rs2, rs3 }
+    }
+}
+
 impl IType {
     pub fn from(code: u32) -> IType {
         let rd = Reg::extract_rd(code);
@@ -118,6 +170,15 @@ impl SType {
     }
 }
 
+impl FenceType {
+    pub fn from(code: u32) -> FenceType {
+        FenceType {
+            pred: ((code >> 24) & 0xf) as u8,
+            succ: ((code >> 20) & 0xf) as u8,
+        }
+    }
+}
+
 impl BType {
     pub fn from(code: u32) -> BType {
         let rs1 = Reg::extract_rs1(code);
@@ -151,6 +212,59 @@ impl JType {
     }
 }
 
+impl OpVType {
+    pub fn from(code: u32) -> OpVType {
+        let vd = Reg::extract_rd(code);
+        let vs2 = Reg::extract_rs2(code);
+        let rs1 = Reg::extract_rs1(code);
+        let vm = (code >> 25) & 1 != 0;
+        // OPIVV, OPFVV and OPMVV take a vector, OPIVI an immediate, OPFVF a floating-point
+        // scalar and the others an integer one
+        let src = match funct3(code) {
+            0b000..=0b010 => VSrc::V(rs1),
+            0b011 => VSrc::I(((rs1.index() as i32) << 27) >> 27),
+            0b101 => VSrc::F(rs1),
+            _ => VSrc::X(rs1),
+        };
+        OpVType { vd, vs2, src, vm }
+    }
+}
+
+impl VMemType {
+    pub fn from(code: u32) -> VMemType {
+        let vd = Reg::extract_rd(code);
+        let rs1 = Reg::extract_rs1(code);
+        let rs2 = Reg::extract_rs2(code);
+        let vm = (code >> 25) & 1 != 0;
+        VMemType { vd, rs1, rs2, vm }
+    }
+}
+
+impl VSetVlType {
+    pub fn from(code: u32) -> Option<VSetVlType> {
+        let rd = Reg::extract_rd(code);
+        let rs1 = Reg::extract_rs1(code);
+        match code >> 30 {
+            0b00 | 0b01 => Some(VSetVlType {
+                rd,
+                avl: VlSrc::Reg(rs1),
+                vtype: VTypeSrc::Imm((code >> 20) & 0x7ff),
+            }),
+            0b11 => Some(VSetVlType {
+                rd,
+                avl: VlSrc::Imm(rs1.index()),
+                vtype: VTypeSrc::Imm((code >> 20) & 0x3ff),
+            }),
+            _ if (code >> 25) & 0x3f == 0 => Some(VSetVlType {
+                rd,
+                avl: VlSrc::Reg(rs1),
+                vtype: VTypeSrc::Reg(Reg::extract_rs2(code)),
+            }),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
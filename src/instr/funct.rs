@@ -11,13 +11,15 @@ pub fn funct7(code: u32) -> u8 {
     ((code >> 25) & FUNCT7_MASK) as u8
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum OpFunct {
     I(OpIFunct),
     M(OpMFunct),
+    B(OpBFunct),
+    Cond(OpCondFunct),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum OpIFunct {
     Add,
     Sub,
@@ -31,7 +33,7 @@ pub enum OpIFunct {
     Sra,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum OpMFunct {
     Mul,
     Mulh,
@@ -43,7 +45,52 @@ pub enum OpMFunct {
     Remu,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+// Bit manipulation (Zba, Zbb, Zbc and Zbs)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OpBFunct {
+    Sh1add,
+    Sh2add,
+    Sh3add,
+    Andn,
+    Orn,
+    Xnor,
+    Max,
+    Maxu,
+    Min,
+    Minu,
+    Rol,
+    Ror,
+    ZextH,
+    Clmul,
+    Clmulh,
+    Clmulr,
+    Bclr,
+    Bext,
+    Binv,
+    Bset,
+}
+
+// Integer conditional operations (Zicond)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OpCondFunct {
+    CzeroEqz,
+    CzeroNez,
+}
+
+// Bits of the ordering sets of fences: device input and output, memory reads and writes
+pub const FENCE_I: u8 = 0b1000;
+pub const FENCE_O: u8 = 0b0100;
+pub const FENCE_R: u8 = 0b0010;
+pub const FENCE_W: u8 = 0b0001;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FenceFunct {
+    Fence,
+    Tso,
+    I, // fence.i (Zifencei)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum OpImmFunct {
     Add,
     Slt,
@@ -54,9 +101,23 @@ pub enum OpImmFunct {
     Sll,
     Srl,
     Sra,
+    // Zbb (the unary ones ignore the immediate)
+    Clz,
+    Ctz,
+    Cpop,
+    SextB,
+    SextH,
+    OrcB,
+    Rev8,
+    Rori,
+    // Zbs
+    Bclri,
+    Bexti,
+    Binvi,
+    Bseti,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum LoadFunct {
     B,
     H,
@@ -65,14 +126,14 @@ pub enum LoadFunct {
     Hu,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum StoreFunct {
     B,
     H,
     W,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum BranchFunct {
     Eq,
     Ne,
@@ -82,19 +143,19 @@ pub enum BranchFunct {
     Geu,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum AtomicFunct {
     LrSc(LrScFunct),
     Amo(AmoFunct),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum LrScFunct {
     Lr,
     Sc,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum AmoFunct {
     Add,
     Swap,
@@ -107,24 +168,312 @@ pub enum AmoFunct {
     Maxu,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum EnvFunct {
     Call,
     Break,
     Sret,
     Mret,
     Wfi,
-    SfenceVma,
+    SfenceVma(Reg, Reg), // Virtual address and ASID
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CsrFunct {
     Rw,
     Rs,
     Rc,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum LoadFpFunct {
+    W,
+    D,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum StoreFpFunct {
+    W,
+    D,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FpFmt {
+    S,
+    D,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FpRm {
+    Rne,
+    Rtz,
+    Rdn,
+    Rup,
+    Rmm,
+    Dyn, // Use frm
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FmaFunct {
+    Madd(FpRm),
+    Msub(FpRm),
+    Nmsub(FpRm),
+    Nmadd(FpRm),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OpFpFunct {
+    Add(FpRm),
+    Sub(FpRm),
+    Mul(FpRm),
+    Div(FpRm),
+    Sqrt(FpRm),
+    Sgnj,
+    Sgnjn,
+    Sgnjx,
+    Min,
+    Max,
+    CvtFF(FpRm), // Between S and D
+    CvtWF(FpRm),
+    CvtWuF(FpRm),
+    CvtFW(FpRm),
+    CvtFWu(FpRm),
+    Eq,
+    Lt,
+    Le,
+    Class,
+    MvXF,
+    MvFX,
+}
+
+// Element width of vector loads and stores (EEW)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VEew {
+    E8,
+    E16,
+    E32,
+}
+
+// Vector loads and stores, with the number of fields for segments
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum VMemFunct {
+    Unit(VEew, u8),
+    FaultFirst(VEew, u8), // Unit-stride fault-only-first (loads only)
+    Strided(VEew, u8),
+    Indexed(VEew, u8), // Ordered and unordered alike, since elements are accessed in order
+    Whole(VEew, u8),   // Number of whole registers
+    Mask,              // vlm.v and vsm.v
+}
+
+// Vector arithmetic (Zve32x, and Zve32f for the floating-point operations)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OpVFunct {
+    // Single-width
+    Add,
+    Sub,
+    Rsub,
+    Minu,
+    Min,
+    Maxu,
+    Max,
+    And,
+    Or,
+    Xor,
+    Sll,
+    Srl,
+    Sra,
+    Mul,
+    Mulh,
+    Mulhu,
+    Mulhsu,
+    Divu,
+    Div,
+    Remu,
+    Rem,
+    Macc,
+    Nmsac,
+    Madd,
+    Nmsub,
+    // Carry and borrow (Madc and Msbc without carry-in if unmasked)
+    Adc,
+    Madc,
+    Sbc,
+    Msbc,
+    // Comparison (to a mask)
+    Mseq,
+    Msne,
+    Msltu,
+    Mslt,
+    Msleu,
+    Msle,
+    Msgtu,
+    Msgt,
+    // Fixed-point
+    Saddu,
+    Sadd,
+    Ssubu,
+    Ssub,
+    Aaddu,
+    Aadd,
+    Asubu,
+    Asub,
+    Smul,
+    Ssrl,
+    Ssra,
+    // Widening (the W variants take a wide vs2)
+    Waddu,
+    Wadd,
+    Wsubu,
+    Wsub,
+    WadduW,
+    WaddW,
+    WsubuW,
+    WsubW,
+    Wmulu,
+    Wmulsu,
+    Wmul,
+    Wmaccu,
+    Wmacc,
+    Wmaccsu,
+    Wmaccus,
+    // Narrowing
+    Nsrl,
+    Nsra,
+    Nclipu,
+    Nclip,
+    // Extension from a fraction (2, 4 or 8) of SEW
+    Zext(u8),
+    Sext(u8),
+    // Reduction
+    Redsum,
+    Redand,
+    Redor,
+    Redxor,
+    Redminu,
+    Redmin,
+    Redmaxu,
+    Redmax,
+    Wredsumu,
+    Wredsum,
+    // Permutation
+    Merge,
+    Mv,
+    MvXS,
+    MvSX,
+    MvNr(u8), // Number of whole registers
+    Slideup,
+    Slidedown,
+    Slide1up,
+    Slide1down,
+    Rgather,
+    Rgatherei16,
+    Compress,
+    // Mask
+    Mand,
+    Mnand,
+    Mandn,
+    Mxor,
+    Mor,
+    Mnor,
+    Morn,
+    Mxnor,
+    Cpop,
+    First,
+    Msbf,
+    Msif,
+    Msof,
+    Iota,
+    Id,
+    // Floating-point
+    F(OpFVFunct),
+}
+
+// Vector single-precision arithmetic (Zve32f)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OpFVFunct {
+    // Single-width
+    Add,
+    Sub,
+    Rsub,
+    Mul,
+    Div,
+    Rdiv,
+    Min,
+    Max,
+    Sgnj,
+    Sgnjn,
+    Sgnjx,
+    Macc,
+    Nmacc,
+    Msac,
+    Nmsac,
+    Madd,
+    Nmadd,
+    Msub,
+    Nmsub,
+    // Unary
+    Sqrt,
+    Rsqrt7,
+    Rec7,
+    Class,
+    // Conversion (to and from 16-bit integers for the widening and narrowing ones)
+    CvtXuF,
+    CvtXF,
+    CvtFXu,
+    CvtFX,
+    CvtRtzXuF,
+    CvtRtzXF,
+    WcvtFXu,
+    WcvtFX,
+    NcvtXuF,
+    NcvtXF,
+    NcvtRtzXuF,
+    NcvtRtzXF,
+    // Comparison (to a mask)
+    Mfeq,
+    Mfne,
+    Mflt,
+    Mfle,
+    Mfgt,
+    Mfge,
+    // Reduction
+    Redusum,
+    Redosum,
+    Redmin,
+    Redmax,
+    // Permutation (as the integer ones, with a scalar from f[rs1])
+    Merge,
+    Mv,
+    MvFS,
+    MvSF,
+    Slide1up,
+    Slide1down,
+}
+
+// Second operand of vector arithmetic
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum VSrc {
+    V(Reg),
+    X(Reg),
+    F(Reg),
+    I(i32), // Sign-extended (only the lower 5 bits are used by shifts, slides and gathers)
+}
+
+// Application vector length of vsetvl
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum VlSrc {
+    Reg(Reg),
+    Imm(u8),
+}
+
+// New vtype of vsetvl
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum VTypeSrc {
+    Reg(Reg),
+    Imm(u32),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CsrSrc {
     Reg(Reg),
     Imm(u8),
@@ -155,6 +504,32 @@ impl OpFunct {
             (0b101, 0b0000001) => Some(OpFunct::M(OpMFunct::Divu)),
             (0b110, 0b0000001) => Some(OpFunct::M(OpMFunct::Rem)),
             (0b111, 0b0000001) => Some(OpFunct::M(OpMFunct::Remu)),
+            // OpB
+            (0b010, 0b0010000) => Some(OpFunct::B(OpBFunct::Sh1add)),
+            (0b100, 0b0010000) => Some(OpFunct::B(OpBFunct::Sh2add)),
+            (0b110, 0b0010000) => Some(OpFunct::B(OpBFunct::Sh3add)),
+            (0b111, 0b0100000) => Some(OpFunct::B(OpBFunct::Andn)),
+            (0b110, 0b0100000) => Some(OpFunct::B(OpBFunct::Orn)),
+            (0b100, 0b0100000) => Some(OpFunct::B(OpBFunct::Xnor)),
+            (0b110, 0b0000101) => Some(OpFunct::B(OpBFunct::Max)),
+            (0b111, 0b0000101) => Some(OpFunct::B(OpBFunct::Maxu)),
+            (0b100, 0b0000101) => Some(OpFunct::B(OpBFunct::Min)),
+            (0b101, 0b0000101) => Some(OpFunct::B(OpBFunct::Minu)),
+            (0b001, 0b0110000) => Some(OpFunct::B(OpBFunct::Rol)),
+            (0b101, 0b0110000) => Some(OpFunct::B(OpBFunct::Ror)),
+            (0b100, 0b0000100) if Reg::extract_rs2(code).index() == 0 => {
+                Some(OpFunct::B(OpBFunct::ZextH))
+            }
+            (0b001, 0b0000101) => Some(OpFunct::B(OpBFunct::Clmul)),
+            (0b011, 0b0000101) => Some(OpFunct::B(OpBFunct::Clmulh)),
+            (0b010, 0b0000101) => Some(OpFunct::B(OpBFunct::Clmulr)),
+            (0b001, 0b0100100) => Some(OpFunct::B(OpBFunct::Bclr)),
+            (0b101, 0b0100100) => Some(OpFunct::B(OpBFunct::Bext)),
+            (0b001, 0b0110100) => Some(OpFunct::B(OpBFunct::Binv)),
+            (0b001, 0b0010100) => Some(OpFunct::B(OpBFunct::Bset)),
+            // OpCond
+            (0b101, 0b0000111) => Some(OpFunct::Cond(OpCondFunct::CzeroEqz)),
+            (0b111, 0b0000111) => Some(OpFunct::Cond(OpCondFunct::CzeroNez)),
             _ => None,
         }
     }
@@ -164,6 +539,7 @@ impl OpImmFunct {
     pub fn from(code: u32) -> Option<OpImmFunct> {
         let f3 = funct3(code);
         let f7 = funct7(code);
+        let rs2 = Reg::extract_rs2(code).index(); // Selects the unary operation
         match (f3, f7) {
             (0b000, _) => Some(OpImmFunct::Add),
             (0b001, 0b0000000) => Some(OpImmFunct::Sll),
@@ -174,6 +550,35 @@ impl OpImmFunct {
             (0b101, 0b0100000) => Some(OpImmFunct::Sra),
             (0b110, _) => Some(OpImmFunct::Or),
             (0b111, _) => Some(OpImmFunct::And),
+            (0b001, 0b0110000) => match rs2 {
+                0b00000 => Some(OpImmFunct::Clz),
+                0b00001 => Some(OpImmFunct::Ctz),
+                0b00010 => Some(OpImmFunct::Cpop),
+                0b00100 => Some(OpImmFunct::SextB),
+                0b00101 => Some(OpImmFunct::SextH),
+                _ => None,
+            },
+            (0b101, 0b0010100) if rs2 == 0b00111 => Some(OpImmFunct::OrcB),
+            (0b101, 0b0110100) if rs2 == 0b11000 => Some(OpImmFunct::Rev8),
+            (0b101, 0b0110000) => Some(OpImmFunct::Rori),
+            (0b001, 0b0100100) => Some(OpImmFunct::Bclri),
+            (0b101, 0b0100100) => Some(OpImmFunct::Bexti),
+            (0b001, 0b0110100) => Some(OpImmFunct::Binvi),
+            (0b001, 0b0010100) => Some(OpImmFunct::Bseti),
+            _ => None,
+        }
+    }
+}
+
+impl FenceFunct {
+    pub fn from(code: u32) -> Option<FenceFunct> {
+        let f3 = funct3(code);
+        let fm = code >> 28;
+        match f3 {
+            // Reserved values of fm are regular fences
+            0b000 if fm == 0b1000 => Some(FenceFunct::Tso),
+            0b000 => Some(FenceFunct::Fence),
+            0b001 => Some(FenceFunct::I),
             _ => None,
         }
     }
@@ -244,6 +649,364 @@ impl AtomicFunct {
     }
 }
 
+impl LoadFpFunct {
+    pub fn from(code: u32) -> Option<LoadFpFunct> {
+        let f3 = funct3(code);
+        match f3 {
+            0b010 => Some(LoadFpFunct::W),
+            0b011 => Some(LoadFpFunct::D),
+            _ => None,
+        }
+    }
+}
+
+impl StoreFpFunct {
+    pub fn from(code: u32) -> Option<StoreFpFunct> {
+        let f3 = funct3(code);
+        match f3 {
+            0b010 => Some(StoreFpFunct::W),
+            0b011 => Some(StoreFpFunct::D),
+            _ => None,
+        }
+    }
+}
+
+impl VEew {
+    pub fn from(code: u32) -> Option<VEew> {
+        // EEW of 64 bits and more is reserved since ELEN is 32
+        let mew = (code >> 28) & 1;
+        match (mew, funct3(code)) {
+            (0, 0b000) => Some(VEew::E8),
+            (0, 0b101) => Some(VEew::E16),
+            (0, 0b110) => Some(VEew::E32),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        match self {
+            VEew::E8 => 1,
+            VEew::E16 => 2,
+            VEew::E32 => 4,
+        }
+    }
+}
+
+impl VMemFunct {
+    pub fn from(code: u32, is_load: bool) -> Option<VMemFunct> {
+        let eew = VEew::from(code)?;
+        let nf = ((code >> 29) & 0b111) as u8 + 1;
+        let vm = (code >> 25) & 1 != 0;
+        let mop = (code >> 26) & 0b11;
+        let umop = Reg::extract_rs2(code).index();
+        match (mop, umop) {
+            (0b00, 0b00000) => Some(VMemFunct::Unit(eew, nf)),
+            (0b00, 0b10000) if is_load => Some(VMemFunct::FaultFirst(eew, nf)),
+            // Whole register stores only have the EEW of 8 bits encoding
+            (0b00, 0b01000) if vm && nf.is_power_of_two() && (is_load || eew == VEew::E8) => {
+                Some(VMemFunct::Whole(eew, nf))
+            }
+            (0b00, 0b01011) if vm && nf == 1 && eew == VEew::E8 => Some(VMemFunct::Mask),
+            (0b00, _) => None,
+            (0b10, _) => Some(VMemFunct::Strided(eew, nf)),
+            _ => Some(VMemFunct::Indexed(eew, nf)),
+        }
+    }
+}
+
+impl OpVFunct {
+    pub fn from(code: u32) -> Option<OpVFunct> {
+        use OpVFunct::*;
+        let f3 = funct3(code);
+        let f6 = funct7(code) >> 1;
+        let vm = (code >> 25) & 1 != 0;
+        let vs1 = Reg::extract_rs1(code).index();
+        let vs2 = Reg::extract_rs2(code).index();
+        // Categories of funct3
+        const IVV: u8 = 0b000;
+        const FVV: u8 = 0b001;
+        const MVV: u8 = 0b010;
+        const IVI: u8 = 0b011;
+        const IVX: u8 = 0b100;
+        const FVF: u8 = 0b101;
+        const MVX: u8 = 0b110;
+        if f3 == FVV || f3 == FVF {
+            return OpFVFunct::from(code).map(F);
+        }
+        let i = f3 == IVV || f3 == IVX || f3 == IVI;
+        let (ivx, m) = (f3 != IVI, f3 == MVV || f3 == MVX);
+        match f6 {
+            // OPIVV, OPIVX and OPIVI
+            0b000000 if i => Some(Add),
+            0b000010 if i && ivx => Some(Sub),
+            0b000011 if i && f3 != IVV => Some(Rsub),
+            0b000100 if i && ivx => Some(Minu),
+            0b000101 if i && ivx => Some(Min),
+            0b000110 if i && ivx => Some(Maxu),
+            0b000111 if i && ivx => Some(Max),
+            0b001001 if i => Some(And),
+            0b001010 if i => Some(Or),
+            0b001011 if i => Some(Xor),
+            0b001100 if i => Some(Rgather),
+            0b001110 if f3 == IVV => Some(Rgatherei16),
+            0b001110 if i => Some(Slideup),
+            0b001111 if i && f3 != IVV => Some(Slidedown),
+            0b010000 if i && !vm => Some(Adc),
+            0b010001 if i => Some(Madc),
+            0b010010 if i && ivx && !vm => Some(Sbc),
+            0b010011 if i && ivx => Some(Msbc),
+            0b010111 if i && !vm => Some(Merge),
+            0b010111 if i && vs2 == 0 => Some(Mv),
+            0b011000 if i => Some(Mseq),
+            0b011001 if i => Some(Msne),
+            0b011010 if i && ivx => Some(Msltu),
+            0b011011 if i && ivx => Some(Mslt),
+            0b011100 if i => Some(Msleu),
+            0b011101 if i => Some(Msle),
+            0b011110 if i && f3 != IVV => Some(Msgtu),
+            0b011111 if i && f3 != IVV => Some(Msgt),
+            0b100000 if i => Some(Saddu),
+            0b100001 if i => Some(Sadd),
+            0b100010 if i && ivx => Some(Ssubu),
+            0b100011 if i && ivx => Some(Ssub),
+            0b100101 if i => Some(Sll),
+            0b100111 if i && ivx => Some(Smul),
+            0b100111 if f3 == IVI && vm && matches!(vs1, 0 | 1 | 3 | 7) => Some(MvNr(vs1 + 1)),
+            0b101000 if i => Some(Srl),
+            0b101001 if i => Some(Sra),
+            0b101010 if i => Some(Ssrl),
+            0b101011 if i => Some(Ssra),
+            0b101100 if i => Some(Nsrl),
+            0b101101 if i => Some(Nsra),
+            0b101110 if i => Some(Nclipu),
+            0b101111 if i => Some(Nclip),
+            0b110000 if f3 == IVV => Some(Wredsumu),
+            0b110001 if f3 == IVV => Some(Wredsum),
+            // OPMVV and OPMVX
+            0b000000 if f3 == MVV => Some(Redsum),
+            0b000001 if f3 == MVV => Some(Redand),
+            0b000010 if f3 == MVV => Some(Redor),
+            0b000011 if f3 == MVV => Some(Redxor),
+            0b000100 if f3 == MVV => Some(Redminu),
+            0b000101 if f3 == MVV => Some(Redmin),
+            0b000110 if f3 == MVV => Some(Redmaxu),
+            0b000111 if f3 == MVV => Some(Redmax),
+            0b001000 if m => Some(Aaddu),
+            0b001001 if m => Some(Aadd),
+            0b001010 if m => Some(Asubu),
+            0b001011 if m => Some(Asub),
+            0b001110 if f3 == MVX => Some(Slide1up),
+            0b001111 if f3 == MVX => Some(Slide1down),
+            0b010000 if f3 == MVV && vm && vs1 == 0b00000 => Some(MvXS),
+            0b010000 if f3 == MVV && vs1 == 0b10000 => Some(Cpop),
+            0b010000 if f3 == MVV && vs1 == 0b10001 => Some(First),
+            0b010000 if f3 == MVX && vm && vs2 == 0 => Some(MvSX),
+            0b010010 if f3 == MVV => match vs1 {
+                0b00010 => Some(Zext(8)),
+                0b00011 => Some(Sext(8)),
+                0b00100 => Some(Zext(4)),
+                0b00101 => Some(Sext(4)),
+                0b00110 => Some(Zext(2)),
+                0b00111 => Some(Sext(2)),
+                _ => None,
+            },
+            0b010100 if f3 == MVV => match vs1 {
+                0b00001 => Some(Msbf),
+                0b00010 => Some(Msof),
+                0b00011 => Some(Msif),
+                0b10000 => Some(Iota),
+                0b10001 if vs2 == 0 => Some(Id),
+                _ => None,
+            },
+            0b010111 if f3 == MVV && vm => Some(Compress),
+            0b011000 if f3 == MVV && vm => Some(Mandn),
+            0b011001 if f3 == MVV && vm => Some(Mand),
+            0b011010 if f3 == MVV && vm => Some(Mor),
+            0b011011 if f3 == MVV && vm => Some(Mxor),
+            0b011100 if f3 == MVV && vm => Some(Morn),
+            0b011101 if f3 == MVV && vm => Some(Mnand),
+            0b011110 if f3 == MVV && vm => Some(Mnor),
+            0b011111 if f3 == MVV && vm => Some(Mxnor),
+            0b100000 if m => Some(Divu),
+            0b100001 if m => Some(Div),
+            0b100010 if m => Some(Remu),
+            0b100011 if m => Some(Rem),
+            0b100100 if m => Some(Mulhu),
+            0b100101 if m => Some(Mul),
+            0b100110 if m => Some(Mulhsu),
+            0b100111 if m => Some(Mulh),
+            0b101001 if m => Some(Madd),
+            0b101011 if m => Some(Nmsub),
+            0b101101 if m => Some(Macc),
+            0b101111 if m => Some(Nmsac),
+            0b110000 if m => Some(Waddu),
+            0b110001 if m => Some(Wadd),
+            0b110010 if m => Some(Wsubu),
+            0b110011 if m => Some(Wsub),
+            0b110100 if m => Some(WadduW),
+            0b110101 if m => Some(WaddW),
+            0b110110 if m => Some(WsubuW),
+            0b110111 if m => Some(WsubW),
+            0b111000 if m => Some(Wmulu),
+            0b111010 if m => Some(Wmulsu),
+            0b111011 if m => Some(Wmul),
+            0b111100 if m => Some(Wmaccu),
+            0b111101 if m => Some(Wmacc),
+            0b111110 if f3 == MVX => Some(Wmaccus),
+            0b111111 if m => Some(Wmaccsu),
+            _ => None,
+        }
+    }
+}
+
+impl OpFVFunct {
+    // OPFVV or OPFVF (the widening operations need double precision, and are not supported)
+    pub fn from(code: u32) -> Option<OpFVFunct> {
+        use OpFVFunct::*;
+        let f6 = funct7(code) >> 1;
+        let vm = (code >> 25) & 1 != 0;
+        let vs1 = Reg::extract_rs1(code).index();
+        let vs2 = Reg::extract_rs2(code).index();
+        let (vv, vf) = (funct3(code) == 0b001, funct3(code) == 0b101);
+        match f6 {
+            0b000000 => Some(Add),
+            0b000001 if vv => Some(Redusum),
+            0b000010 => Some(Sub),
+            0b000011 if vv => Some(Redosum),
+            0b000100 => Some(Min),
+            0b000101 if vv => Some(Redmin),
+            0b000110 => Some(Max),
+            0b000111 if vv => Some(Redmax),
+            0b001000 => Some(Sgnj),
+            0b001001 => Some(Sgnjn),
+            0b001010 => Some(Sgnjx),
+            0b001110 if vf => Some(Slide1up),
+            0b001111 if vf => Some(Slide1down),
+            0b010000 if vv && vm && vs1 == 0 => Some(MvFS),
+            0b010000 if vf && vm && vs2 == 0 => Some(MvSF),
+            0b010010 if vv => match vs1 {
+                0b00000 => Some(CvtXuF),
+                0b00001 => Some(CvtXF),
+                0b00010 => Some(CvtFXu),
+                0b00011 => Some(CvtFX),
+                0b00110 => Some(CvtRtzXuF),
+                0b00111 => Some(CvtRtzXF),
+                0b01010 => Some(WcvtFXu),
+                0b01011 => Some(WcvtFX),
+                0b10000 => Some(NcvtXuF),
+                0b10001 => Some(NcvtXF),
+                0b10110 => Some(NcvtRtzXuF),
+                0b10111 => Some(NcvtRtzXF),
+                _ => None,
+            },
+            0b010011 if vv => match vs1 {
+                0b00000 => Some(Sqrt),
+                0b00100 => Some(Rsqrt7),
+                0b00101 => Some(Rec7),
+                0b10000 => Some(Class),
+                _ => None,
+            },
+            0b010111 if vf && !vm => Some(Merge),
+            0b010111 if vf && vs2 == 0 => Some(Mv),
+            0b011000 => Some(Mfeq),
+            0b011001 => Some(Mfle),
+            0b011011 => Some(Mflt),
+            0b011100 => Some(Mfne),
+            0b011101 if vf => Some(Mfgt),
+            0b011111 if vf => Some(Mfge),
+            0b100000 => Some(Div),
+            0b100001 if vf => Some(Rdiv),
+            0b100100 => Some(Mul),
+            0b100111 if vf => Some(Rsub),
+            0b101000 => Some(Madd),
+            0b101001 => Some(Nmadd),
+            0b101010 => Some(Msub),
+            0b101011 => Some(Nmsub),
+            0b101100 => Some(Macc),
+            0b101101 => Some(Nmacc),
+            0b101110 => Some(Msac),
+            0b101111 => Some(Nmsac),
+            _ => None,
+        }
+    }
+}
+
+impl FpFmt {
+    pub fn from(code: u32) -> Option<FpFmt> {
+        let fmt = funct7(code) & 0b11;
+        match fmt {
+            0b00 => Some(FpFmt::S),
+            0b01 => Some(FpFmt::D),
+            _ => None,
+        }
+    }
+}
+
+impl FpRm {
+    pub fn from(code: u32) -> Option<FpRm> {
+        let f3 = funct3(code);
+        match f3 {
+            0b000 => Some(FpRm::Rne),
+            0b001 => Some(FpRm::Rtz),
+            0b010 => Some(FpRm::Rdn),
+            0b011 => Some(FpRm::Rup),
+            0b100 => Some(FpRm::Rmm),
+            0b111 => Some(FpRm::Dyn),
+            _ => None,
+        }
+    }
+}
+
+impl FmaFunct {
+    pub fn from(code: u32) -> Option<FmaFunct> {
+        let rm = FpRm::from(code)?;
+        // Bits 3:2 of the opcode select the operation
+        match (code >> 2) & 0b11 {
+            0b00 => Some(FmaFunct::Madd(rm)),
+            0b01 => Some(FmaFunct::Msub(rm)),
+            0b10 => Some(FmaFunct::Nmsub(rm)),
+            _ => Some(FmaFunct::Nmadd(rm)),
+        }
+    }
+}
+
+impl OpFpFunct {
+    pub fn from(code: u32) -> Option<OpFpFunct> {
+        let f3 = funct3(code);
+        let f5 = funct7(code) >> 2;
+        let fmt = FpFmt::from(code)?;
+        let rs2 = Reg::extract_rs2(code).index();
+        let rm = || FpRm::from(code);
+        match (f5, f3, rs2) {
+            (0b00000, _, _) => Some(OpFpFunct::Add(rm()?)),
+            (0b00001, _, _) => Some(OpFpFunct::Sub(rm()?)),
+            (0b00010, _, _) => Some(OpFpFunct::Mul(rm()?)),
+            (0b00011, _, _) => Some(OpFpFunct::Div(rm()?)),
+            (0b01011, _, 0b00000) => Some(OpFpFunct::Sqrt(rm()?)),
+            (0b00100, 0b000, _) => Some(OpFpFunct::Sgnj),
+            (0b00100, 0b001, _) => Some(OpFpFunct::Sgnjn),
+            (0b00100, 0b010, _) => Some(OpFpFunct::Sgnjx),
+            (0b00101, 0b000, _) => Some(OpFpFunct::Min),
+            (0b00101, 0b001, _) => Some(OpFpFunct::Max),
+            // rs2 holds the source format, which must be the other one
+            (0b01000, _, 0b00000) if fmt == FpFmt::D => Some(OpFpFunct::CvtFF(rm()?)),
+            (0b01000, _, 0b00001) if fmt == FpFmt::S => Some(OpFpFunct::CvtFF(rm()?)),
+            (0b10100, 0b010, _) => Some(OpFpFunct::Eq),
+            (0b10100, 0b001, _) => Some(OpFpFunct::Lt),
+            (0b10100, 0b000, _) => Some(OpFpFunct::Le),
+            (0b11000, _, 0b00000) => Some(OpFpFunct::CvtWF(rm()?)),
+            (0b11000, _, 0b00001) => Some(OpFpFunct::CvtWuF(rm()?)),
+            (0b11010, _, 0b00000) => Some(OpFpFunct::CvtFW(rm()?)),
+            (0b11010, _, 0b00001) => Some(OpFpFunct::CvtFWu(rm()?)),
+            (0b11100, 0b001, 0b00000) => Some(OpFpFunct::Class),
+            // Moves between integer and FP registers are single-precision only on RV32
+            (0b11100, 0b000, 0b00000) if fmt == FpFmt::S => Some(OpFpFunct::MvXF),
+            (0b11110, 0b000, 0b00000) if fmt == FpFmt::S => Some(OpFpFunct::MvFX),
+            _ => None,
+        }
+    }
+}
+
 impl CsrSrc {
     pub fn is_zero(&self) -> bool {
         match self {
@@ -7,7 +7,7 @@ const DISPLAY_NAMES: [&str; 32] = [
     "t5", "t6",
 ];
 
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, Clone)]
 pub struct Reg {
     index: u8,
 }
@@ -44,6 +44,12 @@ impl Reg {
         }
     }
 
+    pub fn extract_rs3(code: u32) -> Reg {
+        Reg {
+            index: ((code >> 27) & REG_MASK) as u8,
+        }
+    }
+
     pub fn index(&self) -> u8 {
         self.index
     }
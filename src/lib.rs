@@ -1,20 +1,33 @@
 pub mod config;
 pub mod decode;
+pub mod elf;
 pub mod exec;
+pub mod gdb;
+pub mod hpm;
+pub mod htif;
+pub mod icache;
 pub mod instr;
+pub mod linux;
+pub mod pmp;
 pub mod proc;
 pub mod run;
+pub mod sbi;
+pub mod semihosting;
+pub mod snapshot;
+pub mod softfloat;
 pub mod sys;
+pub mod tlb;
 pub mod translate;
 pub mod trap;
 
 pub use config::Config;
 pub use instr::{reg::Reg, Instr};
 pub use run::{
-    load_image_from_file, run_for, run_for_or_until_ecall, run_forever, run_until_ecall,
-    run_until_trapped,
+    load_binary_or_elf_from_file, load_elf_from_file, load_image_from_file,
+    restore_snapshot_from_file, run_for, run_for_or_until_ecall, run_for_or_until_stop,
+    run_forever, run_until_ecall, run_until_trapped, save_snapshot_to_file,
 };
-pub use sys::System;
+pub use sys::{StopReason, System};
 pub use trap::{Exception, Interrupt, Trap};
 
 pub type Result = core::result::Result<(), Trap>;
@@ -0,0 +1,893 @@
+use crate::{
+    elf::Elf,
+    instr::reg::Reg,
+    run::load_segment,
+    sys::{control::MPriv, log_with_pc, StopReason},
+    trap::TrapCause,
+    Exception, Result, System, Trap,
+};
+use colored::*;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::{MetadataExt, OpenOptionsExt},
+    path::Path,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+// Linux user-mode emulation (as qemu-user):
+// - A statically linked riscv32 program runs in U-mode, with argv, envp and auxv on its stack
+// - Ecalls from U-mode are serviced by the simulator using the host file system and clocks
+// - Faults kill the process with the exit status of the matching signal
+// - Addresses are physical (no translation), and the program must fit in RAM
+
+const PAGE_SIZE: u32 = 4096;
+const STACK_SIZE: u32 = 8 << 20;
+
+// System call numbers (riscv32 only has the 64-bit time variants)
+const SYS_GETCWD: u32 = 17;
+const SYS_DUP: u32 = 23;
+const SYS_DUP3: u32 = 24;
+const SYS_FCNTL64: u32 = 25;
+const SYS_IOCTL: u32 = 29;
+const SYS_FACCESSAT: u32 = 48;
+const SYS_OPENAT: u32 = 56;
+const SYS_CLOSE: u32 = 57;
+const SYS_LLSEEK: u32 = 62;
+const SYS_READ: u32 = 63;
+const SYS_WRITE: u32 = 64;
+const SYS_READV: u32 = 65;
+const SYS_WRITEV: u32 = 66;
+const SYS_EXIT: u32 = 93;
+const SYS_EXIT_GROUP: u32 = 94;
+const SYS_SET_TID_ADDRESS: u32 = 96;
+const SYS_SET_ROBUST_LIST: u32 = 99;
+const SYS_RT_SIGACTION: u32 = 134;
+const SYS_RT_SIGPROCMASK: u32 = 135;
+const SYS_UNAME: u32 = 160;
+const SYS_GETPID: u32 = 172;
+const SYS_GETPPID: u32 = 173;
+const SYS_GETUID: u32 = 174;
+const SYS_GETEUID: u32 = 175;
+const SYS_GETGID: u32 = 176;
+const SYS_GETEGID: u32 = 177;
+const SYS_GETTID: u32 = 178;
+const SYS_BRK: u32 = 214;
+const SYS_MUNMAP: u32 = 215;
+const SYS_MMAP2: u32 = 222;
+const SYS_MPROTECT: u32 = 226;
+const SYS_MADVISE: u32 = 233;
+const SYS_GETRANDOM: u32 = 278;
+const SYS_STATX: u32 = 291;
+const SYS_CLOCK_GETTIME64: u32 = 403;
+
+// Error numbers (the same on the host, so host errors are passed through)
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const EBADF: i32 = 9;
+const ENOMEM: i32 = 12;
+const EFAULT: i32 = 14;
+const EINVAL: i32 = 22;
+const EMFILE: i32 = 24;
+const ENOTTY: i32 = 25;
+const ESPIPE: i32 = 29;
+const ERANGE: i32 = 34;
+const ENOSYS: i32 = 38;
+const ENOTSUP: i32 = 95;
+
+// Flags of openat, mmap2, statx and fcntl64
+const AT_FDCWD: i32 = -100;
+const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+const AT_EMPTY_PATH: u32 = 0x1000;
+const O_ACCMODE: u32 = 0o3;
+const O_WRONLY: u32 = 0o1;
+const O_CREAT: u32 = 0o100;
+const O_EXCL: u32 = 0o200;
+const O_TRUNC: u32 = 0o1000;
+const O_APPEND: u32 = 0o2000;
+const MAP_FIXED: u32 = 0x10;
+const MAP_ANONYMOUS: u32 = 0x20;
+const F_DUPFD: u32 = 0;
+const F_DUPFD_CLOEXEC: u32 = 1030;
+const STATX_BASIC_STATS: u32 = 0x7ff;
+const STATX_SIZE: usize = 0x100;
+
+// Auxiliary vector
+const AT_NULL: u32 = 0;
+const AT_PHDR: u32 = 3;
+const AT_PHENT: u32 = 4;
+const AT_PHNUM: u32 = 5;
+const AT_PAGESZ: u32 = 6;
+const AT_BASE: u32 = 7;
+const AT_ENTRY: u32 = 9;
+const AT_UID: u32 = 11;
+const AT_EUID: u32 = 12;
+const AT_GID: u32 = 13;
+const AT_EGID: u32 = 14;
+const AT_HWCAP: u32 = 16;
+const AT_CLKTCK: u32 = 17;
+const AT_SECURE: u32 = 23;
+const AT_RANDOM: u32 = 25;
+const AT_EXECFN: u32 = 31;
+
+// One bit per single-letter extension (IMAFDC)
+const HWCAP: u32 = 1 << 8 | 1 << 12 | 1 << 0 | 1 << 5 | 1 << 3 | 1 << 2;
+const PHDR_SIZE: u32 = 32;
+
+// Signals killing the process on faults
+const SIGILL: i32 = 4;
+const SIGTRAP: i32 = 5;
+const SIGBUS: i32 = 7;
+const SIGSEGV: i32 = 11;
+
+// Return value in a0, or error number (negated in a0)
+type SysResult = core::result::Result<u32, i32>;
+
+#[derive(Debug)]
+enum FileDesc {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+// Host side of the emulated process. It is not part of the snapshot, so --user
+// cannot be combined with --snapshot or --restore.
+#[derive(Debug)]
+pub struct Process {
+    files: Vec<Option<FileDesc>>,
+    brk_start: u32,
+    brk: u32,
+    mmap_bottom: u32, // Lowest mapped address, mappings grow down towards brk
+    mmap_top: u32,    // Bottom of the stack
+    start: Instant,
+}
+
+// Load a program and prepare its process to start on the current hart
+pub fn load_program<P>(
+    sys: &mut System,
+    path: P,
+    args: &[String],
+    env: &[String],
+) -> io::Result<Process>
+where
+    P: AsRef<Path>,
+{
+    let elf = Elf::parse(&fs::read(path)?)?;
+    Process::load(sys, elf, args, env)
+}
+
+impl Process {
+    pub fn load(
+        sys: &mut System,
+        elf: Elf,
+        args: &[String],
+        env: &[String],
+    ) -> io::Result<Process> {
+        for seg in elf.segments.iter() {
+            load_segment(sys, seg)?;
+        }
+        let end = elf
+            .segments
+            .iter()
+            .map(|s| s.vaddr.wrapping_add(s.mem_size))
+            .max()
+            .unwrap_or(0);
+        let (entry, phdr, phnum) = (elf.entry, elf.phdr, elf.phnum);
+        sys.symbols.extend(elf.symbols);
+
+        // The stack is at the top of RAM, and mappings are below it
+        let ram_end = (sys.mem.ram_base + sys.mem.ram.size()).min(1 << 32) - 1;
+        let stack_top = (ram_end as u32 + 1).wrapping_sub(16) & !0xf;
+        let brk_start = page_align(end);
+        let mmap_top = stack_top.saturating_sub(STACK_SIZE) & !(PAGE_SIZE - 1);
+        if brk_start > mmap_top {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "program does not fit in RAM",
+            ));
+        }
+        let mut proc = Process {
+            files: vec![
+                Some(FileDesc::Stdin),
+                Some(FileDesc::Stdout),
+                Some(FileDesc::Stderr),
+            ],
+            brk_start,
+            brk: brk_start,
+            mmap_bottom: mmap_top,
+            mmap_top,
+            start: Instant::now(),
+        };
+
+        // Strings and random bytes, then argc, argv, envp and auxv
+        let mut sp = stack_top;
+        let mut push = |sys: &mut System, bytes: &[u8]| {
+            sp -= bytes.len() as u32;
+            write_guest(sys, sp, bytes).map(|_| sp)
+        };
+        let mut random = [0; 16];
+        let _ = random_bytes(&mut random);
+        let random = push(sys, &random);
+        let mut push_strs = |sys: &mut System, strs: &[String]| {
+            strs.iter()
+                .map(|s| push(sys, format!("{s}\0").as_bytes()))
+                .collect::<core::result::Result<Vec<_>, _>>()
+        };
+        let (Ok(random), Ok(argv), Ok(envp)) = (random, push_strs(sys, args), push_strs(sys, env))
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "arguments do not fit on the stack",
+            ));
+        };
+        let auxv = [
+            (AT_PHDR, phdr),
+            (AT_PHENT, PHDR_SIZE),
+            (AT_PHNUM, phnum),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_BASE, 0),
+            (AT_ENTRY, entry),
+            (AT_UID, 0),
+            (AT_EUID, 0),
+            (AT_GID, 0),
+            (AT_EGID, 0),
+            (AT_HWCAP, HWCAP),
+            (AT_CLKTCK, 100),
+            (AT_SECURE, 0),
+            (AT_RANDOM, random),
+            (AT_EXECFN, argv.first().copied().unwrap_or(0)),
+            (AT_NULL, 0),
+        ];
+        let mut words = vec![argv.len() as u32];
+        words.extend(argv.iter().chain([&0]));
+        words.extend(envp.iter().chain([&0]));
+        words.extend(auxv.iter().flat_map(|(k, v)| [*k, *v]));
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let sp = (sp - bytes.len() as u32) & !0xf;
+        write_guest(sys, sp, &bytes).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, "auxv does not fit on the stack")
+        })?;
+        proc.mmap_top = proc.mmap_top.min(sp & !(PAGE_SIZE - 1));
+        proc.mmap_bottom = proc.mmap_top;
+
+        // Registers are zero, except sp
+        for i in 1..32 {
+            *sys.reg_mut(&Reg::new(i)) = 0;
+        }
+        *sys.reg_mut(&Reg::new(2)) = sp as i32;
+        *sys.pc_mut() = entry;
+
+        // U-mode can access all the memory (NAPOT, RWX)
+        sys.ctrl.privilege = MPriv::U;
+        sys.ctrl.pmp.write_addr(0, u32::MAX);
+        sys.ctrl.pmp.write_cfg(0, 0x1f);
+        sys.ctrl.mcycle_en = true;
+        sys.ctrl.mtime_en = true;
+        sys.ctrl.minstret_en = true;
+        sys.ctrl.scycle_en = true;
+        sys.ctrl.stime_en = true;
+        sys.ctrl.sinstret_en = true;
+
+        Ok(proc)
+    }
+}
+
+// Emulate a system call, or kill the process on a fault
+pub fn handle_trap(sys: &mut System, trap: Trap) -> Result {
+    let TrapCause::Exception(ex) = trap.cause else {
+        return Err(trap);
+    };
+    if ex != Exception::EcallFromU {
+        let sig = match ex {
+            Exception::IllegalInstr => SIGILL,
+            Exception::Breakpoint => SIGTRAP,
+            Exception::InstrAddrMisaligned
+            | Exception::LoadAddrMisaligned
+            | Exception::StoreAddrMisaligned => SIGBUS,
+            _ => SIGSEGV,
+        };
+        eprintln!(
+            "{} at 0x{:08x}: {:?} (tval 0x{:08x}), killed by signal {sig}",
+            "Fault".red(),
+            sys.pc(),
+            ex,
+            trap.val
+        );
+        sys.stop = Some(StopReason::Exit(128 + sig));
+        return Err(trap);
+    }
+
+    let nr = sys.reg(&Reg::new(17)) as u32;
+    let args: Vec<u32> = (10..16).map(|i| sys.reg(&Reg::new(i)) as u32).collect();
+
+    let Some(mut proc) = sys.process.take() else {
+        return Err(trap);
+    };
+    let res = proc.syscall(sys, nr, &args);
+    sys.process = Some(proc);
+
+    log_with_pc(
+        sys,
+        &format!("{} {nr}({:x?}) = {:x?}", "Syscall".blue(), args, res),
+        true,
+    );
+    *sys.reg_mut(&Reg::new(10)) = match res {
+        Ok(val) => val as i32,
+        Err(err) => -err,
+    };
+    *sys.pc_mut() = sys.next_pc();
+    Ok(())
+}
+
+impl Process {
+    fn syscall(&mut self, sys: &mut System, nr: u32, args: &[u32]) -> SysResult {
+        match nr {
+            SYS_READ => self.read(sys, args[0], args[1], args[2]),
+            SYS_WRITE => self.write(sys, args[0], args[1], args[2]),
+            SYS_READV | SYS_WRITEV => self.readv_writev(sys, nr == SYS_WRITEV, args),
+            SYS_OPENAT => self.openat(sys, args),
+            SYS_CLOSE => self.close(args[0]),
+            SYS_LLSEEK => self.llseek(sys, args),
+            SYS_STATX => self.statx(sys, args),
+            SYS_FACCESSAT => {
+                let path = read_path(sys, args[0], args[1])?;
+                fs::symlink_metadata(path).map(|_| 0).map_err(errno)
+            }
+            SYS_GETCWD => {
+                let cwd = std::env::current_dir().map_err(errno)?;
+                let cwd = format!("{}\0", cwd.display());
+                if cwd.len() > args[1] as usize {
+                    return Err(ERANGE);
+                }
+                write_guest(sys, args[0], cwd.as_bytes())?;
+                Ok(cwd.len() as u32)
+            }
+            SYS_DUP => self.dup(args[0], 0),
+            SYS_DUP3 => self.dup3(args[0], args[1]),
+            SYS_FCNTL64 => match args[1] {
+                F_DUPFD | F_DUPFD_CLOEXEC => self.dup(args[0], args[2]),
+                // Descriptor and status flags are not emulated
+                _ => self.file(args[0]).map(|_| 0),
+            },
+            SYS_IOCTL => self.file(args[0]).and(Err(ENOTTY)),
+            SYS_BRK => Ok(self.brk(sys, args[0])),
+            SYS_MMAP2 => self.mmap2(sys, args),
+            SYS_MUNMAP => self.munmap(args[0], args[1]),
+            SYS_MPROTECT | SYS_MADVISE => Ok(0),
+            SYS_EXIT | SYS_EXIT_GROUP => {
+                sys.stop = Some(StopReason::Exit(args[0] as i32 & 0xff));
+                Ok(0)
+            }
+            SYS_CLOCK_GETTIME64 => {
+                let time = match args[0] {
+                    // Realtime, realtime coarse and TAI
+                    0 | 5 | 11 => SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_err(|_| EINVAL)?,
+                    // Monotonic clocks and CPU time
+                    1..=4 | 6 | 7 => self.start.elapsed(),
+                    _ => return Err(EINVAL),
+                };
+                let mut ts = time.as_secs().to_le_bytes().to_vec();
+                ts.extend((time.subsec_nanos() as u64).to_le_bytes());
+                write_guest(sys, args[1], &ts)?;
+                Ok(0)
+            }
+            SYS_GETRANDOM => {
+                guest_range(sys, args[0], args[1])?;
+                let mut buf = vec![0; args[1] as usize];
+                random_bytes(&mut buf).map_err(errno)?;
+                write_guest(sys, args[0], &buf)?;
+                Ok(args[1])
+            }
+            SYS_UNAME => {
+                let fields = ["Linux", "riscv-sim", "6.1.0", "#1", "riscv32", ""];
+                let mut buf = vec![0; 65 * fields.len()];
+                for (i, field) in fields.iter().enumerate() {
+                    buf[65 * i..65 * i + field.len()].copy_from_slice(field.as_bytes());
+                }
+                write_guest(sys, args[0], &buf)?;
+                Ok(0)
+            }
+            SYS_SET_TID_ADDRESS | SYS_GETPID | SYS_GETTID => Ok(1),
+            SYS_GETPPID | SYS_GETUID | SYS_GETEUID | SYS_GETGID | SYS_GETEGID => Ok(0),
+            // Signals are never delivered, and there's a single thread
+            SYS_SET_ROBUST_LIST | SYS_RT_SIGACTION | SYS_RT_SIGPROCMASK => Ok(0),
+            _ => {
+                log_with_pc(
+                    sys,
+                    &format!("{} {nr}", "Unsupported syscall".yellow()),
+                    true,
+                );
+                Err(ENOSYS)
+            }
+        }
+    }
+
+    // -------------------- FILES --------------------
+    fn file(&mut self, fd: u32) -> core::result::Result<&mut FileDesc, i32> {
+        match self.files.get_mut(fd as usize) {
+            Some(Some(file)) => Ok(file),
+            _ => Err(EBADF),
+        }
+    }
+
+    // Insert at the lowest free descriptor from min
+    fn insert(&mut self, file: FileDesc, min: u32) -> SysResult {
+        let min = min as usize;
+        if self.files.len() < min {
+            self.files.resize_with(min, || None);
+        }
+        match self.files.iter().skip(min).position(|f| f.is_none()) {
+            Some(i) => {
+                self.files[min + i] = Some(file);
+                Ok((min + i) as u32)
+            }
+            None if self.files.len() < 1024 => {
+                self.files.push(Some(file));
+                Ok(self.files.len() as u32 - 1)
+            }
+            None => Err(EMFILE),
+        }
+    }
+
+    fn read(&mut self, sys: &mut System, fd: u32, addr: u32, len: u32) -> SysResult {
+        guest_range(sys, addr, len)?;
+        let mut buf = vec![0; len as usize];
+        let n = match self.file(fd)? {
+            FileDesc::Stdin => io::stdin().read(&mut buf),
+            FileDesc::File(f) => f.read(&mut buf),
+            _ => return Err(EBADF),
+        }
+        .map_err(errno)?;
+        write_guest(sys, addr, &buf[..n])?;
+        Ok(n as u32)
+    }
+
+    fn write(&mut self, sys: &mut System, fd: u32, addr: u32, len: u32) -> SysResult {
+        let buf = read_guest(sys, addr, len)?;
+        match self.file(fd)? {
+            FileDesc::Stdout => io::stdout().write_all(&buf).and(io::stdout().flush()),
+            FileDesc::Stderr => io::stderr().write_all(&buf),
+            FileDesc::File(f) => f.write_all(&buf),
+            _ => return Err(EBADF),
+        }
+        .map_err(errno)?;
+        Ok(len)
+    }
+
+    fn readv_writev(&mut self, sys: &mut System, write: bool, args: &[u32]) -> SysResult {
+        let iov = read_guest(sys, args[1], args[2].saturating_mul(8))?;
+        let mut total = 0u32;
+        for v in iov.chunks_exact(8) {
+            let addr = u32::from_le_bytes(v[0..4].try_into().unwrap());
+            let len = u32::from_le_bytes(v[4..8].try_into().unwrap());
+            let n = if write {
+                self.write(sys, args[0], addr, len)?
+            } else {
+                self.read(sys, args[0], addr, len)?
+            };
+            total += n;
+            if n < len {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    fn openat(&mut self, sys: &mut System, args: &[u32]) -> SysResult {
+        let path = read_path(sys, args[0], args[1])?;
+        let flags = args[2];
+        let access = flags & O_ACCMODE;
+        let file = OpenOptions::new()
+            .read(access != O_WRONLY)
+            .write(access != 0)
+            .append(flags & O_APPEND != 0)
+            .truncate(flags & O_TRUNC != 0)
+            .create(flags & O_CREAT != 0)
+            .create_new(flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL)
+            .mode(args[3])
+            .open(path)
+            .map_err(errno)?;
+        self.insert(FileDesc::File(file), 0)
+    }
+
+    fn close(&mut self, fd: u32) -> SysResult {
+        self.file(fd)?;
+        self.files[fd as usize] = None;
+        Ok(0)
+    }
+
+    fn dup(&mut self, fd: u32, min: u32) -> SysResult {
+        let file = self.file(fd)?.try_clone().map_err(errno)?;
+        self.insert(file, min)
+    }
+
+    fn dup3(&mut self, fd: u32, new_fd: u32) -> SysResult {
+        if fd == new_fd || new_fd >= 1024 {
+            return Err(EINVAL);
+        }
+        let file = self.file(fd)?.try_clone().map_err(errno)?;
+        if self.files.len() <= new_fd as usize {
+            self.files.resize_with(new_fd as usize + 1, || None);
+        }
+        self.files[new_fd as usize] = Some(file);
+        Ok(new_fd)
+    }
+
+    fn llseek(&mut self, sys: &mut System, args: &[u32]) -> SysResult {
+        let offset = ((args[1] as u64) << 32 | args[2] as u64) as i64;
+        let pos = match args[4] {
+            0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(EINVAL),
+        };
+        let FileDesc::File(f) = self.file(args[0])? else {
+            return Err(ESPIPE);
+        };
+        let pos = f.seek(pos).map_err(errno)?;
+        write_guest(sys, args[3], &pos.to_le_bytes())?;
+        Ok(0)
+    }
+
+    // fstat and stat are implemented with statx on riscv32
+    fn statx(&mut self, sys: &mut System, args: &[u32]) -> SysResult {
+        let flags = args[2];
+        let path = read_cstr(sys, args[1])?;
+        let meta = if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+            match self.file(args[0])? {
+                FileDesc::File(f) => Some(f.metadata().map_err(errno)?),
+                // Standard streams are character devices
+                _ => None,
+            }
+        } else {
+            let path = read_path(sys, args[0], args[1])?;
+            Some(if flags & AT_SYMLINK_NOFOLLOW != 0 {
+                fs::symlink_metadata(path)
+            } else {
+                fs::metadata(path)
+            })
+            .transpose()
+            .map_err(errno)?
+        };
+
+        let mut buf = [0; STATX_SIZE];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0x00, &STATX_BASIC_STATS.to_le_bytes());
+        match meta {
+            Some(m) => {
+                put(0x04, &(m.blksize() as u32).to_le_bytes());
+                put(0x10, &(m.nlink() as u32).to_le_bytes());
+                put(0x14, &m.uid().to_le_bytes());
+                put(0x18, &m.gid().to_le_bytes());
+                put(0x1c, &(m.mode() as u16).to_le_bytes());
+                put(0x20, &m.ino().to_le_bytes());
+                put(0x28, &m.size().to_le_bytes());
+                put(0x30, &m.blocks().to_le_bytes());
+                for (offset, sec, nsec) in [
+                    (0x40, m.atime(), m.atime_nsec()),
+                    (0x60, m.ctime(), m.ctime_nsec()),
+                    (0x70, m.mtime(), m.mtime_nsec()),
+                ] {
+                    put(offset, &sec.to_le_bytes());
+                    put(offset + 8, &(nsec as u32).to_le_bytes());
+                }
+                for (offset, dev) in [(0x80, m.rdev()), (0x88, m.dev())] {
+                    put(offset, &dev_major(dev).to_le_bytes());
+                    put(offset + 4, &dev_minor(dev).to_le_bytes());
+                }
+            }
+            None => {
+                put(0x04, &PAGE_SIZE.to_le_bytes());
+                put(0x10, &1u32.to_le_bytes());
+                put(0x1c, &0o20620u16.to_le_bytes());
+            }
+        }
+        write_guest(sys, args[4], &buf)?;
+        Ok(0)
+    }
+
+    // -------------------- MEMORY --------------------
+    // Returns the new break, or the current one if it can't be moved
+    fn brk(&mut self, sys: &mut System, addr: u32) -> u32 {
+        if (self.brk_start..=self.mmap_bottom).contains(&addr) {
+            if addr > self.brk {
+                zero_guest(sys, self.brk, addr - self.brk);
+            }
+            self.brk = addr;
+        }
+        self.brk
+    }
+
+    fn mmap2(&mut self, sys: &mut System, args: &[u32]) -> SysResult {
+        let (addr, flags, fd) = (args[0], args[3], args[4]);
+        if args[1] == 0 {
+            return Err(EINVAL);
+        }
+        let len = page_align(args[1]);
+        let addr = if flags & MAP_FIXED != 0 {
+            if !addr.is_multiple_of(PAGE_SIZE) {
+                return Err(EINVAL);
+            }
+            guest_range(sys, addr, len).map_err(|_| ENOMEM)?;
+            addr
+        } else {
+            match self.mmap_bottom.checked_sub(len) {
+                Some(addr) if addr >= self.brk => {
+                    self.mmap_bottom = addr;
+                    addr
+                }
+                _ => return Err(ENOMEM),
+            }
+        };
+        zero_guest(sys, addr, len);
+
+        // File mappings are private copies
+        if flags & MAP_ANONYMOUS == 0 {
+            let FileDesc::File(f) = self.file(fd)? else {
+                return Err(ENOTSUP);
+            };
+            let mut buf = vec![];
+            f.try_clone()
+                .and_then(|mut f| {
+                    f.seek(SeekFrom::Start(args[5] as u64 * PAGE_SIZE as u64))?;
+                    f.take(len as u64).read_to_end(&mut buf)
+                })
+                .map_err(errno)?;
+            write_guest(sys, addr, &buf)?;
+        }
+        Ok(addr)
+    }
+
+    // Only the lowest mapping can be reused
+    fn munmap(&mut self, addr: u32, len: u32) -> SysResult {
+        if !addr.is_multiple_of(PAGE_SIZE) || len == 0 {
+            return Err(EINVAL);
+        }
+        if addr == self.mmap_bottom {
+            self.mmap_bottom = addr.saturating_add(page_align(len)).min(self.mmap_top);
+        }
+        Ok(0)
+    }
+}
+
+impl FileDesc {
+    fn try_clone(&self) -> io::Result<FileDesc> {
+        Ok(match self {
+            FileDesc::Stdin => FileDesc::Stdin,
+            FileDesc::Stdout => FileDesc::Stdout,
+            FileDesc::Stderr => FileDesc::Stderr,
+            FileDesc::File(f) => FileDesc::File(f.try_clone()?),
+        })
+    }
+}
+
+// -------------------- HELPERS --------------------
+fn errno(e: io::Error) -> i32 {
+    e.raw_os_error().unwrap_or(EIO)
+}
+
+fn page_align(addr: u32) -> u32 {
+    addr.saturating_add(PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+fn dev_major(dev: u64) -> u32 {
+    ((dev >> 8) & 0xfff | (dev >> 32) & !0xfff) as u32
+}
+
+fn dev_minor(dev: u64) -> u32 {
+    (dev & 0xff | (dev >> 12) & !0xff) as u32
+}
+
+fn random_bytes(buf: &mut [u8]) -> io::Result<()> {
+    File::open("/dev/urandom")?.read_exact(buf)
+}
+
+// Offsets in RAM of a range of guest memory
+pub fn guest_range(
+    sys: &System,
+    addr: u32,
+    len: u32,
+) -> core::result::Result<std::ops::Range<usize>, i32> {
+    let start = (addr as u64).checked_sub(sys.mem.ram_base).ok_or(EFAULT)?;
+    let end = start + len as u64;
+    if end > sys.mem.ram.size() {
+        return Err(EFAULT);
+    }
+    Ok(start as usize..end as usize)
+}
+
+pub fn read_guest(sys: &System, addr: u32, len: u32) -> core::result::Result<Vec<u8>, i32> {
+    let range = guest_range(sys, addr, len)?;
+    Ok(sys.mem.ram.as_u8()[range].to_vec())
+}
+
+pub fn write_guest(sys: &mut System, addr: u32, bytes: &[u8]) -> core::result::Result<(), i32> {
+    let range = guest_range(sys, addr, bytes.len() as u32)?;
+    sys.mem.ram.as_u8_mut()[range].copy_from_slice(bytes);
+    sys.mem.icache.invalidate(addr as u64, bytes.len() as u64);
+    Ok(())
+}
+
+fn zero_guest(sys: &mut System, addr: u32, len: u32) {
+    if let Ok(range) = guest_range(sys, addr, len) {
+        sys.mem.ram.as_u8_mut()[range].fill(0);
+        sys.mem.icache.invalidate(addr as u64, len as u64);
+    }
+}
+
+fn read_cstr(sys: &System, addr: u32) -> core::result::Result<String, i32> {
+    let start = guest_range(sys, addr, 0)?.start;
+    let bytes = &sys.mem.ram.as_u8()[start..];
+    let len = bytes.iter().position(|&c| c == 0).ok_or(EFAULT)?;
+    String::from_utf8(bytes[..len].to_vec()).map_err(|_| EINVAL)
+}
+
+// Paths relative to a directory descriptor are only supported for the working directory
+fn read_path(sys: &System, dirfd: u32, addr: u32) -> core::result::Result<String, i32> {
+    let path = read_cstr(sys, addr)?;
+    if path.is_empty() {
+        return Err(ENOENT);
+    }
+    if !path.starts_with('/') && dirfd as i32 != AT_FDCWD {
+        return Err(ENOTSUP);
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::{Segment, SymbolTable};
+
+    const ENTRY: u32 = 0x1_0000;
+
+    fn make_sys(args: &[&str], env: &[&str]) -> System {
+        let mut sys = System::new();
+        let elf = Elf {
+            entry: ENTRY,
+            segments: vec![Segment {
+                vaddr: ENTRY,
+                paddr: ENTRY,
+                mem_size: 0x1800,
+                data: vec![0x13, 0, 0, 0],
+            }],
+            symbols: SymbolTable::default(),
+            phdr: 0,
+            phnum: 1,
+        };
+        let args: Vec<_> = args.iter().map(|s| s.to_string()).collect();
+        let env: Vec<_> = env.iter().map(|s| s.to_string()).collect();
+        sys.process = Some(Process::load(&mut sys, elf, &args, &env).unwrap());
+        sys
+    }
+
+    fn call(sys: &mut System, nr: u32, args: &[u32]) -> i32 {
+        for (i, arg) in args.iter().enumerate() {
+            *sys.reg_mut(&Reg::new(10 + i as u8)) = *arg as i32;
+        }
+        *sys.reg_mut(&Reg::new(17)) = nr as i32;
+        handle_trap(sys, Trap::from_exception(Exception::EcallFromU, 0)).unwrap();
+        sys.reg(&Reg::new(10))
+    }
+
+    fn word(sys: &System, addr: u32) -> u32 {
+        sys.mem.ram.as_u32()[addr as usize / 4]
+    }
+
+    #[test]
+    fn test_stack() {
+        let sys = make_sys(&["prog", "-x"], &["A=1"]);
+        assert_eq!(sys.ctrl.privilege, MPriv::U);
+        assert_eq!(sys.pc(), ENTRY);
+        let sp = sys.reg(&Reg::new(2)) as u32;
+        assert_eq!(sp % 16, 0);
+
+        // argc, argv, envp
+        assert_eq!(word(&sys, sp), 2);
+        assert_eq!(read_cstr(&sys, word(&sys, sp + 4)).unwrap(), "prog");
+        assert_eq!(read_cstr(&sys, word(&sys, sp + 8)).unwrap(), "-x");
+        assert_eq!(word(&sys, sp + 12), 0);
+        assert_eq!(read_cstr(&sys, word(&sys, sp + 16)).unwrap(), "A=1");
+        assert_eq!(word(&sys, sp + 20), 0);
+
+        // auxv, up to AT_NULL
+        let mut auxv = vec![];
+        let mut addr = sp + 24;
+        while word(&sys, addr) != AT_NULL {
+            auxv.push((word(&sys, addr), word(&sys, addr + 4)));
+            addr += 8;
+        }
+        assert!(auxv.contains(&(AT_ENTRY, ENTRY)));
+        assert!(auxv.contains(&(AT_PAGESZ, PAGE_SIZE)));
+        assert!(auxv.iter().any(|(k, v)| *k == AT_RANDOM && *v > sp));
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut sys = make_sys(&["prog"], &[]);
+        let brk = call(&mut sys, SYS_BRK, &[0]) as u32;
+        assert_eq!(brk, 0x1_2000);
+        sys.mem.ram.as_u8_mut()[brk as usize] = 0xff;
+        assert_eq!(call(&mut sys, SYS_BRK, &[brk + 0x100]) as u32, brk + 0x100);
+        assert_eq!(call(&mut sys, SYS_BRK, &[brk]) as u32, brk);
+        assert_eq!(call(&mut sys, SYS_BRK, &[brk + 0x100]) as u32, brk + 0x100);
+        assert_eq!(sys.mem.ram.as_u8()[brk as usize], 0);
+        assert_eq!(call(&mut sys, SYS_BRK, &[0x100]) as u32, brk + 0x100);
+
+        // Anonymous mappings grow down, and the lowest one can be reused
+        let args = [0, 0x1800, 3, MAP_ANONYMOUS | 0x2, u32::MAX, 0];
+        let a = call(&mut sys, SYS_MMAP2, &args) as u32;
+        let b = call(&mut sys, SYS_MMAP2, &args) as u32;
+        assert_eq!((a % PAGE_SIZE, a - b), (0, 0x2000));
+        assert_eq!(call(&mut sys, SYS_MUNMAP, &[b, 0x1800]), 0);
+        assert_eq!(call(&mut sys, SYS_MMAP2, &args) as u32, b);
+        assert_eq!(
+            call(&mut sys, SYS_MMAP2, &[0, 0x1000_0000, 3, 0x22, 0, 0]),
+            -ENOMEM
+        );
+    }
+
+    #[test]
+    fn test_files() {
+        let mut sys = make_sys(&["prog"], &[]);
+        let path = std::env::temp_dir().join(format!("riscv_sim_linux_{}", std::process::id()));
+        let path_addr = 0x2_0000;
+        write_guest(
+            &mut sys,
+            path_addr,
+            format!("{}\0", path.display()).as_bytes(),
+        )
+        .unwrap();
+        write_guest(&mut sys, 0x2_1000, b"hello").unwrap();
+
+        // openat(AT_FDCWD, path, O_RDWR | O_CREAT | O_TRUNC, 0o644)
+        let flags = 2 | O_CREAT | O_TRUNC;
+        let fd = call(
+            &mut sys,
+            SYS_OPENAT,
+            &[AT_FDCWD as u32, path_addr, flags, 0o644],
+        );
+        assert_eq!(fd, 3);
+        assert_eq!(call(&mut sys, SYS_WRITE, &[3, 0x2_1000, 5]), 5);
+        assert_eq!(call(&mut sys, SYS_LLSEEK, &[3, 0, 1, 0x2_2000, 0]), 0);
+        assert_eq!(word(&sys, 0x2_2000), 1);
+        assert_eq!(call(&mut sys, SYS_READ, &[3, 0x2_3000, 16]), 4);
+        assert_eq!(&sys.mem.ram.as_u8()[0x2_3000..0x2_3004], b"ello");
+
+        // fstat, then close
+        let args = [3, 0x2_4000, AT_EMPTY_PATH, STATX_BASIC_STATS, 0x2_5000];
+        assert_eq!(call(&mut sys, SYS_STATX, &args), 0);
+        assert_eq!(word(&sys, 0x2_5000 + 0x28), 5);
+        assert_eq!(call(&mut sys, SYS_CLOSE, &[3]), 0);
+        assert_eq!(call(&mut sys, SYS_CLOSE, &[3]), -EBADF);
+        assert_eq!(call(&mut sys, SYS_READ, &[3, 0x2_3000, 16]), -EBADF);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            call(&mut sys, SYS_OPENAT, &[AT_FDCWD as u32, path_addr, 0, 0]),
+            -ENOENT
+        );
+        assert_eq!(call(&mut sys, SYS_WRITE, &[1, 0x1000_0000, 5]), -EFAULT);
+    }
+
+    #[test]
+    fn test_exit_and_faults() {
+        let mut sys = make_sys(&["prog"], &[]);
+        assert_eq!(call(&mut sys, 0xfff, &[]), -ENOSYS);
+        assert_eq!(call(&mut sys, SYS_GETPID, &[]), 1);
+        assert_eq!(sys.pc(), ENTRY + 8);
+        assert_eq!(sys.stop, None);
+        call(&mut sys, SYS_EXIT_GROUP, &[0x101]);
+        assert_eq!(sys.stop, Some(StopReason::Exit(1)));
+
+        // An illegal instruction kills the process with SIGILL
+        let mut sys = make_sys(&["prog"], &[]);
+        write_guest(&mut sys, ENTRY, &[0; 4]).unwrap();
+        let _ = sys.step();
+        assert_eq!(sys.stop, Some(StopReason::Exit(128 + SIGILL)));
+    }
+}
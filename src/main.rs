@@ -1,6 +1,7 @@
 use clap::Parser;
 use config::ConfigError;
-use riscv_sim::*;
+use gdb::GdbExit;
+use riscv_sim::{sys::mem_map::virtio::VIRTIO_SLOTS, *};
 use std::process;
 
 fn main() {
@@ -21,9 +22,75 @@ fn main() {
                 eprintln!("Invalid kernel file: {}", f.display());
                 process::exit(3);
             }
+            ConfigError::InvalidRestore(f) => {
+                eprintln!("Invalid snapshot file: {}", f.display());
+                process::exit(5);
+            }
+            ConfigError::InvalidDrive(f) => {
+                eprintln!("Invalid drive file: {}", f.display());
+                process::exit(7);
+            }
+            ConfigError::InvalidNic(f) => {
+                eprintln!("Invalid nic path: {}", f.display());
+                process::exit(10);
+            }
+            ConfigError::TooManyDevices(n) => {
+                eprintln!("Too many virtio devices: {n} (at most {VIRTIO_SLOTS})");
+                process::exit(8);
+            }
+            ConfigError::NoSnapshot(opt) => {
+                eprintln!("Snapshots are not supported with {opt}");
+                process::exit(9);
+            }
         },
     };
 
     let mut sys = System::from_config(cfg);
-    run_forever(&mut sys);
+    // An emulated Linux program reads stdin directly
+    if !sys.cfg.user {
+        sys.mem.uart.connect_stdin();
+    }
+
+    // Resume from a snapshot instead of the initial state
+    if let Some(path) = sys.cfg.restore.clone() {
+        if let Err(e) = restore_snapshot_from_file(&mut sys, &path) {
+            eprintln!("Cannot restore snapshot {}: {e}", path.display());
+            process::exit(5);
+        }
+    }
+
+    // Hand over control to the debugger until it detaches
+    if let Some(port) = sys.cfg.gdb {
+        match gdb::serve(&mut sys, port) {
+            Ok(GdbExit::Detach) => {}
+            Ok(GdbExit::Kill) => process::exit(0),
+            Err(e) => {
+                eprintln!("GDB connection failed: {e}");
+                process::exit(4);
+            }
+        }
+    }
+
+    // Save a snapshot after the given number of steps, then keep running. There is
+    // nothing to save if the machine stops first, which is handled as usual.
+    let mut stop = None;
+    if let (Some(path), Some(steps)) = (sys.cfg.snapshot.clone(), sys.cfg.snapshot_at) {
+        stop = run_for_or_until_stop(&mut sys, steps as usize);
+        if stop.is_some() {
+            eprintln!(
+                "Machine stopped before the snapshot {} was taken",
+                path.display()
+            );
+        } else if let Err(e) = save_snapshot_to_file(&sys, &path) {
+            eprintln!("Cannot save snapshot {}: {e}", path.display());
+            process::exit(6);
+        }
+    }
+    // Exit with the code given by the guest, or start again on reset
+    loop {
+        match stop.take().unwrap_or_else(|| run_forever(&mut sys)) {
+            StopReason::Exit(code) => process::exit(code),
+            StopReason::Reset => sys.reset(),
+        }
+    }
 }
@@ -0,0 +1,301 @@
+use crate::{
+    snapshot::{SnapshotReader, SnapshotWriter},
+    sys::{
+        control::MPriv,
+        mem_map::{access_fault, AccessAttr, AccessType, AccessWidth},
+    },
+    translate::effective_privilege,
+    ResultE, System,
+};
+use std::io;
+
+pub const PMP_MAX_ENTRIES: usize = 64;
+
+const CFG_R: u8 = 1 << 0;
+const CFG_W: u8 = 1 << 1;
+const CFG_X: u8 = 1 << 2;
+const CFG_A: u8 = 0b11 << 3;
+const CFG_L: u8 = 1 << 7;
+const CFG_MASK: u8 = CFG_R | CFG_W | CFG_X | CFG_A | CFG_L;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum AddrMatch {
+    Off,
+    Tor,
+    Na4,
+    Napot,
+}
+
+// Physical memory protection with a granularity of 4 bytes
+#[derive(Debug)]
+pub struct Pmp {
+    entries: usize,
+    cfg: [u8; PMP_MAX_ENTRIES],
+    addr: [u32; PMP_MAX_ENTRIES], // Bits 33:2 of the address
+}
+
+impl Pmp {
+    pub fn new(entries: usize) -> Pmp {
+        Pmp {
+            entries: entries.min(PMP_MAX_ENTRIES),
+            cfg: [0; PMP_MAX_ENTRIES],
+            addr: [0; PMP_MAX_ENTRIES],
+        }
+    }
+
+    // pmpcfg0-15 each hold the configuration of 4 entries
+    pub fn read_cfg(&self, reg: usize) -> u32 {
+        (0..4).fold(0, |val, j| {
+            val | (self.get_cfg(reg * 4 + j) as u32) << (j * 8)
+        })
+    }
+
+    pub fn write_cfg(&mut self, reg: usize, val: u32) {
+        for j in 0..4 {
+            let i = reg * 4 + j;
+            if i < self.entries && !self.is_locked(i) {
+                let mut cfg = (val >> (j * 8)) as u8 & CFG_MASK;
+                // W without R is reserved
+                if cfg & CFG_R == 0 {
+                    cfg &= !CFG_W;
+                }
+                self.cfg[i] = cfg;
+            }
+        }
+    }
+
+    pub fn read_addr(&self, i: usize) -> u32 {
+        if i < self.entries {
+            self.addr[i]
+        } else {
+            0
+        }
+    }
+
+    pub fn write_addr(&mut self, i: usize, val: u32) {
+        // Also locked if it's the bottom of a locked TOR entry
+        let locked_tor = i + 1 < self.entries
+            && self.is_locked(i + 1)
+            && self.addr_match(i + 1) == AddrMatch::Tor;
+        if i < self.entries && !self.is_locked(i) && !locked_tor {
+            self.addr[i] = val;
+        }
+    }
+
+    // Whether an access of size bytes at addr is allowed
+    pub fn check(&self, addr: u64, size: u64, access_type: AccessType, privilege: MPriv) -> bool {
+        let end = addr + size;
+        // The entry with the lowest number matching any byte decides
+        for i in 0..self.entries {
+            let Some((lo, hi)) = self.range(i) else {
+                continue;
+            };
+            if end <= lo || hi <= addr {
+                continue;
+            }
+            // All bytes must match
+            if addr < lo || hi < end {
+                return false;
+            }
+            let cfg = self.cfg[i];
+            if privilege == MPriv::M && cfg & CFG_L == 0 {
+                return true;
+            }
+            let perm = match access_type {
+                AccessType::Instr => CFG_X,
+                AccessType::Load => CFG_R,
+                AccessType::Store => CFG_W,
+            };
+            return cfg & perm != 0;
+        }
+        // Without a matching entry, S/U-mode accesses only fail if some entry is active
+        // (as in QEMU, so that software unaware of PMP still runs)
+        privilege == MPriv::M || (0..self.entries).all(|i| self.addr_match(i) == AddrMatch::Off)
+    }
+
+    fn get_cfg(&self, i: usize) -> u8 {
+        if i < self.entries {
+            self.cfg[i]
+        } else {
+            0
+        }
+    }
+
+    fn is_locked(&self, i: usize) -> bool {
+        self.cfg[i] & CFG_L != 0
+    }
+
+    fn addr_match(&self, i: usize) -> AddrMatch {
+        match (self.cfg[i] & CFG_A) >> 3 {
+            0 => AddrMatch::Off,
+            1 => AddrMatch::Tor,
+            2 => AddrMatch::Na4,
+            _ => AddrMatch::Napot,
+        }
+    }
+
+    // Byte range [lo, hi) matched by an entry
+    fn range(&self, i: usize) -> Option<(u64, u64)> {
+        let addr = (self.addr[i] as u64) << 2;
+        match self.addr_match(i) {
+            AddrMatch::Off => None,
+            AddrMatch::Tor => {
+                let lo = if i == 0 {
+                    0
+                } else {
+                    (self.addr[i - 1] as u64) << 2
+                };
+                (lo < addr).then_some((lo, addr))
+            }
+            AddrMatch::Na4 => Some((addr, addr + 4)),
+            AddrMatch::Napot => {
+                let size = 1_u64 << (self.addr[i].trailing_ones() + 3);
+                let lo = addr & !(size - 1);
+                Some((lo, lo + size))
+            }
+        }
+    }
+}
+
+// Check a physical access of a load, store or instruction fetch
+pub fn check_pmp(sys: &System, addr: u64, attr: AccessAttr) -> ResultE {
+    let privilege = effective_privilege(sys, attr.atype);
+    let size = match attr.width {
+        AccessWidth::Byte => 1,
+        AccessWidth::HalfWord => 2,
+        AccessWidth::Word => 4,
+        AccessWidth::DoubleWord => 8,
+    };
+    let pmp = &sys.ctrl.pmp;
+    // AMOs need both read and write permissions
+    let allowed = pmp.check(addr, size, attr.atype, privilege)
+        && (!attr.amo || pmp.check(addr, size, AccessType::Load, privilege));
+    if allowed {
+        Ok(())
+    } else {
+        Err(access_fault(attr.atype))
+    }
+}
+
+impl Pmp {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_u32(self.entries as u32);
+        self.cfg.iter().for_each(|c| w.put_u8(*c));
+        self.addr.iter().for_each(|a| w.put_u32(*a));
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        self.entries = (r.get_u32()? as usize).min(PMP_MAX_ENTRIES);
+        for c in self.cfg.iter_mut() {
+            *c = r.get_u8()?;
+        }
+        for a in self.addr.iter_mut() {
+            *a = r.get_u32()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAPOT: u8 = 3 << 3;
+    const NA4: u8 = 2 << 3;
+    const TOR: u8 = 1 << 3;
+    const RWX: u8 = CFG_R | CFG_W | CFG_X;
+
+    #[test]
+    fn test_csr_warl() {
+        let mut pmp = Pmp::new(16);
+
+        // Reserved bits and W without R are cleared
+        pmp.write_cfg(0, 0xff_6f_02_07);
+        assert_eq!(pmp.read_cfg(0), 0x9f_0f_00_07);
+        // Entry 3 is now locked
+        pmp.write_addr(3, 0x1234);
+        pmp.write_addr(5, 0x1234);
+        assert_eq!(pmp.read_addr(3), 0);
+        assert_eq!(pmp.read_addr(5), 0x1234);
+
+        // Unimplemented entries are read-only zero
+        pmp.write_cfg(4, 0x07);
+        pmp.write_addr(16, 0x1234);
+        assert_eq!(pmp.read_cfg(4), 0);
+        assert_eq!(pmp.read_addr(16), 0);
+
+        let mut pmp = Pmp::new(64);
+        pmp.write_cfg(15, 0x07_00_00_00);
+        pmp.write_addr(63, 0x1234);
+        assert_eq!(pmp.read_cfg(15), 0x07_00_00_00);
+        assert_eq!(pmp.read_addr(63), 0x1234);
+    }
+
+    #[test]
+    fn test_lock() {
+        let mut pmp = Pmp::new(16);
+        pmp.write_addr(0, 0x100);
+        pmp.write_addr(1, 0x200);
+        pmp.write_cfg(0, ((CFG_L | TOR | CFG_R) as u32) << 8);
+
+        // Locked entries and the bottom of a locked TOR entry cannot be changed
+        pmp.write_cfg(0, 0x07);
+        pmp.write_addr(0, 0);
+        pmp.write_addr(1, 0);
+        assert_eq!(pmp.read_cfg(0), 0x89_07);
+        assert_eq!(pmp.read_addr(0), 0x100);
+        assert_eq!(pmp.read_addr(1), 0x200);
+
+        // Locked entries also apply to M-mode
+        assert!(pmp.check(0x400, 4, AccessType::Load, MPriv::M));
+        assert!(!pmp.check(0x400, 4, AccessType::Store, MPriv::M));
+        assert!(!pmp.check(0x7fc, 4, AccessType::Instr, MPriv::M));
+        assert!(pmp.check(0x800, 4, AccessType::Store, MPriv::M));
+    }
+
+    #[test]
+    fn test_match_modes() {
+        let mut pmp = Pmp::new(16);
+        // 0: NA4 at 0x1000 (R), 1: NAPOT 0x2000-0x2fff (RX), 2: TOR from pmpaddr1 to 0x4000 (RW)
+        pmp.write_addr(0, 0x1000 >> 2);
+        pmp.write_addr(1, (0x2000 >> 2) | 0x1ff);
+        pmp.write_addr(2, 0x4000 >> 2);
+        let cfg = [NA4 | CFG_R, NAPOT | CFG_R | CFG_X, TOR | CFG_R | CFG_W];
+        pmp.write_cfg(0, u32::from_le_bytes([cfg[0], cfg[1], cfg[2], 0]));
+
+        let s = MPriv::S;
+        assert!(pmp.check(0x1000, 4, AccessType::Load, s));
+        assert!(!pmp.check(0x1000, 4, AccessType::Store, s));
+        assert!(!pmp.check(0x1004, 4, AccessType::Load, s));
+        // Partially matching accesses fail
+        assert!(!pmp.check(0x1002, 4, AccessType::Load, s));
+
+        assert!(pmp.check(0x2ffc, 4, AccessType::Instr, s));
+        assert!(!pmp.check(0x2000, 4, AccessType::Store, s));
+        // Entry 2 starts at pmpaddr1, and is shadowed by entry 1 up to 0x3000
+        assert!(pmp.check(0x3000, 4, AccessType::Store, s));
+        assert!(!pmp.check(0x3000, 4, AccessType::Instr, s));
+        assert!(!pmp.check(0x4000, 4, AccessType::Load, MPriv::U));
+
+        // M-mode is not restricted by unlocked entries
+        assert!(pmp.check(0x2000, 4, AccessType::Store, MPriv::M));
+        assert!(pmp.check(0x4000, 4, AccessType::Store, MPriv::M));
+    }
+
+    #[test]
+    fn test_no_active_entry() {
+        let mut pmp = Pmp::new(16);
+        assert!(pmp.check(0x1000, 4, AccessType::Load, MPriv::U));
+
+        // Whole address space
+        pmp.write_addr(0, 0xffff_ffff);
+        pmp.write_cfg(0, (NAPOT | RWX) as u32);
+        assert!(pmp.check(0xffff_fffc, 4, AccessType::Store, MPriv::U));
+
+        pmp.write_cfg(0, (NAPOT | CFG_R) as u32);
+        assert!(!pmp.check(0xffff_fffc, 4, AccessType::Store, MPriv::U));
+
+        let pmp = Pmp::new(0);
+        assert!(pmp.check(0x1000, 4, AccessType::Store, MPriv::U));
+    }
+}
@@ -1,22 +1,55 @@
 use crate::{
-    sys::control::{Control, MPriv, SPriv, TvecMode},
+    sys::{
+        control::{Control, MPriv, SPriv, TvecMode},
+        mem_map::{
+            plic::{PLIC_CTX_M, PLIC_CTX_S},
+            uart::UART_IRQ,
+            virtio::{VIRTIO_IRQ, VIRTIO_SLOTS},
+        },
+    },
     trap::TrapCause,
     Interrupt, Result, System, Trap,
 };
 
-const INTERRUPT_ORDER: [Interrupt; 6] = [
+const INTERRUPT_ORDER: [Interrupt; 7] = [
     Interrupt::MExt,
     Interrupt::MSoft,
     Interrupt::MTimer,
     Interrupt::SExt,
     Interrupt::SSoft,
     Interrupt::STimer,
+    Interrupt::LocalCountOverflow,
 ];
 
 // ------------ Interrupt condition -------------
 pub fn update_interrupt(sys: &mut System) {
-    // Update timer interrupt
-    sys.ctrl.ip.set(&Interrupt::MTimer, sys.mem.timer.is_interrupt_set());
+    // Update timer and software interrupts of this hart
+    let hart = sys.hart_id;
+    let mtip = sys.mem.timer.is_interrupt_set(hart);
+    let msip = sys.mem.mswi.is_interrupt_set(hart);
+    sys.ctrl.ip.set(&Interrupt::MTimer, mtip);
+    sys.ctrl.ip.set(&Interrupt::MSoft, msip);
+    if sys.mem.sswi.take_interrupt(hart) {
+        sys.ctrl.ip.set(&Interrupt::SSoft, true);
+    }
+    // STIP is driven by stimecmp if enabled (otherwise it's written by M-mode)
+    if sys.ctrl.stce {
+        let stip = sys.mem.timer.time >= sys.ctrl.stimecmp;
+        sys.ctrl.ip.set(&Interrupt::STimer, stip);
+    }
+    // Update external interrupts from the PLIC (the lines only matter when they change)
+    sys.mem.poll_host();
+    let mut levels = (sys.mem.uart.is_interrupt_set() as u32) << UART_IRQ;
+    for (slot, virtio) in sys.mem.virtio.iter().enumerate() {
+        levels |= (virtio.is_interrupt_set() as u32) << (VIRTIO_IRQ + slot as u32);
+    }
+    let mask = (1 << UART_IRQ) | (((1 << VIRTIO_SLOTS) - 1) << VIRTIO_IRQ);
+    sys.mem.plic.set_levels(mask, levels);
+    let plic = &sys.mem.plic;
+    let (ctx_m, ctx_s) = (2 * hart + PLIC_CTX_M, 2 * hart + PLIC_CTX_S);
+    let (meip, seip) = (plic.is_interrupt_set(ctx_m), plic.is_interrupt_set(ctx_s));
+    sys.ctrl.ip.set(&Interrupt::MExt, meip);
+    sys.ctrl.ip.set(&Interrupt::SExt, seip || sys.ctrl.seip);
 }
 
 pub fn check_interrupt(sys: &mut System) -> Result {
@@ -78,9 +111,9 @@ pub fn pop_trap_m(sys: &mut System) {
         sys.ctrl.mprv = false;
     }
     // Jump back to original PC
-    *sys.pc_mut() = sys.ctrl.mepc;
+    *sys.pc_mut() = sys.ctrl.mepc & sys.ctrl.pc_mask();
     // Also clear LR reservation
-    sys.mem.clear_reservation();
+    sys.mem.clear_reservation(sys.hart_id);
 }
 
 pub fn push_trap_s(sys: &mut System, trap: Trap) {
@@ -107,9 +140,9 @@ pub fn pop_trap_s(sys: &mut System) {
     // Clear MPRV (since SRET always change the privilege mode to either S or U)
     sys.ctrl.mprv = false;
     // Jump back to original PC
-    *sys.pc_mut() = sys.ctrl.sepc;
+    *sys.pc_mut() = sys.ctrl.sepc & sys.ctrl.pc_mask();
     // Also clear LR reservation
-    sys.mem.clear_reservation();
+    sys.mem.clear_reservation(sys.hart_id);
 }
 
 // -------------- Trap handling -----------------
@@ -1,10 +1,15 @@
 use crate::{
-    sys::{log_with_pc, mem_map::dtb::Dtb},
+    elf::{is_elf, Elf, Segment},
+    sys::{log_with_pc, mem_map::dtb::Dtb, StopReason},
     trap::TrapCause,
     Exception, System, Trap,
 };
 use colored::*;
-use std::{fs, io, path::Path};
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
 
 pub fn load_image_from_file<P>(sys: &mut System, file_name: P, addr: u64) -> io::Result<()>
 where
@@ -20,9 +25,78 @@ where
         false,
     );
     sys.mem.ram.as_u8_mut()[addr..end].copy_from_slice(&image);
+    sys.mem.icache.flush();
     Ok(())
 }
 
+pub fn load_elf_from_file<P>(sys: &mut System, file_name: P) -> io::Result<u32>
+where
+    P: AsRef<Path>,
+{
+    let elf = Elf::parse(&fs::read(file_name)?)?;
+    log_with_pc(
+        sys,
+        &format!(
+            "{} with {} segments, entry at 0x{:08x}",
+            "Load elf".blue(),
+            elf.segments.len(),
+            elf.entry
+        ),
+        false,
+    );
+    for seg in elf.segments.iter() {
+        load_segment(sys, seg)?;
+    }
+    sys.symbols.extend(elf.symbols);
+    Ok(elf.entry)
+}
+
+// Load an ELF file at its own addresses, or a flat binary at addr (offset from RAM base).
+// Returns the entry point if the file is an ELF.
+pub fn load_binary_or_elf_from_file<P>(
+    sys: &mut System,
+    file_name: P,
+    addr: u64,
+) -> io::Result<Option<u32>>
+where
+    P: AsRef<Path>,
+{
+    let mut magic = [0; 4];
+    let len = io::Read::read(&mut fs::File::open(&file_name)?, &mut magic)?;
+    if is_elf(&magic[..len]) {
+        Ok(Some(load_elf_from_file(sys, file_name)?))
+    } else {
+        load_image_from_file(sys, file_name, addr)?;
+        Ok(None)
+    }
+}
+
+pub fn load_segment(sys: &mut System, seg: &Segment) -> io::Result<()> {
+    let len = seg.mem_size as u64;
+    // Prefer the physical address, but fall back to the virtual one
+    // for images linked with a load address outside of RAM
+    let addr = [seg.paddr, seg.vaddr]
+        .into_iter()
+        .map(|a| a as u64)
+        .find(|a| sys.mem.is_ram(*a..a + len))
+        .unwrap_or(seg.paddr as u64);
+    // Zero the rest of the segment (.bss)
+    let mut data = seg.data.clone();
+    data.resize(len as usize, 0);
+    // The parts in RAM are loaded even if the rest is not
+    let res = sys.mem.load(addr, &data);
+    sys.mem.icache.flush();
+    res.map_err(|r| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{:?}: 0x{:08x}..0x{:08x} is not in RAM",
+                seg, r.start, r.end
+            ),
+        )
+    })
+}
+
 pub fn load_dtb_from_file<P>(sys: &mut System, file_name: P) -> io::Result<()>
 where
     P: AsRef<Path>,
@@ -41,6 +115,37 @@ where
     Ok(())
 }
 
+pub fn save_snapshot_to_file<P>(sys: &System, file_name: P) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let snapshot = sys.save_snapshot();
+    log_with_pc(
+        sys,
+        &format!("{} with {} bytes", "Save snapshot".blue(), snapshot.len()),
+        false,
+    );
+    fs::write(file_name, snapshot)
+}
+
+pub fn restore_snapshot_from_file<P>(sys: &mut System, file_name: P) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let snapshot = fs::read(file_name)?;
+    sys.restore_snapshot(&snapshot)?;
+    log_with_pc(
+        sys,
+        &format!(
+            "{} with {} bytes",
+            "Restore snapshot".blue(),
+            snapshot.len()
+        ),
+        false,
+    );
+    Ok(())
+}
+
 pub fn run_until_trapped(sys: &mut System) -> Trap {
     loop {
         if let Err(trap) = sys.step() {
@@ -90,10 +195,25 @@ pub fn run_for(sys: &mut System, repeat: usize) {
     }
 }
 
-pub fn run_forever(sys: &mut System) {
+// Run until the machine shuts down or resets
+pub fn run_forever(sys: &mut System) -> StopReason {
     loop {
+        if let Some(stop) = sys.stop {
+            return stop;
+        }
+        let _ = sys.step();
+    }
+}
+
+// Returns None if the machine is still running after repeat steps
+pub fn run_for_or_until_stop(sys: &mut System, repeat: usize) -> Option<StopReason> {
+    for _ in 0..repeat {
+        if sys.stop.is_some() {
+            break;
+        }
         let _ = sys.step();
     }
+    sys.stop
 }
 
 pub fn run_for_or_until_ecall(sys: &mut System, repeat: usize) -> Result<(), Exception> {
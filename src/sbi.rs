@@ -0,0 +1,449 @@
+use crate::{
+    exec::csr::supervisor::SATP_ASID_MASK,
+    instr::reg::Reg,
+    sys::{
+        control::{Control, ExceptionMap, InterruptMap, MPriv, SatpMode},
+        log_with_pc,
+        mem_map::{AccessAttr, AccessType, AccessWidth},
+        state::State,
+        StopReason,
+    },
+    tlb::Tlb,
+    Result, System,
+};
+use colored::*;
+
+// Built-in SBI firmware:
+// - Ecalls from S-mode are serviced by the simulator, as if M-mode software did it
+// - Implements SBI v2.0 base, TIME, IPI, RFENCE, HSM, SRST and DBCN, and the legacy console
+// - The kernel starts in S-mode on hart 0, and the other harts wait for HSM hart_start
+
+const SBI_SPEC_VERSION: u32 = 2 << 24; // 2.0
+const SBI_IMPL_ID: u32 = 0xfff; // Not a registered implementation
+const SBI_IMPL_VERSION: u32 = 1;
+
+// Extension IDs
+const EXT_LEGACY_PUTCHAR: u32 = 0x01;
+const EXT_LEGACY_GETCHAR: u32 = 0x02;
+const EXT_BASE: u32 = 0x10;
+const EXT_TIME: u32 = 0x5449_4d45;
+const EXT_IPI: u32 = 0x0073_5049;
+const EXT_RFENCE: u32 = 0x5246_4e43;
+const EXT_HSM: u32 = 0x0048_534d;
+const EXT_SRST: u32 = 0x5352_5354;
+const EXT_DBCN: u32 = 0x4442_434e;
+
+// Error codes
+const SBI_ERR_FAILED: i32 = -1;
+const SBI_ERR_NOT_SUPPORTED: i32 = -2;
+const SBI_ERR_INVALID_PARAM: i32 = -3;
+const SBI_ERR_INVALID_ADDRESS: i32 = -5;
+const SBI_ERR_ALREADY_AVAILABLE: i32 = -6;
+
+// HSM states and suspend types
+const HSM_STARTED: u32 = 0;
+const HSM_STOPPED: u32 = 1;
+const HSM_SUSPEND_RETENTIVE: u32 = 0x0000_0000;
+const HSM_SUSPEND_NON_RETENTIVE: u32 = 0x8000_0000;
+
+// Exceptions (misaligned, access faults, illegal instruction, breakpoint, ecall from U,
+// page faults) and interrupts (SSI, STI, SEI) handled by S-mode
+const MEDELEG: u32 = 0xb1ff;
+const MIDELEG: u32 = 0x222;
+
+// Flush the whole TLB rather than many pages
+const RFENCE_MAX_PAGES: u32 = 64;
+
+// Value of a1 or error code in a0
+type SbiResult = core::result::Result<u32, i32>;
+
+// Machine-mode setup done by the firmware before entering S-mode
+pub fn init_hart(ctrl: &mut Control) {
+    ctrl.privilege = MPriv::S;
+    ctrl.medeleg = ExceptionMap(MEDELEG);
+    ctrl.mideleg = InterruptMap(MIDELEG);
+    // Counters and stimecmp are available to S-mode
+    ctrl.mcycle_en = true;
+    ctrl.mtime_en = true;
+    ctrl.minstret_en = true;
+    ctrl.stce = true;
+    // S-mode can access all the memory (NAPOT, RWX)
+    ctrl.pmp.write_addr(0, u32::MAX);
+    ctrl.pmp.write_cfg(0, 0x1f);
+}
+
+pub fn handle_ecall(sys: &mut System) -> Result {
+    let ext = sys.reg(&Reg::new(17)) as u32;
+    let fid = sys.reg(&Reg::new(16)) as u32;
+    let args: Vec<u32> = (10..16).map(|i| sys.reg(&Reg::new(i)) as u32).collect();
+
+    // Return to the next instruction (unless the call jumps elsewhere)
+    *sys.pc_mut() = sys.next_pc();
+
+    // Legacy extensions only return a value in a0
+    match ext {
+        EXT_LEGACY_PUTCHAR => {
+            sys.mem.uart.console_write(&[args[0] as u8]);
+            *sys.reg_mut(&Reg::new(10)) = 0;
+            return Ok(());
+        }
+        EXT_LEGACY_GETCHAR => {
+            *sys.reg_mut(&Reg::new(10)) = sys.mem.uart.console_read().map_or(-1, |c| c as i32);
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    let res = match ext {
+        EXT_BASE => base(fid, &args),
+        EXT_TIME => time(sys, fid, &args),
+        EXT_IPI => ipi(sys, fid, &args),
+        EXT_RFENCE => rfence(sys, fid, &args),
+        EXT_HSM => hsm(sys, fid, &args),
+        EXT_SRST => srst(sys, fid, &args),
+        EXT_DBCN => dbcn(sys, fid, &args),
+        _ => Err(SBI_ERR_NOT_SUPPORTED),
+    };
+    match res {
+        Ok(val) => {
+            *sys.reg_mut(&Reg::new(10)) = 0;
+            *sys.reg_mut(&Reg::new(11)) = val as i32;
+        }
+        Err(err) => *sys.reg_mut(&Reg::new(10)) = err,
+    }
+    Ok(())
+}
+
+// ------------------- BASE ---------------------
+fn base(fid: u32, args: &[u32]) -> SbiResult {
+    match fid {
+        0 => Ok(SBI_SPEC_VERSION),
+        1 => Ok(SBI_IMPL_ID),
+        2 => Ok(SBI_IMPL_VERSION),
+        3 => Ok(is_supported(args[0]) as u32),
+        4..=6 => Ok(0), // mvendorid, marchid, mimpid
+        _ => Err(SBI_ERR_NOT_SUPPORTED),
+    }
+}
+
+fn is_supported(ext: u32) -> bool {
+    matches!(
+        ext,
+        EXT_LEGACY_PUTCHAR
+            | EXT_LEGACY_GETCHAR
+            | EXT_BASE
+            | EXT_TIME
+            | EXT_IPI
+            | EXT_RFENCE
+            | EXT_HSM
+            | EXT_SRST
+            | EXT_DBCN
+    )
+}
+
+// ------------------- TIME ---------------------
+fn time(sys: &mut System, fid: u32, args: &[u32]) -> SbiResult {
+    match fid {
+        0 => {
+            // STIP follows stimecmp (menvcfg.STCE is set)
+            sys.ctrl.stimecmp = args[0] as u64 | (args[1] as u64) << 32;
+            Ok(0)
+        }
+        _ => Err(SBI_ERR_NOT_SUPPORTED),
+    }
+}
+
+// Harts selected by hart_mask and hart_mask_base (-1 means all harts)
+fn selected_harts(sys: &System, mask: u32, base: u32) -> core::result::Result<Vec<usize>, i32> {
+    if base == u32::MAX {
+        return Ok((0..sys.num_harts()).collect());
+    }
+    let ids: Vec<usize> = (0..32)
+        .filter(|i| mask >> i & 1 != 0)
+        .map(|i| base as usize + i)
+        .collect();
+    if ids.iter().any(|id| *id >= sys.num_harts()) {
+        Err(SBI_ERR_INVALID_PARAM)
+    } else {
+        Ok(ids)
+    }
+}
+
+// -------------------- IPI ---------------------
+fn ipi(sys: &mut System, fid: u32, args: &[u32]) -> SbiResult {
+    match fid {
+        0 => {
+            // Raise SSIP through the SSWI
+            for id in selected_harts(sys, args[0], args[1])? {
+                sys.mem.sswi.write(4 * id as u64, 1);
+            }
+            Ok(0)
+        }
+        _ => Err(SBI_ERR_NOT_SUPPORTED),
+    }
+}
+
+// ------------------ RFENCE --------------------
+fn rfence(sys: &mut System, fid: u32, args: &[u32]) -> SbiResult {
+    let asid = match fid {
+        0 => {
+            // remote_fence_i (the decode cache is shared by all harts)
+            selected_harts(sys, args[0], args[1])?;
+            sys.mem.icache.flush();
+            return Ok(0);
+        }
+        1 => None,
+        2 => Some(args[4] & SATP_ASID_MASK),
+        _ => return Err(SBI_ERR_NOT_SUPPORTED),
+    };
+    // remote_sfence_vma(_asid)
+    for id in selected_harts(sys, args[0], args[1])? {
+        let (_, ctrl) = sys.hart_context_mut(id);
+        flush_tlb(&mut ctrl.tlb, args[2], args[3], asid);
+    }
+    Ok(0)
+}
+
+fn flush_tlb(tlb: &mut Tlb, start: u32, size: u32, asid: Option<u32>) {
+    if start == 0 && size == 0 || size > RFENCE_MAX_PAGES * 4096 {
+        tlb.flush(None, asid);
+    } else {
+        let end = start as u64 + size as u64;
+        for addr in (start as u64 & !0xfff..end).step_by(4096) {
+            tlb.flush(Some(addr as u32), asid);
+        }
+    }
+}
+
+// -------------------- HSM ---------------------
+fn hsm(sys: &mut System, fid: u32, args: &[u32]) -> SbiResult {
+    match fid {
+        0 => {
+            // hart_start
+            let id = args[0] as usize;
+            if id >= sys.num_harts() {
+                Err(SBI_ERR_INVALID_PARAM)?
+            }
+            if !sys.harts[id].stopped {
+                Err(SBI_ERR_ALREADY_AVAILABLE)?
+            }
+            check_ram(sys, args[1] as u64, 4).map_err(|_| SBI_ERR_INVALID_ADDRESS)?;
+            let (state, ctrl) = sys.hart_context_mut(id);
+            enter_supervisor(state, ctrl, id, args[1], args[2]);
+            sys.harts[id].stopped = false;
+            Ok(0)
+        }
+        1 => {
+            // hart_stop (the last running hart cannot stop)
+            let id = sys.hart_id;
+            if sys
+                .harts
+                .iter()
+                .enumerate()
+                .all(|(i, h)| i == id || h.stopped)
+            {
+                Err(SBI_ERR_FAILED)?
+            }
+            sys.harts[id].stopped = true;
+            Ok(0)
+        }
+        2 => {
+            // hart_get_status
+            let id = args[0] as usize;
+            match sys.harts.get(id) {
+                Some(hart) if hart.stopped => Ok(HSM_STOPPED),
+                Some(_) => Ok(HSM_STARTED),
+                None => Err(SBI_ERR_INVALID_PARAM),
+            }
+        }
+        3 => match args[0] {
+            // hart_suspend (resumes at once, as if woken up by an interrupt)
+            HSM_SUSPEND_RETENTIVE => Ok(0),
+            HSM_SUSPEND_NON_RETENTIVE => {
+                check_ram(sys, args[1] as u64, 4).map_err(|_| SBI_ERR_INVALID_ADDRESS)?;
+                let id = sys.hart_id;
+                enter_supervisor(&mut sys.state, &mut sys.ctrl, id, args[1], args[2]);
+                Ok(0)
+            }
+            _ => Err(SBI_ERR_INVALID_PARAM),
+        },
+        _ => Err(SBI_ERR_NOT_SUPPORTED),
+    }
+}
+
+// Jump to addr in S-mode with a0 = hartid, a1 = opaque and translation and interrupts off
+fn enter_supervisor(state: &mut State, ctrl: &mut Control, id: usize, addr: u32, opaque: u32) {
+    ctrl.privilege = MPriv::S;
+    ctrl.satp_mode = SatpMode::Bare;
+    ctrl.sie = false;
+    ctrl.tlb.flush_all();
+    state.pc = addr;
+    state.regs[10] = id as i32;
+    state.regs[11] = opaque as i32;
+}
+
+// -------------------- SRST --------------------
+fn srst(sys: &mut System, fid: u32, args: &[u32]) -> SbiResult {
+    match (fid, args[0]) {
+        // Shutdown, with a failure if there's a reason
+        (0, 0) => {
+            let code = (args[1] != 0) as i32;
+            log_with_pc(
+                sys,
+                &format!("{} (reason {})", "Shutdown".blue(), args[1]),
+                false,
+            );
+            sys.stop = Some(StopReason::Exit(code));
+            Ok(0)
+        }
+        // Cold and warm reboots both reset the machine
+        (0, 1..=2) => {
+            log_with_pc(sys, &format!("{}", "Reboot".blue()), false);
+            sys.stop = Some(StopReason::Reset);
+            Ok(0)
+        }
+        (0, _) => Err(SBI_ERR_INVALID_PARAM),
+        _ => Err(SBI_ERR_NOT_SUPPORTED),
+    }
+}
+
+// -------------------- DBCN --------------------
+fn dbcn(sys: &mut System, fid: u32, args: &[u32]) -> SbiResult {
+    let (len, addr) = (args[0], args[1] as u64 | (args[2] as u64) << 32);
+    match fid {
+        0 => {
+            // console_write
+            check_ram(sys, addr, len as u64)?;
+            let start = (addr - sys.mem.ram_base) as usize;
+            let bytes = sys.mem.ram.as_u8()[start..start + len as usize].to_vec();
+            sys.mem.uart.console_write(&bytes);
+            Ok(len)
+        }
+        1 => {
+            // console_read (only what has been received)
+            check_ram(sys, addr, len as u64)?;
+            let attr = AccessAttr {
+                atype: AccessType::Store,
+                width: AccessWidth::Byte,
+                lrsc: false,
+                amo: false,
+            };
+            let mut count = 0;
+            while count < len {
+                let Some(c) = sys.mem.uart.console_read() else {
+                    break;
+                };
+                sys.mem.write_u8(addr + count as u64, c, attr).unwrap();
+                count += 1;
+            }
+            Ok(count)
+        }
+        2 => {
+            // console_write_byte
+            sys.mem.uart.console_write(&[args[0] as u8]);
+            Ok(0)
+        }
+        _ => Err(SBI_ERR_NOT_SUPPORTED),
+    }
+}
+
+fn check_ram(sys: &System, addr: u64, len: u64) -> core::result::Result<(), i32> {
+    let ram_range = sys.mem.ram_base..=(sys.mem.ram_base + sys.mem.ram.size());
+    if ram_range.contains(&addr) && ram_range.contains(&(addr + len)) {
+        Ok(())
+    } else {
+        Err(SBI_ERR_INVALID_PARAM)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    fn make_sys(harts: u8) -> System {
+        let mut cfg = Config::new();
+        cfg.sbi = true;
+        cfg.harts = harts;
+        cfg.quantum = 1;
+        System::from_config(cfg)
+    }
+
+    fn call(sys: &mut System, ext: u32, fid: u32, args: &[u32]) -> (i32, u32) {
+        for (i, arg) in args.iter().enumerate() {
+            *sys.reg_mut(&Reg::new(10 + i as u8)) = *arg as i32;
+        }
+        *sys.reg_mut(&Reg::new(17)) = ext as i32;
+        *sys.reg_mut(&Reg::new(16)) = fid as i32;
+        handle_ecall(sys).unwrap();
+        (sys.reg(&Reg::new(10)), sys.reg(&Reg::new(11)) as u32)
+    }
+
+    #[test]
+    fn test_boot() {
+        let sys = make_sys(2);
+        assert_eq!(sys.ctrl.privilege, MPriv::S);
+        assert_eq!(sys.pc(), 0x0040_0000);
+        assert_eq!(sys.reg(&Reg::new(11)) as u32 as u64, sys.mem.dtb_base);
+        assert!(!sys.harts[0].stopped && sys.harts[1].stopped);
+    }
+
+    #[test]
+    fn test_base_and_time() {
+        let mut sys = make_sys(1);
+        assert_eq!(call(&mut sys, EXT_BASE, 0, &[]), (0, SBI_SPEC_VERSION));
+        assert_eq!(call(&mut sys, EXT_BASE, 3, &[EXT_HSM]), (0, 1));
+        assert_eq!(call(&mut sys, EXT_BASE, 3, &[0x1234]), (0, 0));
+        assert_eq!(call(&mut sys, 0x1234, 0, &[]).0, SBI_ERR_NOT_SUPPORTED);
+        assert_eq!(sys.pc(), 0x0040_0010);
+
+        call(&mut sys, EXT_TIME, 0, &[0x5, 0x1]);
+        assert_eq!(sys.ctrl.stimecmp, 0x1_0000_0005);
+    }
+
+    #[test]
+    fn test_hsm_and_ipi() {
+        let mut sys = make_sys(2);
+        assert_eq!(call(&mut sys, EXT_HSM, 2, &[1]), (0, HSM_STOPPED));
+        assert_eq!(call(&mut sys, EXT_HSM, 1, &[]).0, SBI_ERR_FAILED);
+        assert_eq!(call(&mut sys, EXT_HSM, 0, &[1, 0x1000, 0x42]), (0, 0));
+        assert_eq!(
+            call(&mut sys, EXT_HSM, 0, &[1, 0x1000, 0x42]).0,
+            SBI_ERR_ALREADY_AVAILABLE
+        );
+        assert_eq!(call(&mut sys, EXT_HSM, 2, &[1]), (0, HSM_STARTED));
+
+        // IPI to all harts
+        assert_eq!(call(&mut sys, EXT_IPI, 0, &[0, u32::MAX]), (0, 0));
+        assert_eq!(
+            call(&mut sys, EXT_IPI, 0, &[0b100, 0]).0,
+            SBI_ERR_INVALID_PARAM
+        );
+        sys.switch_hart(1);
+        assert_eq!(sys.pc(), 0x1000);
+        assert_eq!(sys.ctrl.privilege, MPriv::S);
+        assert_eq!((sys.reg(&Reg::new(10)), sys.reg(&Reg::new(11))), (1, 0x42));
+        assert!(sys.mem.sswi.take_interrupt(1));
+
+        // Hart 1 stops, so the scheduler switches back to hart 0
+        call(&mut sys, EXT_HSM, 1, &[]);
+        let _ = sys.step();
+        assert_eq!(sys.hart_id, 0);
+    }
+
+    #[test]
+    fn test_srst_and_dbcn() {
+        let mut sys = make_sys(1);
+        sys.mem.ram.as_u8_mut()[0x100..0x103].copy_from_slice(b"ok\n");
+        assert_eq!(call(&mut sys, EXT_DBCN, 0, &[3, 0x100, 0]), (0, 3));
+        assert_eq!(
+            call(&mut sys, EXT_DBCN, 0, &[3, 0x100, 1]).0,
+            SBI_ERR_INVALID_PARAM
+        );
+
+        assert_eq!(call(&mut sys, EXT_SRST, 0, &[1, 0]).0, 0);
+        assert_eq!(sys.stop, Some(StopReason::Reset));
+        call(&mut sys, EXT_SRST, 0, &[0, 1]);
+        assert_eq!(sys.stop, Some(StopReason::Exit(1)));
+    }
+}
@@ -0,0 +1,480 @@
+use crate::{
+    instr::reg::Reg,
+    linux::{guest_range, read_guest, write_guest},
+    sys::{
+        control::{MPriv, SatpMode},
+        log_with_pc,
+        mem_map::AccessType,
+        StopReason,
+    },
+    translate::effective_privilege,
+    trap::TrapCause,
+    Exception, Result, System, Trap,
+};
+use colored::*;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+// RISC-V semihosting (the ARM semihosting calls, for newlib and the proxy kernel):
+// - A call is an ebreak between "slli x0, x0, 0x1f" and "srai x0, x0, 7"
+// - a0 is the operation, a1 the parameter (usually a block of 32-bit words), and a0 the result
+// - Files are host files, and ":tt" is the console (host stdin, stdout and stderr)
+// - Addresses are physical, so calls are only honoured without address translation
+//   (otherwise the ebreak traps as any other)
+
+const SLLI_X0_X0_0X1F: u32 = 0x01f0_1013;
+const EBREAK: u32 = 0x0010_0073;
+const SRAI_X0_X0_7: u32 = 0x4070_5013;
+
+// Operations
+const SYS_OPEN: u32 = 0x01;
+const SYS_CLOSE: u32 = 0x02;
+const SYS_WRITEC: u32 = 0x03;
+const SYS_WRITE0: u32 = 0x04;
+const SYS_WRITE: u32 = 0x05;
+const SYS_READ: u32 = 0x06;
+const SYS_READC: u32 = 0x07;
+const SYS_ISERROR: u32 = 0x08;
+const SYS_ISTTY: u32 = 0x09;
+const SYS_SEEK: u32 = 0x0a;
+const SYS_FLEN: u32 = 0x0c;
+const SYS_REMOVE: u32 = 0x0e;
+const SYS_RENAME: u32 = 0x0f;
+const SYS_CLOCK: u32 = 0x10;
+const SYS_TIME: u32 = 0x11;
+const SYS_ERRNO: u32 = 0x13;
+const SYS_GET_CMDLINE: u32 = 0x15;
+const SYS_HEAPINFO: u32 = 0x16;
+const SYS_EXIT: u32 = 0x18;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+const SYS_ELAPSED: u32 = 0x30;
+const SYS_TICKFREQ: u32 = 0x31;
+
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x2_0026;
+const TICK_FREQ: u64 = 1_000_000;
+
+// Error numbers of the calls not made on the host
+const EBADF: i32 = 9;
+const EFAULT: i32 = 14;
+const EINVAL: i32 = 22;
+
+#[derive(Debug)]
+enum SemiFile {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+// Open files are not part of the snapshot, so --semihosting cannot be combined with
+// --snapshot or --restore
+#[derive(Debug)]
+pub struct Semihosting {
+    files: Vec<Option<SemiFile>>, // Handle 0 is never used
+    errno: i32,
+    cmdline: String,
+    start: Instant,
+}
+
+impl Semihosting {
+    pub fn new(cmdline: String) -> Semihosting {
+        Semihosting {
+            files: vec![None],
+            errno: 0,
+            cmdline,
+            start: Instant::now(),
+        }
+    }
+}
+
+// Service a semihosting call, or return the trap of any other ebreak
+pub fn handle_trap(sys: &mut System, trap: Trap) -> Result {
+    if trap.cause != TrapCause::Exception(Exception::Breakpoint) || !is_semihosting_call(sys) {
+        return Err(trap);
+    }
+    let op = sys.reg(&Reg::new(10)) as u32;
+    let param = sys.reg(&Reg::new(11)) as u32;
+
+    let Some(mut semi) = sys.semihosting.take() else {
+        return Err(trap);
+    };
+    let res = semi.call(sys, op, param);
+    if let Err(errno) = res {
+        semi.errno = errno;
+    }
+    sys.semihosting = Some(semi);
+
+    log_with_pc(
+        sys,
+        &format!(
+            "{} 0x{op:x}(0x{param:x}) = {:x?}",
+            "Semihosting".blue(),
+            res
+        ),
+        true,
+    );
+    *sys.reg_mut(&Reg::new(10)) = res.unwrap_or(-1);
+    // Continue after the ebreak (srai is a nop)
+    *sys.pc_mut() = sys.pc().wrapping_add(4);
+    Ok(())
+}
+
+fn is_semihosting_call(sys: &System) -> bool {
+    if !is_untranslated(sys) {
+        return false;
+    }
+    let pc = sys.pc();
+    let Ok(code) = read_guest(sys, pc.wrapping_sub(4), 12) else {
+        return false;
+    };
+    let word = |i: usize| u32::from_le_bytes(code[4 * i..4 * i + 4].try_into().unwrap());
+    word(0) == SLLI_X0_X0_0X1F && word(1) == EBREAK && word(2) == SRAI_X0_X0_7
+}
+
+// Both the code and the parameters must be at physical addresses
+fn is_untranslated(sys: &System) -> bool {
+    sys.ctrl.satp_mode == SatpMode::Bare
+        || [AccessType::Instr, AccessType::Load, AccessType::Store]
+            .into_iter()
+            .all(|atype| effective_privilege(sys, atype) == MPriv::M)
+}
+
+// Result in a0, or error number for SYS_ERRNO (a0 is then -1)
+type SemiResult = core::result::Result<i32, i32>;
+
+impl Semihosting {
+    fn call(&mut self, sys: &mut System, op: u32, param: u32) -> SemiResult {
+        // Parameter blocks are arrays of words
+        let args = |n: u32| -> core::result::Result<Vec<u32>, i32> {
+            Ok(read_guest(sys, param, 4 * n)?
+                .chunks_exact(4)
+                .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+                .collect())
+        };
+        match op {
+            SYS_OPEN => {
+                let args = args(3)?;
+                let name = read_string(sys, args[0], args[2])?;
+                self.open(&name, args[1])
+            }
+            SYS_CLOSE => {
+                let handle = args(1)?[0];
+                self.file(handle)?;
+                self.files[handle as usize] = None;
+                Ok(0)
+            }
+            SYS_WRITEC => {
+                let c = read_guest(sys, param, 1)?;
+                io::stdout().write_all(&c).map_err(errno)?;
+                io::stdout().flush().map_err(errno)?;
+                Ok(0)
+            }
+            SYS_WRITE0 => {
+                let start = guest_range(sys, param, 0)?.start;
+                let bytes = &sys.mem.ram.as_u8()[start..];
+                let len = bytes.iter().position(|&c| c == 0).ok_or(EFAULT)?;
+                io::stdout().write_all(&bytes[..len]).map_err(errno)?;
+                io::stdout().flush().map_err(errno)?;
+                Ok(0)
+            }
+            // Returns the number of bytes not written
+            SYS_WRITE => {
+                let args = args(3)?;
+                let buf = read_guest(sys, args[1], args[2])?;
+                match self.file(args[0])? {
+                    SemiFile::Stdout => io::stdout().write_all(&buf).and(io::stdout().flush()),
+                    SemiFile::Stderr => io::stderr().write_all(&buf),
+                    SemiFile::File(f) => f.write_all(&buf),
+                    SemiFile::Stdin => return Err(EBADF),
+                }
+                .map_err(errno)?;
+                Ok(0)
+            }
+            // Returns the number of bytes not read
+            SYS_READ => {
+                let args = args(3)?;
+                guest_range(sys, args[1], args[2])?;
+                let mut buf = vec![0; args[2] as usize];
+                let n = match self.file(args[0])? {
+                    SemiFile::Stdin => io::stdin().read(&mut buf),
+                    SemiFile::File(f) => f.read(&mut buf),
+                    _ => return Err(EBADF),
+                }
+                .map_err(errno)?;
+                write_guest(sys, args[1], &buf[..n])?;
+                Ok((args[2] as usize - n) as i32)
+            }
+            SYS_READC => {
+                let mut c = [0];
+                io::stdin().read_exact(&mut c).map_err(errno)?;
+                Ok(c[0] as i32)
+            }
+            SYS_ISERROR => Ok(((args(1)?[0] as i32) < 0) as i32),
+            SYS_ISTTY => match self.file(args(1)?[0])? {
+                SemiFile::File(_) => Ok(0),
+                _ => Ok(1),
+            },
+            SYS_SEEK => {
+                let args = args(2)?;
+                let SemiFile::File(f) = self.file(args[0])? else {
+                    return Err(EBADF);
+                };
+                f.seek(SeekFrom::Start(args[1] as u64)).map_err(errno)?;
+                Ok(0)
+            }
+            SYS_FLEN => {
+                let SemiFile::File(f) = self.file(args(1)?[0])? else {
+                    return Err(EBADF);
+                };
+                Ok(f.metadata().map_err(errno)?.len() as i32)
+            }
+            SYS_REMOVE => {
+                let args = args(2)?;
+                fs::remove_file(read_string(sys, args[0], args[1])?).map_err(errno)?;
+                Ok(0)
+            }
+            SYS_RENAME => {
+                let args = args(4)?;
+                let from = read_string(sys, args[0], args[1])?;
+                let to = read_string(sys, args[2], args[3])?;
+                fs::rename(from, to).map_err(errno)?;
+                Ok(0)
+            }
+            // Centiseconds since the start
+            SYS_CLOCK => Ok((self.start.elapsed().as_millis() / 10) as i32),
+            SYS_TIME => Ok(SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |t| t.as_secs() as i32)),
+            SYS_ERRNO => Ok(self.errno),
+            SYS_GET_CMDLINE => {
+                let args = args(2)?;
+                let cmdline = format!("{}\0", self.cmdline);
+                if cmdline.len() > args[1] as usize {
+                    return Err(EINVAL);
+                }
+                write_guest(sys, args[0], cmdline.as_bytes())?;
+                // The length doesn't include the terminating nul
+                let len = (cmdline.len() as u32 - 1).to_le_bytes();
+                write_guest(sys, param + 4, &len)?;
+                Ok(0)
+            }
+            // Zeros let the C runtime use the addresses it was linked with
+            SYS_HEAPINFO => {
+                write_guest(sys, args(1)?[0], &[0; 16])?;
+                Ok(0)
+            }
+            // The status is 0 if the program stopped normally
+            SYS_EXIT => {
+                self.exit(sys, param, 0);
+                Ok(0)
+            }
+            SYS_EXIT_EXTENDED => {
+                let args = args(2)?;
+                self.exit(sys, args[0], args[1]);
+                Ok(0)
+            }
+            SYS_ELAPSED => {
+                let ticks = self.start.elapsed().as_micros() as u64 * TICK_FREQ / 1_000_000;
+                write_guest(sys, param, &ticks.to_le_bytes())?;
+                Ok(0)
+            }
+            SYS_TICKFREQ => Ok(TICK_FREQ as i32),
+            _ => {
+                log_with_pc(
+                    sys,
+                    &format!("{} 0x{op:x}", "Unsupported semihosting call".yellow()),
+                    true,
+                );
+                Err(EINVAL)
+            }
+        }
+    }
+
+    fn exit(&self, sys: &mut System, reason: u32, subcode: u32) {
+        let code = if reason == ADP_STOPPED_APPLICATION_EXIT {
+            subcode as i32
+        } else {
+            1
+        };
+        log_with_pc(
+            sys,
+            &format!("{} (reason 0x{reason:x}, code {code})", "Exit".blue()),
+            true,
+        );
+        sys.stop = Some(StopReason::Exit(code));
+    }
+
+    fn file(&mut self, handle: u32) -> core::result::Result<&mut SemiFile, i32> {
+        match self.files.get_mut(handle as usize) {
+            Some(Some(file)) => Ok(file),
+            _ => Err(EBADF),
+        }
+    }
+
+    // Modes are those of fopen: r, rb, r+, r+b, w, wb, w+, w+b, a, ab, a+, a+b
+    fn open(&mut self, name: &str, mode: u32) -> SemiResult {
+        if mode > 11 {
+            return Err(EINVAL);
+        }
+        let file = if name == ":tt" {
+            match mode / 4 {
+                0 => SemiFile::Stdin,
+                1 => SemiFile::Stdout,
+                _ => SemiFile::Stderr,
+            }
+        } else {
+            let plus = mode & 2 != 0;
+            let file = match mode / 4 {
+                0 => OpenOptions::new().read(true).write(plus).open(name),
+                1 => OpenOptions::new()
+                    .read(plus)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(name),
+                _ => OpenOptions::new()
+                    .read(plus)
+                    .append(true)
+                    .create(true)
+                    .open(name),
+            };
+            SemiFile::File(file.map_err(errno)?)
+        };
+        let handle = match self.files.iter().skip(1).position(|f| f.is_none()) {
+            Some(i) => i + 1,
+            None => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+        };
+        self.files[handle] = Some(file);
+        Ok(handle as i32)
+    }
+}
+
+fn errno(e: io::Error) -> i32 {
+    e.raw_os_error().unwrap_or(EINVAL)
+}
+
+fn read_string(sys: &System, addr: u32, len: u32) -> core::result::Result<String, i32> {
+    String::from_utf8(read_guest(sys, addr, len)?).map_err(|_| EINVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+    use bytesize::ByteSize;
+
+    fn make_sys() -> System {
+        let mut cfg = Config::new();
+        cfg.size = ByteSize::mib(1);
+        cfg.semihosting = true;
+        let mut sys = System::from_config(cfg);
+        let code: Vec<u8> = [SLLI_X0_X0_0X1F, EBREAK, SRAI_X0_X0_7]
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect();
+        write_guest(&mut sys, 0, &code).unwrap();
+        sys
+    }
+
+    fn write_words(sys: &mut System, addr: u32, words: &[u32]) {
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        write_guest(sys, addr, &bytes).unwrap();
+    }
+
+    fn call(sys: &mut System, op: u32, args: &[u32]) -> i32 {
+        write_words(sys, 0x100, args);
+        *sys.reg_mut(&Reg::new(10)) = op as i32;
+        *sys.reg_mut(&Reg::new(11)) = 0x100;
+        *sys.pc_mut() = 4;
+        handle_trap(sys, Trap::from_exception(Exception::Breakpoint, 0)).unwrap();
+        assert_eq!(sys.pc(), 8);
+        sys.reg(&Reg::new(10))
+    }
+
+    #[test]
+    fn test_files() {
+        let mut sys = make_sys();
+        let path = std::env::temp_dir().join(format!("riscv_sim_semi_{}", std::process::id()));
+        let name = path.display().to_string();
+        write_guest(&mut sys, 0x200, name.as_bytes()).unwrap();
+        write_guest(&mut sys, 0x300, b"hello").unwrap();
+
+        // fopen(name, "w+b")
+        let handle = call(&mut sys, SYS_OPEN, &[0x200, 7, name.len() as u32]);
+        assert_eq!(handle, 1);
+        let handle = handle as u32;
+        assert_eq!(call(&mut sys, SYS_WRITE, &[handle, 0x300, 5]), 0);
+        assert_eq!(call(&mut sys, SYS_FLEN, &[handle]), 5);
+        assert_eq!(call(&mut sys, SYS_ISTTY, &[handle]), 0);
+        assert_eq!(call(&mut sys, SYS_SEEK, &[handle, 1]), 0);
+        assert_eq!(call(&mut sys, SYS_READ, &[handle, 0x400, 10]), 6);
+        assert_eq!(&sys.mem.ram.as_u8()[0x400..0x404], b"ello");
+        assert_eq!(call(&mut sys, SYS_CLOSE, &[handle]), 0);
+        assert_eq!(call(&mut sys, SYS_CLOSE, &[handle]), -1);
+        assert_eq!(call(&mut sys, SYS_ERRNO, &[]), EBADF);
+        assert_eq!(call(&mut sys, SYS_REMOVE, &[0x200, name.len() as u32]), 0);
+        assert_eq!(call(&mut sys, SYS_OPEN, &[0x200, 0, name.len() as u32]), -1);
+
+        // The console
+        write_guest(&mut sys, 0x200, b":tt").unwrap();
+        assert_eq!(call(&mut sys, SYS_OPEN, &[0x200, 4, 3]), 1);
+        assert_eq!(call(&mut sys, SYS_ISTTY, &[1]), 1);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut sys = make_sys();
+        assert!(call(&mut sys, SYS_TIME, &[]) > 0);
+        assert_eq!(call(&mut sys, SYS_TICKFREQ, &[]), TICK_FREQ as i32);
+
+        // The whole sequence runs as 3 instructions
+        write_words(&mut sys, 0x100, &[ADP_STOPPED_APPLICATION_EXIT, 3]);
+        *sys.reg_mut(&Reg::new(10)) = SYS_EXIT_EXTENDED as i32;
+        *sys.pc_mut() = 0;
+        for _ in 0..3 {
+            sys.step().unwrap();
+        }
+        assert_eq!((sys.stop, sys.pc()), (Some(StopReason::Exit(3)), 12));
+
+        // SYS_EXIT only has the reason, in a1
+        *sys.reg_mut(&Reg::new(10)) = SYS_EXIT as i32;
+        *sys.reg_mut(&Reg::new(11)) = 0x2_0023; // Run-time error
+        *sys.pc_mut() = 4;
+        let _ = sys.step();
+        assert_eq!(sys.stop, Some(StopReason::Exit(1)));
+        *sys.reg_mut(&Reg::new(10)) = SYS_EXIT as i32;
+        *sys.reg_mut(&Reg::new(11)) = ADP_STOPPED_APPLICATION_EXIT as i32;
+        *sys.pc_mut() = 4;
+        let _ = sys.step();
+        assert_eq!(sys.stop, Some(StopReason::Exit(0)));
+
+        // Not with address translation
+        sys.stop = None;
+        sys.ctrl.privilege = MPriv::S;
+        sys.ctrl.satp_mode = SatpMode::Sv32;
+        let trap = Trap::from_exception(Exception::Breakpoint, 0);
+        *sys.pc_mut() = 4;
+        assert_eq!(handle_trap(&mut sys, trap), Err(trap));
+        sys.ctrl.privilege = MPriv::M;
+        assert_eq!(handle_trap(&mut sys, trap), Ok(()));
+        sys.ctrl.mprv = true;
+        sys.ctrl.mpp = MPriv::U;
+        *sys.pc_mut() = 4;
+        assert_eq!(handle_trap(&mut sys, trap), Err(trap));
+        sys.ctrl.satp_mode = SatpMode::Bare;
+
+        // Other breakpoints trap
+        *sys.pc_mut() = 0x8;
+        write_words(&mut sys, 0x8, &[EBREAK]);
+        assert_eq!(
+            sys.step(),
+            Err(Trap::from_exception(Exception::Breakpoint, 0))
+        );
+    }
+}
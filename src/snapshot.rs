@@ -0,0 +1,162 @@
+use std::io::{self, ErrorKind};
+
+// Snapshot file layout: magic, version, then each component in a fixed order
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"RVSIMSNP";
+pub const SNAPSHOT_VERSION: u32 = 9;
+
+// Serializes values in little-endian
+pub struct SnapshotWriter {
+    buf: Vec<u8>,
+}
+
+// Deserializes values written by SnapshotWriter
+pub struct SnapshotReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Default for SnapshotWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotWriter {
+    pub fn new() -> SnapshotWriter {
+        let mut w = SnapshotWriter { buf: Vec::new() };
+        w.buf.extend_from_slice(SNAPSHOT_MAGIC);
+        w.put_u32(SNAPSHOT_VERSION);
+        w
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn put_u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn put_bool(&mut self, val: bool) {
+        self.put_u8(val as u8);
+    }
+
+    pub fn put_u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    // Length-prefixed
+    pub fn put_bytes(&mut self, val: &[u8]) {
+        self.put_u64(val.len() as u64);
+        self.buf.extend_from_slice(val);
+    }
+}
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(buf: &'a [u8]) -> io::Result<SnapshotReader<'a>> {
+        let mut r = SnapshotReader { buf, pos: 0 };
+        if r.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(invalid_data("not a snapshot file"));
+        }
+        let version = r.get_u32()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported snapshot version {version}"
+            )));
+        }
+        Ok(r)
+    }
+
+    // Whether everything has been read
+    pub fn is_done(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.buf.len());
+        let end =
+            end.ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "truncated snapshot"))?;
+        let res = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    pub fn get_u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn get_bool(&mut self) -> io::Result<bool> {
+        match self.get_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_data("invalid bool")),
+        }
+    }
+
+    pub fn get_u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn get_u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn get_bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.get_u64()?;
+        let len = usize::try_from(len).map_err(|_| invalid_data("invalid length"))?;
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+// Decode a value with one of the from() functions, which return None if invalid
+pub fn decode<T>(val: Option<T>, what: &str) -> io::Result<T> {
+    val.ok_or_else(|| invalid_data(&format!("invalid {what}")))
+}
+
+pub fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut w = SnapshotWriter::new();
+        w.put_u8(0x12);
+        w.put_bool(true);
+        w.put_u32(0xdeadbeef);
+        w.put_u64(0x0123_4567_89ab_cdef);
+        w.put_bytes(b"abc");
+        let bytes = w.into_bytes();
+
+        let mut r = SnapshotReader::new(&bytes).unwrap();
+        assert_eq!(r.get_u8().unwrap(), 0x12);
+        assert!(r.get_bool().unwrap());
+        assert_eq!(r.get_u32().unwrap(), 0xdeadbeef);
+        assert_eq!(r.get_u64().unwrap(), 0x0123_4567_89ab_cdef);
+        assert_eq!(r.get_bytes().unwrap(), b"abc");
+        assert!(r.is_done());
+        assert_eq!(r.get_u8().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_header() {
+        assert!(SnapshotReader::new(b"RVSIMSNP").is_err());
+        assert!(SnapshotReader::new(b"NOTASNAP\x01\x00\x00\x00").is_err());
+        assert!(SnapshotReader::new(b"RVSIMSNP\x08\x00\x00\x00").is_err());
+        assert!(SnapshotReader::new(b"RVSIMSNP\x09\x00\x00\x00").is_ok());
+    }
+}
@@ -0,0 +1,900 @@
+use std::cmp::Ordering;
+
+// Software IEEE-754 binary32/binary64 arithmetic with RISC-V semantics:
+// all five rounding modes, accrued exception flags, canonical NaNs and
+// tininess detected after rounding. Values are passed around as raw bits.
+
+// Exception flags (same layout as fflags)
+pub const FLAG_NX: u8 = 1 << 0; // Inexact
+pub const FLAG_UF: u8 = 1 << 1; // Underflow
+pub const FLAG_OF: u8 = 1 << 2; // Overflow
+pub const FLAG_DZ: u8 = 1 << 3; // Divide by zero
+pub const FLAG_NV: u8 = 1 << 4; // Invalid operation
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    S,
+    D,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RoundingMode {
+    Rne, // Round to nearest, ties to even
+    Rtz, // Round towards zero
+    Rdn, // Round down (towards -inf)
+    Rup, // Round up (towards +inf)
+    Rmm, // Round to nearest, ties to max magnitude
+}
+
+// Unpacked operand: value = sig * 2^exp (sig is 0 for zeros)
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Nan(bool), // Signaling
+    Inf(bool), // Sign
+    Num(bool, i32, u64),
+}
+
+impl Format {
+    fn exp_bits(self) -> u32 {
+        match self {
+            Format::S => 8,
+            Format::D => 11,
+        }
+    }
+
+    fn frac_bits(self) -> u32 {
+        match self {
+            Format::S => 23,
+            Format::D => 52,
+        }
+    }
+
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits() - 1)) - 1
+    }
+
+    fn exp_max(self) -> u64 {
+        (1 << self.exp_bits()) - 1
+    }
+
+    fn sign_bit(self) -> u64 {
+        1 << (self.exp_bits() + self.frac_bits())
+    }
+
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits()) - 1
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        match self {
+            Format::S => 0x7fc0_0000,
+            Format::D => 0x7ff8_0000_0000_0000,
+        }
+    }
+
+    fn zero(self, sign: bool) -> u64 {
+        if sign {
+            self.sign_bit()
+        } else {
+            0
+        }
+    }
+
+    fn inf(self, sign: bool) -> u64 {
+        self.zero(sign) | self.exp_max() << self.frac_bits()
+    }
+
+    fn max_finite(self, sign: bool) -> u64 {
+        self.inf(sign) - 1
+    }
+
+    fn unpack(self, x: u64) -> Value {
+        let sign = x & self.sign_bit() != 0;
+        let exp = (x >> self.frac_bits()) & self.exp_max();
+        let frac = x & self.frac_mask();
+        let m = self.frac_bits() as i32;
+        if exp == self.exp_max() {
+            if frac == 0 {
+                Value::Inf(sign)
+            } else {
+                Value::Nan(frac >> (m - 1) == 0)
+            }
+        } else if exp == 0 {
+            Value::Num(sign, 1 - self.bias() - m, frac)
+        } else {
+            Value::Num(sign, exp as i32 - self.bias() - m, frac | 1 << m)
+        }
+    }
+}
+
+impl RoundingMode {
+    pub fn from(code: u8) -> Option<RoundingMode> {
+        match code {
+            0b000 => Some(RoundingMode::Rne),
+            0b001 => Some(RoundingMode::Rtz),
+            0b010 => Some(RoundingMode::Rdn),
+            0b011 => Some(RoundingMode::Rup),
+            0b100 => Some(RoundingMode::Rmm),
+            _ => None,
+        }
+    }
+}
+
+// ---------------- Rounding --------------------
+fn shift_right_jam(x: u128, n: i32) -> u128 {
+    // Bits shifted out are ORed into the LSB so that rounding stays correct
+    if n <= 0 {
+        x
+    } else if n >= 128 {
+        (x != 0) as u128
+    } else {
+        x >> n | (x & ((1 << n) - 1) != 0) as u128
+    }
+}
+
+fn shift_round(sig: u128, n: i32, sign: bool, rm: RoundingMode) -> (u128, bool) {
+    // Shift right by n bits and round, returning the result and whether it is inexact
+    if n <= 0 {
+        return (sig << -n, false);
+    }
+    let (q, rem) = if n >= 128 {
+        (0, sig)
+    } else {
+        (sig >> n, sig & ((1 << n) - 1))
+    };
+    let half = if n > 128 {
+        Ordering::Less
+    } else {
+        rem.cmp(&(1 << (n - 1)))
+    };
+    let inexact = rem != 0;
+    let up = match rm {
+        RoundingMode::Rne => half == Ordering::Greater || half == Ordering::Equal && q & 1 != 0,
+        RoundingMode::Rmm => half != Ordering::Less,
+        RoundingMode::Rtz => false,
+        RoundingMode::Rdn => inexact && sign,
+        RoundingMode::Rup => inexact && !sign,
+    };
+    (q + up as u128, inexact)
+}
+
+fn overflow(fmt: Format, sign: bool, rm: RoundingMode) -> u64 {
+    let to_inf = match rm {
+        RoundingMode::Rne | RoundingMode::Rmm => true,
+        RoundingMode::Rtz => false,
+        RoundingMode::Rdn => sign,
+        RoundingMode::Rup => !sign,
+    };
+    if to_inf {
+        fmt.inf(sign)
+    } else {
+        fmt.max_finite(sign)
+    }
+}
+
+fn round_pack(
+    fmt: Format,
+    sign: bool,
+    exp: i32,
+    sig: u128,
+    rm: RoundingMode,
+    flags: &mut u8,
+) -> u64 {
+    // Round sig * 2^exp to the format (sig may carry a sticky bit in its LSB)
+    if sig == 0 {
+        return fmt.zero(sign);
+    }
+    let m = fmt.frac_bits() as i32;
+    let emin = 1 - fmt.bias();
+    // Exponent of the leading bit
+    let e = exp + 127 - sig.leading_zeros() as i32;
+
+    // Tiny if the result rounded with an unbounded exponent is below 2^emin
+    let tiny = e < emin && {
+        let (q, _) = shift_round(sig, e - m - exp, sign, rm);
+        q >> (m + 1) == 0 || e + 1 < emin
+    };
+
+    // Subnormals have a fixed LSB exponent
+    let mut lsb = e.max(emin) - m;
+    let (mut q, inexact) = shift_round(sig, lsb - exp, sign, rm);
+    if q >> (m + 1) != 0 {
+        q >>= 1;
+        lsb += 1;
+    }
+    if inexact {
+        *flags |= FLAG_NX;
+        if tiny {
+            *flags |= FLAG_UF;
+        }
+    }
+
+    let biased = if q >> m != 0 { lsb + m + fmt.bias() } else { 0 };
+    if biased as u64 >= fmt.exp_max() {
+        *flags |= FLAG_OF | FLAG_NX;
+        return overflow(fmt, sign, rm);
+    }
+    fmt.zero(sign) | (biased as u64) << m | (q as u64 & fmt.frac_mask())
+}
+
+// ----------------- Helpers --------------------
+fn invalid(fmt: Format, flags: &mut u8) -> u64 {
+    *flags |= FLAG_NV;
+    fmt.canonical_nan()
+}
+
+fn propagate_nan(fmt: Format, vals: &[Value], flags: &mut u8) -> u64 {
+    // Any NaN input gives the canonical NaN (invalid if signaling)
+    if vals.iter().any(|v| matches!(v, Value::Nan(true))) {
+        *flags |= FLAG_NV;
+    }
+    fmt.canonical_nan()
+}
+
+fn is_nan(v: Value) -> bool {
+    matches!(v, Value::Nan(_))
+}
+
+fn add_num(
+    fmt: Format,
+    a: (bool, i32, u128),
+    b: (bool, i32, u128),
+    rm: RoundingMode,
+    flags: &mut u8,
+) -> u64 {
+    let (sa, ea, ma) = a;
+    let (sb, eb, mb) = b;
+    if ma == 0 && mb == 0 {
+        // Exact zero sum is -0 only if both are -0 (or when rounding down)
+        return fmt.zero(if sa == sb {
+            sa
+        } else {
+            rm == RoundingMode::Rdn
+        });
+    }
+    if ma == 0 {
+        return round_pack(fmt, sb, eb, mb, rm, flags);
+    }
+    if mb == 0 {
+        return round_pack(fmt, sa, ea, ma, rm, flags);
+    }
+
+    // Normalize both to bit 125, leaving room for a carry
+    let norm = |e: i32, m: u128| {
+        let s = m.leading_zeros() as i32 - 2;
+        (e - s, m << s)
+    };
+    let (ea, ma) = norm(ea, ma);
+    let (eb, mb) = norm(eb, mb);
+    let ((sa, ea, ma), (sb, eb, mb)) = if ea >= eb {
+        ((sa, ea, ma), (sb, eb, mb))
+    } else {
+        ((sb, eb, mb), (sa, ea, ma))
+    };
+    let mb = shift_right_jam(mb, ea - eb);
+
+    if sa == sb {
+        round_pack(fmt, sa, ea, ma + mb, rm, flags)
+    } else {
+        match ma.cmp(&mb) {
+            Ordering::Greater => round_pack(fmt, sa, ea, ma - mb, rm, flags),
+            Ordering::Less => round_pack(fmt, sb, ea, mb - ma, rm, flags),
+            Ordering::Equal => fmt.zero(rm == RoundingMode::Rdn),
+        }
+    }
+}
+
+// ---------------- Arithmetic ------------------
+pub fn add(fmt: Format, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    add_sub(fmt, a, b, false, rm, flags)
+}
+
+pub fn sub(fmt: Format, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    add_sub(fmt, a, b, true, rm, flags)
+}
+
+fn add_sub(fmt: Format, a: u64, b: u64, neg_b: bool, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let va = fmt.unpack(a);
+    let vb = fmt.unpack(b);
+    match (va, vb) {
+        _ if is_nan(va) || is_nan(vb) => propagate_nan(fmt, &[va, vb], flags),
+        (Value::Inf(sa), Value::Inf(sb)) => {
+            if sa != (sb ^ neg_b) {
+                invalid(fmt, flags)
+            } else {
+                fmt.inf(sa)
+            }
+        }
+        (Value::Inf(sa), _) => fmt.inf(sa),
+        (_, Value::Inf(sb)) => fmt.inf(sb ^ neg_b),
+        (Value::Num(sa, ea, ma), Value::Num(sb, eb, mb)) => add_num(
+            fmt,
+            (sa, ea, ma as u128),
+            (sb ^ neg_b, eb, mb as u128),
+            rm,
+            flags,
+        ),
+        _ => unreachable!(),
+    }
+}
+
+pub fn mul(fmt: Format, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let va = fmt.unpack(a);
+    let vb = fmt.unpack(b);
+    match (va, vb) {
+        _ if is_nan(va) || is_nan(vb) => propagate_nan(fmt, &[va, vb], flags),
+        (Value::Inf(_), Value::Num(_, _, 0)) | (Value::Num(_, _, 0), Value::Inf(_)) => {
+            invalid(fmt, flags)
+        }
+        (Value::Inf(sa), Value::Inf(sb))
+        | (Value::Inf(sa), Value::Num(sb, _, _))
+        | (Value::Num(sa, _, _), Value::Inf(sb)) => fmt.inf(sa ^ sb),
+        (Value::Num(sa, ea, ma), Value::Num(sb, eb, mb)) => {
+            round_pack(fmt, sa ^ sb, ea + eb, ma as u128 * mb as u128, rm, flags)
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub fn div(fmt: Format, a: u64, b: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let va = fmt.unpack(a);
+    let vb = fmt.unpack(b);
+    match (va, vb) {
+        _ if is_nan(va) || is_nan(vb) => propagate_nan(fmt, &[va, vb], flags),
+        (Value::Inf(_), Value::Inf(_)) | (Value::Num(_, _, 0), Value::Num(_, _, 0)) => {
+            invalid(fmt, flags)
+        }
+        (Value::Inf(sa), Value::Num(sb, _, _)) => fmt.inf(sa ^ sb),
+        (Value::Num(sa, _, _), Value::Inf(sb)) => fmt.zero(sa ^ sb),
+        (Value::Num(sa, _, _), Value::Num(sb, _, 0)) => {
+            *flags |= FLAG_DZ;
+            fmt.inf(sa ^ sb)
+        }
+        (Value::Num(sa, ea, ma), Value::Num(sb, eb, mb)) => {
+            if ma == 0 {
+                return fmt.zero(sa ^ sb);
+            }
+            // Normalize both to bit 63 so the quotient has at least 64 bits
+            let (sha, shb) = (ma.leading_zeros(), mb.leading_zeros());
+            let ma = ((ma << sha) as u128) << 64;
+            let mb = (mb << shb) as u128;
+            let q = ma / mb;
+            let sticky = !ma.is_multiple_of(mb) as u128;
+            let exp = ea - sha as i32 - eb + shb as i32 - 64;
+            round_pack(fmt, sa ^ sb, exp, q | sticky, rm, flags)
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub fn sqrt(fmt: Format, a: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    match fmt.unpack(a) {
+        va @ Value::Nan(_) => propagate_nan(fmt, &[va], flags),
+        Value::Num(sign, _, 0) => fmt.zero(sign),
+        Value::Inf(true) | Value::Num(true, _, _) => invalid(fmt, flags),
+        Value::Inf(false) => fmt.inf(false),
+        Value::Num(false, exp, m) => {
+            // Normalize to bit 62 and make the exponent even
+            let sh = m.leading_zeros() as i32 - 1;
+            let (exp, m) = (exp - sh, m << sh);
+            let (exp, m) = if exp & 1 != 0 {
+                (exp - 1, m << 1)
+            } else {
+                (exp, m)
+            };
+            let m = (m as u128) << 64;
+            let r = m.isqrt();
+            let sticky = (r * r != m) as u128;
+            round_pack(fmt, false, (exp - 64) / 2, r | sticky, rm, flags)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FmaOp {
+    Madd,  //  (a * b) + c
+    Msub,  //  (a * b) - c
+    Nmsub, // -(a * b) + c
+    Nmadd, // -(a * b) - c
+}
+
+pub fn fma(
+    fmt: Format,
+    a: u64,
+    b: u64,
+    c: u64,
+    op: FmaOp,
+    rm: RoundingMode,
+    flags: &mut u8,
+) -> u64 {
+    let (neg_p, neg_c) = match op {
+        FmaOp::Madd => (false, false),
+        FmaOp::Msub => (false, true),
+        FmaOp::Nmsub => (true, false),
+        FmaOp::Nmadd => (true, true),
+    };
+    let va = fmt.unpack(a);
+    let vb = fmt.unpack(b);
+    let vc = fmt.unpack(c);
+
+    // Infinity times zero is invalid even if the addend is a quiet NaN
+    let inf_zero = matches!(
+        (va, vb),
+        (Value::Inf(_), Value::Num(_, _, 0)) | (Value::Num(_, _, 0), Value::Inf(_))
+    );
+    if inf_zero {
+        *flags |= FLAG_NV;
+    }
+    if is_nan(va) || is_nan(vb) || is_nan(vc) {
+        return propagate_nan(fmt, &[va, vb, vc], flags);
+    }
+    if inf_zero {
+        return fmt.canonical_nan();
+    }
+
+    // Product is infinite if either operand is
+    let prod_inf = match (va, vb) {
+        (Value::Inf(sa), Value::Inf(sb))
+        | (Value::Inf(sa), Value::Num(sb, _, _))
+        | (Value::Num(sa, _, _), Value::Inf(sb)) => Some(sa ^ sb ^ neg_p),
+        _ => None,
+    };
+    match (prod_inf, vc) {
+        (Some(sp), Value::Inf(sc)) => {
+            if sp != sc ^ neg_c {
+                invalid(fmt, flags)
+            } else {
+                fmt.inf(sp)
+            }
+        }
+        (Some(sp), _) => fmt.inf(sp),
+        (None, Value::Inf(sc)) => fmt.inf(sc ^ neg_c),
+        (None, Value::Num(sc, ec, mc)) => {
+            let (Value::Num(sa, ea, ma), Value::Num(sb, eb, mb)) = (va, vb) else {
+                unreachable!()
+            };
+            let prod = (sa ^ sb ^ neg_p, ea + eb, ma as u128 * mb as u128);
+            add_num(fmt, prod, (sc ^ neg_c, ec, mc as u128), rm, flags)
+        }
+        _ => unreachable!(),
+    }
+}
+
+// ------------- Sign, min and max --------------
+pub fn min(fmt: Format, a: u64, b: u64, flags: &mut u8) -> u64 {
+    min_max(fmt, a, b, false, flags)
+}
+
+pub fn max(fmt: Format, a: u64, b: u64, flags: &mut u8) -> u64 {
+    min_max(fmt, a, b, true, flags)
+}
+
+fn min_max(fmt: Format, a: u64, b: u64, is_max: bool, flags: &mut u8) -> u64 {
+    // A single NaN operand is ignored, -0 is less than +0
+    let va = fmt.unpack(a);
+    let vb = fmt.unpack(b);
+    if matches!(va, Value::Nan(true)) || matches!(vb, Value::Nan(true)) {
+        *flags |= FLAG_NV;
+    }
+    match (is_nan(va), is_nan(vb)) {
+        (true, true) => fmt.canonical_nan(),
+        (true, false) => b,
+        (false, true) => a,
+        (false, false) => {
+            let a_less = total_key(fmt, a) < total_key(fmt, b);
+            if a_less != is_max {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+fn total_key(fmt: Format, x: u64) -> i64 {
+    // Order of non-NaN values (with -0 below +0)
+    let mag = (x & !fmt.sign_bit()) as i64;
+    if x & fmt.sign_bit() != 0 {
+        -mag - 1
+    } else {
+        mag
+    }
+}
+
+// ---------------- Comparison ------------------
+pub fn eq(fmt: Format, a: u64, b: u64, flags: &mut u8) -> bool {
+    compare(fmt, a, b, false, flags) == Some(Ordering::Equal)
+}
+
+pub fn lt(fmt: Format, a: u64, b: u64, flags: &mut u8) -> bool {
+    compare(fmt, a, b, true, flags) == Some(Ordering::Less)
+}
+
+pub fn le(fmt: Format, a: u64, b: u64, flags: &mut u8) -> bool {
+    matches!(
+        compare(fmt, a, b, true, flags),
+        Some(Ordering::Less | Ordering::Equal)
+    )
+}
+
+fn compare(fmt: Format, a: u64, b: u64, signaling: bool, flags: &mut u8) -> Option<Ordering> {
+    // Quiet comparisons only raise invalid for signaling NaNs
+    let va = fmt.unpack(a);
+    let vb = fmt.unpack(b);
+    match (va, vb) {
+        (Value::Nan(sa), _) | (_, Value::Nan(sa)) => {
+            let snan = sa || matches!(vb, Value::Nan(true));
+            if signaling || snan {
+                *flags |= FLAG_NV;
+            }
+            None
+        }
+        _ => {
+            // Both zeros compare equal regardless of sign
+            let key = |x: u64| {
+                let mag = (x & !fmt.sign_bit()) as i64;
+                if x & fmt.sign_bit() != 0 {
+                    -mag
+                } else {
+                    mag
+                }
+            };
+            Some(key(a).cmp(&key(b)))
+        }
+    }
+}
+
+pub fn classify(fmt: Format, a: u64) -> u32 {
+    let subnormal = (a >> fmt.frac_bits()) & fmt.exp_max() == 0;
+    match fmt.unpack(a) {
+        Value::Inf(true) => 1 << 0,
+        Value::Num(true, _, 0) => 1 << 3,
+        Value::Num(true, _, _) if subnormal => 1 << 2,
+        Value::Num(true, _, _) => 1 << 1,
+        Value::Num(false, _, 0) => 1 << 4,
+        Value::Num(false, _, _) if subnormal => 1 << 5,
+        Value::Num(false, _, _) => 1 << 6,
+        Value::Inf(false) => 1 << 7,
+        Value::Nan(true) => 1 << 8,
+        Value::Nan(false) => 1 << 9,
+    }
+}
+
+// ---------------- Conversion ------------------
+pub fn convert(from: Format, to: Format, a: u64, rm: RoundingMode, flags: &mut u8) -> u64 {
+    match from.unpack(a) {
+        va @ Value::Nan(_) => propagate_nan(to, &[va], flags),
+        Value::Inf(sign) => to.inf(sign),
+        Value::Num(sign, exp, m) => round_pack(to, sign, exp, m as u128, rm, flags),
+    }
+}
+
+pub fn to_i32(fmt: Format, a: u64, rm: RoundingMode, flags: &mut u8) -> u32 {
+    to_int(fmt, a, true, rm, flags)
+}
+
+pub fn to_u32(fmt: Format, a: u64, rm: RoundingMode, flags: &mut u8) -> u32 {
+    to_int(fmt, a, false, rm, flags)
+}
+
+fn to_int(fmt: Format, a: u64, signed: bool, rm: RoundingMode, flags: &mut u8) -> u32 {
+    // Out of range values (and NaN) saturate and raise invalid instead of inexact
+    let (min, max) = if signed {
+        (i32::MIN as u32, i32::MAX as u32)
+    } else {
+        (0, u32::MAX)
+    };
+    let (sign, q, inexact) = match fmt.unpack(a) {
+        Value::Nan(_) => {
+            *flags |= FLAG_NV;
+            return max;
+        }
+        Value::Inf(sign) => (sign, u128::MAX, false),
+        Value::Num(sign, exp, m) => {
+            if exp > 64 {
+                (sign, u128::MAX, false)
+            } else {
+                let (q, inexact) = shift_round(m as u128, -exp, sign, rm);
+                (sign, q, inexact)
+            }
+        }
+    };
+    let limit = match (signed, sign) {
+        (true, true) => 1 << 31,
+        (true, false) => (1 << 31) - 1,
+        (false, true) => 0,
+        (false, false) => u32::MAX as u128,
+    };
+    if q > limit {
+        *flags |= FLAG_NV;
+        return if sign { min } else { max };
+    }
+    if inexact {
+        *flags |= FLAG_NX;
+    }
+    if sign {
+        (q as u32).wrapping_neg()
+    } else {
+        q as u32
+    }
+}
+
+pub fn from_i32(fmt: Format, a: u32, rm: RoundingMode, flags: &mut u8) -> u64 {
+    let sign = (a as i32) < 0;
+    round_pack(fmt, sign, 0, (a as i32).unsigned_abs() as u128, rm, flags)
+}
+
+pub fn from_u32(fmt: Format, a: u32, rm: RoundingMode, flags: &mut u8) -> u64 {
+    round_pack(fmt, false, 0, a as u128, rm, flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format::*, RoundingMode::*, *};
+
+    fn s(x: f32) -> u64 {
+        x.to_bits() as u64
+    }
+
+    fn d(x: f64) -> u64 {
+        x.to_bits()
+    }
+
+    #[test]
+    fn test_add_sub() {
+        let mut flags = 0;
+        assert_eq!(add(S, s(1.5), s(2.25), Rne, &mut flags), s(3.75));
+        assert_eq!(sub(D, d(1.0), d(3.5), Rne, &mut flags), d(-2.5));
+        assert_eq!(flags, 0);
+
+        // Exact cancellation gives +0, or -0 when rounding down
+        assert_eq!(sub(S, s(1.0), s(1.0), Rne, &mut flags), s(0.0));
+        assert_eq!(sub(S, s(1.0), s(1.0), Rdn, &mut flags), s(-0.0));
+        assert_eq!(add(S, s(-0.0), s(-0.0), Rne, &mut flags), s(-0.0));
+        assert_eq!(flags, 0);
+
+        // 1 + 2^-30 is inexact in single precision
+        let tiny = s(2.0f32.powi(-30));
+        assert_eq!(add(S, s(1.0), tiny, Rne, &mut flags), s(1.0));
+        assert_eq!(flags, FLAG_NX);
+        assert_eq!(add(S, s(1.0), tiny, Rup, &mut flags), s(1.0) + 1);
+        assert_eq!(sub(S, s(1.0), tiny, Rtz, &mut flags), s(1.0) - 1);
+
+        // inf - inf is invalid
+        let mut flags = 0;
+        assert_eq!(
+            sub(D, d(f64::INFINITY), d(f64::INFINITY), Rne, &mut flags),
+            D.canonical_nan()
+        );
+        assert_eq!(flags, FLAG_NV);
+    }
+
+    #[test]
+    fn test_mul_div() {
+        let mut flags = 0;
+        assert_eq!(mul(S, s(1.5), s(-4.0), Rne, &mut flags), s(-6.0));
+        assert_eq!(div(D, d(1.0), d(8.0), Rne, &mut flags), d(0.125));
+        assert_eq!(flags, 0);
+
+        assert_eq!(div(D, d(1.0), d(3.0), Rne, &mut flags), d(1.0 / 3.0));
+        assert_eq!(div(S, s(2.0), s(3.0), Rne, &mut flags), s(2.0 / 3.0));
+        assert_eq!(flags, FLAG_NX);
+
+        let mut flags = 0;
+        assert_eq!(
+            div(S, s(-1.0), s(0.0), Rne, &mut flags),
+            s(f32::NEG_INFINITY)
+        );
+        assert_eq!(flags, FLAG_DZ);
+
+        let mut flags = 0;
+        assert_eq!(
+            mul(S, s(0.0), s(f32::INFINITY), Rne, &mut flags),
+            S.canonical_nan()
+        );
+        assert_eq!(flags, FLAG_NV);
+
+        // Overflow rounds to infinity or the largest finite value
+        let mut flags = 0;
+        assert_eq!(
+            mul(S, s(f32::MAX), s(2.0), Rne, &mut flags),
+            s(f32::INFINITY)
+        );
+        assert_eq!(flags, FLAG_OF | FLAG_NX);
+        assert_eq!(mul(S, s(f32::MAX), s(2.0), Rtz, &mut flags), s(f32::MAX));
+        assert_eq!(mul(S, s(f32::MAX), s(-2.0), Rup, &mut flags), s(f32::MIN));
+    }
+
+    #[test]
+    fn test_subnormal() {
+        // Products below the normal range are rounded into subnormals
+        let mut flags = 0;
+        let min = f32::MIN_POSITIVE;
+        assert_eq!(mul(S, s(min), s(0.5), Rne, &mut flags), s(min * 0.5));
+        assert_eq!(flags, 0);
+        assert_eq!(mul(S, s(min), s(min), Rne, &mut flags), s(0.0));
+        assert_eq!(flags, FLAG_UF | FLAG_NX);
+        assert_eq!(mul(S, s(min), s(min), Rup, &mut flags), 1);
+
+        // Tininess is detected after rounding
+        let mut flags = 0;
+        let below = s(min) - 1; // Largest subnormal
+        assert_eq!(
+            mul(S, below, s(1.0 + f32::EPSILON), Rne, &mut flags),
+            s(min)
+        );
+        assert_eq!(flags, FLAG_NX);
+        assert_eq!(
+            add(D, d(f64::MIN_POSITIVE), d(-5e-324), Rne, &mut flags),
+            d(f64::MIN_POSITIVE) - 1
+        );
+    }
+
+    #[test]
+    fn test_sqrt_fma() {
+        let mut flags = 0;
+        assert_eq!(sqrt(D, d(2.25), Rne, &mut flags), d(1.5));
+        assert_eq!(sqrt(S, s(-0.0), Rne, &mut flags), s(-0.0));
+        assert_eq!(flags, 0);
+        assert_eq!(sqrt(D, d(2.0), Rne, &mut flags), d(2.0f64.sqrt()));
+        assert_eq!(sqrt(S, s(1e-40), Rne, &mut flags), s(1e-40f32.sqrt()));
+        assert_eq!(flags, FLAG_NX);
+
+        let mut flags = 0;
+        assert_eq!(sqrt(S, s(-1.0), Rne, &mut flags), S.canonical_nan());
+        assert_eq!(flags, FLAG_NV);
+
+        let mut flags = 0;
+        let (a, b, c) = (1.0 + f64::EPSILON, 1.0 - f64::EPSILON, -1.0);
+        assert_eq!(
+            fma(D, d(a), d(b), d(c), FmaOp::Madd, Rne, &mut flags),
+            d(a.mul_add(b, c))
+        );
+        assert_eq!(
+            fma(S, s(2.0), s(3.0), s(1.0), FmaOp::Msub, Rne, &mut flags),
+            s(5.0)
+        );
+        assert_eq!(
+            fma(S, s(2.0), s(3.0), s(1.0), FmaOp::Nmsub, Rne, &mut flags),
+            s(-5.0)
+        );
+        assert_eq!(
+            fma(S, s(2.0), s(3.0), s(1.0), FmaOp::Nmadd, Rne, &mut flags),
+            s(-7.0)
+        );
+        assert_eq!(flags, 0);
+
+        // Infinity times zero is invalid even with a quiet NaN addend
+        let qnan = S.canonical_nan();
+        assert_eq!(
+            fma(
+                S,
+                s(f32::INFINITY),
+                s(0.0),
+                qnan,
+                FmaOp::Madd,
+                Rne,
+                &mut flags
+            ),
+            qnan
+        );
+        assert_eq!(flags, FLAG_NV);
+    }
+
+    #[test]
+    fn test_random_against_host() {
+        // The host FPU rounds to nearest even, which must match bit for bit
+        for _ in 0..20000 {
+            let (a, b): (u64, u64) = (rand::random(), rand::random());
+            let (fa, fb) = (f64::from_bits(a), f64::from_bits(b));
+            let mut flags = 0;
+            let check = |got: u64, want: f64| {
+                if want.is_nan() {
+                    assert_eq!(got, D.canonical_nan());
+                } else {
+                    assert_eq!(got, want.to_bits(), "{fa:e} {fb:e}");
+                }
+            };
+            check(add(D, a, b, Rne, &mut flags), fa + fb);
+            check(sub(D, a, b, Rne, &mut flags), fa - fb);
+            check(mul(D, a, b, Rne, &mut flags), fa * fb);
+            check(div(D, a, b, Rne, &mut flags), fa / fb);
+            check(sqrt(D, a, Rne, &mut flags), fa.sqrt());
+            check(
+                fma(D, a, b, a, FmaOp::Madd, Rne, &mut flags),
+                fa.mul_add(fb, fa),
+            );
+
+            let (a, b) = (a as u32, b as u32);
+            let (fa, fb) = (f32::from_bits(a), f32::from_bits(b));
+            let check = |got: u64, want: f32| {
+                if want.is_nan() {
+                    assert_eq!(got, S.canonical_nan());
+                } else {
+                    assert_eq!(got, want.to_bits() as u64, "{fa:e} {fb:e}");
+                }
+            };
+            check(add(S, a as u64, b as u64, Rne, &mut flags), fa + fb);
+            check(mul(S, a as u64, b as u64, Rne, &mut flags), fa * fb);
+            check(div(S, a as u64, b as u64, Rne, &mut flags), fa / fb);
+            check(sqrt(S, a as u64, Rne, &mut flags), fa.sqrt());
+            check(convert(D, S, (fa as f64).to_bits(), Rne, &mut flags), fa);
+            let widened = convert(S, D, a as u64, Rne, &mut flags);
+            if fa.is_nan() {
+                assert_eq!(widened, D.canonical_nan());
+            } else {
+                assert_eq!(widened, (fa as f64).to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn test_min_max_compare() {
+        let mut flags = 0;
+        assert_eq!(min(S, s(-0.0), s(0.0), &mut flags), s(-0.0));
+        assert_eq!(max(S, s(-0.0), s(0.0), &mut flags), s(0.0));
+        assert_eq!(max(D, d(f64::NAN), d(-3.0), &mut flags), d(-3.0));
+        assert_eq!(
+            min(D, d(f64::NAN), d(f64::NAN), &mut flags),
+            D.canonical_nan()
+        );
+        assert_eq!(flags, 0);
+        assert_eq!(min(S, 0x7f80_0001, s(1.0), &mut flags), s(1.0));
+        assert_eq!(flags, FLAG_NV);
+
+        let mut flags = 0;
+        assert!(eq(S, s(-0.0), s(0.0), &mut flags));
+        assert!(lt(D, d(-1.0), d(0.5), &mut flags));
+        assert!(le(D, d(0.5), d(0.5), &mut flags));
+        assert!(!lt(D, d(0.5), d(0.5), &mut flags));
+        assert!(!eq(S, S.canonical_nan(), S.canonical_nan(), &mut flags));
+        assert_eq!(flags, 0);
+        assert!(!lt(S, S.canonical_nan(), s(1.0), &mut flags));
+        assert_eq!(flags, FLAG_NV);
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(S, s(f32::NEG_INFINITY)), 1 << 0);
+        assert_eq!(classify(S, s(-1.0)), 1 << 1);
+        assert_eq!(classify(S, s(-1e-40)), 1 << 2);
+        assert_eq!(classify(S, s(-0.0)), 1 << 3);
+        assert_eq!(classify(D, d(0.0)), 1 << 4);
+        assert_eq!(classify(D, d(5e-324)), 1 << 5);
+        assert_eq!(classify(D, d(1.0)), 1 << 6);
+        assert_eq!(classify(D, d(f64::INFINITY)), 1 << 7);
+        assert_eq!(classify(S, 0x7f80_0001), 1 << 8);
+        assert_eq!(classify(S, 0x7fc0_0000), 1 << 9);
+    }
+
+    #[test]
+    fn test_int_conversion() {
+        let mut flags = 0;
+        assert_eq!(to_i32(D, d(-7.0), Rne, &mut flags), -7i32 as u32);
+        assert_eq!(to_u32(S, s(4e9), Rne, &mut flags), 4_000_000_000);
+        assert_eq!(
+            from_i32(D, i32::MIN as u32, Rne, &mut flags),
+            d(-2147483648.0)
+        );
+        assert_eq!(from_u32(D, u32::MAX, Rne, &mut flags), d(4294967295.0));
+        assert_eq!(flags, 0);
+
+        assert_eq!(to_i32(S, s(2.5), Rne, &mut flags), 2);
+        assert_eq!(to_i32(S, s(2.5), Rmm, &mut flags), 3);
+        assert_eq!(to_i32(S, s(-2.5), Rdn, &mut flags), -3i32 as u32);
+        assert_eq!(to_u32(D, d(-0.5), Rtz, &mut flags), 0);
+        assert_eq!(from_u32(S, u32::MAX, Rtz, &mut flags), s(4294967040.0));
+        assert_eq!(flags, FLAG_NX);
+
+        // Out of range values saturate
+        let mut flags = 0;
+        assert_eq!(to_i32(D, d(3e9), Rne, &mut flags), i32::MAX as u32);
+        assert_eq!(
+            to_i32(D, d(f64::NEG_INFINITY), Rne, &mut flags),
+            i32::MIN as u32
+        );
+        assert_eq!(to_u32(S, s(-1.0), Rne, &mut flags), 0);
+        assert_eq!(to_u32(S, S.canonical_nan(), Rne, &mut flags), u32::MAX);
+        assert_eq!(flags, FLAG_NV);
+    }
+}
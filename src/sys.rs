@@ -1,32 +1,71 @@
 use std::u64;
 
 use crate::{
-    decode::decode,
+    decode::{decode, decode_compressed, is_compressed},
+    elf::SymbolTable,
     exec::execute,
-    instr::reg::Reg,
+    htif::Htif,
+    instr::{reg::Reg, Instr},
+    linux::{self, Process},
+    pmp::{check_pmp, Pmp},
     proc::*,
-    run::{load_dtb_from_file, load_image_from_file},
+    run::{load_binary_or_elf_from_file, load_dtb_from_file},
+    sbi,
+    semihosting::{self, Semihosting},
+    snapshot::{invalid_data, SnapshotReader, SnapshotWriter},
     translate::*,
     trap::TrapCause,
-    Config, Exception, Result, Result32, Trap,
+    Config, Exception, Interrupt, Result, Result32, Trap,
 };
 use colored::*;
+use std::io;
 
 pub mod control;
+pub mod hart;
 pub mod mem_map;
 pub mod state;
 
 use control::*;
-use mem_map::*;
+use hart::*;
+use mem_map::{
+    virtio::{VirtioDevice, VirtioMmio},
+    virtio_blk::VirtioBlk,
+    virtio_net::{VirtioNet, DEFAULT_MAC},
+    *,
+};
 use state::*;
 
+// Instructions are fetched 16 bits at a time
+const FETCH_ATTR: AccessAttr = AccessAttr {
+    atype: AccessType::Instr,
+    width: AccessWidth::HalfWord,
+    lrsc: false,
+    amo: false,
+};
+
+// Why the machine stopped running
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StopReason {
+    Exit(i32), // Shut down, with an exit code (0 on success)
+    Reset,
+}
+
 #[derive(Debug)]
 pub struct System {
     pub cfg: Config,
     pub state: State,
     pub mem: MemMap,
     pub ctrl: Control,
+    pub symbols: SymbolTable,
     code: u32,
+    code_len: u32,
+    pub hart_id: usize,   // The hart running in state and ctrl
+    pub harts: Vec<Hart>, // The context in the slot of the running hart is stale
+    sched: Scheduler,
+    pub stop: Option<StopReason>, // Set when the machine shuts down or resets
+    pub process: Option<Process>, // Emulated Linux process (user mode)
+    pub semihosting: Option<Semihosting>,
+    pub htif: Option<Htif>,
 }
 
 impl System {
@@ -39,23 +78,54 @@ impl System {
         let binary = cfg.binary.clone();
         let dtb = cfg.dtb.clone();
         let kernel = cfg.kernel.clone();
+        let pmp_entries = cfg.pmp_entries as usize;
+        let num_harts = cfg.harts as usize;
+        let vlen = cfg.vlen;
+        let sched = Scheduler::new(cfg.quantum, cfg.sched_seed);
 
         let mut sys = System {
             cfg,
-            state: State::new(),
+            state: State::new(vlen),
             mem: MemMap::new(size),
             ctrl: Control::new(),
+            symbols: SymbolTable::default(),
             code: 0,
+            code_len: 4,
+            hart_id: 0,
+            harts: (0..num_harts)
+                .map(|_| Hart::new(pmp_entries, vlen))
+                .collect(),
+            sched,
+            stop: None,
+            process: None,
+            semihosting: None,
+            htif: None,
         };
 
+        // Number of PMP entries
+        sys.ctrl.pmp = Pmp::new(pmp_entries);
+
+        // Per-hart registers of the devices
+        sys.mem.set_num_harts(num_harts);
+
         // Adjust the ram base
         let ram_base = sys.cfg.base as u64;
         sys.mem.ram_base = ram_base;
         *sys.pc_mut() = sys.cfg.base;
 
-        // Load binary file to ram
-        if let Some(path) = binary {
-            load_image_from_file(&mut sys, path, 0).unwrap();
+        // Semihosting calls get the binary as command line
+        if sys.cfg.semihosting {
+            let cmdline = binary
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string());
+            sys.semihosting = Some(Semihosting::new(cmdline));
+        }
+
+        // Load binary file to ram (an ELF file also sets the entry point)
+        if let Some(path) = binary.clone().filter(|_| !sys.cfg.user) {
+            if let Some(entry) = load_binary_or_elf_from_file(&mut sys, path, 0).unwrap() {
+                *sys.pc_mut() = entry;
+            }
         }
 
         // Load device tree blob to rom
@@ -63,18 +133,122 @@ impl System {
             load_dtb_from_file(&mut sys, path).unwrap();
         }
 
-        // Load kernel file to ram at 0x00400000
+        // Load kernel file to ram at 0x00400000 (or at its own addresses if ELF)
         if let Some(path) = kernel {
-            load_image_from_file(&mut sys, path, 0x00400000).unwrap();
+            let entry = load_binary_or_elf_from_file(&mut sys, path, 0x00400000).unwrap();
+            // The built-in firmware jumps to the kernel directly
+            if sys.cfg.sbi {
+                *sys.pc_mut() = entry.unwrap_or(sys.cfg.base.wrapping_add(0x00400000));
+            }
+        } else if sys.cfg.sbi {
+            *sys.pc_mut() = sys.cfg.base.wrapping_add(0x00400000);
+        }
+
+        // Virtio block devices in the first slots, then network devices
+        for (slot, drive) in sys.cfg.drive.iter().enumerate() {
+            let blk = VirtioBlk::open(drive).unwrap();
+            sys.mem.virtio[slot] = VirtioMmio::new(Some(VirtioDevice::Blk(blk)));
+        }
+        let num_drives = sys.cfg.drive.len();
+        for (i, nic) in sys.cfg.nic.iter().enumerate() {
+            let mut mac = nic.mac.unwrap_or(DEFAULT_MAC);
+            if nic.mac.is_none() {
+                mac[5] = mac[5].wrapping_add(i as u8);
+            }
+            let net = VirtioNet::open(nic, mac).unwrap();
+            sys.mem.virtio[num_drives + i] = VirtioMmio::new(Some(VirtioDevice::Net(net)));
         }
 
-        // Initialize a0 to hartid (0) and a1 to dtb_base
-        *sys.reg_mut(&Reg::new(10)) = 0;
-        *sys.reg_mut(&Reg::new(11)) = sys.mem.dtb_base as i32;
+        // HTIF at the configured address, or at the tohost symbol
+        sys.htif = Htif::new(sys.cfg.tohost, &sys.symbols);
+
+        // All harts start at the entry point, with a0 set to hartid and a1 to dtb_base
+        let entry = sys.pc();
+        for id in 0..num_harts {
+            sys.switch_hart(id);
+            *sys.pc_mut() = entry;
+            *sys.reg_mut(&Reg::new(10)) = id as i32;
+            *sys.reg_mut(&Reg::new(11)) = sys.mem.dtb_base as i32;
+            // The built-in firmware starts the kernel in S-mode on hart 0 only
+            if sys.cfg.sbi {
+                sbi::init_hart(&mut sys.ctrl);
+                sys.harts[id].stopped = id != 0;
+            }
+        }
+        sys.switch_hart(0);
+
+        // A Linux program runs alone on hart 0, with the arguments and environment on its stack
+        if let (Some(path), true) = (binary, sys.cfg.user) {
+            let args: Vec<_> = [path.display().to_string()]
+                .into_iter()
+                .chain(sys.cfg.args.iter().cloned())
+                .collect();
+            let env: Vec<_> = std::env::vars().map(|(k, v)| format!("{k}={v}")).collect();
+            sys.process = Some(linux::load_program(&mut sys, &path, &args, &env).unwrap());
+            (1..num_harts).for_each(|id| sys.harts[id].stopped = true);
+        }
 
         sys
     }
 
+    // Start again from the configuration, as if the simulator was restarted (the terminal
+    // stays connected, and the network devices keep their backends rather than binding the
+    // socket or creating the capture again)
+    pub fn reset(&mut self) {
+        let cfg = Config {
+            nic: vec![],
+            ..self.cfg.clone()
+        };
+        let mut sys = System::from_config(cfg);
+        sys.cfg.nic = self.cfg.nic.clone();
+        sys.mem.uart.take_input(&mut self.mem.uart);
+        let num_drives = self.cfg.drive.len();
+        for (i, nic) in self.cfg.nic.iter().enumerate() {
+            let slot = num_drives + i;
+            if let Some(VirtioDevice::Net(mut net)) = self.mem.virtio[slot].take_device() {
+                net.reset(nic.link_up);
+                sys.mem.virtio[slot] = VirtioMmio::new(Some(VirtioDevice::Net(net)));
+            }
+        }
+        *self = sys;
+    }
+
+    pub fn num_harts(&self) -> usize {
+        self.harts.len()
+    }
+
+    // Make another hart the running one
+    pub fn switch_hart(&mut self, id: usize) {
+        if id != self.hart_id {
+            self.swap_hart(self.hart_id);
+            self.swap_hart(id);
+            self.hart_id = id;
+        }
+    }
+
+    // Let another hart run from the next step on (the rest of the quantum is given up)
+    pub fn yield_hart(&mut self) {
+        self.sched.end_quantum();
+    }
+
+    // Registers and CSRs of any hart (those of the running one are in state and ctrl)
+    pub fn hart_context_mut(&mut self, id: usize) -> (&mut State, &mut Control) {
+        if id == self.hart_id {
+            (&mut self.state, &mut self.ctrl)
+        } else {
+            let hart = &mut self.harts[id];
+            (&mut hart.state, &mut hart.ctrl)
+        }
+    }
+
+    fn swap_hart(&mut self, id: usize) {
+        let hart = &mut self.harts[id];
+        std::mem::swap(&mut self.state, &mut hart.state);
+        std::mem::swap(&mut self.ctrl, &mut hart.ctrl);
+        std::mem::swap(&mut self.code, &mut hart.code);
+        std::mem::swap(&mut self.code_len, &mut hart.code_len);
+    }
+
     pub fn reg(&self, r: &Reg) -> i32 {
         self.state.reg(r)
     }
@@ -83,6 +257,14 @@ impl System {
         self.state.reg_mut(r)
     }
 
+    pub fn freg(&self, r: &Reg) -> u64 {
+        self.state.freg(r)
+    }
+
+    pub fn freg_mut(&mut self, r: &Reg) -> &mut u64 {
+        self.state.freg_mut(r)
+    }
+
     pub fn pc(&self) -> u32 {
         self.state.pc()
     }
@@ -91,9 +273,27 @@ impl System {
         self.state.pc_mut()
     }
 
+    // PC of the instruction following the current one (2 or 4 bytes later)
+    pub fn next_pc(&self) -> u32 {
+        self.pc().wrapping_add(self.code_len)
+    }
+
     pub fn step(&mut self) -> Result {
         // Fetch decode exec
-        let res = fetch_decode_exec(self);
+        self.ctrl.hpm.begin(self.ctrl.privilege, &self.ctrl.tlb);
+        let mut res = fetch_decode_exec(self);
+
+        // Calls to the built-in firmware return like any other instruction
+        if self.cfg.sbi && res == Err(Trap::from_exception(Exception::EcallFromS, 0)) {
+            res = sbi::handle_ecall(self);
+        }
+        // System calls of a Linux process are emulated, and faults kill it
+        if let (Some(_), Err(trap)) = (&self.process, res) {
+            res = linux::handle_trap(self, trap);
+        }
+        if let (Some(_), Err(trap)) = (&self.semihosting, res) {
+            res = semihosting::handle_trap(self, trap);
+        }
         if let Err(e) = res {
             log_with_pc(self, &format!("{}", format!("{:?}", e).yellow()), true);
         }
@@ -101,17 +301,83 @@ impl System {
         // Retire
         retire(self, res);
 
+        // Commands to the host are handled between instructions
+        if let Some(htif) = self.htif {
+            htif.poll(self);
+        }
+        if let Some(stop) = self.mem.finisher.take_stop() {
+            self.stop = Some(stop);
+        }
+
+        // Let another hart run
+        if let Some(id) = self.sched.next(self.hart_id, &self.harts) {
+            self.switch_hart(id);
+        }
+
         res
     }
 }
 
+impl System {
+    // Serialize the whole machine state (the configuration and symbols are not included)
+    pub fn save_snapshot(&self) -> Vec<u8> {
+        let mut w = SnapshotWriter::new();
+        w.put_u32(self.harts.len() as u32);
+        w.put_u32(self.hart_id as u32);
+        for (id, hart) in self.harts.iter().enumerate() {
+            if id == self.hart_id {
+                self.state.save(&mut w);
+                self.ctrl.save(&mut w);
+                w.put_u32(self.code);
+                w.put_u32(self.code_len);
+            } else {
+                hart.save(&mut w);
+            }
+            w.put_bool(hart.stopped);
+        }
+        self.sched.save(&mut w);
+        self.mem.save(&mut w);
+        w.into_bytes()
+    }
+
+    // Resume from a snapshot, as if it had been running all along
+    pub fn restore_snapshot(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut r = SnapshotReader::new(buf)?;
+        let num_harts = r.get_u32()? as usize;
+        let hart_id = r.get_u32()? as usize;
+        if hart_id >= num_harts {
+            return Err(invalid_data("invalid running hart in snapshot"));
+        }
+        let vlen = self.cfg.vlen;
+        let mut harts: Vec<_> = (0..num_harts).map(|_| Hart::new(0, vlen)).collect();
+        for hart in harts.iter_mut() {
+            hart.restore(&mut r)?;
+            hart.stopped = r.get_bool()?;
+        }
+        self.sched.restore(&mut r)?;
+        self.mem.restore(&mut r)?;
+
+        // Load the running hart (its slot is stale from now on)
+        self.harts = harts;
+        self.hart_id = hart_id;
+        self.swap_hart(hart_id);
+        if !r.is_done() {
+            return Err(invalid_data("trailing data in snapshot"));
+        }
+        Ok(())
+    }
+}
+
 pub fn make_illegal(sys: &System) -> Trap {
     Trap::from_exception(Exception::IllegalInstr, sys.code)
 }
 
 pub fn log_with_pc(sys: &System, str: &str, debug: bool) {
     if !debug || sys.cfg.verbose {
-        println!("{:8x} {str}", sys.pc());
+        match sys.symbols.describe(sys.pc()) {
+            Some(sym) => println!("{:8x} <{sym}> {str}", sys.pc()),
+            None => println!("{:8x} {str}", sys.pc()),
+        }
     }
 }
 
@@ -120,36 +386,105 @@ fn fetch_decode_exec(sys: &mut System) -> Result {
     update_interrupt(sys);
     check_interrupt(sys)?;
 
+    // Fetch and decode
+    let instr = fetch_decode(sys)?;
+    if sys.cfg.verbose {
+        log_with_pc(sys, &format!("{:?}", instr), true);
+    }
+
+    // Execute (branches are taken if they don't fall through)
+    let next_pc = sys.next_pc();
+    execute(sys, &instr)?;
+    let taken = sys.pc() != next_pc;
+    sys.ctrl.hpm.executed(&instr, taken);
+    Ok(())
+}
+
+fn fetch_decode(sys: &mut System) -> core::result::Result<Instr, Trap> {
+    let vpc = sys.pc();
+    let ppc = translate(sys, vpc, AccessType::Instr).map_err(|ex| Trap::from_exception(ex, vpc))?;
+
+    // Cached instructions skip reading and decoding, but fetching is still checked by PMP
+    if let Some((code, instr)) = sys.mem.icache.get(ppc) {
+        let instr = instr.clone();
+        let code_len = if is_compressed(code) { 2 } else { 4 };
+        check_fetch_pmp(sys, vpc, ppc)?;
+        if code_len == 4 {
+            check_fetch_pmp(sys, vpc.wrapping_add(2), ppc + 2)?;
+        }
+        sys.code = code;
+        sys.code_len = code_len;
+        // Decoded while C was enabled
+        if code_len == 2 && !sys.ctrl.ext_c {
+            return Err(Trap::from_exception(Exception::IllegalInstr, code));
+        }
+        return Ok(instr);
+    }
+
     // Fetch
-    let code = fetch(sys)?;
+    let code = fetch_at(sys, vpc, ppc)?;
     sys.code = code;
+    sys.code_len = if is_compressed(code) { 2 } else { 4 };
 
-    // Decode
-    let instr = decode(code).ok_or(Trap {
+    // Decode (compressed instructions are illegal if C is disabled, and so is bitmanip)
+    let instr = if is_compressed(code) && sys.ctrl.ext_c {
+        decode_compressed(code as u16)
+    } else {
+        decode(code).filter(|i| sys.cfg.bitmanip || !i.is_bitmanip())
+    };
+    let instr = instr.ok_or(Trap {
         cause: TrapCause::Exception(Exception::IllegalInstr),
         val: code,
     })?;
-    log_with_pc(sys, &format!("{:?}", instr), true);
 
-    // Execute
-    execute(sys, &instr)
+    // Instructions crossing a page are not cached, since the second page may be remapped
+    let in_ram = ppc >= sys.mem.ram_base && ppc + 4 <= sys.mem.ram_base + sys.mem.ram.size();
+    if in_ram && (sys.code_len == 2 || vpc & 0xfff != 0xffe) {
+        sys.mem.icache.insert(ppc, code, instr.clone());
+    }
+    Ok(instr)
 }
 
 pub fn fetch(sys: &mut System) -> Result32 {
     let vpc = sys.pc();
-    let make_trap = |ex| Trap::from_exception(ex, vpc);
-    let ppc = translate(sys, vpc, AccessType::Instr).map_err(make_trap)?;
-    let attr = AccessAttr {
-        atype: AccessType::Instr,
-        width: AccessWidth::Word,
-        lrsc: false,
-        amo: false,
+    let ppc = translate(sys, vpc, AccessType::Instr).map_err(|ex| Trap::from_exception(ex, vpc))?;
+    fetch_at(sys, vpc, ppc)
+}
+
+fn fetch_at(sys: &mut System, vpc: u32, ppc: u64) -> Result32 {
+    // Fetch the lower 16 bits first, which tell the length of the instruction
+    let lo = fetch_u16(sys, vpc, ppc)?;
+    if is_compressed(lo as u32) {
+        return Ok(lo as u32);
+    }
+
+    // The upper 16 bits may be on the next page, which must be translated separately
+    let vpc_hi = vpc.wrapping_add(2);
+    let ppc_hi = if vpc_hi & 0xfff == 0 {
+        translate(sys, vpc_hi, AccessType::Instr).map_err(|ex| Trap::from_exception(ex, vpc_hi))?
+    } else {
+        ppc + 2
     };
-    let code: u32 = sys.mem.read_u32(ppc, attr).map_err(make_trap)?;
-    Ok(code)
+    let hi = fetch_u16(sys, vpc_hi, ppc_hi)?;
+    Ok(lo as u32 | (hi as u32) << 16)
+}
+
+fn fetch_u16(sys: &mut System, vaddr: u32, paddr: u64) -> core::result::Result<u16, Trap> {
+    check_fetch_pmp(sys, vaddr, paddr)?;
+    sys.mem
+        .read_u16(paddr, FETCH_ATTR)
+        .map_err(|ex| Trap::from_exception(ex, vaddr))
+}
+
+fn check_fetch_pmp(sys: &System, vaddr: u32, paddr: u64) -> core::result::Result<(), Trap> {
+    check_pmp(sys, paddr, FETCH_ATTR).map_err(|ex| Trap::from_exception(ex, vaddr))
 }
 
 fn retire(sys: &mut System, res: Result) {
+    // Count the events of the programmable counters, whose overflows raise LCOFI
+    if sys.ctrl.hpm.retire(&res, &sys.ctrl.tlb) {
+        sys.ctrl.ip.set(&Interrupt::LocalCountOverflow, true);
+    }
     match res {
         Ok(_) => {
             // Count the number of retired instruction if Ok
@@ -169,3 +504,426 @@ fn retire(sys: &mut System, res: Result) {
     // Tick the timer
     sys.mem.timer.time = sys.mem.timer.time.wrapping_add(1);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Nic, NicBackend},
+        exec::csr::csr_write_debug,
+        instr::csr::CsrReg,
+        run::run_for,
+        Interrupt,
+    };
+    use bytesize::ByteSize;
+    use mem_map::virtio::VIRTIO_IRQ;
+    use std::io::Write;
+
+    fn write_u16(sys: &mut System, addr: usize, val: u16) {
+        sys.mem.ram.as_u8_mut()[addr..addr + 2].copy_from_slice(&val.to_le_bytes());
+    }
+
+    fn write_u32(sys: &mut System, addr: usize, val: u32) {
+        sys.mem.ram.as_u8_mut()[addr..addr + 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    #[test]
+    fn test_fetch_compressed() {
+        let mut sys = System::new();
+        write_u16(&mut sys, 0x0, 0x0505); // c.addi a0, 1
+        write_u32(&mut sys, 0x2, 0x00150513); // addi a0, a0, 1
+        write_u16(&mut sys, 0x6, 0x0505); // c.addi a0, 1
+
+        assert_eq!(fetch(&mut sys).unwrap(), 0x0505);
+        sys.step().unwrap();
+        assert_eq!(fetch(&mut sys).unwrap(), 0x00150513);
+        sys.step().unwrap();
+        sys.step().unwrap();
+        assert_eq!(sys.reg(&Reg::new(10)), 3);
+        assert_eq!(sys.pc(), 0x8);
+    }
+
+    #[test]
+    fn test_fetch_compressed_disabled() {
+        let mut sys = System::new();
+        sys.ctrl.ext_c = false;
+        write_u16(&mut sys, 0x0, 0x0505); // c.addi a0, 1
+
+        assert_eq!(
+            fetch_decode_exec(&mut sys),
+            Err(Trap::from_exception(Exception::IllegalInstr, 0x0505))
+        );
+
+        // Also when decoded before C was disabled
+        sys.ctrl.ext_c = true;
+        sys.step().unwrap();
+        assert!(sys.mem.icache.get(0x0).is_some());
+        *sys.pc_mut() = 0x0;
+        sys.ctrl.ext_c = false;
+        assert_eq!(
+            fetch_decode_exec(&mut sys),
+            Err(Trap::from_exception(Exception::IllegalInstr, 0x0505))
+        );
+    }
+
+    #[test]
+    fn test_bitmanip_disabled() {
+        let mut cfg = Config::new();
+        cfg.bitmanip = false;
+        let mut sys = System::from_config(cfg);
+        write_u32(&mut sys, 0x0, 0x00150513); // addi a0, a0, 1
+        write_u32(&mut sys, 0x4, 0x60051513); // clz a0, a0
+
+        sys.step().unwrap();
+        assert_eq!(
+            fetch_decode_exec(&mut sys),
+            Err(Trap::from_exception(Exception::IllegalInstr, 0x60051513))
+        );
+    }
+
+    #[test]
+    fn test_external_interrupt() {
+        let mut sys = System::new();
+        let plic_base = sys.mem.plic_base;
+        let attr = AccessAttr {
+            atype: AccessType::Load,
+            width: AccessWidth::Word,
+            lrsc: false,
+            amo: false,
+        };
+        sys.ctrl.mtvec_base = 0x100;
+        sys.ctrl.mie = true;
+        sys.ctrl.ie.set(&Interrupt::MExt, true);
+        sys.mem.plic.write(5 * 4, 1); // Priority of source 5
+        sys.mem.plic.write(0x2000, 1 << 5); // Enable for M-mode
+
+        sys.mem.plic.set_level(5, true);
+        assert_eq!(sys.step(), Err(Trap::from_interrupt(Interrupt::MExt, 0)));
+        assert_eq!(sys.pc(), 0x100);
+
+        // Claiming clears MEIP
+        assert_eq!(sys.mem.read_u32(plic_base + 0x20_0004, attr).unwrap(), 5);
+        update_interrupt(&mut sys);
+        assert!(!sys.ctrl.ip.get(&Interrupt::MExt));
+
+        // SEIP is the OR of the software bit and the S-mode context
+        sys.ctrl.seip = true;
+        update_interrupt(&mut sys);
+        assert!(sys.ctrl.ip.get(&Interrupt::SExt));
+    }
+
+    #[test]
+    fn test_fetch_pmp() {
+        let mut sys = System::new();
+        write_u32(&mut sys, 0x0, 0x00150513); // addi a0, a0, 1
+        write_u32(&mut sys, 0x1000, 0x00150513); // addi a0, a0, 1
+        sys.ctrl.privilege = MPriv::U;
+
+        // Only the first page is executable in U-mode
+        sys.ctrl.pmp.write_addr(0, 0x1ff);
+        sys.ctrl.pmp.write_cfg(0, 0x1d); // NAPOT, RX
+        assert_eq!(fetch(&mut sys), Ok(0x00150513));
+        *sys.pc_mut() = 0x1000;
+        assert_eq!(
+            fetch(&mut sys),
+            Err(Trap::from_exception(Exception::InstrAccessFault, 0x1000))
+        );
+
+        // M-mode is not restricted
+        sys.ctrl.privilege = MPriv::M;
+        assert_eq!(fetch(&mut sys), Ok(0x00150513));
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let mut sys = System::new();
+        write_u32(&mut sys, 0x0, 0x00150513); // addi a0, a0, 1
+        write_u32(&mut sys, 0x4, 0x00602023); // sw t1, 0(zero)
+        write_u32(&mut sys, 0x8, 0xff9ff06f); // j -8
+        *sys.reg_mut(&Reg::new(6)) = 0x01050513; // addi a0, a0, 16
+
+        // The store replaces the cached instruction
+        run_for(&mut sys, 4);
+        assert_eq!(sys.reg(&Reg::new(10)), 17);
+        assert_eq!(sys.mem.icache.get(0x0).unwrap().0, 0x01050513);
+
+        // Disabling C drops cached compressed instructions
+        write_u16(&mut sys, 0x0, 0x0505); // c.addi a0, 1
+        sys.mem.icache.flush();
+        *sys.pc_mut() = 0x0;
+        sys.step().unwrap();
+        assert_eq!(sys.reg(&Reg::new(10)), 18);
+        *sys.pc_mut() = 0x2; // So that the next instruction is aligned
+        let misa = CsrReg::from(0x301).unwrap();
+        csr_write_debug(&mut sys, &misa, 0x4000_1129).unwrap(); // Without C
+        *sys.pc_mut() = 0x0;
+        assert_eq!(
+            sys.step(),
+            Err(Trap::from_exception(Exception::IllegalInstr, 0x0505))
+        );
+    }
+
+    #[test]
+    fn test_fetch_across_pages() {
+        let mut cfg = Config::new();
+        cfg.size = ByteSize::kib(64);
+        let mut sys = System::from_config(cfg);
+
+        // VA page 0 maps to PA page 5, VA page 1 maps to PA page 2
+        write_u32(&mut sys, 0x8000, (9 << 10) | 0x01); // Root page table
+        write_u32(&mut sys, 0x9000, (5 << 10) | 0xcf); // Leaf page table
+        write_u32(&mut sys, 0x9004, (2 << 10) | 0xcf);
+        sys.ctrl.privilege = MPriv::S;
+        sys.ctrl.satp_mode = SatpMode::Sv32;
+        sys.ctrl.satp_ppn = 8;
+
+        // addi a0, a0, 1 straddling the page boundary, then c.addi a0, 1
+        write_u16(&mut sys, 0x5ffe, 0x0513);
+        write_u16(&mut sys, 0x2000, 0x0015);
+        write_u16(&mut sys, 0x2002, 0x0505);
+        *sys.pc_mut() = 0xffe;
+
+        assert_eq!(fetch(&mut sys).unwrap(), 0x00150513);
+        sys.step().unwrap();
+        sys.step().unwrap();
+        assert_eq!(sys.reg(&Reg::new(10)), 2);
+        assert_eq!(sys.pc(), 0x1004);
+    }
+
+    #[test]
+    fn test_fetch_across_pages_fault() {
+        let mut cfg = Config::new();
+        cfg.size = ByteSize::kib(64);
+        let mut sys = System::from_config(cfg);
+
+        // VA page 0 is mapped, VA page 1 is not
+        write_u32(&mut sys, 0x8000, (9 << 10) | 0x01);
+        write_u32(&mut sys, 0x9000, (5 << 10) | 0xcf);
+        sys.ctrl.privilege = MPriv::S;
+        sys.ctrl.satp_mode = SatpMode::Sv32;
+        sys.ctrl.satp_ppn = 8;
+
+        // The page fault reports the address of the upper half
+        write_u16(&mut sys, 0x5ffe, 0x0513);
+        *sys.pc_mut() = 0xffe;
+        assert_eq!(
+            fetch(&mut sys),
+            Err(Trap::from_exception(Exception::InstrPageFault, 0x1000))
+        );
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut sys = System::new();
+        write_u32(&mut sys, 0x0, 0x00108093); // addi ra, ra, 1
+        write_u32(&mut sys, 0x4, 0x10102023); // sw ra, 256(zero)
+        write_u32(&mut sys, 0x8, 0xff9ff06f); // j -8
+        sys.mem.uart.write(7, 0x5a);
+        sys.mem.reserve(0, 0x100);
+        run_for(&mut sys, 100);
+        let snapshot = sys.save_snapshot();
+        run_for(&mut sys, 50);
+
+        // A fresh machine of another size resumes from the same point
+        let mut cfg = Config::new();
+        cfg.size = ByteSize::kib(4);
+        let mut restored = System::from_config(cfg);
+        restored.restore_snapshot(&snapshot).unwrap();
+        assert_eq!(restored.save_snapshot(), snapshot);
+        run_for(&mut restored, 50);
+        assert_eq!(restored.save_snapshot(), sys.save_snapshot());
+        assert_eq!(restored.reg(&Reg::new(1)), 50);
+        assert_eq!(restored.mem.uart.read(7), 0x5a);
+
+        // Truncated or trailing data
+        assert!(restored
+            .restore_snapshot(&snapshot[..snapshot.len() - 1])
+            .is_err());
+        let mut longer = snapshot.clone();
+        longer.push(0);
+        assert!(restored.restore_snapshot(&longer).is_err());
+    }
+
+    #[test]
+    fn test_smp() {
+        let mut cfg = Config::new();
+        cfg.harts = 2;
+        cfg.quantum = 1;
+        let mut sys = System::from_config(cfg);
+        write_u32(&mut sys, 0x0, 0xf1402673); // csrr a2, mhartid
+        write_u32(&mut sys, 0x4, 0x1004232f); // lr.w t1, (s0)
+        write_u32(&mut sys, 0x8, 0x186423af); // sc.w t2, t1, (s0)
+        for id in [1, 0] {
+            sys.switch_hart(id);
+            assert_eq!(sys.reg(&Reg::new(10)), id as i32);
+            *sys.reg_mut(&Reg::new(8)) = 0x100;
+        }
+
+        // Both harts reserve the word, then the store of hart 0 breaks the reservation of hart 1
+        run_for(&mut sys, 6);
+        assert_eq!(sys.hart_id, 0);
+        assert_eq!((sys.reg(&Reg::new(12)), sys.reg(&Reg::new(7))), (0, 0));
+        let snapshot = sys.save_snapshot();
+        sys.switch_hart(1);
+        assert_eq!((sys.reg(&Reg::new(12)), sys.reg(&Reg::new(7))), (1, 1));
+        assert_eq!(sys.pc(), 0xc);
+
+        // Snapshots include every hart
+        let mut restored = System::new();
+        restored.restore_snapshot(&snapshot).unwrap();
+        assert_eq!((restored.num_harts(), restored.hart_id), (2, 0));
+        assert_eq!(restored.save_snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_pause_skip() {
+        let mut cfg = Config::new();
+        cfg.harts = 2;
+        cfg.pause_skip = 1000;
+        let mut sys = System::from_config(cfg);
+        write_u32(&mut sys, 0x0, 0x0100000f); // pause
+        write_u32(&mut sys, 0x4, 0x0ec5d533); // czero.eqz a0, a1, a2
+
+        // The timer skips ahead and the other hart runs next, despite the quantum of 100
+        run_for(&mut sys, 1);
+        assert_eq!(sys.mem.timer.time, 1001);
+        assert_eq!(sys.hart_id, 1);
+        run_for(&mut sys, 1);
+        assert_eq!((sys.hart_id, sys.pc()), (0, 0x4));
+        assert_eq!(sys.mem.timer.time, 2002);
+    }
+
+    #[test]
+    fn test_counter_overflow_interrupt() {
+        let mut sys = System::new();
+        write_u32(&mut sys, 0x0, 0x00150513); // addi a0, a0, 1
+        write_u32(&mut sys, 0x4, 0xfe000ee3); // beqz zero, 0x0
+        let hpm = &mut sys.ctrl.hpm;
+        hpm.write_event(3, 0x07); // Taken branches
+        hpm.write_event(4, 0x02); // Instructions
+        hpm.write_counter(4, u64::MAX - 2);
+        sys.ctrl.mie = true;
+        sys.ctrl.ie.set(&Interrupt::LocalCountOverflow, true);
+
+        // The overflow on the third instruction traps before the fourth
+        run_for(&mut sys, 4);
+        assert_eq!(sys.ctrl.mtrap.cause.to_int(), 0x8000_000d);
+        assert_eq!(sys.ctrl.mepc, 0x4);
+        assert_eq!(sys.ctrl.hpm.read_counter(3), 1);
+        assert_eq!(sys.ctrl.hpm.read_counter(4), 0);
+        assert_eq!(sys.ctrl.hpm.overflows(), 1 << 4);
+    }
+
+    #[test]
+    fn test_smp_interrupts() {
+        let mut cfg = Config::new();
+        cfg.harts = 2;
+        let mut sys = System::from_config(cfg);
+
+        // Software, timer and external interrupts only reach their own hart
+        sys.mem.mswi.write(4, 1);
+        sys.mem.sswi.write(0, 1);
+        sys.mem.timer.write_timecmp(0, 0);
+        sys.mem.timer.write_timecmp(4, 0);
+        sys.mem.plic.write(5 * 4, 1); // Priority of source 5
+        sys.mem.plic.write(0x2100, 1 << 5); // Enable for M-mode of hart 1
+        sys.mem.plic.set_level(5, true);
+        update_interrupt(&mut sys);
+        assert!(!sys.ctrl.ip.get(&Interrupt::MSoft));
+        assert!(sys.ctrl.ip.get(&Interrupt::SSoft));
+        assert!(sys.ctrl.ip.get(&Interrupt::MTimer));
+        assert!(!sys.ctrl.ip.get(&Interrupt::MExt));
+        sys.switch_hart(1);
+        update_interrupt(&mut sys);
+        assert!(sys.ctrl.ip.get(&Interrupt::MSoft));
+        assert!(!sys.ctrl.ip.get(&Interrupt::SSoft));
+        assert!(!sys.ctrl.ip.get(&Interrupt::MTimer));
+        assert!(sys.ctrl.ip.get(&Interrupt::MExt));
+    }
+
+    #[test]
+    fn test_finisher_and_reset() {
+        let mut sys = System::new();
+        write_u32(&mut sys, 0x0, 0xc00012b7); // lui t0, 0xc0001
+        write_u32(&mut sys, 0x4, 0x00007337); // lui t1, 0x7
+        write_u32(&mut sys, 0x8, 0x77730313); // addi t1, t1, 0x777
+        write_u32(&mut sys, 0xc, 0x0062a023); // sw t1, 0(t0)
+        run_for(&mut sys, 3);
+        assert_eq!(sys.stop, None);
+        run_for(&mut sys, 1);
+        assert_eq!(sys.stop, Some(StopReason::Reset));
+
+        // Everything starts again
+        sys.reset();
+        assert_eq!((sys.stop, sys.pc()), (None, 0));
+        assert_eq!(sys.reg(&Reg::new(5)), 0);
+        assert_eq!(sys.mem.ram.as_u32()[0], 0);
+
+        // Failure code
+        sys.mem.finisher.write(0, 0x0003_3333);
+        run_for(&mut sys, 1);
+        assert_eq!(sys.stop, Some(StopReason::Exit(3)));
+    }
+
+    #[test]
+    fn test_virtio_nics() {
+        let mut cfg = Config::new();
+        cfg.size = ByteSize::mib(1);
+        let nic = Nic {
+            backend: NicBackend::Loopback,
+            mac: None,
+            link_up: true,
+        };
+        cfg.nic = vec![nic.clone(), nic];
+        let mut sys = System::from_config(cfg);
+        let base = sys.mem.virtio_base;
+        let mut read_u8 = |addr| {
+            let attr = AccessAttr {
+                atype: AccessType::Load,
+                width: AccessWidth::Byte,
+                lrsc: false,
+                amo: false,
+            };
+            sys.mem.read_u8(addr, attr).unwrap()
+        };
+        // Default MAC addresses, one per device
+        assert_eq!(read_u8(base + 0x105), 0x56);
+        assert_eq!(read_u8(base + 0x1105), 0x57);
+        assert_eq!(read_u8(base + 0x2105), 0);
+
+        // Link down, raising the interrupt of slot 1
+        assert!(sys.mem.virtio[1].set_link_up(false));
+        update_interrupt(&mut sys);
+        assert_eq!(sys.mem.plic.read(0x1000), 1 << (VIRTIO_IRQ + 1));
+
+        // Up again after a reset
+        sys.reset();
+        update_interrupt(&mut sys);
+        assert_eq!(sys.mem.plic.read(0x1000), 0);
+        assert!(sys.mem.virtio[1].set_link_up(true));
+        assert!(!sys.mem.virtio[1].is_interrupt_set());
+    }
+
+    #[test]
+    fn test_reset_keeps_nic() {
+        let path = std::env::temp_dir().join(format!("riscv_sim_reset_{}", std::process::id()));
+        let mut cfg = Config::new();
+        cfg.size = ByteSize::mib(1);
+        cfg.nic = vec![Nic {
+            backend: NicBackend::Pcap(path.clone()),
+            mac: None,
+            link_up: true,
+        }];
+        let mut sys = System::from_config(cfg);
+        // The capture is not truncated
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0; 16])
+            .unwrap();
+        sys.reset();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 24 + 16);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,16 +1,27 @@
-use crate::{Exception, Interrupt, Trap};
+use crate::{
+    hpm::Hpm,
+    pmp::Pmp,
+    snapshot::{decode, SnapshotReader, SnapshotWriter},
+    tlb::Tlb,
+    Exception, Interrupt, Trap,
+};
+use std::io;
 
 #[derive(Debug)]
 pub struct Control {
     pub privilege: MPriv, // Current privilege mode
+    // misa: ISA extensions
+    pub ext_c: bool, // Compressed instructions
     // mstatus: Status
-    pub mie: bool,  // M-mode interrupt enable
-    pub mpie: bool, // M-mode previous interrupt enable
-    pub mpp: MPriv, // M-mode previous privilege mode
-    pub mprv: bool, // Modify privilege
-    pub tvm: bool,  // Trap virtual memory
-    pub tw: bool,   // Trap wait-for-interrupt
-    pub tsr: bool,  // Trap SRET
+    pub mie: bool,     // M-mode interrupt enable
+    pub mpie: bool,    // M-mode previous interrupt enable
+    pub mpp: MPriv,    // M-mode previous privilege mode
+    pub mprv: bool,    // Modify privilege
+    pub tvm: bool,     // Trap virtual memory
+    pub tw: bool,      // Trap wait-for-interrupt
+    pub tsr: bool,     // Trap SRET
+    pub fs: ExtStatus, // Floating-point unit status
+    pub vs: ExtStatus, // Vector unit status
     // mtvec: Trap vector
     pub mtvec_base: u32,      // Trap vector base address
     pub mtvec_mode: TvecMode, // Trap vector mode
@@ -20,6 +31,7 @@ pub struct Control {
     pub mideleg: InterruptMap,
     // mip: Interrupt pending
     pub ip: InterruptMap,
+    pub seip: bool, // Software-writable SEIP (ORed with the PLIC output)
     // mie: Interrupt enable
     pub ie: InterruptMap,
     // mscratch: Scratch register
@@ -30,6 +42,9 @@ pub struct Control {
     pub mtrap: Trap,
     // menvcfg: Environment configuration
     pub mfiom: bool, // Fence IO implies memory
+    pub stce: bool,  // STimecmp enable (Sstc)
+    // pmpcfg & pmpaddr: Physical memory protection
+    pub pmp: Pmp,
     // mcycle: Counter for clock cycles
     pub mcycle: u64,
     pub mcycle_en: bool,
@@ -40,6 +55,8 @@ pub struct Control {
     pub minstret: u64,
     pub minstret_en: bool,
     pub minstret_inhibit: bool,
+    // mhpmcounter & mhpmevent: Programmable counters
+    pub hpm: Hpm,
     // sstatus: Status
     pub sie: bool,  // S-mode interrupt enable
     pub spie: bool, // S-mode previous interrupt enable
@@ -63,9 +80,24 @@ pub struct Control {
     pub strap: Trap,
     // senvcfg: Environment configuration
     pub sfiom: bool, // Fence IO implies memory
+    // stimecmp: Supervisor timer compare (Sstc)
+    pub stimecmp: u64,
     // satp: Address translation
     pub satp_mode: SatpMode, // Translation mode
     pub satp_ppn: u32,       // PPN of root page table
+    pub satp_asid: u32,      // Address space identifier
+    pub tlb: Tlb,
+    // fcsr: Floating-point control and status
+    pub fflags: u8, // Accrued exception flags
+    pub frm: u8,    // Dynamic rounding mode
+    // vtype & vl: Vector configuration
+    pub vtype: u32, // Raw value (vill in bit 31)
+    pub vl: u32,    // Vector length
+    // vstart: First element to execute
+    pub vstart: u32,
+    // vcsr: Vector control and status
+    pub vxrm: u8,    // Fixed-point rounding mode
+    pub vxsat: bool, // Fixed-point saturation flag
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -81,6 +113,14 @@ pub enum SPriv {
     S,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExtStatus {
+    Off,
+    Initial,
+    Clean,
+    Dirty,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum TvecMode {
     Direct,
@@ -103,6 +143,7 @@ impl Control {
     pub fn new() -> Control {
         Control {
             privilege: MPriv::M,
+            ext_c: true,
             mie: false,
             mpie: false,
             mpp: MPriv::U,
@@ -110,16 +151,21 @@ impl Control {
             tvm: false,
             tw: false,
             tsr: false,
+            fs: ExtStatus::Initial, // So that FP code runs without any setup
+            vs: ExtStatus::Initial, // Likewise for vector code
             mtvec_base: 0x100,
             mtvec_mode: TvecMode::Direct,
             medeleg: ExceptionMap::new(),
             mideleg: InterruptMap::new(),
             ip: InterruptMap::new(),
+            seip: false,
             ie: InterruptMap::new(),
             mscratch: 0,
             mepc: 0,
             mtrap: Trap::from_exception(Exception::InstrAddrMisaligned, 0),
             mfiom: false,
+            stce: false,
+            pmp: Pmp::new(16),
             mcycle: 0,
             mcycle_en: false,
             mcycle_inhibit: false,
@@ -127,6 +173,7 @@ impl Control {
             minstret: 0,
             minstret_en: false,
             minstret_inhibit: false,
+            hpm: Hpm::new(),
             sie: false,
             spie: false,
             spp: SPriv::U,
@@ -141,12 +188,169 @@ impl Control {
             sepc: 0,
             strap: Trap::from_exception(Exception::InstrAddrMisaligned, 0),
             sfiom: false,
+            stimecmp: u64::MAX,
             satp_mode: SatpMode::Bare,
             satp_ppn: 0,
+            satp_asid: 0,
+            tlb: Tlb::new(),
+            fflags: 0,
+            frm: 0,
+            vtype: 1 << 31,
+            vl: 0,
+            vstart: 0,
+            vxrm: 0,
+            vxsat: false,
+        }
+    }
+}
+
+impl Control {
+    // Valid bits of PC (IALIGN is 16 with the C extension, 32 otherwise)
+    pub fn pc_mask(&self) -> u32 {
+        if self.ext_c {
+            0xffff_fffe
+        } else {
+            0xffff_fffc
         }
     }
 }
 
+impl Control {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_u8(self.privilege.to_int() as u8);
+        w.put_bool(self.ext_c);
+        w.put_bool(self.mie);
+        w.put_bool(self.mpie);
+        w.put_u8(self.mpp.to_int() as u8);
+        w.put_bool(self.mprv);
+        w.put_bool(self.tvm);
+        w.put_bool(self.tw);
+        w.put_bool(self.tsr);
+        w.put_u8(self.fs.to_int() as u8);
+        w.put_u8(self.vs.to_int() as u8);
+        w.put_u32(self.mtvec_base);
+        w.put_u8(self.mtvec_mode.to_int() as u8);
+        w.put_u32(self.medeleg.0);
+        w.put_u32(self.mideleg.0);
+        w.put_u32(self.ip.0);
+        w.put_bool(self.seip);
+        w.put_u32(self.ie.0);
+        w.put_u32(self.mscratch);
+        w.put_u32(self.mepc);
+        save_trap(w, &self.mtrap);
+        w.put_bool(self.mfiom);
+        w.put_bool(self.stce);
+        self.pmp.save(w);
+        w.put_u64(self.mcycle);
+        w.put_bool(self.mcycle_en);
+        w.put_bool(self.mcycle_inhibit);
+        w.put_bool(self.mtime_en);
+        w.put_u64(self.minstret);
+        w.put_bool(self.minstret_en);
+        w.put_bool(self.minstret_inhibit);
+        self.hpm.save(w);
+        w.put_bool(self.sie);
+        w.put_bool(self.spie);
+        w.put_u8(self.spp.to_int() as u8);
+        w.put_bool(self.sum);
+        w.put_bool(self.mxr);
+        w.put_u32(self.stvec_base);
+        w.put_u8(self.stvec_mode.to_int() as u8);
+        w.put_bool(self.scycle_en);
+        w.put_bool(self.stime_en);
+        w.put_bool(self.sinstret_en);
+        w.put_u32(self.sscratch);
+        w.put_u32(self.sepc);
+        save_trap(w, &self.strap);
+        w.put_bool(self.sfiom);
+        w.put_u64(self.stimecmp);
+        w.put_u8(self.satp_mode.to_int() as u8);
+        w.put_u32(self.satp_ppn);
+        w.put_u32(self.satp_asid);
+        self.tlb.save(w);
+        w.put_u8(self.fflags);
+        w.put_u8(self.frm);
+        w.put_u32(self.vtype);
+        w.put_u32(self.vl);
+        w.put_u32(self.vstart);
+        w.put_u8(self.vxrm);
+        w.put_bool(self.vxsat);
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        self.privilege = decode(MPriv::from(r.get_u8()? as u32), "privilege")?;
+        self.ext_c = r.get_bool()?;
+        self.mie = r.get_bool()?;
+        self.mpie = r.get_bool()?;
+        self.mpp = decode(MPriv::from(r.get_u8()? as u32), "mpp")?;
+        self.mprv = r.get_bool()?;
+        self.tvm = r.get_bool()?;
+        self.tw = r.get_bool()?;
+        self.tsr = r.get_bool()?;
+        self.fs = decode(ExtStatus::from(r.get_u8()? as u32), "fs")?;
+        self.vs = decode(ExtStatus::from(r.get_u8()? as u32), "vs")?;
+        self.mtvec_base = r.get_u32()?;
+        self.mtvec_mode = decode(TvecMode::from(r.get_u8()? as u32), "mtvec mode")?;
+        self.medeleg = ExceptionMap(r.get_u32()?);
+        self.mideleg = InterruptMap(r.get_u32()?);
+        self.ip = InterruptMap(r.get_u32()?);
+        self.seip = r.get_bool()?;
+        self.ie = InterruptMap(r.get_u32()?);
+        self.mscratch = r.get_u32()?;
+        self.mepc = r.get_u32()?;
+        self.mtrap = restore_trap(r)?;
+        self.mfiom = r.get_bool()?;
+        self.stce = r.get_bool()?;
+        self.pmp.restore(r)?;
+        self.mcycle = r.get_u64()?;
+        self.mcycle_en = r.get_bool()?;
+        self.mcycle_inhibit = r.get_bool()?;
+        self.mtime_en = r.get_bool()?;
+        self.minstret = r.get_u64()?;
+        self.minstret_en = r.get_bool()?;
+        self.minstret_inhibit = r.get_bool()?;
+        self.hpm.restore(r)?;
+        self.sie = r.get_bool()?;
+        self.spie = r.get_bool()?;
+        self.spp = decode(SPriv::from(r.get_u8()? as u32), "spp")?;
+        self.sum = r.get_bool()?;
+        self.mxr = r.get_bool()?;
+        self.stvec_base = r.get_u32()?;
+        self.stvec_mode = decode(TvecMode::from(r.get_u8()? as u32), "stvec mode")?;
+        self.scycle_en = r.get_bool()?;
+        self.stime_en = r.get_bool()?;
+        self.sinstret_en = r.get_bool()?;
+        self.sscratch = r.get_u32()?;
+        self.sepc = r.get_u32()?;
+        self.strap = restore_trap(r)?;
+        self.sfiom = r.get_bool()?;
+        self.stimecmp = r.get_u64()?;
+        self.satp_mode = decode(SatpMode::from(r.get_u8()? as u32), "satp mode")?;
+        self.satp_ppn = r.get_u32()?;
+        self.satp_asid = r.get_u32()?;
+        self.tlb.restore(r)?;
+        self.fflags = r.get_u8()?;
+        self.frm = r.get_u8()?;
+        self.vtype = r.get_u32()?;
+        self.vl = r.get_u32()?;
+        self.vstart = r.get_u32()?;
+        self.vxrm = r.get_u8()?;
+        self.vxsat = r.get_bool()?;
+        Ok(())
+    }
+}
+
+fn save_trap(w: &mut SnapshotWriter, trap: &Trap) {
+    w.put_u32(trap.cause.to_int());
+    w.put_u32(trap.val);
+}
+
+fn restore_trap(r: &mut SnapshotReader) -> io::Result<Trap> {
+    let cause = r.get_u32()?;
+    let val = r.get_u32()?;
+    decode(Trap::from_code(cause, val), "trap cause")
+}
+
 impl MPriv {
     pub fn from(code: u32) -> Option<MPriv> {
         match code {
@@ -198,6 +402,27 @@ impl SPriv {
     }
 }
 
+impl ExtStatus {
+    pub fn from(code: u32) -> Option<ExtStatus> {
+        match code {
+            0b00 => Some(ExtStatus::Off),
+            0b01 => Some(ExtStatus::Initial),
+            0b10 => Some(ExtStatus::Clean),
+            0b11 => Some(ExtStatus::Dirty),
+            _ => None,
+        }
+    }
+
+    pub fn to_int(&self) -> u32 {
+        match self {
+            ExtStatus::Off => 0b00,
+            ExtStatus::Initial => 0b01,
+            ExtStatus::Clean => 0b10,
+            ExtStatus::Dirty => 0b11,
+        }
+    }
+}
+
 impl TvecMode {
     pub fn from(code: u32) -> Option<TvecMode> {
         match code {
@@ -228,7 +453,9 @@ impl InterruptMap {
     pub fn set(&mut self, int: &Interrupt, val: bool) {
         let mask = 1 << int.to_int();
         self.0 &= !mask;
-        self.0 |= mask & (val as u32);
+        if val {
+            self.0 |= mask;
+        }
     }
 }
 
@@ -0,0 +1,184 @@
+use crate::{
+    pmp::Pmp,
+    snapshot::{SnapshotReader, SnapshotWriter},
+    sys::{control::Control, state::State},
+};
+use std::io;
+
+// Context of a hart which is not running (registers, CSRs and privilege mode)
+#[derive(Debug)]
+pub struct Hart {
+    pub state: State,
+    pub ctrl: Control,
+    pub code: u32,
+    pub code_len: u32,
+    pub stopped: bool, // Not scheduled (kept up to date for the running hart too)
+}
+
+impl Hart {
+    pub fn new(pmp_entries: usize, vlen: u32) -> Hart {
+        let mut ctrl = Control::new();
+        ctrl.pmp = Pmp::new(pmp_entries);
+        Hart {
+            state: State::new(vlen),
+            ctrl,
+            code: 0,
+            code_len: 4,
+            stopped: false,
+        }
+    }
+}
+
+impl Hart {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        self.state.save(w);
+        self.ctrl.save(w);
+        w.put_u32(self.code);
+        w.put_u32(self.code_len);
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        self.state.restore(r)?;
+        self.ctrl.restore(r)?;
+        self.code = r.get_u32()?;
+        self.code_len = r.get_u32()?;
+        Ok(())
+    }
+}
+
+// Interleaving of the harts:
+// - Each hart runs for a quantum of steps before switching to another one
+// - Without a seed, harts take turns with a fixed quantum
+// - With a seed, both the next hart and the quantum (1..=quantum) are pseudo-random
+// - Stopped harts are skipped, and a stopped hart is switched out immediately
+#[derive(Debug)]
+pub struct Scheduler {
+    quantum: u64,
+    rng: Option<u64>, // xorshift64 state
+    remaining: u64,
+}
+
+impl Scheduler {
+    pub fn new(quantum: u64, seed: Option<u64>) -> Scheduler {
+        let quantum = quantum.max(1);
+        // The state of xorshift must not be 0
+        let rng = seed.map(|s| s.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+        Scheduler {
+            quantum,
+            rng,
+            remaining: quantum,
+        }
+    }
+
+    // Called after each step, returns the hart to switch to (if any)
+    pub fn next(&mut self, current: usize, harts: &[Hart]) -> Option<usize> {
+        if harts.len() <= 1 {
+            return None;
+        }
+        self.remaining = self.remaining.saturating_sub(1);
+        if self.remaining > 0 && !harts[current].stopped {
+            return None;
+        }
+        let runnable: Vec<_> = (1..=harts.len())
+            .map(|i| (current + i) % harts.len())
+            .filter(|id| !harts[*id].stopped)
+            .collect();
+        let next = match self.rng {
+            Some(_) if !runnable.is_empty() => {
+                self.remaining = self.random() % self.quantum + 1;
+                runnable[(self.random() % runnable.len() as u64) as usize]
+            }
+            _ => {
+                self.remaining = self.quantum;
+                *runnable.first()?
+            }
+        };
+        (next != current).then_some(next)
+    }
+
+    // Switch to another hart after the current step
+    pub fn end_quantum(&mut self) {
+        self.remaining = 1;
+    }
+
+    fn random(&mut self) -> u64 {
+        let mut x = self.rng.unwrap_or(1);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng = Some(x);
+        x
+    }
+}
+
+impl Scheduler {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_u64(self.quantum);
+        w.put_bool(self.rng.is_some());
+        w.put_u64(self.rng.unwrap_or(0));
+        w.put_u64(self.remaining);
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        self.quantum = r.get_u64()?.max(1);
+        let seeded = r.get_bool()?;
+        let rng = r.get_u64()?;
+        self.rng = seeded.then_some(rng);
+        self.remaining = r.get_u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_harts(n: usize) -> Vec<Hart> {
+        (0..n).map(|_| Hart::new(0, 128)).collect()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let mut sched = Scheduler::new(2, None);
+        let mut harts = make_harts(3);
+        let mut current = 0;
+        let mut order = vec![];
+        for _ in 0..8 {
+            if let Some(next) = sched.next(current, &harts) {
+                current = next;
+            }
+            order.push(current);
+        }
+        assert_eq!(order, vec![0, 1, 1, 2, 2, 0, 0, 1]);
+
+        // Stopped harts are skipped, or switched out at once
+        harts[2].stopped = true;
+        assert_eq!(sched.next(1, &harts), None);
+        assert_eq!(sched.next(1, &harts), Some(0));
+        harts[0].stopped = true;
+        assert_eq!(sched.next(0, &harts), Some(1));
+        assert_eq!(sched.next(1, &harts), None);
+
+        // A single hart never switches
+        assert_eq!(Scheduler::new(1, None).next(0, &make_harts(1)), None);
+    }
+
+    #[test]
+    fn test_random() {
+        let harts = make_harts(2);
+        let run = |seed| {
+            let mut sched = Scheduler::new(4, Some(seed));
+            let mut current = 0;
+            (0..100)
+                .map(|_| {
+                    current = sched.next(current, &harts).unwrap_or(current);
+                    current
+                })
+                .collect::<Vec<_>>()
+        };
+        // Reproducible with the same seed, and every hart gets to run
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+        assert!(run(1).contains(&0) && run(1).contains(&1));
+    }
+}
@@ -1,18 +1,35 @@
 use crate::{
+    icache::DecodeCache,
+    snapshot::{SnapshotReader, SnapshotWriter},
     Exception::{self, *},
-    Result16E, Result32E, Result8E, ResultE,
+    Result16E, Result32E, Result64E, Result8E, ResultE,
 };
 use core::panic;
+use std::{io, ops::Range};
+
+const HOST_POLL_INTERVAL: u32 = 1000; // Steps between polls of the host inputs
 
 pub mod dtb;
+pub mod finisher;
+pub mod mswi;
+pub mod plic;
 pub mod ram;
+pub mod sswi;
 pub mod timer;
 pub mod uart;
+pub mod virtio;
+pub mod virtio_blk;
+pub mod virtio_net;
 
 use dtb::*;
+use finisher::*;
+use mswi::*;
+use plic::*;
 use ram::*;
+use sswi::*;
 use timer::*;
 use uart::*;
+use virtio::*;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum AccessType {
@@ -26,6 +43,7 @@ pub enum AccessWidth {
     Byte,
     HalfWord,
     Word,
+    DoubleWord,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -41,8 +59,12 @@ pub enum MemTarget {
     Ram(u64),
     Uart(u64),
     Dtb(u64),
-    Time(u64),
-    TimeCmp(u64),
+    Mtimer(u64),
+    Mswi(u64),
+    Sswi(u64),
+    Plic(u64),
+    Finisher(u64),
+    Virtio(u64),
 }
 
 #[derive(Debug)]
@@ -51,12 +73,23 @@ pub struct MemMap {
     pub uart: Uart,
     pub dtb: Dtb,
     pub timer: Timer,
+    pub mswi: Mswi,
+    pub sswi: Sswi,
+    pub plic: Plic,
+    pub finisher: Finisher,
+    pub virtio: Vec<VirtioMmio>,
     pub ram_base: u64,
     pub uart_base: u64,
     pub dtb_base: u64,
-    pub time_base: u64,
-    pub timecmp_base: u64,
-    reserved_word: Option<u64>, // For atomic lr/sc
+    pub mswi_base: u64,
+    pub mtimer_base: u64,
+    pub sswi_base: u64,
+    pub plic_base: u64,
+    pub finisher_base: u64,
+    pub virtio_base: u64,             // Slot i at virtio_base + i * VIRTIO_SIZE
+    reserved_words: Vec<Option<u64>>, // For atomic lr/sc (one per hart)
+    pub icache: DecodeCache,          // Invalidated by writes to RAM
+    steps_to_poll: u32,               // Until the host inputs are polled again
 }
 
 impl MemMap {
@@ -65,26 +98,56 @@ impl MemMap {
         let uart = Uart::new();
         let dtb = Dtb::new(vec![]);
         let timer = Timer::new();
+        let mswi = Mswi::new();
+        let sswi = Sswi::new();
+        let plic = Plic::new();
+        let finisher = Finisher::new();
+        let virtio = (0..VIRTIO_SLOTS).map(|_| VirtioMmio::new(None)).collect();
         MemMap {
             ram,
             uart,
             dtb,
             timer,
+            mswi,
+            sswi,
+            plic,
+            finisher,
+            virtio,
             ram_base: 0,
             uart_base: 0xc000_0000,
             dtb_base: 0xf000_0000,
-            time_base: 0xd000_0000,
-            timecmp_base: 0xd000_1000,
-            reserved_word: None,
+            // MSWI and MTIMER together have the layout of a SiFive CLINT
+            mswi_base: 0xd000_0000,
+            mtimer_base: 0xd000_4000,
+            sswi_base: 0xd000_c000,
+            plic_base: 0xe000_0000,
+            finisher_base: 0xc000_1000,
+            virtio_base: 0xc001_0000,
+            reserved_words: vec![None],
+            icache: DecodeCache::new(),
+            steps_to_poll: 1,
         }
     }
 
+    // Per-hart registers of the devices
+    pub fn set_num_harts(&mut self, num_harts: usize) {
+        self.timer.set_num_harts(num_harts);
+        self.mswi.set_num_harts(num_harts);
+        self.sswi.set_num_harts(num_harts);
+        self.plic.set_num_harts(num_harts);
+        self.reserved_words.resize(num_harts, None);
+    }
+
     pub fn check_and_translate(&self, addr: u64, attr: AccessAttr) -> Result<MemTarget, Exception> {
         let ram_range = self.ram_base..(self.ram_base + self.ram.size());
         let uart_range = self.uart_base..(self.uart_base + 8);
         let dtb_range = self.dtb_base..(self.dtb_base + self.dtb.size());
-        let time_range = self.time_base..(self.time_base + 8);
-        let timecmp_range = self.timecmp_base..(self.timecmp_base + 8);
+        let mswi_range = self.mswi_base..(self.mswi_base + MSWI_SIZE);
+        let mtimer_range = self.mtimer_base..(self.mtimer_base + MTIMER_SIZE);
+        let sswi_range = self.sswi_base..(self.sswi_base + SSWI_SIZE);
+        let plic_range = self.plic_base..(self.plic_base + PLIC_SIZE);
+        let finisher_range = self.finisher_base..(self.finisher_base + FINISHER_SIZE);
+        let virtio_range = self.virtio_base..(self.virtio_base + VIRTIO_SIZE * VIRTIO_SLOTS as u64);
 
         if ram_range.contains(&addr) {
             // RAM
@@ -104,20 +167,58 @@ impl MemMap {
             check_read_only(attr)?;
             check_misaligned(addr, attr)?;
             Ok(MemTarget::Dtb(addr - self.dtb_base))
-        } else if time_range.contains(&addr) {
-            // Time
+        } else if mswi_range.contains(&addr) {
+            // MSWI (word-access-only)
+            check_only_width(attr, AccessWidth::Word)?;
+            check_no_lrsc(attr)?;
+            check_no_amo(attr)?;
+            check_read_write(attr)?;
+            check_misaligned(addr, attr)?;
+            Ok(MemTarget::Mswi(addr - self.mswi_base))
+        } else if mtimer_range.contains(&addr) {
+            // MTIMER (word-access-only)
+            check_only_width(attr, AccessWidth::Word)?;
+            check_no_lrsc(attr)?;
+            check_no_amo(attr)?;
+            check_read_write(attr)?;
+            check_misaligned(addr, attr)?;
+            Ok(MemTarget::Mtimer(addr - self.mtimer_base))
+        } else if sswi_range.contains(&addr) {
+            // SSWI (word-access-only)
+            check_only_width(attr, AccessWidth::Word)?;
+            check_no_lrsc(attr)?;
+            check_no_amo(attr)?;
+            check_read_write(attr)?;
+            check_misaligned(addr, attr)?;
+            Ok(MemTarget::Sswi(addr - self.sswi_base))
+        } else if plic_range.contains(&addr) {
+            // PLIC (word-access-only)
             check_only_width(attr, AccessWidth::Word)?;
             check_no_lrsc(attr)?;
             check_no_amo(attr)?;
             check_read_write(attr)?;
-            Ok(MemTarget::Time(addr - self.time_base))
-        } else if timecmp_range.contains(&addr) {
-            // TimeCmp
+            check_misaligned(addr, attr)?;
+            Ok(MemTarget::Plic(addr - self.plic_base))
+        } else if finisher_range.contains(&addr) {
+            // Test finisher (word-access-only)
             check_only_width(attr, AccessWidth::Word)?;
             check_no_lrsc(attr)?;
             check_no_amo(attr)?;
             check_read_write(attr)?;
-            Ok(MemTarget::TimeCmp(addr - self.timecmp_base))
+            check_misaligned(addr, attr)?;
+            Ok(MemTarget::Finisher(addr - self.finisher_base))
+        } else if virtio_range.contains(&addr) {
+            // Virtio (word-access-only, but for the configuration space)
+            if (addr - self.virtio_base) % VIRTIO_SIZE < VIRTIO_CONFIG {
+                check_only_width(attr, AccessWidth::Word)?;
+            } else if attr.width == AccessWidth::DoubleWord {
+                Err(access_fault(attr.atype))?
+            }
+            check_no_lrsc(attr)?;
+            check_no_amo(attr)?;
+            check_read_write(attr)?;
+            check_misaligned(addr, attr)?;
+            Ok(MemTarget::Virtio(addr - self.virtio_base))
         } else {
             Err(access_fault(attr.atype))
         }
@@ -135,8 +236,15 @@ impl MemMap {
                 let buf = self.dtb.as_u8();
                 Ok(buf[dtb_addr as usize])
             }
-            MemTarget::Time(_) => panic!("cannot read a byte from Timer"),
-            MemTarget::TimeCmp(_) => panic!("cannot read a byte from Timer"),
+            MemTarget::Mtimer(_) => panic!("cannot read a byte from Mtimer"),
+            MemTarget::Mswi(_) => panic!("cannot read a byte from Mswi"),
+            MemTarget::Sswi(_) => panic!("cannot read a byte from Sswi"),
+            MemTarget::Plic(_) => panic!("cannot read a byte from Plic"),
+            MemTarget::Finisher(_) => panic!("cannot read a byte from Finisher"),
+            MemTarget::Virtio(virtio_addr) => {
+                let (slot, addr) = virtio_slot(virtio_addr);
+                Ok(self.virtio[slot].read_config(addr, 1) as u8)
+            }
         }
     }
 
@@ -153,8 +261,15 @@ impl MemMap {
                 let buf = self.dtb.as_u8();
                 Ok(u16::from_le_bytes([buf[dtb_addr], buf[dtb_addr + 1]]))
             }
-            MemTarget::Time(_) => panic!("cannot read a half-word from Timer"),
-            MemTarget::TimeCmp(_) => panic!("cannot read a half-word from Timer"),
+            MemTarget::Mtimer(_) => panic!("cannot read a half-word from Mtimer"),
+            MemTarget::Mswi(_) => panic!("cannot read a half-word from Mswi"),
+            MemTarget::Sswi(_) => panic!("cannot read a half-word from Sswi"),
+            MemTarget::Plic(_) => panic!("cannot read a half-word from Plic"),
+            MemTarget::Finisher(_) => panic!("cannot read a half-word from Finisher"),
+            MemTarget::Virtio(virtio_addr) => {
+                let (slot, addr) = virtio_slot(virtio_addr);
+                Ok(self.virtio[slot].read_config(addr, 2) as u16)
+            }
         }
     }
 
@@ -181,24 +296,62 @@ impl MemMap {
                     buf[dtb_addr + 3],
                 ]))
             }
-            MemTarget::Time(time_addr) => Ok(self.timer.read_time(time_addr)),
-            MemTarget::TimeCmp(timecmp_addr) => Ok(self.timer.read_timecmp(timecmp_addr)),
+            MemTarget::Mtimer(mtimer_addr) => Ok(self.timer.read(mtimer_addr)),
+            MemTarget::Mswi(mswi_addr) => Ok(self.mswi.read(mswi_addr)),
+            MemTarget::Sswi(sswi_addr) => Ok(self.sswi.read(sswi_addr)),
+            MemTarget::Plic(plic_addr) => Ok(self.plic.read(plic_addr)),
+            MemTarget::Finisher(finisher_addr) => Ok(self.finisher.read(finisher_addr)),
+            MemTarget::Virtio(virtio_addr) => {
+                let (slot, addr) = virtio_slot(virtio_addr);
+                Ok(self.virtio[slot].read(addr))
+            }
+        }
+    }
+
+    pub fn read_u64(&mut self, addr: u64, attr: AccessAttr) -> Result64E {
+        match self.check_and_translate(addr, attr)? {
+            MemTarget::Ram(ram_addr) => {
+                let ram_addr = ram_addr as usize;
+                let buf = self.ram.as_u8();
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&buf[ram_addr..ram_addr + 8]);
+                Ok(u64::from_le_bytes(bytes))
+            }
+            MemTarget::Uart(_) => panic!("cannot read a double-word from Uart"),
+            MemTarget::Dtb(dtb_addr) => {
+                let dtb_addr = dtb_addr as usize;
+                let buf = self.dtb.as_u8();
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&buf[dtb_addr..dtb_addr + 8]);
+                Ok(u64::from_le_bytes(bytes))
+            }
+            MemTarget::Mtimer(_) => panic!("cannot read a double-word from Mtimer"),
+            MemTarget::Mswi(_) => panic!("cannot read a double-word from Mswi"),
+            MemTarget::Sswi(_) => panic!("cannot read a double-word from Sswi"),
+            MemTarget::Plic(_) => panic!("cannot read a double-word from Plic"),
+            MemTarget::Finisher(_) => panic!("cannot read a double-word from Finisher"),
+            MemTarget::Virtio(_) => panic!("cannot read a double-word from Virtio"),
         }
     }
 
-    // Write (also clear reservation when needed)
+    // Write (also clear reservation and decoded instructions when needed)
     pub fn write_u8(&mut self, addr: u64, val: u8, attr: AccessAttr) -> ResultE {
         match self.check_and_translate(addr, attr)? {
             MemTarget::Ram(ram_addr) => {
                 self.clear_reservation_if_matched(addr);
+                self.icache.invalidate(addr, 1);
                 let buf = self.ram.as_u8_mut();
                 buf[ram_addr as usize] = val;
                 Ok(())
             }
             MemTarget::Uart(uart_addr) => Ok(self.uart.write(uart_addr, val)),
             MemTarget::Dtb(_) => panic!("cannot write to Dtb"),
-            MemTarget::Time(_) => panic!("cannot write a byte to Timer"),
-            MemTarget::TimeCmp(_) => panic!("cannot write a byte to Timer"),
+            MemTarget::Mtimer(_) => panic!("cannot write a byte to Mtimer"),
+            MemTarget::Mswi(_) => panic!("cannot write a byte to Mswi"),
+            MemTarget::Sswi(_) => panic!("cannot write a byte to Sswi"),
+            MemTarget::Plic(_) => panic!("cannot write a byte to Plic"),
+            MemTarget::Finisher(_) => panic!("cannot write a byte to Finisher"),
+            MemTarget::Virtio(_) => Ok(()), // Read-only configuration space
         }
     }
 
@@ -206,6 +359,7 @@ impl MemMap {
         match self.check_and_translate(addr, attr)? {
             MemTarget::Ram(ram_addr) => {
                 self.clear_reservation_if_matched(addr);
+                self.icache.invalidate(addr, 2);
                 let ram_addr = ram_addr as usize;
                 let buf = self.ram.as_u8_mut();
                 let bytes = val.to_le_bytes();
@@ -215,8 +369,12 @@ impl MemMap {
             }
             MemTarget::Uart(_) => panic!("cannot write a half-word to Uart"),
             MemTarget::Dtb(_) => panic!("cannot write to Dtb"),
-            MemTarget::Time(_) => panic!("cannot write a half-word to Timer"),
-            MemTarget::TimeCmp(_) => panic!("cannot write a half-word to Timer"),
+            MemTarget::Mtimer(_) => panic!("cannot write a half-word to Mtimer"),
+            MemTarget::Mswi(_) => panic!("cannot write a half-word to Mswi"),
+            MemTarget::Sswi(_) => panic!("cannot write a half-word to Sswi"),
+            MemTarget::Plic(_) => panic!("cannot write a half-word to Plic"),
+            MemTarget::Finisher(_) => panic!("cannot write a half-word to Finisher"),
+            MemTarget::Virtio(_) => Ok(()), // Read-only configuration space
         }
     }
 
@@ -224,6 +382,7 @@ impl MemMap {
         match self.check_and_translate(addr, attr)? {
             MemTarget::Ram(ram_addr) => {
                 self.clear_reservation_if_matched(addr);
+                self.icache.invalidate(addr, 4);
                 let ram_addr = ram_addr as usize;
                 let buf = self.ram.as_u8_mut();
                 let bytes = val.to_le_bytes();
@@ -235,30 +394,265 @@ impl MemMap {
             }
             MemTarget::Uart(_) => panic!("cannot write a word to Uart"),
             MemTarget::Dtb(_) => panic!("cannot write to Dtb"),
-            MemTarget::Time(time_addr) => Ok(self.timer.write_time(time_addr, val)),
-            MemTarget::TimeCmp(timecmp_addr) => Ok(self.timer.write_timecmp(timecmp_addr, val)),
+            MemTarget::Mtimer(mtimer_addr) => Ok(self.timer.write(mtimer_addr, val)),
+            MemTarget::Mswi(mswi_addr) => Ok(self.mswi.write(mswi_addr, val)),
+            MemTarget::Sswi(sswi_addr) => Ok(self.sswi.write(sswi_addr, val)),
+            MemTarget::Plic(plic_addr) => Ok(self.plic.write(plic_addr, val)),
+            MemTarget::Finisher(finisher_addr) => Ok(self.finisher.write(finisher_addr, val)),
+            MemTarget::Virtio(virtio_addr) => {
+                let (slot, addr) = virtio_slot(virtio_addr);
+                self.virtio[slot].write(addr, val);
+                // Notified queues are processed right away
+                self.poll_virtio_slot(slot);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn write_u64(&mut self, addr: u64, val: u64, attr: AccessAttr) -> ResultE {
+        match self.check_and_translate(addr, attr)? {
+            MemTarget::Ram(ram_addr) => {
+                self.clear_reservation_if_matched(addr);
+                self.clear_reservation_if_matched(addr + 4);
+                self.icache.invalidate(addr, 8);
+                let ram_addr = ram_addr as usize;
+                let buf = self.ram.as_u8_mut();
+                buf[ram_addr..ram_addr + 8].copy_from_slice(&val.to_le_bytes());
+                Ok(())
+            }
+            MemTarget::Uart(_) => panic!("cannot write a double-word to Uart"),
+            MemTarget::Dtb(_) => panic!("cannot write to Dtb"),
+            MemTarget::Mtimer(_) => panic!("cannot write a double-word to Mtimer"),
+            MemTarget::Mswi(_) => panic!("cannot write a double-word to Mswi"),
+            MemTarget::Sswi(_) => panic!("cannot write a double-word to Sswi"),
+            MemTarget::Plic(_) => panic!("cannot write a double-word to Plic"),
+            MemTarget::Finisher(_) => panic!("cannot write a double-word to Finisher"),
+            MemTarget::Virtio(_) => panic!("cannot write a double-word to Virtio"),
+        }
+    }
+
+    // Whether the addresses are all in RAM
+    pub fn is_ram(&self, range: Range<u64>) -> bool {
+        let ram_range = self.ram_base..(self.ram_base + self.ram.size());
+        ram_range.contains(&range.start) && range.end <= ram_range.end
+    }
+
+    // Write an image loaded by the simulator. Only RAM is written, as a store to a device
+    // register has side effects (and the device tree is read-only). Returns the addresses
+    // (from the first to the last) outside of RAM, after loading the rest.
+    pub fn load(&mut self, addr: u64, data: &[u8]) -> Result<(), Range<u64>> {
+        let range = addr..addr + data.len() as u64;
+        let ram_range = self.ram_base..(self.ram_base + self.ram.size());
+        let start = range.start.max(ram_range.start);
+        let end = range.end.min(ram_range.end);
+        if start >= end {
+            return Err(range);
+        }
+        let ram_start = (start - self.ram_base) as usize;
+        let ram_end = (end - self.ram_base) as usize;
+        let data_start = (start - addr) as usize;
+        self.ram.as_u8_mut()[ram_start..ram_end]
+            .copy_from_slice(&data[data_start..data_start + ram_end - ram_start]);
+        match (range.start < start, range.end > end) {
+            (false, false) => Ok(()),
+            (true, false) => Err(range.start..start),
+            (false, true) => Err(end..range.end),
+            (true, true) => Err(range),
+        }
+    }
+
+    // Read for the debugger: only RAM and the device tree, as reading a device register
+    // can change its state (popping the UART FIFO, claiming a PLIC interrupt...)
+    pub fn peek_u8(&self, addr: u64) -> Option<u8> {
+        let attr = AccessAttr {
+            atype: AccessType::Load,
+            width: AccessWidth::Byte,
+            lrsc: false,
+            amo: false,
+        };
+        match self.check_and_translate(addr, attr).ok()? {
+            MemTarget::Ram(ram_addr) => Some(self.ram.as_u8()[ram_addr as usize]),
+            MemTarget::Dtb(dtb_addr) => Some(self.dtb.as_u8()[dtb_addr as usize]),
+            _ => None,
         }
     }
 
+    pub fn peek_u32(&self, addr: u64) -> Option<u32> {
+        let mut buf = [0; 4];
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = self.peek_u8(addr + i as u64)?;
+        }
+        Some(u32::from_le_bytes(buf))
+    }
+
+    // Write for the debugger: only RAM, like a store (the device tree is read-only)
+    pub fn poke_u8(&mut self, addr: u64, val: u8) -> Option<()> {
+        let attr = AccessAttr {
+            atype: AccessType::Store,
+            width: AccessWidth::Byte,
+            lrsc: false,
+            amo: false,
+        };
+        match self.check_and_translate(addr, attr).ok()? {
+            MemTarget::Ram(_) => self.write_u8(addr, val, attr).ok(),
+            _ => None,
+        }
+    }
+
+    // Receive from the host (terminal and network) once every HOST_POLL_INTERVAL steps,
+    // rather than checking the channels before every instruction
+    pub fn poll_host(&mut self) {
+        self.steps_to_poll = self.steps_to_poll.saturating_sub(1);
+        if self.steps_to_poll == 0 {
+            self.steps_to_poll = HOST_POLL_INTERVAL;
+            self.uart.poll();
+            self.poll_virtio();
+        }
+    }
+
+    // Let the virtio devices process their notified queues and received frames
+    pub fn poll_virtio(&mut self) {
+        (0..self.virtio.len()).for_each(|slot| self.poll_virtio_slot(slot));
+    }
+
+    fn poll_virtio_slot(&mut self, slot: usize) {
+        let mut dma = Dma {
+            ram: &mut self.ram,
+            ram_base: self.ram_base,
+            icache: &mut self.icache,
+            reserved_words: &mut self.reserved_words,
+        };
+        self.virtio[slot].poll(&mut dma);
+    }
+
     // Reservation
-    pub fn reserve(&mut self, addr: u64) {
-        self.reserved_word = Some(addr >> 2);
+    pub fn reserve(&mut self, hart: usize, addr: u64) {
+        self.reserved_words[hart] = Some(addr >> 2);
     }
 
-    pub fn is_reserved(&self, addr: u64) -> bool {
-        self.reserved_word.map_or(false, |word| word == addr >> 2)
+    pub fn is_reserved(&self, hart: usize, addr: u64) -> bool {
+        self.reserved_words[hart].is_some_and(|word| word == addr >> 2)
     }
 
-    pub fn clear_reservation(&mut self) {
-        self.reserved_word = None;
+    pub fn clear_reservation(&mut self, hart: usize) {
+        self.reserved_words[hart] = None;
     }
 
+    // A store by any hart breaks the reservations on the word
     pub fn clear_reservation_if_matched(&mut self, addr: u64) {
-        if let Some(word) = self.reserved_word {
-            if word == addr >> 2 {
-                self.reserved_word = None;
+        for reserved in self.reserved_words.iter_mut() {
+            if *reserved == Some(addr >> 2) {
+                *reserved = None;
+            }
+        }
+    }
+}
+
+fn virtio_slot(virtio_addr: u64) -> (usize, u64) {
+    (
+        (virtio_addr / VIRTIO_SIZE) as usize,
+        virtio_addr % VIRTIO_SIZE,
+    )
+}
+
+// RAM as accessed by the devices (only whole buffers in RAM)
+pub struct Dma<'a> {
+    ram: &'a mut Ram,
+    ram_base: u64,
+    icache: &'a mut DecodeCache,
+    reserved_words: &'a mut Vec<Option<u64>>,
+}
+
+impl Dma<'_> {
+    fn range(&self, addr: u64, len: usize) -> Option<std::ops::Range<usize>> {
+        let start = addr.checked_sub(self.ram_base)?;
+        let end = start.checked_add(len as u64)?;
+        (end <= self.ram.size()).then_some(start as usize..end as usize)
+    }
+
+    pub fn is_ram(&self, addr: u64, len: usize) -> bool {
+        self.range(addr, len).is_some()
+    }
+
+    pub fn ram_size(&self) -> u64 {
+        self.ram.size()
+    }
+
+    pub fn read(&self, addr: u64, buf: &mut [u8]) -> Option<()> {
+        let range = self.range(addr, buf.len())?;
+        buf.copy_from_slice(&self.ram.as_u8()[range]);
+        Some(())
+    }
+
+    // Also clear reservation and decoded instructions, as stores do
+    pub fn write(&mut self, addr: u64, buf: &[u8]) -> Option<()> {
+        let range = self.range(addr, buf.len())?;
+        self.ram.as_u8_mut()[range].copy_from_slice(buf);
+        self.icache.invalidate(addr, buf.len() as u64);
+        let words = (addr >> 2)..=((addr + buf.len() as u64).saturating_sub(1) >> 2);
+        for reserved in self.reserved_words.iter_mut() {
+            if reserved.is_some_and(|word| words.contains(&word)) {
+                *reserved = None;
             }
         }
+        Some(())
+    }
+}
+
+impl MemMap {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        self.ram.save(w);
+        self.uart.save(w);
+        self.dtb.save(w);
+        self.timer.save(w);
+        self.mswi.save(w);
+        self.sswi.save(w);
+        self.plic.save(w);
+        self.virtio.iter().for_each(|v| v.save(w));
+        let bases = [
+            self.ram_base,
+            self.uart_base,
+            self.dtb_base,
+            self.mswi_base,
+            self.mtimer_base,
+            self.sswi_base,
+            self.plic_base,
+        ];
+        bases.iter().for_each(|b| w.put_u64(*b));
+        w.put_u32(self.reserved_words.len() as u32);
+        for reserved in self.reserved_words.iter() {
+            w.put_bool(reserved.is_some());
+            w.put_u64(reserved.unwrap_or(0));
+        }
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        self.ram.restore(r)?;
+        self.uart.restore(r)?;
+        self.dtb.restore(r)?;
+        self.timer.restore(r)?;
+        self.mswi.restore(r)?;
+        self.sswi.restore(r)?;
+        self.plic.restore(r)?;
+        for virtio in self.virtio.iter_mut() {
+            virtio.restore(r)?;
+        }
+        self.ram_base = r.get_u64()?;
+        self.uart_base = r.get_u64()?;
+        self.dtb_base = r.get_u64()?;
+        self.mswi_base = r.get_u64()?;
+        self.mtimer_base = r.get_u64()?;
+        self.sswi_base = r.get_u64()?;
+        self.plic_base = r.get_u64()?;
+        let num_harts = r.get_u32()? as usize;
+        self.reserved_words.clear();
+        for _ in 0..num_harts {
+            let reserved = r.get_bool()?;
+            let word = r.get_u64()?;
+            self.reserved_words.push(reserved.then_some(word));
+        }
+        self.icache.flush();
+        Ok(())
     }
 }
 
@@ -300,6 +694,12 @@ fn check_misaligned(addr: u64, attr: AccessAttr) -> Result<(), Exception> {
             }
             Ok(())
         }
+        AccessWidth::DoubleWord => {
+            if addr & 0b111 != 0 {
+                Err(misaligned_fault(attr.atype))?
+            }
+            Ok(())
+        }
         _ => Ok(()),
     }
 }
@@ -451,6 +851,90 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_u64_read_write() {
+        let mut mem = MemMap::new(MEM_SIZE); // 1 kB
+
+        for _ in 0..10 {
+            let addr = rand::thread_rng().gen_range(0..MEM_SIZE) & !0b111;
+            let data: u64 = rand::random();
+            mem.write_u64(addr, data, store_attr(DoubleWord)).unwrap();
+            assert_eq!(mem.read_u32(addr, load_attr(Word)).unwrap(), data as u32);
+            assert_eq!(
+                mem.read_u32(addr + 4, load_attr(Word)).unwrap(),
+                (data >> 32) as u32
+            );
+            assert_eq!(mem.read_u64(addr, load_attr(DoubleWord)).unwrap(), data);
+        }
+        assert_eq!(
+            mem.read_u64(4, load_attr(DoubleWord)).unwrap_err(),
+            LoadAddrMisaligned
+        );
+        assert_eq!(
+            mem.write_u64(4, 0, store_attr(DoubleWord)).unwrap_err(),
+            StoreAddrMisaligned
+        );
+    }
+
+    #[test]
+    fn test_plic_access() {
+        let mut mem = MemMap::new(MEM_SIZE); // 1 kB
+        let base = mem.plic_base;
+
+        mem.write_u32(base + 4, 3, store_attr(Word)).unwrap();
+        assert_eq!(mem.read_u32(base + 4, load_attr(Word)).unwrap(), 3);
+        assert_eq!(
+            mem.read_u8(base + 4, load_attr(Byte)).unwrap_err(),
+            LoadAccessFault
+        );
+        assert_eq!(
+            mem.write_u32(base + 2, 0, store_attr(Word)).unwrap_err(),
+            StoreAddrMisaligned
+        );
+        assert_eq!(
+            mem.read_u32(base, instr_attr()).unwrap_err(),
+            InstrAccessFault
+        );
+    }
+
+    #[test]
+    fn test_clint_layout() {
+        let mut mem = MemMap::new(MEM_SIZE); // 1 kB
+        mem.set_num_harts(2);
+        let base = mem.mswi_base;
+
+        // msip, mtimecmp and mtime at the offsets of a CLINT
+        mem.write_u32(base + 0x4, 1, store_attr(Word)).unwrap();
+        mem.write_u32(base + 0x400c, 0x12, store_attr(Word))
+            .unwrap();
+        mem.write_u32(base + 0xbff8, 0x34, store_attr(Word))
+            .unwrap();
+        assert!(mem.mswi.is_interrupt_set(1));
+        assert_eq!(mem.timer.timecmp[1], 0x12_ffff_ffff);
+        assert_eq!(mem.timer.time, 0x34);
+        assert_eq!(mem.read_u32(base + 0xbff8, load_attr(Word)).unwrap(), 0x34);
+        assert_eq!(
+            mem.read_u16(base, load_attr(HalfWord)).unwrap_err(),
+            LoadAccessFault
+        );
+
+        // setssip of hart 1
+        mem.write_u32(mem.sswi_base + 0x4, 1, store_attr(Word))
+            .unwrap();
+        assert!(mem.sswi.take_interrupt(1));
+    }
+
+    #[test]
+    fn test_clear_reservation_on_write_u64() {
+        let mut mem = MemMap::new(MEM_SIZE); // 1 kB
+
+        for i in 0..=1 {
+            mem.reserve(0, 4 * i);
+            mem.write_u64(0, 0, store_attr(DoubleWord)).unwrap();
+            assert_word_reserved(&mem, 4 * i, false);
+        }
+    }
+
     #[test]
     #[rustfmt::skip]
     fn test_read_fault() {
@@ -545,10 +1029,10 @@ mod tests {
     }
 
     fn assert_word_reserved(mem: &MemMap, addr: u64, expect: bool) {
-        assert_eq!(mem.is_reserved(addr), expect);
-        assert_eq!(mem.is_reserved(addr + 1), expect);
-        assert_eq!(mem.is_reserved(addr + 2), expect);
-        assert_eq!(mem.is_reserved(addr + 3), expect);
+        assert_eq!(mem.is_reserved(0, addr), expect);
+        assert_eq!(mem.is_reserved(0, addr + 1), expect);
+        assert_eq!(mem.is_reserved(0, addr + 2), expect);
+        assert_eq!(mem.is_reserved(0, addr + 3), expect);
     }
 
     #[test]
@@ -556,10 +1040,10 @@ mod tests {
         let mut mem = MemMap::new(MEM_SIZE); // 1 kB
 
         for i in 0..=3 {
-            mem.reserve(i);
+            mem.reserve(0, i);
             assert_word_reserved(&mem, 0, true);
-            assert!(!mem.is_reserved(4));
-            assert!(!mem.is_reserved(u64::MAX));
+            assert!(!mem.is_reserved(0, 4));
+            assert!(!mem.is_reserved(0, u64::MAX));
         }
     }
 
@@ -568,8 +1052,8 @@ mod tests {
         let mut mem = MemMap::new(MEM_SIZE); // 1 kB
 
         for i in 0..=3 {
-            mem.reserve(i);
-            mem.reserve(4 + i);
+            mem.reserve(0, i);
+            mem.reserve(0, 4 + i);
             assert_word_reserved(&mem, 0, false);
             assert_word_reserved(&mem, 4, true);
         }
@@ -580,8 +1064,8 @@ mod tests {
         let mut mem = MemMap::new(MEM_SIZE); // 1 kB
 
         for i in 0..=3 {
-            mem.reserve(i);
-            mem.clear_reservation();
+            mem.reserve(0, i);
+            mem.clear_reservation(0);
             assert_word_reserved(&mem, 0, false);
         }
     }
@@ -592,7 +1076,7 @@ mod tests {
 
         for i in 0..=3 {
             for j in 0..=3 {
-                mem.reserve(i);
+                mem.reserve(0, i);
                 mem.clear_reservation_if_matched(j);
                 assert_word_reserved(&mem, 0, false);
             }
@@ -605,7 +1089,7 @@ mod tests {
 
         for i in 0..=3 {
             for j in 0..=3 {
-                mem.reserve(i);
+                mem.reserve(0, i);
                 mem.clear_reservation_if_matched(4 + j);
                 assert_word_reserved(&mem, 0, true);
             }
@@ -618,7 +1102,7 @@ mod tests {
 
         for i in 0..=3 {
             for j in 0..=3 {
-                mem.reserve(i);
+                mem.reserve(0, i);
                 mem.write_u8(j, 0, store_attr(Byte)).unwrap();
                 assert_word_reserved(&mem, 0, false);
             }
@@ -631,7 +1115,7 @@ mod tests {
 
         for i in 0..=3 {
             for j in 0..=1 {
-                mem.reserve(i);
+                mem.reserve(0, i);
                 mem.write_u16(2 * j, 0, store_attr(HalfWord)).unwrap();
                 assert_word_reserved(&mem, 0, false);
             }
@@ -643,7 +1127,7 @@ mod tests {
         let mut mem = MemMap::new(MEM_SIZE); // 1 kB
 
         for i in 0..=3 {
-            mem.reserve(i);
+            mem.reserve(0, i);
             mem.write_u32(0, 0, store_attr(Word)).unwrap();
             assert_word_reserved(&mem, 0, false);
         }
@@ -655,10 +1139,58 @@ mod tests {
 
         for i in 0..=3 {
             for j in 0..=3 {
-                mem.reserve(i);
+                mem.reserve(0, i);
                 mem.write_u8(4 + j, 0, store_attr(Byte)).unwrap();
                 assert_word_reserved(&mem, 0, true);
             }
         }
     }
+
+    #[test]
+    fn test_load() {
+        let mut mem = MemMap::new(MEM_SIZE); // 1 kB
+        mem.dtb = Dtb::new(vec![0; 8]);
+
+        // Devices are not written
+        assert_eq!(
+            mem.load(mem.dtb_base + 4, &[1, 2, 3, 4]),
+            Err(mem.dtb_base + 4..mem.dtb_base + 8)
+        );
+        assert_eq!(mem.dtb.as_u8(), &[0; 8]);
+        let finisher = mem.finisher_base;
+        assert_eq!(
+            mem.load(finisher, &[0x55, 0x55, 0, 0]),
+            Err(finisher..finisher + 4)
+        );
+        assert_eq!(mem.finisher.take_stop(), None);
+        assert!(mem.is_ram(MEM_SIZE - 4..MEM_SIZE));
+        assert!(!mem.is_ram(MEM_SIZE - 4..MEM_SIZE + 4));
+        assert!(!mem.is_ram(mem.uart_base..mem.uart_base + 1));
+
+        // Only the part past the end of RAM fails
+        let res = mem.load(MEM_SIZE - 2, &[0xaa; 6]);
+        assert_eq!(res, Err(MEM_SIZE..MEM_SIZE + 4));
+        assert_eq!(&mem.ram.as_u8()[MEM_SIZE as usize - 2..], &[0xaa, 0xaa]);
+    }
+
+    #[test]
+    fn test_peek_poke() {
+        let mut mem = MemMap::new(MEM_SIZE); // 1 kB
+        mem.dtb = Dtb::new(vec![1, 2, 3, 4]);
+        let (tx, rx) = std::sync::mpsc::channel();
+        mem.uart.connect(rx);
+        tx.send(b'x').unwrap();
+        mem.uart.poll();
+
+        assert_eq!(mem.poke_u8(0x10, 0x78), Some(()));
+        assert_eq!(mem.peek_u32(0x10), Some(0x78));
+        assert_eq!(mem.peek_u32(mem.dtb_base), Some(0x04030201));
+        assert_eq!(mem.poke_u8(mem.dtb_base, 0), None);
+
+        // Devices are left alone
+        assert_eq!(mem.peek_u8(mem.uart_base), None);
+        assert_eq!(mem.poke_u8(mem.uart_base, 0), None);
+        assert_eq!(mem.peek_u32(mem.plic_base + 0x20_0004), None);
+        assert_eq!(mem.uart.read(0), b'x');
+    }
 }
@@ -1,4 +1,5 @@
-use std::fmt::Debug;
+use crate::snapshot::{SnapshotReader, SnapshotWriter};
+use std::{fmt::Debug, io};
 
 pub struct Dtb {
     buf: Vec<u8>,
@@ -18,6 +19,17 @@ impl Dtb {
     }
 }
 
+impl Dtb {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_bytes(&self.buf);
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        self.buf = r.get_bytes()?;
+        Ok(())
+    }
+}
+
 impl Debug for Dtb {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Dtb ({} bytes)", self.buf.len())
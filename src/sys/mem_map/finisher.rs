@@ -0,0 +1,70 @@
+use crate::sys::StopReason;
+
+// Test finisher (as the SiFive test device, "sifive,test0"):
+// - A single 32-bit register, read as 0
+// - Writing 0x5555 passes, 0x3333 fails with the code in bits 31:16, and 0x7777 resets
+// - Other values are ignored
+pub const FINISHER_SIZE: u64 = 0x1000;
+
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_RESET: u32 = 0x7777;
+
+#[derive(Debug)]
+pub struct Finisher {
+    stop: Option<StopReason>, // Not yet seen by the system
+}
+
+impl Default for Finisher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Finisher {
+    pub fn new() -> Finisher {
+        Finisher { stop: None }
+    }
+
+    pub fn take_stop(&mut self) -> Option<StopReason> {
+        self.stop.take()
+    }
+
+    pub fn read(&self, _addr: u64) -> u32 {
+        0
+    }
+
+    pub fn write(&mut self, addr: u64, val: u32) {
+        if addr != 0 {
+            return;
+        }
+        match val & 0xffff {
+            FINISHER_FAIL => self.stop = Some(StopReason::Exit((val >> 16) as i32)),
+            FINISHER_PASS => self.stop = Some(StopReason::Exit(0)),
+            FINISHER_RESET => self.stop = Some(StopReason::Reset),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let mut finisher = Finisher::new();
+        finisher.write(0, 0x1234);
+        finisher.write(4, FINISHER_PASS);
+        assert_eq!(finisher.take_stop(), None);
+
+        finisher.write(0, 0x0002_0000 | FINISHER_FAIL);
+        assert_eq!(finisher.take_stop(), Some(StopReason::Exit(2)));
+        assert_eq!(finisher.take_stop(), None);
+        finisher.write(0, FINISHER_PASS);
+        assert_eq!(finisher.take_stop(), Some(StopReason::Exit(0)));
+        finisher.write(0, FINISHER_RESET);
+        assert_eq!(finisher.take_stop(), Some(StopReason::Reset));
+        assert_eq!(finisher.read(0), 0);
+    }
+}
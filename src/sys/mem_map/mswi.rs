@@ -0,0 +1,81 @@
+use crate::snapshot::{SnapshotReader, SnapshotWriter};
+use std::io;
+
+// Machine-level software interrupts (as the ACLINT MSWI):
+// - One 32-bit msip register per hart, at 4 * hartid
+// - Only bit 0 is writable, and drives MSIP of the hart
+pub const MSWI_SIZE: u64 = 0x4000;
+
+#[derive(Debug)]
+pub struct Mswi {
+    msip: Vec<bool>,
+}
+
+impl Default for Mswi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mswi {
+    pub fn new() -> Mswi {
+        Mswi { msip: vec![false] }
+    }
+
+    pub fn set_num_harts(&mut self, num_harts: usize) {
+        self.msip.resize(num_harts, false);
+    }
+
+    pub fn is_interrupt_set(&self, hart: usize) -> bool {
+        self.msip[hart]
+    }
+
+    // Registers of harts which don't exist are read as 0 and ignore writes
+    pub fn read(&self, addr: u64) -> u32 {
+        self.msip.get((addr / 4) as usize).map_or(0, |m| *m as u32)
+    }
+
+    pub fn write(&mut self, addr: u64, val: u32) {
+        if let Some(msip) = self.msip.get_mut((addr / 4) as usize) {
+            *msip = val & 1 != 0;
+        }
+    }
+}
+
+impl Mswi {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_u32(self.msip.len() as u32);
+        self.msip.iter().for_each(|m| w.put_bool(*m));
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        let num_harts = r.get_u32()? as usize;
+        self.msip = (0..num_harts)
+            .map(|_| r.get_bool())
+            .collect::<io::Result<_>>()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_msip() {
+        let mut mswi = Mswi::new();
+        mswi.set_num_harts(2);
+
+        mswi.write(4, 0xffff_ffff);
+        assert_eq!(mswi.read(0), 0);
+        assert_eq!(mswi.read(4), 1);
+        assert!(!mswi.is_interrupt_set(0));
+        assert!(mswi.is_interrupt_set(1));
+
+        mswi.write(4, 0x2);
+        assert!(!mswi.is_interrupt_set(1));
+
+        mswi.write(8, 1);
+        assert_eq!(mswi.read(8), 0);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::snapshot::{SnapshotReader, SnapshotWriter};
+use std::{fmt::Debug, io};
+
+// This is an emulator for the SiFive PLIC with:
+// - 31 interrupt sources (source 0 is reserved)
+// - 2 contexts per hart (M-mode and S-mode)
+// - 7 priority levels
+// - Level-triggered gateways
+pub const PLIC_NUM_SOURCES: u32 = 32;
+pub const PLIC_CTX_M: usize = 0; // Context of hart i is 2 * i + PLIC_CTX_M/S
+pub const PLIC_CTX_S: usize = 1;
+pub const PLIC_SIZE: u64 = 0x400_0000;
+
+const CONTEXTS_PER_HART: usize = 2;
+const PRIORITY_MASK: u32 = 0b111;
+const SOURCE_MASK: u32 = !1; // Source 0 does not exist
+
+const PRIORITY_BASE: u64 = 0x0;
+const PENDING_BASE: u64 = 0x1000;
+const ENABLE_BASE: u64 = 0x2000;
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT_BASE: u64 = 0x20_0000;
+const CONTEXT_STRIDE: u64 = 0x1000;
+
+#[derive(Debug)]
+pub struct Plic {
+    priority: [u32; PLIC_NUM_SOURCES as usize],
+    level: u32,   // Interrupt lines from devices
+    pending: u32, // Latched by the gateways
+    claimed: u32, // Claimed but not completed yet
+    enable: Vec<u32>,
+    threshold: Vec<u32>,
+}
+
+impl Default for Plic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Plic {
+    pub fn new() -> Plic {
+        Plic {
+            priority: [0; PLIC_NUM_SOURCES as usize],
+            level: 0,
+            pending: 0,
+            claimed: 0,
+            enable: vec![0; CONTEXTS_PER_HART],
+            threshold: vec![0; CONTEXTS_PER_HART],
+        }
+    }
+
+    pub fn set_num_harts(&mut self, num_harts: usize) {
+        self.enable.resize(num_harts * CONTEXTS_PER_HART, 0);
+        self.threshold.resize(num_harts * CONTEXTS_PER_HART, 0);
+    }
+
+    // Drive the interrupt line of a source
+    pub fn set_level(&mut self, source: u32, level: bool) {
+        let mask = (1 << source) & SOURCE_MASK;
+        if level {
+            self.level |= mask;
+        } else {
+            self.level &= !mask;
+        }
+        self.update_pending();
+    }
+
+    // Drive the lines of several sources (in mask) at once, if any of them changed
+    pub fn set_levels(&mut self, mask: u32, levels: u32) {
+        let level = (self.level & !mask) | (levels & mask & SOURCE_MASK);
+        if level != self.level {
+            self.level = level;
+            self.update_pending();
+        }
+    }
+
+    // Whether the context should be interrupted (MEIP/SEIP)
+    pub fn is_interrupt_set(&self, ctx: usize) -> bool {
+        self.best_source(ctx).is_some()
+    }
+
+    // A new request is only forwarded once the previous one is completed
+    fn update_pending(&mut self) {
+        self.pending |= self.level & !self.claimed;
+    }
+
+    // Enabled pending source with the highest priority above the threshold (lowest ID wins ties)
+    fn best_source(&self, ctx: usize) -> Option<u32> {
+        let candidates = self.pending & self.enable[ctx];
+        (1..PLIC_NUM_SOURCES)
+            .filter(|i| candidates & (1 << i) != 0)
+            .filter(|i| self.priority[*i as usize] > self.threshold[ctx])
+            .fold(None, |best: Option<u32>, i| match best {
+                Some(b) if self.priority[b as usize] >= self.priority[i as usize] => Some(b),
+                _ => Some(i),
+            })
+    }
+
+    fn claim(&mut self, ctx: usize) -> u32 {
+        match self.best_source(ctx) {
+            Some(source) => {
+                self.pending &= !(1 << source);
+                self.claimed |= 1 << source;
+                source
+            }
+            None => 0,
+        }
+    }
+
+    fn complete(&mut self, ctx: usize, source: u32) {
+        // Completion is ignored if the source is not enabled for the context
+        if source < PLIC_NUM_SOURCES && self.enable[ctx] & (1 << source) != 0 {
+            self.claimed &= !(1 << source);
+            self.update_pending();
+        }
+    }
+
+    pub fn read(&mut self, addr: u64) -> u32 {
+        match addr {
+            PRIORITY_BASE..PENDING_BASE => {
+                let source = ((addr - PRIORITY_BASE) >> 2) as usize;
+                self.priority.get(source).copied().unwrap_or(0)
+            }
+            PENDING_BASE => self.pending,
+            ENABLE_BASE..CONTEXT_BASE => {
+                let ctx = ((addr - ENABLE_BASE) / ENABLE_STRIDE) as usize;
+                let offset = (addr - ENABLE_BASE) % ENABLE_STRIDE;
+                match (self.enable.get(ctx), offset) {
+                    (Some(enable), 0) => *enable,
+                    _ => 0,
+                }
+            }
+            CONTEXT_BASE.. => {
+                let ctx = ((addr - CONTEXT_BASE) / CONTEXT_STRIDE) as usize;
+                let offset = (addr - CONTEXT_BASE) % CONTEXT_STRIDE;
+                match offset {
+                    0 if ctx < self.enable.len() => self.threshold[ctx],
+                    4 if ctx < self.enable.len() => self.claim(ctx),
+                    _ => 0,
+                }
+            }
+            // Reserved
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, val: u32) {
+        match addr {
+            PRIORITY_BASE..PENDING_BASE => {
+                let source = ((addr - PRIORITY_BASE) >> 2) as usize;
+                if (1..PLIC_NUM_SOURCES as usize).contains(&source) {
+                    self.priority[source] = val & PRIORITY_MASK;
+                }
+            }
+            ENABLE_BASE..CONTEXT_BASE => {
+                let ctx = ((addr - ENABLE_BASE) / ENABLE_STRIDE) as usize;
+                let offset = (addr - ENABLE_BASE) % ENABLE_STRIDE;
+                if ctx < self.enable.len() && offset == 0 {
+                    self.enable[ctx] = val & SOURCE_MASK;
+                }
+            }
+            CONTEXT_BASE.. => {
+                let ctx = ((addr - CONTEXT_BASE) / CONTEXT_STRIDE) as usize;
+                let offset = (addr - CONTEXT_BASE) % CONTEXT_STRIDE;
+                match offset {
+                    0 if ctx < self.enable.len() => self.threshold[ctx] = val & PRIORITY_MASK,
+                    4 if ctx < self.enable.len() => self.complete(ctx, val),
+                    _ => (),
+                }
+            }
+            // Pending bits are read-only, others are reserved
+            _ => (),
+        }
+    }
+}
+
+impl Plic {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        self.priority.iter().for_each(|p| w.put_u32(*p));
+        w.put_u32(self.level);
+        w.put_u32(self.pending);
+        w.put_u32(self.claimed);
+        w.put_u32(self.enable.len() as u32);
+        self.enable.iter().for_each(|e| w.put_u32(*e));
+        self.threshold.iter().for_each(|t| w.put_u32(*t));
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        for p in self.priority.iter_mut() {
+            *p = r.get_u32()?;
+        }
+        self.level = r.get_u32()?;
+        self.pending = r.get_u32()?;
+        self.claimed = r.get_u32()?;
+        let num_contexts = r.get_u32()? as usize;
+        self.enable = (0..num_contexts)
+            .map(|_| r.get_u32())
+            .collect::<io::Result<_>>()?;
+        self.threshold = (0..num_contexts)
+            .map(|_| r.get_u32())
+            .collect::<io::Result<_>>()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD_M: u64 = CONTEXT_BASE;
+    const CLAIM_M: u64 = CONTEXT_BASE + 4;
+    const THRESHOLD_S: u64 = CONTEXT_BASE + CONTEXT_STRIDE;
+    const CLAIM_S: u64 = CONTEXT_BASE + CONTEXT_STRIDE + 4;
+
+    fn make_plic() -> Plic {
+        let mut plic = Plic::new();
+        for i in 1..PLIC_NUM_SOURCES {
+            plic.write(PRIORITY_BASE + 4 * i as u64, 1);
+        }
+        plic
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut plic = Plic::new();
+
+        plic.write(PRIORITY_BASE, 7);
+        plic.write(PRIORITY_BASE + 4, 0xf);
+        assert_eq!(plic.read(PRIORITY_BASE), 0);
+        assert_eq!(plic.read(PRIORITY_BASE + 4), 7);
+
+        plic.write(ENABLE_BASE, 0xffff_ffff);
+        plic.write(ENABLE_BASE + ENABLE_STRIDE, 0x6);
+        assert_eq!(plic.read(ENABLE_BASE), 0xffff_fffe);
+        assert_eq!(plic.read(ENABLE_BASE + ENABLE_STRIDE), 0x6);
+
+        plic.write(THRESHOLD_S, 3);
+        assert_eq!(plic.read(THRESHOLD_M), 0);
+        assert_eq!(plic.read(THRESHOLD_S), 3);
+
+        // Pending bits are read-only
+        plic.write(PENDING_BASE, 0xffff_ffff);
+        assert_eq!(plic.read(PENDING_BASE), 0);
+        plic.set_level(3, true);
+        plic.set_level(0, true);
+        assert_eq!(plic.read(PENDING_BASE), 0x8);
+    }
+
+    #[test]
+    fn test_priority_and_threshold() {
+        let mut plic = make_plic();
+        plic.write(ENABLE_BASE, 0xe);
+        plic.write(PRIORITY_BASE + 8, 5);
+        plic.write(PRIORITY_BASE + 12, 5);
+
+        plic.set_level(1, true);
+        plic.set_level(3, true);
+        plic.set_level(2, true);
+        assert!(plic.is_interrupt_set(PLIC_CTX_M));
+        assert!(!plic.is_interrupt_set(PLIC_CTX_S));
+
+        // Highest priority first, then the lowest ID
+        plic.write(THRESHOLD_M, 4);
+        assert_eq!(plic.read(CLAIM_M), 2);
+        assert_eq!(plic.read(CLAIM_M), 3);
+        assert_eq!(plic.read(CLAIM_M), 0);
+        assert!(!plic.is_interrupt_set(PLIC_CTX_M));
+
+        plic.write(THRESHOLD_M, 0);
+        assert_eq!(plic.read(CLAIM_M), 1);
+    }
+
+    #[test]
+    fn test_claim_complete() {
+        let mut plic = make_plic();
+        plic.write(ENABLE_BASE + ENABLE_STRIDE, 0x10);
+
+        plic.set_level(4, true);
+        assert!(plic.is_interrupt_set(PLIC_CTX_S));
+        assert_eq!(plic.read(CLAIM_S), 4);
+        assert!(!plic.is_interrupt_set(PLIC_CTX_S));
+
+        // Not pending again until completed, even if the line stays high
+        plic.set_level(4, true);
+        assert!(!plic.is_interrupt_set(PLIC_CTX_S));
+        plic.write(CLAIM_M, 4); // Not enabled for M-mode, ignored
+        assert!(!plic.is_interrupt_set(PLIC_CTX_S));
+        plic.write(CLAIM_S, 4);
+        assert!(plic.is_interrupt_set(PLIC_CTX_S));
+
+        // The request is latched, even if the line drops
+        assert_eq!(plic.read(CLAIM_S), 4);
+        plic.set_level(4, false);
+        plic.write(CLAIM_S, 4);
+        assert!(!plic.is_interrupt_set(PLIC_CTX_S));
+        assert_eq!(plic.read(CLAIM_S), 0);
+
+        // Lines driven together, a line kept high is pending again once completed
+        plic.set_levels(0x30, 0x1f);
+        assert_eq!(plic.read(CLAIM_S), 4);
+        plic.set_levels(0x30, 0x10);
+        plic.write(CLAIM_S, 4);
+        assert!(plic.is_interrupt_set(PLIC_CTX_S));
+    }
+}
@@ -1,4 +1,5 @@
-use std::fmt::Debug;
+use crate::snapshot::{SnapshotReader, SnapshotWriter};
+use std::{fmt::Debug, io};
 
 pub struct Ram {
     buf: Vec<u8>,
@@ -42,6 +43,17 @@ impl Ram {
     }
 }
 
+impl Ram {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_bytes(&self.buf);
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        self.buf = r.get_bytes()?;
+        Ok(())
+    }
+}
+
 impl Debug for Ram {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "({} bytes)", self.buf.len())
@@ -0,0 +1,79 @@
+use crate::snapshot::{SnapshotReader, SnapshotWriter};
+use std::io;
+
+// Supervisor-level software interrupts (as the ACLINT SSWI):
+// - One 32-bit setssip register per hart, at 4 * hartid
+// - Writing 1 to bit 0 sets SSIP of the hart (edge-triggered), and it's read as 0
+// - SSIP is cleared by software through sip
+pub const SSWI_SIZE: u64 = 0x4000;
+
+#[derive(Debug)]
+pub struct Sswi {
+    pending: Vec<bool>, // Not yet delivered to the hart
+}
+
+impl Default for Sswi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sswi {
+    pub fn new() -> Sswi {
+        Sswi {
+            pending: vec![false],
+        }
+    }
+
+    pub fn set_num_harts(&mut self, num_harts: usize) {
+        self.pending.resize(num_harts, false);
+    }
+
+    // Whether SSIP of the hart should be set (only once per write)
+    pub fn take_interrupt(&mut self, hart: usize) -> bool {
+        std::mem::take(&mut self.pending[hart])
+    }
+
+    pub fn read(&self, _addr: u64) -> u32 {
+        0
+    }
+
+    pub fn write(&mut self, addr: u64, val: u32) {
+        if let Some(pending) = self.pending.get_mut((addr / 4) as usize) {
+            *pending |= val & 1 != 0;
+        }
+    }
+}
+
+impl Sswi {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_u32(self.pending.len() as u32);
+        self.pending.iter().for_each(|p| w.put_bool(*p));
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        let num_harts = r.get_u32()? as usize;
+        self.pending = (0..num_harts)
+            .map(|_| r.get_bool())
+            .collect::<io::Result<_>>()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setssip() {
+        let mut sswi = Sswi::new();
+        sswi.set_num_harts(2);
+
+        sswi.write(4, 1);
+        sswi.write(0, 0);
+        assert_eq!(sswi.read(4), 0);
+        assert!(!sswi.take_interrupt(0));
+        assert!(sswi.take_interrupt(1));
+        assert!(!sswi.take_interrupt(1));
+    }
+}
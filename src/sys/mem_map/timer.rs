@@ -1,9 +1,16 @@
-use std::fmt::Debug;
+use crate::snapshot::{SnapshotReader, SnapshotWriter};
+use std::{fmt::Debug, io};
+
+// Machine-level timer (as the ACLINT MTIMER):
+// - One 64-bit mtimecmp register per hart, at 8 * hartid
+// - The 64-bit mtime register, shared by all harts, at 0x7ff8
+pub const MTIMER_SIZE: u64 = 0x8000;
+const MTIME_OFFSET: u64 = 0x7ff8;
 
 #[derive(Debug)]
 pub struct Timer {
     pub time: u64,
-    pub timecmp: u64,
+    pub timecmp: Vec<u64>, // One per hart
 }
 
 const MASK_LO: u64 = 0x00000000_ffffffffu64;
@@ -13,22 +20,43 @@ impl Timer {
     pub fn new() -> Timer {
         Timer {
             time: 0,
-            timecmp: u64::max_value(),
+            timecmp: vec![u64::MAX],
+        }
+    }
+
+    pub fn set_num_harts(&mut self, num_harts: usize) {
+        self.timecmp.resize(num_harts, u64::MAX);
+    }
+
+    pub fn is_interrupt_set(&self, hart: usize) -> bool {
+        self.time >= self.timecmp[hart]
+    }
+
+    // Registers of harts which don't exist are read as 0 and ignore writes
+    pub fn read(&self, addr: u64) -> u32 {
+        if addr >= MTIME_OFFSET {
+            self.read_time(addr - MTIME_OFFSET)
+        } else if addr < 8 * self.timecmp.len() as u64 {
+            self.read_timecmp(addr)
+        } else {
+            0
         }
     }
 
-    pub fn is_interrupt_set(&self) -> bool {
-        self.time >= self.timecmp
+    pub fn write(&mut self, addr: u64, val: u32) {
+        if addr >= MTIME_OFFSET {
+            self.write_time(addr - MTIME_OFFSET, val)
+        } else if addr < 8 * self.timecmp.len() as u64 {
+            self.write_timecmp(addr, val)
+        }
     }
 
     pub fn read_time(&self, addr: u64) -> u32 {
-        let res = match addr {
+        match addr {
             0 => self.time as u32,
             4 => (self.time >> 32) as u32,
             _ => panic!("invalid addr for Timer::read_time (addr = {addr})"),
-        };
-        // println!("Read time[{addr}] = 0x{res:08x}");
-        res
+        }
     }
 
     pub fn write_time(&mut self, addr: u64, val: u32) {
@@ -43,32 +71,48 @@ impl Timer {
             }
             _ => panic!("invalid addr for Timer::write_time (addr = {addr})"),
         };
-        println!("Write time[{addr}] = 0x{val:08x}");
     }
 
+    // The timecmp of hart i is at 8 * i
     pub fn read_timecmp(&self, addr: u64) -> u32 {
-        let res = match addr {
-            0 => self.timecmp as u32,
-            4 => (self.timecmp >> 32) as u32,
+        let timecmp = self.timecmp[(addr / 8) as usize];
+        match addr % 8 {
+            0 => timecmp as u32,
+            4 => (timecmp >> 32) as u32,
             _ => panic!("invalid addr for Timer::read_timecmp (addr = {addr})"),
-        };
-        println!("Read timecmp[{addr}] = 0x{res:08x}");
-        res
+        }
     }
 
     pub fn write_timecmp(&mut self, addr: u64, val: u32) {
-        match addr {
+        let timecmp = &mut self.timecmp[(addr / 8) as usize];
+        match addr % 8 {
             0 => {
-                self.timecmp &= !MASK_LO;
-                self.timecmp |= val as u64;
+                *timecmp &= !MASK_LO;
+                *timecmp |= val as u64;
             }
             4 => {
-                self.timecmp &= !MASK_HI;
-                self.timecmp |= (val as u64) << 32;
+                *timecmp &= !MASK_HI;
+                *timecmp |= (val as u64) << 32;
             }
             _ => panic!("invalid addr for Timer::write_timecmp (addr = {addr})"),
         };
-        println!("Write timecmp[{addr}] = 0x{val:08x}");
+    }
+}
+
+impl Timer {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_u64(self.time);
+        w.put_u32(self.timecmp.len() as u32);
+        self.timecmp.iter().for_each(|t| w.put_u64(*t));
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        self.time = r.get_u64()?;
+        let num_harts = r.get_u32()? as usize;
+        self.timecmp = (0..num_harts)
+            .map(|_| r.get_u64())
+            .collect::<io::Result<_>>()?;
+        Ok(())
     }
 }
 
@@ -100,10 +144,12 @@ mod tests {
     fn test_read_timecmp() {
         let mut timer = Timer::new();
 
-        timer.timecmp = 0x51290ce3_bcfec832_u64;
+        timer.set_num_harts(2);
+        timer.timecmp[1] = 0x51290ce3_bcfec832_u64;
 
-        assert_eq!(timer.read_timecmp(0), 0xbcfec832_u32);
-        assert_eq!(timer.read_timecmp(4), 0x51290ce3_u32);
+        assert_eq!(timer.read_timecmp(8), 0xbcfec832_u32);
+        assert_eq!(timer.read_timecmp(12), 0x51290ce3_u32);
+        assert_eq!(timer.read_timecmp(0), 0xffffffff_u32);
     }
 
     #[test]
@@ -113,6 +159,24 @@ mod tests {
         timer.write_timecmp(0, 0xbcfec832_u32);
         timer.write_timecmp(4, 0x51290ce3_u32);
 
-        assert_eq!(timer.timecmp, 0x51290ce3_bcfec832_u64);
+        assert_eq!(timer.timecmp[0], 0x51290ce3_bcfec832_u64);
+        assert!(!timer.is_interrupt_set(0));
+        timer.time = 0x51290ce3_bcfec832_u64;
+        assert!(timer.is_interrupt_set(0));
+    }
+
+    #[test]
+    fn test_layout() {
+        let mut timer = Timer::new();
+
+        timer.write(0x7ff8, 0x1234);
+        timer.write(0x0004, 0x5678);
+        assert_eq!(timer.time, 0x1234);
+        assert_eq!(timer.read(0x7ffc), 0);
+        assert_eq!(timer.read(0x0004), 0x5678);
+
+        // Hart 1 does not exist
+        timer.write(0x0008, 0x9abc);
+        assert_eq!(timer.read(0x0008), 0);
     }
 }
@@ -1,17 +1,28 @@
-use console::Term;
-use core::panic;
-use std::{fmt::Debug, io::{stdout, Write}};
+use crate::snapshot::{SnapshotReader, SnapshotWriter};
+use console::{Key, Term};
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    io::{self, ErrorKind, Read, Write},
+    process,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
 
-// This is an emulator for the 8250 serial chip with:
+// This is an emulator for the 16550 serial chip with:
 // - Infinite-length FIFOs (never full)
 // - Ignore baud-rate and tranmission modes
 // - Error-free
 // - No modem control signals (DTR RTS CTS DSR RI CD)
+// - Input from a background reader (usually stdin)
 #[derive(Debug)]
 pub struct Uart {
     term: Term,
+    input: Option<Receiver<u8>>,
+    rx_fifo: VecDeque<u8>,
     int_en: u8,
-    int_pending: UartInt,
+    thre_int: bool, // THR empty interrupt pending
+    fifo_en: bool,
     line_control: u8,
     modem_control: u8,
     scratch: u8,
@@ -19,23 +30,39 @@ pub struct Uart {
     div_latch_hi: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 enum UartInt {
     NoInt,
+    RxData,
+    ThrEmpty,
 }
 
+// PLIC source of the interrupt line
+pub const UART_IRQ: u32 = 10;
+
 const IER_MASK: u8 = 0b0000_1111;
+const IER_ERBFI: u8 = 0b0000_0001; // Received data available
+const IER_ETBEI: u8 = 0b0000_0010; // Transmitter holding register empty
 const MCR_MASK: u8 = 0b0001_1111;
 
+const FCR_ENABLE: u8 = 0b0000_0001;
+const FCR_CLEAR_RX: u8 = 0b0000_0010;
+const IIR_FIFO: u8 = 0b1100_0000;
+
 const LCR_DLAB: u8 = 0b1000_0000; // Divisor latch access bit
-const LSR_VAL: u8 = 0b0110_0001; // No error, TX always empty, RX always ready
+const LSR_DR: u8 = 0b0000_0001; // Data ready
+const LSR_THRE: u8 = 0b0010_0000; // TX holding register empty
+const LSR_TEMT: u8 = 0b0100_0000; // Transmitter empty
 
 impl Uart {
     pub fn new() -> Uart {
         Uart {
             term: Term::stdout(),
+            input: None,
+            rx_fifo: VecDeque::new(),
             int_en: 0,
-            int_pending: UartInt::NoInt,
+            thre_int: false,
+            fifo_en: false,
             line_control: 0,
             modem_control: 0,
             scratch: 0,
@@ -44,6 +71,77 @@ impl Uart {
         }
     }
 
+    // Receive characters from the terminal (or stdin if it's not a terminal)
+    pub fn connect_stdin(&mut self) {
+        let (tx, rx) = mpsc::channel();
+        let term = Term::stdout();
+        thread::spawn(move || {
+            let send = |bytes: &[u8]| bytes.iter().all(|b| tx.send(*b).is_ok());
+            if term.is_term() {
+                loop {
+                    match term.read_key() {
+                        Ok(key) => {
+                            if !send(&key_to_bytes(key)) {
+                                return;
+                            }
+                        }
+                        // Ctrl-C is caught in raw mode, so exit as if interrupted
+                        Err(e) if e.kind() == ErrorKind::Interrupted => process::exit(130),
+                        Err(_) => return,
+                    }
+                }
+            } else {
+                for b in io::stdin().lock().bytes() {
+                    match b {
+                        Ok(b) if send(&[b]) => (),
+                        _ => return,
+                    }
+                }
+            }
+        });
+        self.connect(rx);
+    }
+
+    pub fn connect(&mut self, input: Receiver<u8>) {
+        self.input = Some(input);
+    }
+
+    // Move the received characters to the RX FIFO
+    pub fn poll(&mut self) {
+        if let Some(input) = &self.input {
+            self.rx_fifo.extend(input.try_iter());
+        }
+    }
+
+    pub fn is_interrupt_set(&self) -> bool {
+        self.pending_int() != UartInt::NoInt
+    }
+
+    // Console of the firmware, bypassing the registers
+    // Keep receiving from the terminal connected to another UART
+    pub fn take_input(&mut self, other: &mut Uart) {
+        self.input = other.input.take();
+    }
+
+    pub fn console_write(&mut self, bytes: &[u8]) {
+        self.term.write_all(bytes).expect("cannot write from Uart");
+    }
+
+    pub fn console_read(&mut self) -> Option<u8> {
+        self.poll();
+        self.rx_fifo.pop_front()
+    }
+
+    fn pending_int(&self) -> UartInt {
+        if self.int_en & IER_ERBFI != 0 && !self.rx_fifo.is_empty() {
+            UartInt::RxData
+        } else if self.int_en & IER_ETBEI != 0 && self.thre_int {
+            UartInt::ThrEmpty
+        } else {
+            UartInt::NoInt
+        }
+    }
+
     fn is_dlab_set(&self) -> bool {
         self.line_control & LCR_DLAB != 0
     }
@@ -52,37 +150,46 @@ impl Uart {
         match addr {
             0 => {
                 if !self.is_dlab_set() {
-                    // THR: Tranmission holding register
-                    self.term.write(&[val]).expect("cannot write from Uart");
+                    // THR: Tranmission holding register (sent immediately, so it's empty again)
+                    self.term.write_all(&[val]).expect("cannot write from Uart");
+                    self.thre_int = true;
                 } else {
                     self.div_latch_lo = val; // Divisor latch
                 }
             }
             1 => {
                 if !self.is_dlab_set() {
-                    // IER: Interrupt enable register
+                    // IER: Interrupt enable register (enabling ETBEI raises THRE since THR is always empty)
+                    if val & IER_ETBEI != 0 && self.int_en & IER_ETBEI == 0 {
+                        self.thre_int = true;
+                    }
                     self.int_en = val & IER_MASK;
                 } else {
                     self.div_latch_hi = val; //Divisor latch
                 }
             }
+            2 => {
+                // FCR: FIFO control register
+                self.fifo_en = val & FCR_ENABLE != 0;
+                if val & FCR_CLEAR_RX != 0 {
+                    self.rx_fifo.clear();
+                }
+            }
             3 => self.line_control = val, // LCR: Line control register
             4 => self.modem_control = val & MCR_MASK, // MCR: Modem control register
             7 => self.scratch = val,      // SPR: Scratch pad register
-            2 | 5 | 6 => (),
+            5 | 6 => (),
             _ => panic!("invalid addr for Uart (addr = {addr})"),
         };
-        println!("Write Uart[{addr}] = 0x{val:02x}");
     }
 
     pub fn read(&mut self, addr: u64) -> u8 {
-        print!("Read Uart[{addr}]... ");
-        stdout().flush().unwrap();
-        let res = match addr {
+        match addr {
             0 => {
                 if !self.is_dlab_set() {
-                    // THR: Tranmission holding register
-                    self.term.read_char().expect("cannot write from Uart") as u8
+                    // RBR: Receiver buffer register
+                    self.poll();
+                    self.rx_fifo.pop_front().unwrap_or(0)
                 } else {
                     self.div_latch_lo // Divisor latch
                 }
@@ -95,19 +202,155 @@ impl Uart {
                 }
             }
             2 => {
-                // ISR: Interrupt status register
-                match self.int_pending {
-                    UartInt::NoInt => 0b0001,
+                // IIR: Interrupt identification register (reading it clears THRE)
+                let fifo = if self.fifo_en { IIR_FIFO } else { 0 };
+                match self.pending_int() {
+                    UartInt::NoInt => fifo | 0b0001,
+                    UartInt::RxData => fifo | 0b0100,
+                    UartInt::ThrEmpty => {
+                        self.thre_int = false;
+                        fifo | 0b0010
+                    }
                 }
             }
             3 => self.line_control,  // LCR: Line control register
             4 => self.modem_control, // MCR: Modem control register
-            5 => LSR_VAL,            // LSR: Line status register
-            6 => 0,                  // MSR: Modem status register
-            7 => self.scratch,       // SPR: Scratch pad register
+            5 => {
+                // LSR: Line status register (TX always empty)
+                self.poll();
+                let dr = if self.rx_fifo.is_empty() { 0 } else { LSR_DR };
+                dr | LSR_THRE | LSR_TEMT
+            }
+            6 => 0,            // MSR: Modem status register
+            7 => self.scratch, // SPR: Scratch pad register
             _ => panic!("invalid addr for Uart (addr = {addr})"),
-        };
-        println!("Read Uart[{addr}] = 0x{res:02x}");
-        res
+        }
+    }
+}
+
+// Bytes sent by a terminal for a key
+fn key_to_bytes(key: Key) -> Vec<u8> {
+    match key {
+        Key::Char(c) => c.to_string().into_bytes(),
+        Key::Enter => vec![b'\n'],
+        Key::Backspace => vec![0x7f],
+        Key::Tab => vec![b'\t'],
+        Key::Escape => vec![0x1b],
+        Key::ArrowUp => b"\x1b[A".to_vec(),
+        Key::ArrowDown => b"\x1b[B".to_vec(),
+        Key::ArrowRight => b"\x1b[C".to_vec(),
+        Key::ArrowLeft => b"\x1b[D".to_vec(),
+        Key::Del => b"\x1b[3~".to_vec(),
+        _ => vec![],
+    }
+}
+
+impl Uart {
+    // The input itself is not part of the snapshot, only what has been received
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_bytes(&self.rx_fifo.iter().copied().collect::<Vec<_>>());
+        w.put_u8(self.int_en);
+        w.put_bool(self.thre_int);
+        w.put_bool(self.fifo_en);
+        w.put_u8(self.line_control);
+        w.put_u8(self.modem_control);
+        w.put_u8(self.scratch);
+        w.put_u8(self.div_latch_lo);
+        w.put_u8(self.div_latch_hi);
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        self.rx_fifo = r.get_bytes()?.into();
+        self.int_en = r.get_u8()?;
+        self.thre_int = r.get_bool()?;
+        self.fifo_en = r.get_bool()?;
+        self.line_control = r.get_u8()?;
+        self.modem_control = r.get_u8()?;
+        self.scratch = r.get_u8()?;
+        self.div_latch_lo = r.get_u8()?;
+        self.div_latch_hi = r.get_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+
+    fn make_uart() -> (Uart, Sender<u8>) {
+        let (tx, rx) = mpsc::channel();
+        let mut uart = Uart::new();
+        uart.connect(rx);
+        (uart, tx)
+    }
+
+    #[test]
+    fn test_rx_fifo() {
+        let (mut uart, tx) = make_uart();
+        assert_eq!(uart.read(5) & LSR_DR, 0);
+        assert_eq!(uart.read(5) & LSR_THRE, LSR_THRE);
+        assert_eq!(uart.read(0), 0);
+
+        for b in b"hi" {
+            tx.send(*b).unwrap();
+        }
+        assert_eq!(uart.read(5) & LSR_DR, LSR_DR);
+        assert_eq!(uart.read(0), b'h');
+        assert_eq!(uart.read(0), b'i');
+        assert_eq!(uart.read(5) & LSR_DR, 0);
+
+        // Clear through FCR
+        tx.send(b'x').unwrap();
+        uart.poll();
+        uart.write(2, FCR_ENABLE | FCR_CLEAR_RX);
+        assert_eq!(uart.read(5) & LSR_DR, 0);
+    }
+
+    #[test]
+    fn test_rx_interrupt() {
+        let (mut uart, tx) = make_uart();
+        uart.write(2, FCR_ENABLE);
+        tx.send(b'a').unwrap();
+        uart.poll();
+        assert!(!uart.is_interrupt_set());
+        assert_eq!(uart.read(2), 0xc1);
+
+        uart.write(1, IER_ERBFI);
+        assert!(uart.is_interrupt_set());
+        assert_eq!(uart.read(2), 0xc4);
+        assert_eq!(uart.read(0), b'a');
+        assert!(!uart.is_interrupt_set());
+        assert_eq!(uart.read(2), 0xc1);
+    }
+
+    #[test]
+    fn test_thre_interrupt() {
+        let (mut uart, _tx) = make_uart();
+
+        // Enabling ETBEI raises it since THR is empty, reading IIR clears it
+        uart.write(1, IER_ETBEI);
+        assert!(uart.is_interrupt_set());
+        assert_eq!(uart.read(2), 0x02);
+        assert!(!uart.is_interrupt_set());
+        assert_eq!(uart.read(2), 0x01);
+
+        // Writing THR raises it again
+        uart.write(0, b'\n');
+        assert!(uart.is_interrupt_set());
+        uart.write(1, 0);
+        assert!(!uart.is_interrupt_set());
+    }
+
+    #[test]
+    fn test_dlab() {
+        let (mut uart, _tx) = make_uart();
+        uart.write(3, LCR_DLAB);
+        uart.write(0, 0x12);
+        uart.write(1, 0x34);
+        assert_eq!(uart.read(0), 0x12);
+        assert_eq!(uart.read(1), 0x34);
+        uart.write(3, 0x03);
+        assert_eq!(uart.read(1), 0);
     }
 }
@@ -0,0 +1,511 @@
+use super::{virtio_blk::VirtioBlk, virtio_net::VirtioNet, Dma};
+use crate::snapshot::{invalid_data, SnapshotReader, SnapshotWriter};
+use std::io;
+
+// This is an emulator for the virtio-mmio transport (version 2) with:
+// - VIRTIO_SLOTS fixed slots, empty ones reading as device ID 0 (as on the QEMU virt machine)
+// - Split virtqueues without indirect descriptors nor event indices
+// - Notified queues processed when the devices are polled
+// - A level-triggered interrupt per slot (source VIRTIO_IRQ + slot of the PLIC)
+pub const VIRTIO_SIZE: u64 = 0x1000;
+pub const VIRTIO_SLOTS: usize = 8;
+pub const VIRTIO_IRQ: u32 = 1;
+pub const VIRTIO_CONFIG: u64 = 0x100; // Device-specific configuration space
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+const MMIO_VERSION: u32 = 2;
+const VENDOR: u32 = 0x4d49_5352; // "RSIM"
+const MAX_QUEUE_NUM: u32 = 256;
+
+// Registers
+const MAGIC_VALUE: u64 = 0x000;
+const VERSION: u64 = 0x004;
+const DEVICE_ID: u64 = 0x008;
+const VENDOR_ID: u64 = 0x00c;
+const DEVICE_FEATURES: u64 = 0x010;
+const DEVICE_FEATURES_SEL: u64 = 0x014;
+const DRIVER_FEATURES: u64 = 0x020;
+const DRIVER_FEATURES_SEL: u64 = 0x024;
+const QUEUE_SEL: u64 = 0x030;
+const QUEUE_NUM_MAX: u64 = 0x034;
+const QUEUE_NUM: u64 = 0x038;
+const QUEUE_READY: u64 = 0x044;
+const QUEUE_NOTIFY: u64 = 0x050;
+const INTERRUPT_STATUS: u64 = 0x060;
+const INTERRUPT_ACK: u64 = 0x064;
+const STATUS: u64 = 0x070;
+const QUEUE_DESC_LOW: u64 = 0x080;
+const QUEUE_DESC_HIGH: u64 = 0x084;
+const QUEUE_DRIVER_LOW: u64 = 0x090;
+const QUEUE_DRIVER_HIGH: u64 = 0x094;
+const QUEUE_DEVICE_LOW: u64 = 0x0a0;
+const QUEUE_DEVICE_HIGH: u64 = 0x0a4;
+const CONFIG_GENERATION: u64 = 0x0fc;
+
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+pub const STATUS_DRIVER_OK: u32 = 0x04;
+pub const STATUS_NEEDS_RESET: u32 = 0x40;
+
+pub const INT_USED_BUFFER: u32 = 0x1;
+pub const INT_CONFIG_CHANGE: u32 = 0x2;
+
+// Descriptor flags
+const DESC_F_NEXT: u16 = 0x1;
+const DESC_F_WRITE: u16 = 0x2;
+
+// Driver area flags
+const AVAIL_F_NO_INTERRUPT: u16 = 0x1;
+
+#[derive(Debug)]
+pub enum VirtioDevice {
+    Blk(VirtioBlk),
+    Net(VirtioNet),
+}
+
+impl VirtioDevice {
+    fn device_id(&self) -> u32 {
+        match self {
+            VirtioDevice::Blk(_) => 2,
+            VirtioDevice::Net(_) => 1,
+        }
+    }
+
+    fn features(&self) -> u64 {
+        match self {
+            VirtioDevice::Blk(blk) => blk.features(),
+            VirtioDevice::Net(net) => net.features(),
+        }
+    }
+
+    fn num_queues(&self) -> usize {
+        match self {
+            VirtioDevice::Blk(_) => 1,
+            VirtioDevice::Net(_) => 2,
+        }
+    }
+
+    fn config(&self) -> Vec<u8> {
+        match self {
+            VirtioDevice::Blk(blk) => blk.config(),
+            VirtioDevice::Net(net) => net.config(),
+        }
+    }
+
+    // Handle the buffers made available in a queue, and tell if some were used
+    fn process(&mut self, index: usize, queue: &mut Virtqueue, dma: &mut Dma) -> Option<bool> {
+        match self {
+            VirtioDevice::Blk(blk) => blk.process(index, queue, dma),
+            VirtioDevice::Net(net) => net.process(index, queue, dma),
+        }
+    }
+
+    // Queues to process even if they were not notified
+    fn pending_queues(&mut self) -> u32 {
+        match self {
+            VirtioDevice::Blk(_) => 0,
+            VirtioDevice::Net(net) => net.pending_queues(),
+        }
+    }
+}
+
+// One descriptor of a chain
+#[derive(Debug, Clone, Copy)]
+pub struct Desc {
+    pub addr: u64,
+    pub len: u32,
+    pub write: bool, // Device-writable
+}
+
+// A chain of descriptors made available by the driver
+#[derive(Debug)]
+pub struct DescChain {
+    pub head: u16,
+    pub descs: Vec<Desc>,
+}
+
+impl DescChain {
+    // Gather the device-readable buffers (already checked to be in RAM)
+    pub fn read_all(&self, dma: &Dma) -> Option<Vec<u8>> {
+        let mut bytes = vec![];
+        for desc in self.descs.iter().filter(|d| !d.write) {
+            let start = bytes.len();
+            bytes.resize(start + desc.len as usize, 0);
+            dma.read(desc.addr, &mut bytes[start..])?;
+        }
+        Some(bytes)
+    }
+
+    pub fn writable_len(&self) -> usize {
+        self.descs
+            .iter()
+            .filter(|d| d.write)
+            .map(|d| d.len as usize)
+            .sum()
+    }
+
+    // Scatter into the device-writable buffers (bytes must fit)
+    pub fn write_all(&self, dma: &mut Dma, mut bytes: &[u8]) -> Option<()> {
+        for desc in self.descs.iter().filter(|d| d.write) {
+            let len = bytes.len().min(desc.len as usize);
+            dma.write(desc.addr, &bytes[..len])?;
+            bytes = &bytes[len..];
+        }
+        Some(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Virtqueue {
+    pub num: u32,
+    pub ready: bool,
+    pub desc: u64,   // Descriptor area
+    pub driver: u64, // Driver area (available ring)
+    pub device: u64, // Device area (used ring)
+    last_avail: u16,
+}
+
+impl Default for Virtqueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Virtqueue {
+    pub fn new() -> Virtqueue {
+        Virtqueue {
+            num: 0,
+            ready: false,
+            desc: 0,
+            driver: 0,
+            device: 0,
+            last_avail: 0,
+        }
+    }
+
+    // Take the next chain made available by the driver (Some(None) if there is none)
+    // An invalid ring or descriptor gives None
+    pub fn pop(&mut self, dma: &Dma) -> Option<Option<DescChain>> {
+        let avail_idx = read_u16(dma, self.driver + 2)?;
+        if avail_idx == self.last_avail {
+            return Some(None);
+        }
+        let slot = (self.last_avail as u32 % self.num) as u64;
+        self.last_avail = self.last_avail.wrapping_add(1);
+        self.read_chain(dma, slot).map(Some)
+    }
+
+    fn read_chain(&self, dma: &Dma, slot: u64) -> Option<DescChain> {
+        let head = read_u16(dma, self.driver + 4 + 2 * slot)?;
+        let mut descs = vec![];
+        let mut index = head;
+        let mut total = 0;
+        loop {
+            // A chain cannot be longer than the queue (no loops)
+            if index as u32 >= self.num || descs.len() as u32 >= self.num {
+                return None;
+            }
+            let mut buf = [0; 16];
+            dma.read(self.desc + 16 * index as u64, &mut buf)?;
+            let flags = u16::from_le_bytes([buf[12], buf[13]]);
+            let desc = Desc {
+                addr: u64::from_le_bytes(buf[0..8].try_into().unwrap()),
+                len: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+                write: flags & DESC_F_WRITE != 0,
+            };
+            // Buffers must be in RAM, and cannot add up to more than all of it (so the
+            // devices never allocate more than that for a chain)
+            total += desc.len as u64;
+            if !dma.is_ram(desc.addr, desc.len as usize) || total > dma.ram_size() {
+                return None;
+            }
+            descs.push(desc);
+            if flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            index = u16::from_le_bytes([buf[14], buf[15]]);
+        }
+        Some(DescChain { head, descs })
+    }
+
+    // Return a chain to the driver with the number of bytes written into it
+    pub fn push(&mut self, dma: &mut Dma, head: u16, len: u32) -> Option<()> {
+        let used_idx = read_u16(dma, self.device + 2)?;
+        let slot = (used_idx as u32 % self.num) as u64;
+        let mut elem = [0; 8];
+        elem[0..4].copy_from_slice(&(head as u32).to_le_bytes());
+        elem[4..8].copy_from_slice(&len.to_le_bytes());
+        dma.write(self.device + 4 + 8 * slot, &elem)?;
+        dma.write(self.device + 2, &used_idx.wrapping_add(1).to_le_bytes())
+    }
+
+    // The driver may ask not to be interrupted
+    pub fn wants_interrupt(&self, dma: &Dma) -> bool {
+        read_u16(dma, self.driver).is_none_or(|flags| flags & AVAIL_F_NO_INTERRUPT == 0)
+    }
+}
+
+fn read_u16(dma: &Dma, addr: u64) -> Option<u16> {
+    let mut buf = [0; 2];
+    dma.read(addr, &mut buf)?;
+    Some(u16::from_le_bytes(buf))
+}
+
+#[derive(Debug)]
+pub struct VirtioMmio {
+    dev: Option<VirtioDevice>,
+    status: u32,
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queues: Vec<Virtqueue>,
+    interrupt_status: u32,
+    notified: u32, // Queues notified but not processed yet
+}
+
+impl VirtioMmio {
+    pub fn new(dev: Option<VirtioDevice>) -> VirtioMmio {
+        let num_queues = dev.as_ref().map_or(0, |d| d.num_queues());
+        VirtioMmio {
+            dev,
+            status: 0,
+            device_features_sel: 0,
+            driver_features: 0,
+            driver_features_sel: 0,
+            queue_sel: 0,
+            queues: vec![Virtqueue::new(); num_queues],
+            interrupt_status: 0,
+            notified: 0,
+        }
+    }
+
+    pub fn is_interrupt_set(&self) -> bool {
+        self.interrupt_status != 0
+    }
+
+    // Change the link status of a network device, telling the driver
+    pub fn set_link_up(&mut self, up: bool) -> bool {
+        let Some(VirtioDevice::Net(net)) = &mut self.dev else {
+            return false;
+        };
+        if net.is_link_up() != up {
+            net.set_link_up(up);
+            self.interrupt_status |= INT_CONFIG_CHANGE;
+        }
+        true
+    }
+
+    pub fn take_device(&mut self) -> Option<VirtioDevice> {
+        self.dev.take()
+    }
+
+    fn reset(&mut self) {
+        let dev = self.dev.take();
+        *self = VirtioMmio::new(dev);
+    }
+
+    fn device_features(&self) -> u64 {
+        self.dev
+            .as_ref()
+            .map_or(0, |d| d.features() | VIRTIO_F_VERSION_1)
+    }
+
+    fn queue(&mut self) -> Option<&mut Virtqueue> {
+        self.queues.get_mut(self.queue_sel as usize)
+    }
+
+    pub fn read(&mut self, addr: u64) -> u32 {
+        let queue = self.queues.get(self.queue_sel as usize);
+        match addr {
+            MAGIC_VALUE => MAGIC,
+            VERSION => MMIO_VERSION,
+            DEVICE_ID => self.dev.as_ref().map_or(0, |d| d.device_id()),
+            VENDOR_ID => VENDOR,
+            DEVICE_FEATURES => match self.device_features_sel {
+                0 => self.device_features() as u32,
+                1 => (self.device_features() >> 32) as u32,
+                _ => 0,
+            },
+            QUEUE_NUM_MAX => queue.map_or(0, |_| MAX_QUEUE_NUM),
+            QUEUE_READY => queue.map_or(0, |q| q.ready as u32),
+            INTERRUPT_STATUS => self.interrupt_status,
+            STATUS => self.status,
+            CONFIG_GENERATION => 0,
+            _ if addr >= VIRTIO_CONFIG => self.read_config(addr, 4),
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, val: u32) {
+        match addr {
+            DEVICE_FEATURES_SEL => self.device_features_sel = val,
+            DRIVER_FEATURES => {
+                let shift = 32 * self.driver_features_sel as u64;
+                if shift < 64 {
+                    self.driver_features &= !(0xffff_ffff << shift);
+                    self.driver_features |= (val as u64) << shift;
+                }
+            }
+            DRIVER_FEATURES_SEL => self.driver_features_sel = val,
+            QUEUE_SEL => self.queue_sel = val,
+            QUEUE_NUM => {
+                if let Some(q) = self.queue() {
+                    q.num = val.min(MAX_QUEUE_NUM);
+                }
+            }
+            QUEUE_READY => {
+                if let Some(q) = self.queue() {
+                    q.ready = val & 1 != 0;
+                }
+            }
+            QUEUE_NOTIFY if (val as usize) < self.queues.len() => self.notified |= 1 << val,
+            INTERRUPT_ACK => self.interrupt_status &= !val,
+            STATUS if val == 0 => self.reset(),
+            STATUS => self.status = val,
+            QUEUE_DESC_LOW | QUEUE_DESC_HIGH => {
+                if let Some(q) = self.queue() {
+                    set_half(&mut q.desc, addr == QUEUE_DESC_HIGH, val);
+                }
+            }
+            QUEUE_DRIVER_LOW | QUEUE_DRIVER_HIGH => {
+                if let Some(q) = self.queue() {
+                    set_half(&mut q.driver, addr == QUEUE_DRIVER_HIGH, val);
+                }
+            }
+            QUEUE_DEVICE_LOW | QUEUE_DEVICE_HIGH => {
+                if let Some(q) = self.queue() {
+                    set_half(&mut q.device, addr == QUEUE_DEVICE_HIGH, val);
+                }
+            }
+            // The configuration of the devices is read-only
+            _ => (),
+        }
+    }
+
+    // Configuration space (accessed by bytes, half-words or words)
+    pub fn read_config(&self, addr: u64, len: usize) -> u32 {
+        let config = self.dev.as_ref().map_or(vec![], |d| d.config());
+        let offset = (addr - VIRTIO_CONFIG) as usize;
+        let mut bytes = [0; 4];
+        for (i, byte) in bytes.iter_mut().enumerate().take(len) {
+            *byte = config.get(offset + i).copied().unwrap_or(0);
+        }
+        u32::from_le_bytes(bytes)
+    }
+
+    // Process the notified queues once the driver is ready
+    pub fn poll(&mut self, dma: &mut Dma) {
+        let Some(dev) = self.dev.as_mut() else {
+            return;
+        };
+        if self.status & STATUS_DRIVER_OK == 0 || self.status & STATUS_NEEDS_RESET != 0 {
+            return;
+        }
+        let pending = self.notified | dev.pending_queues();
+        for (index, queue) in self.queues.iter_mut().enumerate() {
+            if pending & (1 << index) == 0 || !queue.ready || queue.num == 0 {
+                continue;
+            }
+            match dev.process(index, queue, dma) {
+                Some(true) if queue.wants_interrupt(dma) => {
+                    self.interrupt_status |= INT_USED_BUFFER;
+                }
+                Some(_) => (),
+                // The driver broke a queue
+                None => {
+                    self.status |= STATUS_NEEDS_RESET;
+                    self.interrupt_status |= INT_CONFIG_CHANGE;
+                }
+            }
+        }
+        self.notified = 0;
+    }
+}
+
+fn set_half(reg: &mut u64, high: bool, val: u32) {
+    if high {
+        *reg = (*reg & 0xffff_ffff) | (val as u64) << 32;
+    } else {
+        *reg = (*reg & !0xffff_ffff) | val as u64;
+    }
+}
+
+impl VirtioMmio {
+    // The backing files are not part of the snapshot, only the state of the devices
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_u32(self.dev.as_ref().map_or(0, |d| d.device_id()));
+        w.put_u32(self.status);
+        w.put_u32(self.device_features_sel);
+        w.put_u64(self.driver_features);
+        w.put_u32(self.driver_features_sel);
+        w.put_u32(self.queue_sel);
+        for q in self.queues.iter() {
+            w.put_u32(q.num);
+            w.put_bool(q.ready);
+            w.put_u64(q.desc);
+            w.put_u64(q.driver);
+            w.put_u64(q.device);
+            w.put_u32(q.last_avail as u32);
+        }
+        w.put_u32(self.interrupt_status);
+        w.put_u32(self.notified);
+        match &self.dev {
+            Some(VirtioDevice::Blk(blk)) => blk.save(w),
+            Some(VirtioDevice::Net(net)) => net.save(w),
+            None => (),
+        }
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        if r.get_u32()? != self.dev.as_ref().map_or(0, |d| d.device_id()) {
+            return Err(invalid_data("virtio devices differ from the snapshot"));
+        }
+        self.status = r.get_u32()?;
+        self.device_features_sel = r.get_u32()?;
+        self.driver_features = r.get_u64()?;
+        self.driver_features_sel = r.get_u32()?;
+        self.queue_sel = r.get_u32()?;
+        for q in self.queues.iter_mut() {
+            q.num = r.get_u32()?;
+            q.ready = r.get_bool()?;
+            q.desc = r.get_u64()?;
+            q.driver = r.get_u64()?;
+            q.device = r.get_u64()?;
+            q.last_avail = r.get_u32()? as u16;
+        }
+        self.interrupt_status = r.get_u32()?;
+        self.notified = r.get_u32()?;
+        match &mut self.dev {
+            Some(VirtioDevice::Blk(blk)) => blk.restore(r),
+            Some(VirtioDevice::Net(net)) => net.restore(r),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_slot() {
+        let mut virtio = VirtioMmio::new(None);
+        assert_eq!(virtio.read(MAGIC_VALUE), MAGIC);
+        assert_eq!(virtio.read(VERSION), MMIO_VERSION);
+        assert_eq!(virtio.read(DEVICE_ID), 0);
+        assert_eq!(virtio.read(DEVICE_FEATURES), 0);
+        assert_eq!(virtio.read(QUEUE_NUM_MAX), 0);
+        virtio.write(QUEUE_NOTIFY, 0);
+        assert_eq!(virtio.notified, 0);
+    }
+
+    #[test]
+    fn test_set_half() {
+        let mut reg = 0x1111_2222_3333_4444;
+        set_half(&mut reg, false, 0x5555_6666);
+        assert_eq!(reg, 0x1111_2222_5555_6666);
+        set_half(&mut reg, true, 0x7777_8888);
+        assert_eq!(reg, 0x7777_8888_5555_6666);
+    }
+}
@@ -0,0 +1,352 @@
+use super::{
+    virtio::{DescChain, Virtqueue},
+    Dma,
+};
+use crate::{
+    config::Drive,
+    snapshot::{SnapshotReader, SnapshotWriter},
+};
+use std::{collections::HashMap, fs::File, io, os::unix::fs::FileExt};
+
+// This is an emulator for a virtio block device with:
+// - A raw disk image (its size rounded down to whole sectors)
+// - Read-write, read-only, or copy-on-write (writes kept in memory, as QEMU snapshot=on)
+// - One request queue handling read, write, flush and get ID
+pub const SECTOR_SIZE: u64 = 512;
+
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_BLK_SIZE: u64 = 1 << 6;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+// Request types
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+const T_GET_ID: u32 = 8;
+
+// Request status
+const S_OK: u8 = 0;
+const S_IOERR: u8 = 1;
+const S_UNSUPP: u8 = 2;
+
+const HEADER_SIZE: usize = 16;
+const ID_SIZE: usize = 20;
+const ID: &[u8] = b"riscv_sim";
+
+#[derive(Debug)]
+pub struct VirtioBlk {
+    file: File,
+    sectors: u64,
+    read_only: bool,
+    overlay: Option<HashMap<u64, Vec<u8>>>, // Sectors written in copy-on-write mode
+}
+
+impl VirtioBlk {
+    pub fn open(drive: &Drive) -> io::Result<VirtioBlk> {
+        let file = drive.open()?;
+        let sectors = file.metadata()?.len() / SECTOR_SIZE;
+        Ok(VirtioBlk {
+            file,
+            sectors,
+            read_only: drive.read_only,
+            overlay: drive.snapshot.then(HashMap::new),
+        })
+    }
+
+    pub fn features(&self) -> u64 {
+        let ro = if self.read_only { VIRTIO_BLK_F_RO } else { 0 };
+        VIRTIO_BLK_F_BLK_SIZE | VIRTIO_BLK_F_FLUSH | ro
+    }
+
+    // Capacity (in sectors) at offset 0, and block size at offset 20
+    pub fn config(&self) -> Vec<u8> {
+        let mut config = vec![0; 24];
+        config[0..8].copy_from_slice(&self.sectors.to_le_bytes());
+        config[20..24].copy_from_slice(&(SECTOR_SIZE as u32).to_le_bytes());
+        config
+    }
+
+    pub fn process(&mut self, _index: usize, queue: &mut Virtqueue, dma: &mut Dma) -> Option<bool> {
+        let mut used = false;
+        while let Some(chain) = queue.pop(dma)? {
+            let len = self.handle(&chain, dma)?;
+            queue.push(dma, chain.head, len)?;
+            used = true;
+        }
+        Some(used)
+    }
+
+    // A request is a header, data, and a status byte (the last writable one)
+    fn handle(&mut self, chain: &DescChain, dma: &mut Dma) -> Option<u32> {
+        let out = chain.read_all(dma)?;
+        let in_len = chain.writable_len().checked_sub(1)?;
+        if out.len() < HEADER_SIZE {
+            return None;
+        }
+        let rtype = u32::from_le_bytes(out[0..4].try_into().unwrap());
+        let sector = u64::from_le_bytes(out[8..16].try_into().unwrap());
+        let data = &out[HEADER_SIZE..];
+        let (mut reply, status) = match rtype {
+            T_IN => match self.read_sectors(sector, in_len) {
+                Ok(buf) => (buf, S_OK),
+                Err(_) => (vec![], S_IOERR),
+            },
+            T_OUT => match self.write_sectors(sector, data) {
+                Ok(()) => (vec![], S_OK),
+                Err(_) => (vec![], S_IOERR),
+            },
+            T_FLUSH => match self.flush() {
+                Ok(()) => (vec![], S_OK),
+                Err(_) => (vec![], S_IOERR),
+            },
+            T_GET_ID => {
+                let mut id = ID.to_vec();
+                id.resize(ID_SIZE.min(in_len), 0);
+                (id, S_OK)
+            }
+            _ => (vec![], S_UNSUPP),
+        };
+        // The status byte comes right after the data
+        reply.resize(in_len, 0);
+        reply.push(status);
+        chain.write_all(dma, &reply)?;
+        Some(reply.len() as u32)
+    }
+
+    fn check_range(&self, sector: u64, len: usize) -> io::Result<()> {
+        let sectors = len as u64 / SECTOR_SIZE;
+        if !(len as u64).is_multiple_of(SECTOR_SIZE)
+            || sector.saturating_add(sectors) > self.sectors
+        {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        Ok(())
+    }
+
+    pub fn read_sectors(&self, sector: u64, len: usize) -> io::Result<Vec<u8>> {
+        self.check_range(sector, len)?;
+        let mut buf = vec![0; len];
+        self.file.read_exact_at(&mut buf, sector * SECTOR_SIZE)?;
+        // Sectors written in copy-on-write mode replace those of the image
+        if let Some(overlay) = &self.overlay {
+            for (i, chunk) in buf.chunks_mut(SECTOR_SIZE as usize).enumerate() {
+                if let Some(data) = overlay.get(&(sector + i as u64)) {
+                    chunk.copy_from_slice(data);
+                }
+            }
+        }
+        Ok(buf)
+    }
+
+    pub fn write_sectors(&mut self, sector: u64, data: &[u8]) -> io::Result<()> {
+        self.check_range(sector, data.len())?;
+        if self.read_only {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        match &mut self.overlay {
+            Some(overlay) => {
+                for (i, chunk) in data.chunks(SECTOR_SIZE as usize).enumerate() {
+                    overlay.insert(sector + i as u64, chunk.to_vec());
+                }
+                Ok(())
+            }
+            None => self.file.write_all_at(data, sector * SECTOR_SIZE),
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        match self.overlay {
+            Some(_) => Ok(()),
+            None if self.read_only => Ok(()),
+            None => self.file.sync_data(),
+        }
+    }
+}
+
+impl VirtioBlk {
+    // Only the copy-on-write sectors (the image itself is not part of the snapshot)
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        let overlay = self.overlay.as_ref();
+        let mut sectors: Vec<_> = overlay.map_or(vec![], |o| o.keys().copied().collect());
+        sectors.sort();
+        w.put_u32(sectors.len() as u32);
+        for sector in sectors {
+            w.put_u64(sector);
+            w.put_bytes(&overlay.unwrap()[&sector]);
+        }
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        let num_sectors = r.get_u32()?;
+        let mut sectors = HashMap::new();
+        for _ in 0..num_sectors {
+            let sector = r.get_u64()?;
+            sectors.insert(sector, r.get_bytes()?);
+        }
+        if let Some(overlay) = &mut self.overlay {
+            *overlay = sectors;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::mem_map::{virtio::*, AccessAttr, AccessType, AccessWidth, MemMap};
+    use std::path::Path;
+
+    const ATTR: AccessAttr = AccessAttr {
+        atype: AccessType::Store,
+        width: AccessWidth::Word,
+        lrsc: false,
+        amo: false,
+    };
+
+    fn make_image(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("riscv_sim_{name}_{}", std::process::id()));
+        let image: Vec<u8> = (0..4 * SECTOR_SIZE)
+            .map(|i| (i / SECTOR_SIZE) as u8)
+            .collect();
+        std::fs::write(&path, image).unwrap();
+        path
+    }
+
+    fn drive(path: &Path, read_only: bool, snapshot: bool) -> Drive {
+        Drive {
+            file: path.to_path_buf(),
+            read_only,
+            snapshot,
+        }
+    }
+
+    fn write_ram(mem: &mut MemMap, addr: usize, bytes: &[u8]) {
+        mem.ram.as_u8_mut()[addr..addr + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn write_desc(mem: &mut MemMap, index: usize, addr: u64, len: u32, flags: u16, next: u16) {
+        let mut desc = vec![];
+        desc.extend(addr.to_le_bytes());
+        desc.extend(len.to_le_bytes());
+        desc.extend(flags.to_le_bytes());
+        desc.extend(next.to_le_bytes());
+        write_ram(mem, 0x1000 + 16 * index, &desc);
+    }
+
+    // Queue 0 with 8 entries: descriptors at 0x1000, driver area at 0x2000, device at 0x3000
+    fn setup(mem: &mut MemMap) {
+        let base = mem.virtio_base;
+        for (reg, val) in [
+            (0x070, 1 | 2),
+            (0x030, 0),
+            (0x038, 8),
+            (0x080, 0x1000),
+            (0x090, 0x2000),
+            (0x0a0, 0x3000),
+            (0x044, 1),
+            (0x070, 1 | 2 | 8 | 4),
+        ] {
+            mem.write_u32(base + reg, val, ATTR).unwrap();
+        }
+    }
+
+    // Make a request of header, data and status, and run it
+    fn request(mem: &mut MemMap, n: u16, rtype: u32, sector: u64, data_len: u32) -> u8 {
+        let mut header = vec![];
+        header.extend(rtype.to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+        header.extend(sector.to_le_bytes());
+        write_ram(mem, 0x4000, &header);
+        let data_flags = if matches!(rtype, T_IN | T_GET_ID) {
+            2
+        } else {
+            0
+        };
+        write_desc(mem, 0, 0x4000, 16, 1, 1);
+        write_desc(mem, 1, 0x5000, data_len, data_flags | 1, 2);
+        write_desc(mem, 2, 0x4010, 1, 2, 0);
+        write_ram(mem, 0x2004 + 2 * (n as usize % 8), &0u16.to_le_bytes());
+        write_ram(mem, 0x2002, &(n + 1).to_le_bytes());
+        mem.write_u32(mem.virtio_base + 0x050, 0, ATTR).unwrap();
+        mem.poll_virtio();
+        assert_eq!(mem.ram.as_u8()[0x3002], n as u8 + 1);
+        mem.ram.as_u8()[0x4010]
+    }
+
+    #[test]
+    fn test_config() {
+        let path = make_image("blk_config");
+        let blk = VirtioBlk::open(&drive(&path, true, false)).unwrap();
+        assert_eq!(blk.config()[0..8], 4u64.to_le_bytes());
+        assert_ne!(blk.features() & VIRTIO_BLK_F_RO, 0);
+        let mut mem = MemMap::new(0x10000);
+        mem.virtio[0] = VirtioMmio::new(Some(VirtioDevice::Blk(blk)));
+        let mut read = |addr, width| {
+            let attr = AccessAttr { width, ..ATTR };
+            mem.read_u32(mem.virtio_base + addr, attr).unwrap()
+        };
+        assert_eq!(read(0x000, AccessWidth::Word), 0x7472_6976);
+        assert_eq!(read(0x008, AccessWidth::Word), 2);
+        assert_eq!(read(0x100, AccessWidth::Word), 4);
+        assert_eq!(read(0x104, AccessWidth::Word), 0);
+        assert_eq!(mem.read_u8(mem.virtio_base + 0x114, ATTR).unwrap(), 0);
+        assert_eq!(mem.read_u16(mem.virtio_base + 0x114, ATTR).unwrap(), 512);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_requests() {
+        let path = make_image("blk_requests");
+        let mut mem = MemMap::new(0x10000);
+        let blk = VirtioBlk::open(&drive(&path, false, false)).unwrap();
+        mem.virtio[0] = VirtioMmio::new(Some(VirtioDevice::Blk(blk)));
+        setup(&mut mem);
+
+        // Read sectors 2 and 3
+        assert_eq!(request(&mut mem, 0, T_IN, 2, 1024), S_OK);
+        assert_eq!(mem.ram.as_u8()[0x5000], 2);
+        assert_eq!(mem.ram.as_u8()[0x5200], 3);
+        assert_eq!(mem.ram.as_u8()[0x3008..0x300c], 1025u32.to_le_bytes());
+        assert!(mem.virtio[0].is_interrupt_set());
+        mem.write_u32(mem.virtio_base + 0x064, 1, ATTR).unwrap();
+        assert!(!mem.virtio[0].is_interrupt_set());
+
+        // Write sector 1, and read it back
+        write_ram(&mut mem, 0x5000, &[0xaa; 512]);
+        assert_eq!(request(&mut mem, 1, T_OUT, 1, 512), S_OK);
+        assert_eq!(request(&mut mem, 2, T_FLUSH, 0, 0), S_OK);
+        assert_eq!(request(&mut mem, 3, T_IN, 1, 512), S_OK);
+        assert_eq!(mem.ram.as_u8()[0x51ff], 0xaa);
+        assert_eq!(std::fs::read(&path).unwrap()[512], 0xaa);
+
+        // Out of range, and unsupported
+        assert_eq!(request(&mut mem, 4, T_IN, 4, 512), S_IOERR);
+        assert_eq!(request(&mut mem, 5, 99, 0, 0), S_UNSUPP);
+        assert_eq!(request(&mut mem, 6, T_GET_ID, 0, 20), S_OK);
+        assert_eq!(&mem.ram.as_u8()[0x5000..0x5009], ID);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_only_and_snapshot() {
+        let path = make_image("blk_overlay");
+        let mut blk = VirtioBlk::open(&drive(&path, true, false)).unwrap();
+        assert!(blk.write_sectors(0, &[0xbb; 512]).is_err());
+
+        let mut blk = VirtioBlk::open(&drive(&path, false, true)).unwrap();
+        blk.write_sectors(1, &[0xbb; 512]).unwrap();
+        assert_eq!(blk.read_sectors(0, 1024).unwrap()[511..513], [0, 0xbb]);
+        assert_eq!(std::fs::read(&path).unwrap()[512], 1);
+
+        // The overlay is part of the snapshot
+        let mut w = SnapshotWriter::new();
+        blk.save(&mut w);
+        let buf = w.into_bytes();
+        let mut restored = VirtioBlk::open(&drive(&path, false, true)).unwrap();
+        restored
+            .restore(&mut SnapshotReader::new(&buf).unwrap())
+            .unwrap();
+        assert_eq!(restored.read_sectors(1, 512).unwrap()[0], 0xbb);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
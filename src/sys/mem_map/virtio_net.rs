@@ -0,0 +1,549 @@
+use super::{
+    virtio::{DescChain, Virtqueue},
+    Dma,
+};
+use crate::{
+    config::{Nic, NicBackend},
+    snapshot::{SnapshotReader, SnapshotWriter},
+};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::{fs::FileTypeExt, net::UnixDatagram},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// This is an emulator for a virtio network device with:
+// - A receive queue and a transmit queue, without offloads nor merged buffers
+// - A MAC address and a link status (frames are dropped while the link is down)
+// - A local backend: datagrams on a UNIX-domain socket, a pcap capture, a loopback,
+//   or an echo responder answering ARP requests and pings for any address
+pub const DEFAULT_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+pub const ECHO_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x35, 0x02];
+
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+const VIRTIO_NET_F_STATUS: u64 = 1 << 16;
+
+const VIRTIO_NET_S_LINK_UP: u16 = 1;
+
+const RX_QUEUE: usize = 0;
+const TX_QUEUE: usize = 1;
+const HEADER_SIZE: usize = 12; // virtio_net_hdr, with num_buffers
+const MAX_FRAME_SIZE: usize = 65536;
+const MAX_RX_FRAMES: usize = 256; // Received but not delivered yet
+
+// Ethernet, ARP and IPv4 (offsets in the frame)
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+const IP_PROTO_ICMP: u8 = 1;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+#[derive(Debug)]
+enum Backend {
+    Socket {
+        socket: UnixDatagram,
+        peer: PathBuf,
+        input: Receiver<Vec<u8>>,
+    },
+    Pcap(File),
+    Loopback,
+    Echo,
+}
+
+#[derive(Debug)]
+pub struct VirtioNet {
+    backend: Backend,
+    mac: [u8; 6],
+    link_up: bool,
+    rx_frames: VecDeque<Vec<u8>>,
+}
+
+impl VirtioNet {
+    pub fn open(nic: &Nic, mac: [u8; 6]) -> io::Result<VirtioNet> {
+        let backend = match &nic.backend {
+            NicBackend::Socket { path, peer } => {
+                // Replace the socket left by a previous run
+                if fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    fs::remove_file(path)?;
+                }
+                let socket = UnixDatagram::bind(path)?;
+                let (tx, rx) = mpsc::channel();
+                let reader = socket.try_clone()?;
+                thread::spawn(move || {
+                    let mut buf = vec![0; MAX_FRAME_SIZE];
+                    while let Ok(len) = reader.recv(&mut buf) {
+                        if tx.send(buf[..len].to_vec()).is_err() {
+                            return;
+                        }
+                    }
+                });
+                Backend::Socket {
+                    socket,
+                    peer: peer.clone(),
+                    input: rx,
+                }
+            }
+            NicBackend::Pcap(path) => {
+                let mut file = File::create(path)?;
+                file.write_all(&pcap_header())?;
+                Backend::Pcap(file)
+            }
+            NicBackend::Loopback => Backend::Loopback,
+            NicBackend::Echo => Backend::Echo,
+        };
+        Ok(VirtioNet {
+            backend,
+            mac,
+            link_up: nic.link_up,
+            rx_frames: VecDeque::new(),
+        })
+    }
+
+    pub fn features(&self) -> u64 {
+        VIRTIO_NET_F_MAC | VIRTIO_NET_F_STATUS
+    }
+
+    // MAC address at offset 0, and status at offset 6
+    pub fn config(&self) -> Vec<u8> {
+        let mut config = self.mac.to_vec();
+        let status = if self.link_up {
+            VIRTIO_NET_S_LINK_UP
+        } else {
+            0
+        };
+        config.extend(status.to_le_bytes());
+        config
+    }
+
+    pub fn is_link_up(&self) -> bool {
+        self.link_up
+    }
+
+    pub fn set_link_up(&mut self, up: bool) {
+        self.link_up = up;
+        if !up {
+            self.rx_frames.clear();
+        }
+    }
+
+    // As when opened, but with the same backend
+    pub fn reset(&mut self, link_up: bool) {
+        self.rx_frames.clear();
+        self.link_up = link_up;
+    }
+
+    // Queues to process without a notification (frames were received)
+    pub fn pending_queues(&mut self) -> u32 {
+        if let Backend::Socket { input, .. } = &self.backend {
+            let frames: Vec<_> = input.try_iter().collect();
+            frames.into_iter().for_each(|frame| self.receive(frame));
+        }
+        if self.rx_frames.is_empty() {
+            0
+        } else {
+            1 << RX_QUEUE
+        }
+    }
+
+    fn receive(&mut self, frame: Vec<u8>) {
+        if self.link_up && self.rx_frames.len() < MAX_RX_FRAMES {
+            self.rx_frames.push_back(frame);
+        }
+    }
+
+    pub fn process(&mut self, index: usize, queue: &mut Virtqueue, dma: &mut Dma) -> Option<bool> {
+        let mut used = false;
+        match index {
+            // Deliver the received frames while there are buffers
+            RX_QUEUE => {
+                while !self.rx_frames.is_empty() {
+                    let Some(chain) = queue.pop(dma)? else {
+                        break;
+                    };
+                    let frame = self.rx_frames.pop_front().unwrap();
+                    let len = self.deliver(&chain, dma, &frame)?;
+                    queue.push(dma, chain.head, len)?;
+                    used = true;
+                }
+            }
+            TX_QUEUE => {
+                while let Some(chain) = queue.pop(dma)? {
+                    let out = chain.read_all(dma)?;
+                    if out.len() >= HEADER_SIZE && self.link_up {
+                        self.send(&out[HEADER_SIZE..]);
+                    }
+                    queue.push(dma, chain.head, 0)?;
+                    used = true;
+                }
+            }
+            _ => (),
+        }
+        Some(used)
+    }
+
+    // A frame too large for the buffers is dropped
+    fn deliver(&self, chain: &DescChain, dma: &mut Dma, frame: &[u8]) -> Option<u32> {
+        if HEADER_SIZE + frame.len() > chain.writable_len() {
+            return Some(0);
+        }
+        let mut packet = vec![0; HEADER_SIZE];
+        packet[10..12].copy_from_slice(&1u16.to_le_bytes()); // num_buffers
+        packet.extend(frame);
+        chain.write_all(dma, &packet)?;
+        Some(packet.len() as u32)
+    }
+
+    // Errors of the backend are like frames lost on the wire
+    fn send(&mut self, frame: &[u8]) {
+        match &mut self.backend {
+            Backend::Socket { socket, peer, .. } => {
+                let _ = socket.send_to(frame, &*peer);
+            }
+            Backend::Pcap(file) => {
+                let _ = file.write_all(&pcap_record(frame));
+            }
+            Backend::Loopback => self.receive(frame.to_vec()),
+            Backend::Echo => {
+                if let Some(reply) = echo_reply(frame) {
+                    self.receive(reply);
+                }
+            }
+        }
+    }
+}
+
+// Little-endian pcap file with microsecond timestamps, capturing Ethernet frames
+fn pcap_header() -> Vec<u8> {
+    let mut header = vec![];
+    header.extend(0xa1b2_c3d4u32.to_le_bytes()); // Magic number
+    header.extend(2u16.to_le_bytes()); // Version 2.4
+    header.extend(4u16.to_le_bytes());
+    header.extend(0i32.to_le_bytes()); // UTC
+    header.extend(0u32.to_le_bytes()); // Timestamp accuracy
+    header.extend((MAX_FRAME_SIZE as u32).to_le_bytes()); // Snapshot length
+    header.extend(1u32.to_le_bytes()); // Ethernet
+    header
+}
+
+fn pcap_record(frame: &[u8]) -> Vec<u8> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut record = vec![];
+    record.extend((now.as_secs() as u32).to_le_bytes());
+    record.extend(now.subsec_micros().to_le_bytes());
+    record.extend((frame.len() as u32).to_le_bytes());
+    record.extend((frame.len() as u32).to_le_bytes());
+    record.extend(frame);
+    record
+}
+
+// Answer as a host with ECHO_MAC at every IPv4 address (but the sender's)
+fn echo_reply(frame: &[u8]) -> Option<Vec<u8>> {
+    let ethertype = u16::from_be_bytes(frame.get(12..14)?.try_into().unwrap());
+    let mut reply = frame.to_vec();
+    reply[0..6].copy_from_slice(&frame[6..12]);
+    reply[6..12].copy_from_slice(&ECHO_MAC);
+    match ethertype {
+        ETHERTYPE_ARP => {
+            // Ethernet and IPv4 addresses only
+            let arp = frame.get(14..42)?;
+            let oper = u16::from_be_bytes([arp[6], arp[7]]);
+            if arp[0..6] != [0, 1, 8, 0, 6, 4] || oper != ARP_REQUEST || arp[14..18] == arp[24..28]
+            {
+                return None;
+            }
+            let arp_reply = &mut reply[14..42];
+            arp_reply[6..8].copy_from_slice(&ARP_REPLY.to_be_bytes());
+            arp_reply[8..14].copy_from_slice(&ECHO_MAC);
+            arp_reply[14..18].copy_from_slice(&arp[24..28]);
+            arp_reply[18..24].copy_from_slice(&arp[8..14]);
+            arp_reply[24..28].copy_from_slice(&arp[14..18]);
+            Some(reply)
+        }
+        ETHERTYPE_IPV4 => {
+            let ihl = 4 * (*frame.get(14)? & 0xf) as usize;
+            let total_len = u16::from_be_bytes(frame.get(16..18)?.try_into().unwrap()) as usize;
+            let icmp_start = 14 + ihl;
+            if ihl < 20 || total_len < ihl + 8 || frame.len() < 14 + total_len {
+                return None;
+            }
+            if frame[23] != IP_PROTO_ICMP || frame[icmp_start] != ICMP_ECHO_REQUEST {
+                return None;
+            }
+            reply.truncate(14 + total_len);
+            // Swap the addresses, with a new TTL and checksums
+            reply[26..30].copy_from_slice(&frame[30..34]);
+            reply[30..34].copy_from_slice(&frame[26..30]);
+            reply[22] = 64;
+            reply[24..26].fill(0);
+            let ip_sum = checksum(&reply[14..icmp_start]);
+            reply[24..26].copy_from_slice(&ip_sum.to_be_bytes());
+            reply[icmp_start] = ICMP_ECHO_REPLY;
+            reply[icmp_start + 2..icmp_start + 4].fill(0);
+            let icmp_sum = checksum(&reply[icmp_start..]);
+            reply[icmp_start + 2..icmp_start + 4].copy_from_slice(&icmp_sum.to_be_bytes());
+            Some(reply)
+        }
+        _ => None,
+    }
+}
+
+// Internet checksum (one's complement of the one's complement sum)
+fn checksum(bytes: &[u8]) -> u16 {
+    let mut sum: u32 = bytes
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+impl VirtioNet {
+    // The frames in flight are not part of the snapshot
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_bool(self.link_up);
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        self.set_link_up(r.get_bool()?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sys::mem_map::{virtio::*, AccessAttr, AccessType, AccessWidth, MemMap};
+
+    const ATTR: AccessAttr = AccessAttr {
+        atype: AccessType::Store,
+        width: AccessWidth::Word,
+        lrsc: false,
+        amo: false,
+    };
+
+    const GUEST_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 1];
+
+    fn make_net(backend: NicBackend) -> VirtioNet {
+        let nic = Nic {
+            backend,
+            mac: None,
+            link_up: true,
+        };
+        VirtioNet::open(&nic, GUEST_MAC).unwrap()
+    }
+
+    fn write_ram(mem: &mut MemMap, addr: usize, bytes: &[u8]) {
+        mem.ram.as_u8_mut()[addr..addr + bytes.len()].copy_from_slice(bytes);
+    }
+
+    // Queue q with 4 entries at 0x1000 * (q + 1): descriptors, then driver and device areas
+    fn setup(mem: &mut MemMap) {
+        let base = mem.virtio_base;
+        mem.write_u32(base + 0x070, 1 | 2 | 8, ATTR).unwrap();
+        for q in 0..2 {
+            let area = 0x1000 * (q + 1);
+            for (reg, val) in [
+                (0x030, q),
+                (0x038, 4),
+                (0x080, area),
+                (0x090, area + 0x100),
+                (0x0a0, area + 0x200),
+                (0x044, 1),
+            ] {
+                mem.write_u32(base + reg, val, ATTR).unwrap();
+            }
+        }
+        mem.write_u32(base + 0x070, 1 | 2 | 8 | 4, ATTR).unwrap();
+    }
+
+    // Make a buffer of the given length available in queue q, at 0x4000 + 0x1000 * q
+    fn add_buffer(mem: &mut MemMap, q: usize, n: u16, len: u32, bytes: &[u8]) {
+        let (area, buf) = (0x1000 * (q + 1), 0x4000 + 0x1000 * q);
+        let flags: u16 = if q == RX_QUEUE { 2 } else { 0 };
+        let mut desc = vec![];
+        desc.extend((buf as u64).to_le_bytes());
+        desc.extend(len.to_le_bytes());
+        desc.extend(flags.to_le_bytes());
+        desc.extend(0u16.to_le_bytes());
+        write_ram(mem, area, &desc);
+        write_ram(
+            mem,
+            area + 0x104 + 2 * (n as usize % 4),
+            &0u16.to_le_bytes(),
+        );
+        write_ram(mem, area + 0x102, &(n + 1).to_le_bytes());
+        write_ram(mem, buf, bytes);
+    }
+
+    fn used_idx(mem: &MemMap, q: usize) -> u8 {
+        mem.ram.as_u8()[0x1000 * (q + 1) + 0x202]
+    }
+
+    fn ping_request() -> Vec<u8> {
+        let mut frame = vec![];
+        frame.extend(ECHO_MAC);
+        frame.extend(GUEST_MAC);
+        frame.extend(ETHERTYPE_IPV4.to_be_bytes());
+        // IPv4 header from 10.0.2.15 to 10.0.2.2
+        let mut ip = vec![0x45, 0, 0, 36, 0, 1, 0, 0, 64, IP_PROTO_ICMP, 0, 0];
+        ip.extend([10, 0, 2, 15, 10, 0, 2, 2]);
+        let sum = checksum(&ip);
+        ip[10..12].copy_from_slice(&sum.to_be_bytes());
+        frame.extend(ip);
+        let mut icmp = vec![ICMP_ECHO_REQUEST, 0, 0, 0, 0x12, 0x34, 0, 1];
+        icmp.extend(b"pingpong");
+        let sum = checksum(&icmp);
+        icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+        frame.extend(icmp);
+        frame
+    }
+
+    #[test]
+    fn test_checksum() {
+        // Example of RFC 1071
+        assert_eq!(
+            checksum(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]),
+            !0xddf2
+        );
+        assert_eq!(checksum(&[0xff]), 0x00ff);
+    }
+
+    #[test]
+    fn test_echo() {
+        // Ping
+        let request = ping_request();
+        let reply = echo_reply(&request).unwrap();
+        assert_eq!(reply[0..6], GUEST_MAC);
+        assert_eq!(reply[6..12], ECHO_MAC);
+        assert_eq!(reply[26..34], [10, 0, 2, 2, 10, 0, 2, 15]);
+        assert_eq!(reply[34], ICMP_ECHO_REPLY);
+        assert_eq!(checksum(&reply[14..34]), 0);
+        assert_eq!(checksum(&reply[34..]), 0);
+        assert_eq!(reply[42..], *b"pingpong");
+
+        // ARP request for 10.0.2.2
+        let mut request = vec![0xff; 6];
+        request.extend(GUEST_MAC);
+        request.extend(ETHERTYPE_ARP.to_be_bytes());
+        request.extend([0, 1, 8, 0, 6, 4, 0, 1]);
+        request.extend(GUEST_MAC);
+        request.extend([10, 0, 2, 15, 0, 0, 0, 0, 0, 0, 10, 0, 2, 2]);
+        let reply = echo_reply(&request).unwrap();
+        assert_eq!(reply[20..22], ARP_REPLY.to_be_bytes());
+        assert_eq!(reply[22..28], ECHO_MAC);
+        assert_eq!(reply[28..32], [10, 0, 2, 2]);
+        assert_eq!(reply[32..38], GUEST_MAC);
+        assert_eq!(reply[38..42], [10, 0, 2, 15]);
+
+        // Gratuitous ARP, and other protocols
+        request[38..42].copy_from_slice(&[10, 0, 2, 15]);
+        assert_eq!(echo_reply(&request), None);
+        request[12..14].copy_from_slice(&0x86ddu16.to_be_bytes());
+        assert_eq!(echo_reply(&request), None);
+    }
+
+    #[test]
+    fn test_loopback_and_link() {
+        let mut mem = MemMap::new(0x10000);
+        let net = make_net(NicBackend::Loopback);
+        mem.virtio[0] = VirtioMmio::new(Some(VirtioDevice::Net(net)));
+        setup(&mut mem);
+        assert_eq!(mem.read_u32(mem.virtio_base + 0x008, ATTR).unwrap(), 1);
+        assert_eq!(
+            mem.read_u32(mem.virtio_base + 0x100, ATTR).unwrap(),
+            0x0000_0002
+        );
+        let attr = AccessAttr {
+            width: AccessWidth::HalfWord,
+            ..ATTR
+        };
+        assert_eq!(mem.read_u16(mem.virtio_base + 0x106, attr).unwrap(), 1);
+
+        // Send a frame, received back once there is a buffer
+        let mut packet = vec![0; HEADER_SIZE];
+        packet.extend(b"frame");
+        add_buffer(&mut mem, TX_QUEUE, 0, packet.len() as u32, &packet);
+        mem.write_u32(mem.virtio_base + 0x050, TX_QUEUE as u32, ATTR)
+            .unwrap();
+        mem.poll_virtio();
+        assert_eq!(used_idx(&mem, TX_QUEUE), 1);
+        add_buffer(&mut mem, RX_QUEUE, 0, 64, &[]);
+        mem.poll_virtio();
+        assert_eq!(used_idx(&mem, RX_QUEUE), 1);
+        assert_eq!(mem.ram.as_u8()[0x4000 + 10], 1);
+        assert_eq!(&mem.ram.as_u8()[0x400c..0x4011], b"frame");
+        assert!(mem.virtio[0].is_interrupt_set());
+        mem.write_u32(mem.virtio_base + 0x064, 1, ATTR).unwrap();
+
+        // Frames are dropped while the link is down
+        mem.virtio[0].set_link_up(false);
+        assert_eq!(mem.read_u32(mem.virtio_base + 0x060, ATTR).unwrap(), 2);
+        assert_eq!(mem.read_u16(mem.virtio_base + 0x106, attr).unwrap(), 0);
+        add_buffer(&mut mem, TX_QUEUE, 1, packet.len() as u32, &packet);
+        mem.write_u32(mem.virtio_base + 0x050, TX_QUEUE as u32, ATTR)
+            .unwrap();
+        add_buffer(&mut mem, RX_QUEUE, 1, 64, &[]);
+        mem.poll_virtio();
+        assert_eq!(used_idx(&mem, TX_QUEUE), 2);
+        assert_eq!(used_idx(&mem, RX_QUEUE), 1);
+
+        // A buffer past the end of RAM breaks the queue
+        add_buffer(&mut mem, TX_QUEUE, 2, 0xffff_0000, &[]);
+        mem.write_u32(mem.virtio_base + 0x050, TX_QUEUE as u32, ATTR)
+            .unwrap();
+        mem.poll_virtio();
+        assert_eq!(used_idx(&mem, TX_QUEUE), 2);
+        assert_eq!(
+            mem.read_u32(mem.virtio_base + 0x070, ATTR).unwrap() & 0x40,
+            0x40
+        );
+    }
+
+    #[test]
+    fn test_socket_and_pcap() {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let (path_a, path_b) = (
+            dir.join(format!("riscv_sim_net_a_{id}")),
+            dir.join(format!("riscv_sim_net_b_{id}")),
+        );
+        let socket = |path: &PathBuf, peer: &PathBuf| {
+            make_net(NicBackend::Socket {
+                path: path.clone(),
+                peer: peer.clone(),
+            })
+        };
+        let mut a = socket(&path_a, &path_b);
+        let mut b = socket(&path_b, &path_a);
+        a.send(b"hello");
+        let start = std::time::Instant::now();
+        while b.pending_queues() == 0 && start.elapsed().as_secs() < 5 {
+            thread::yield_now();
+        }
+        assert_eq!(b.rx_frames.pop_front().unwrap(), b"hello");
+        fs::remove_file(&path_a).unwrap();
+        fs::remove_file(&path_b).unwrap();
+
+        let path = dir.join(format!("riscv_sim_net_{id}.pcap"));
+        let mut net = make_net(NicBackend::Pcap(path.clone()));
+        net.send(b"frame");
+        assert_eq!(net.pending_queues(), 0);
+        let pcap = fs::read(&path).unwrap();
+        assert_eq!(pcap.len(), 24 + 16 + 5);
+        assert_eq!(pcap[0..4], 0xa1b2_c3d4u32.to_le_bytes());
+        assert_eq!(pcap[32..36], 5u32.to_le_bytes());
+        assert_eq!(pcap[40..], *b"frame");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,16 +1,24 @@
-use crate::instr::reg::Reg;
+use crate::{
+    instr::reg::Reg,
+    snapshot::{invalid_data, SnapshotReader, SnapshotWriter},
+};
+use std::io;
 
 #[derive(Debug)]
 pub struct State {
     pub pc: u32,
     pub regs: [i32; 32],
+    pub fregs: [u64; 32],
+    pub vregs: Vec<u8>, // 32 vector registers of VLEN bits
 }
 
 impl State {
-    pub fn new() -> State {
+    pub fn new(vlen: u32) -> State {
         State {
             pc: 0,
             regs: [0; 32],
+            fregs: [0; 32],
+            vregs: vec![0; 32 * (vlen as usize / 8)],
         }
     }
 
@@ -26,6 +34,43 @@ impl State {
         &mut self.regs[r.index() as usize]
     }
 
+    pub fn freg(&self, r: &Reg) -> u64 {
+        self.fregs[r.index() as usize]
+    }
+
+    pub fn freg_mut(&mut self, r: &Reg) -> &mut u64 {
+        &mut self.fregs[r.index() as usize]
+    }
+
+    // VLEN in bytes
+    pub fn vlenb(&self) -> usize {
+        self.vregs.len() / 32
+    }
+
+    // Element i of the register group starting at vreg (width in bytes)
+    pub fn velem(&self, vreg: u8, i: usize, width: usize) -> u64 {
+        let start = vreg as usize * self.vlenb() + i * width;
+        let mut buf = [0; 8];
+        buf[..width].copy_from_slice(&self.vregs[start..start + width]);
+        u64::from_le_bytes(buf)
+    }
+
+    pub fn set_velem(&mut self, vreg: u8, i: usize, width: usize, val: u64) {
+        let start = vreg as usize * self.vlenb() + i * width;
+        self.vregs[start..start + width].copy_from_slice(&val.to_le_bytes()[..width]);
+    }
+
+    // Bit i of a mask register
+    pub fn vmask(&self, vreg: u8, i: usize) -> bool {
+        self.vregs[vreg as usize * self.vlenb() + i / 8] & (1 << (i % 8)) != 0
+    }
+
+    pub fn set_vmask(&mut self, vreg: u8, i: usize, val: bool) {
+        let start = vreg as usize * self.vlenb();
+        let byte = &mut self.vregs[start + i / 8];
+        *byte = *byte & !(1 << (i % 8)) | (val as u8) << (i % 8);
+    }
+
     pub fn pc(&self) -> u32 {
         self.pc
     }
@@ -35,6 +80,31 @@ impl State {
     }
 }
 
+impl State {
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        w.put_u32(self.pc);
+        self.regs.iter().for_each(|r| w.put_u32(*r as u32));
+        self.fregs.iter().for_each(|r| w.put_u64(*r));
+        w.put_bytes(&self.vregs);
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        self.pc = r.get_u32()?;
+        for reg in self.regs.iter_mut() {
+            *reg = r.get_u32()? as i32;
+        }
+        for freg in self.fregs.iter_mut() {
+            *freg = r.get_u64()?;
+        }
+        let vregs = r.get_bytes()?;
+        if vregs.len() != self.vregs.len() {
+            return Err(invalid_data("VLEN differs from the snapshot"));
+        }
+        self.vregs = vregs;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -42,7 +112,7 @@ mod tests {
 
     #[test]
     fn test_reg_zero() {
-        let mut state = State::new();
+        let mut state = State::new(128);
         assert_eq!(state.reg(&Reg::zero()), 0);
 
         *state.reg_mut(&Reg::zero()) = rand::random();
@@ -51,7 +121,7 @@ mod tests {
 
     #[test]
     fn test_reg_write() {
-        let mut state = State::new();
+        let mut state = State::new(128);
         let data: [i32; 32] = rand::random();
         for i in 1..32 {
             *state.reg_mut(&Reg::new(i)) = data[i as usize];
@@ -61,9 +131,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_freg_write() {
+        // Unlike x0, f0 is a normal register
+        let mut state = State::new(128);
+        let data: [u64; 32] = rand::random();
+        for i in 0..32 {
+            *state.freg_mut(&Reg::new(i)) = data[i as usize];
+        }
+        for i in 0..32 {
+            assert_eq!(state.freg(&Reg::new(i)), data[i as usize]);
+        }
+    }
+
+    #[test]
+    fn test_vreg_elements() {
+        // Register groups are contiguous, and elements are little-endian
+        let mut state = State::new(64);
+        state.set_velem(2, 3, 4, 0x1234_5678);
+        assert_eq!(state.velem(3, 1, 4), 0x1234_5678);
+        assert_eq!(state.velem(3, 2, 2), 0x5678);
+        assert!(state.vmask(3, 35) && !state.vmask(3, 32));
+
+        state.set_vmask(0, 9, true);
+        assert_eq!(state.velem(0, 0, 4), 0x200);
+        state.set_vmask(0, 9, false);
+        assert_eq!(state.velem(0, 0, 4), 0);
+    }
+
     #[test]
     fn test_pc_write() {
-        let mut state = State::new();
+        let mut state = State::new(128);
         let data: u32 = rand::random();
 
         *state.pc_mut() = data;
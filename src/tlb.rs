@@ -0,0 +1,209 @@
+use crate::snapshot::{SnapshotReader, SnapshotWriter};
+use std::io;
+
+// Number of entries for 4 KiB pages and 4 MiB superpages, indexed by the low bits of the VPN
+const TLB_ENTRIES: usize = 64;
+const TLB_SUPER_ENTRIES: usize = 16;
+
+// Translation lookaside buffer for Sv32 (direct-mapped).
+// Only valid leaf PTEs are cached, and permissions are checked again on every hit.
+// Entries stay until flushed by sfence.vma, even if the page table is modified.
+#[derive(Debug)]
+pub struct Tlb {
+    pages: [Option<TlbEntry>; TLB_ENTRIES],
+    superpages: [Option<TlbEntry>; TLB_SUPER_ENTRIES],
+    pub hits: u64,
+    pub misses: u64,
+    pub flushes: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TlbEntry {
+    pub vpn: u32, // VPN[1:0] for pages, VPN[1] for superpages
+    pub asid: u32,
+    pub global: bool,
+    pub pte: u32,
+}
+
+impl Default for Tlb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tlb {
+    pub fn new() -> Tlb {
+        Tlb {
+            pages: [None; TLB_ENTRIES],
+            superpages: [None; TLB_SUPER_ENTRIES],
+            hits: 0,
+            misses: 0,
+            flushes: 0,
+        }
+    }
+
+    // Find the entry translating addr, and whether it's a superpage
+    pub fn lookup(&mut self, addr: u32, asid: u32) -> Option<(TlbEntry, bool)> {
+        let (vpn, super_vpn) = (addr >> 12, addr >> 22);
+        let res = match (
+            self.superpages[super_vpn as usize % TLB_SUPER_ENTRIES],
+            self.pages[vpn as usize % TLB_ENTRIES],
+        ) {
+            (Some(e), _) if e.vpn == super_vpn && e.matches_asid(asid) => Some((e, true)),
+            (_, Some(e)) if e.vpn == vpn && e.matches_asid(asid) => Some((e, false)),
+            _ => None,
+        };
+        if res.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        res
+    }
+
+    pub fn insert(&mut self, addr: u32, asid: u32, global: bool, pte: u32, superpage: bool) {
+        let (vpn, slot) = if superpage {
+            let vpn = addr >> 22;
+            (vpn, &mut self.superpages[vpn as usize % TLB_SUPER_ENTRIES])
+        } else {
+            let vpn = addr >> 12;
+            (vpn, &mut self.pages[vpn as usize % TLB_ENTRIES])
+        };
+        *slot = Some(TlbEntry {
+            vpn,
+            asid,
+            global,
+            pte,
+        });
+    }
+
+    // sfence.vma: flush the entries of an address (if any) and an ASID (if any).
+    // Global entries are only flushed with all ASIDs.
+    pub fn flush(&mut self, addr: Option<u32>, asid: Option<u32>) {
+        self.flushes += 1;
+        let matches = |e: &TlbEntry, superpage: bool| {
+            let addr_match = match addr {
+                Some(addr) if superpage => e.vpn == addr >> 22,
+                Some(addr) => e.vpn == addr >> 12,
+                None => true,
+            };
+            let asid_match = match asid {
+                Some(asid) => !e.global && e.asid == asid,
+                None => true,
+            };
+            addr_match && asid_match
+        };
+        for (entries, superpage) in [
+            (&mut self.pages[..], false),
+            (&mut self.superpages[..], true),
+        ] {
+            for entry in entries.iter_mut() {
+                if entry.is_some_and(|e| matches(&e, superpage)) {
+                    *entry = None;
+                }
+            }
+        }
+    }
+
+    pub fn flush_all(&mut self) {
+        self.flush(None, None);
+    }
+
+    // Statistics are not part of the snapshot
+    pub fn save(&self, w: &mut SnapshotWriter) {
+        for entry in self.pages.iter().chain(self.superpages.iter()) {
+            w.put_bool(entry.is_some());
+            let e = entry.unwrap_or(TlbEntry {
+                vpn: 0,
+                asid: 0,
+                global: false,
+                pte: 0,
+            });
+            w.put_u32(e.vpn);
+            w.put_u32(e.asid);
+            w.put_bool(e.global);
+            w.put_u32(e.pte);
+        }
+    }
+
+    pub fn restore(&mut self, r: &mut SnapshotReader) -> io::Result<()> {
+        for entry in self.pages.iter_mut().chain(self.superpages.iter_mut()) {
+            let valid = r.get_bool()?;
+            let e = TlbEntry {
+                vpn: r.get_u32()?,
+                asid: r.get_u32()?,
+                global: r.get_bool()?,
+                pte: r.get_u32()?,
+            };
+            *entry = valid.then_some(e);
+        }
+        Ok(())
+    }
+}
+
+impl TlbEntry {
+    fn matches_asid(&self, asid: u32) -> bool {
+        self.global || self.asid == asid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let mut tlb = Tlb::new();
+        tlb.insert(0x1234_5000, 1, false, 0x11, false);
+        tlb.insert(0x4000_0000, 1, true, 0x22, true);
+
+        assert_eq!(tlb.lookup(0x1234_5ffc, 1).unwrap().0.pte, 0x11);
+        assert!(tlb.lookup(0x1234_5000, 2).is_none());
+        assert!(tlb.lookup(0x1234_6000, 1).is_none());
+        // Global superpage
+        assert_eq!(
+            tlb.lookup(0x403f_f000, 2),
+            Some((tlb.superpages[0].unwrap(), true))
+        );
+        assert_eq!((tlb.hits, tlb.misses), (2, 2));
+
+        // Same index, different VPN
+        tlb.insert(0x1238_5000, 1, false, 0x33, false);
+        assert!(tlb.lookup(0x1234_5000, 1).is_none());
+    }
+
+    #[test]
+    fn test_flush() {
+        let mut tlb = Tlb::new();
+        let fill = |tlb: &mut Tlb| {
+            tlb.insert(0x1000, 1, false, 0x11, false);
+            tlb.insert(0x2000, 2, false, 0x22, false);
+            tlb.insert(0x3000, 1, true, 0x33, false);
+        };
+
+        // By address
+        fill(&mut tlb);
+        tlb.flush(Some(0x1ffc), None);
+        assert!(tlb.lookup(0x1000, 1).is_none());
+        assert!(tlb.lookup(0x2000, 2).is_some());
+
+        // By ASID (except global entries)
+        fill(&mut tlb);
+        tlb.flush(None, Some(1));
+        assert!(tlb.lookup(0x1000, 1).is_none());
+        assert!(tlb.lookup(0x2000, 2).is_some());
+        assert!(tlb.lookup(0x3000, 1).is_some());
+
+        // By both
+        fill(&mut tlb);
+        tlb.flush(Some(0x2000), Some(1));
+        assert!(tlb.lookup(0x2000, 2).is_some());
+        tlb.flush(Some(0x2000), Some(2));
+        assert!(tlb.lookup(0x2000, 2).is_none());
+
+        fill(&mut tlb);
+        tlb.flush_all();
+        assert!(tlb.lookup(0x3000, 1).is_none());
+        assert_eq!(tlb.flushes, 5);
+    }
+}
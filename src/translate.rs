@@ -1,10 +1,10 @@
 use crate::{
     sys::{
         control::{Control, MPriv, SatpMode},
-        mem_map::{page_fault, AccessAttr, AccessType, AccessWidth},
+        mem_map::{access_fault, page_fault, AccessAttr, AccessType, AccessWidth},
         System,
     },
-    Result64E,
+    Result64E, ResultE,
 };
 
 const MASK_OFFSET: u32 = 0xfff;
@@ -14,6 +14,7 @@ pub fn translate(sys: &mut System, addr: u32, access_type: AccessType) -> Result
     let Control {
         satp_mode,
         satp_ppn,
+        satp_asid,
         ..
     } = sys.ctrl;
 
@@ -25,6 +26,15 @@ pub fn translate(sys: &mut System, addr: u32, access_type: AccessType) -> Result
         return Ok(addr as u64);
     }
 
+    // Cached translation (setting the A/D bits still needs a page table walk)
+    if let Some((entry, superpage)) = sys.ctrl.tlb.lookup(addr, satp_asid) {
+        let pte = PageTableEntry::from(entry.pte).ok_or(page_fault(access_type))?;
+        if pte.access && (access_type != AccessType::Store || pte.dirty) {
+            check_page(sys, &pte, access_type, superpage)?;
+            return Ok(physical_address(&pte, addr, superpage));
+        }
+    }
+
     // Components
     let vpn = [(addr >> 12) & MASK_VPN, (addr >> 22) & MASK_VPN];
     let mut ppn = satp_ppn;
@@ -32,6 +42,7 @@ pub fn translate(sys: &mut System, addr: u32, access_type: AccessType) -> Result
     // Two-level translation
     for level in [1, 0] {
         let pte_addr = ((ppn as u64) << 12) | (vpn[level] << 2) as u64;
+        check_pte_pmp(sys, pte_addr, AccessType::Load, access_type)?;
         let pte = PageTableEntry::from(sys.mem.read_u32(pte_addr, pte_access_attr(access_type))?)
             .ok_or(page_fault(access_type))?;
 
@@ -48,6 +59,40 @@ pub fn translate(sys: &mut System, addr: u32, access_type: AccessType) -> Result
     Err(page_fault(access_type))
 }
 
+// Translation for the debugger: the page table is walked without the TLB and the
+// A/D bits are left as they are (the guest sets them when it makes the access)
+pub fn translate_debug(sys: &System, addr: u32, access_type: AccessType) -> Result64E {
+    let Control {
+        satp_mode,
+        satp_ppn,
+        ..
+    } = sys.ctrl;
+
+    if effective_privilege(sys, access_type) == MPriv::M || satp_mode == SatpMode::Bare {
+        return Ok(addr as u64);
+    }
+
+    let vpn = [(addr >> 12) & MASK_VPN, (addr >> 22) & MASK_VPN];
+    let mut ppn = satp_ppn;
+    for level in [1, 0] {
+        let pte_addr = ((ppn as u64) << 12) | (vpn[level] << 2) as u64;
+        check_pte_pmp(sys, pte_addr, AccessType::Load, access_type)?;
+        let code = sys.mem.peek_u32(pte_addr);
+        let pte = PageTableEntry::from(code.ok_or(access_fault(access_type))?)
+            .ok_or(page_fault(access_type))?;
+
+        if !pte.valid {
+            return Err(page_fault(access_type));
+        } else if pte.perm != Permission::NonLeaf {
+            check_page(sys, &pte, access_type, level != 0)?;
+            return Ok(physical_address(&pte, addr, level != 0));
+        }
+
+        ppn = pte.ppn;
+    }
+    Err(page_fault(access_type))
+}
+
 pub fn process_page(
     sys: &mut System,
     mut pte: PageTableEntry,
@@ -56,6 +101,34 @@ pub fn process_page(
     access_type: AccessType,
     superpage: bool,
 ) -> Result64E {
+    check_page(sys, &pte, access_type, superpage)?;
+
+    // Modify A/D bits
+    if !pte.access || (access_type == AccessType::Store && !pte.dirty) {
+        pte.access = true;
+        if access_type == AccessType::Store {
+            pte.dirty = true;
+        }
+        check_pte_pmp(sys, pte_addr, AccessType::Store, access_type)?;
+        sys.mem
+            .write_u32(pte_addr, pte.to_int(), pte_access_attr(access_type))?;
+    }
+
+    // Translation is successful
+    let asid = sys.ctrl.satp_asid;
+    sys.ctrl
+        .tlb
+        .insert(addr, asid, pte.global, pte.to_int(), superpage);
+    Ok(physical_address(&pte, addr, superpage))
+}
+
+// Check the permissions of a leaf PTE
+fn check_page(
+    sys: &System,
+    pte: &PageTableEntry,
+    access_type: AccessType,
+    superpage: bool,
+) -> ResultE {
     let Control { sum, mxr, .. } = sys.ctrl;
 
     // Check permission
@@ -95,24 +168,30 @@ pub fn process_page(
     if superpage && (pte.ppn & MASK_VPN) != 0 {
         return Err(page_fault(access_type));
     }
+    Ok(())
+}
 
-    // Modify A/D bits
-    if !pte.access || (access_type == AccessType::Store && !pte.dirty) {
-        pte.access = true;
-        if access_type == AccessType::Store {
-            pte.dirty = true;
-        }
-        sys.mem
-            .write_u32(pte_addr, pte.to_int(), pte_access_attr(access_type))?;
-    }
-
-    // Translation is successful
+fn physical_address(pte: &PageTableEntry, addr: u32, superpage: bool) -> u64 {
     if superpage {
         let offset = addr & (MASK_OFFSET | (MASK_VPN << 12));
-        Ok(((pte.ppn as u64) >> 10 << 22) | offset as u64)
+        ((pte.ppn as u64) >> 10 << 22) | offset as u64
     } else {
         let offset = addr & MASK_OFFSET;
-        Ok(((pte.ppn as u64) << 12) | offset as u64)
+        ((pte.ppn as u64) << 12) | offset as u64
+    }
+}
+
+// Page table accesses are made in S-mode, and fault like the original access
+fn check_pte_pmp(
+    sys: &System,
+    pte_addr: u64,
+    pte_access: AccessType,
+    access_type: AccessType,
+) -> ResultE {
+    if sys.ctrl.pmp.check(pte_addr, 4, pte_access, MPriv::S) {
+        Ok(())
+    } else {
+        Err(access_fault(access_type))
     }
 }
 
@@ -125,7 +204,7 @@ fn pte_access_attr(atype: AccessType) -> AccessAttr {
     }
 }
 
-fn effective_privilege(sys: &System, access_type: AccessType) -> MPriv {
+pub fn effective_privilege(sys: &System, access_type: AccessType) -> MPriv {
     let Control {
         privilege,
         mpp,
@@ -301,6 +380,24 @@ mod tests {
             dtb: None,
             kernel: None,
             verbose: true,
+            pmp_entries: 16,
+            bitmanip: true,
+            vlen: 128,
+            pause_skip: 0,
+            gdb: None,
+            snapshot: None,
+            snapshot_at: None,
+            restore: None,
+            harts: 1,
+            quantum: 100,
+            sched_seed: None,
+            sbi: false,
+            user: false,
+            args: vec![],
+            semihosting: false,
+            tohost: None,
+            drive: vec![],
+            nic: vec![],
         });
 
         // Enable paging
@@ -503,4 +600,82 @@ mod tests {
         // MPRV does not apply to instruction fetch
         assert_eq!(translate(&mut sys, U_PAGE_VA | 0x515, AccessType::Instr).unwrap(), U_PAGE_VA as u64 | 0x515);
     }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_translate_pmp() {
+        let mut sys = make_sys();
+        sys.ctrl.privilege = MPriv::S;
+
+        // Only the data pages are accessible, so page table walks fault
+        sys.ctrl.pmp.write_addr(0, (PT_ROOT_PA >> 2) as u32);
+        sys.ctrl.pmp.write_cfg(0, 0x0f); // TOR, RWX
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0x832, AccessType::Load), Err(LoadAccessFault));
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0xce3, AccessType::Store), Err(StoreAccessFault));
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0x515, AccessType::Instr), Err(InstrAccessFault));
+
+        // Read-only page tables cannot have their A/D bits updated
+        sys.ctrl.pmp.write_addr(1, ((RAM_BASE + 0x10_000) >> 2) as u32);
+        sys.ctrl.pmp.write_cfg(0, 0x09_0f); // TOR, R
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0x832, AccessType::Load), Err(LoadAccessFault));
+
+        sys.ctrl.pmp.write_cfg(0, 0x0b_0f); // TOR, RW
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0x832, AccessType::Load).unwrap(), S_PAGE_PA | 0x832);
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_translate_tlb() {
+        let mut sys = make_sys();
+        let attr = pte_access_attr(AccessType::Load);
+        sys.ctrl.privilege = MPriv::S;
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0x832, AccessType::Load).unwrap(), S_PAGE_PA | 0x832);
+        // Setting D needs a walk, the entry is updated
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0xce3, AccessType::Store).unwrap(), S_PAGE_PA | 0xce3);
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0xce3, AccessType::Store).unwrap(), S_PAGE_PA | 0xce3);
+        assert_eq!((sys.ctrl.tlb.hits, sys.ctrl.tlb.misses), (2, 1));
+
+        // Remap the S page without sfence.vma, the old translation is still used
+        let pte_addr = PT_SUB_S_PA | (S_VPN0 << 2) as u64;
+        let pte = sys.mem.read_u32(pte_addr, attr).unwrap();
+        sys.mem.write_u32(pte_addr, (pte & 0x3ff) | (U_PAGE_PA >> 12 << 10) as u32, attr).unwrap();
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0x832, AccessType::Load).unwrap(), S_PAGE_PA | 0x832);
+        sys.ctrl.tlb.flush(Some(U_PAGE_VA), None);
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0x832, AccessType::Load).unwrap(), S_PAGE_PA | 0x832);
+
+        // Only flushed for the right ASID
+        sys.ctrl.tlb.flush(Some(S_PAGE_VA), Some(1));
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0x832, AccessType::Load).unwrap(), S_PAGE_PA | 0x832);
+        sys.ctrl.tlb.flush(Some(S_PAGE_VA), Some(0));
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0x832, AccessType::Load).unwrap(), U_PAGE_PA | 0x832);
+
+        // Other address spaces don't see the entries
+        sys.mem.write_u32(pte_addr, pte, attr).unwrap();
+        sys.ctrl.satp_asid = 1;
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0x832, AccessType::Load).unwrap(), S_PAGE_PA | 0x832);
+
+        // Permissions are checked on hits
+        sys.ctrl.privilege = MPriv::U;
+        assert_eq!(translate(&mut sys, S_PAGE_VA | 0x832, AccessType::Load), Err(LoadPageFault));
+    }
+    #[test]
+    #[rustfmt::skip]
+    fn test_translate_debug() {
+        let mut sys = make_sys();
+        let pte_addr = PT_SUB_S_PA | (S_VPN0 << 2) as u64;
+        let pte = sys.mem.peek_u32(pte_addr).unwrap();
+
+        sys.ctrl.privilege = MPriv::S;
+        assert_eq!(translate_debug(&sys, S_PAGE_VA | 0x832, AccessType::Load).unwrap(), S_PAGE_PA | 0x832);
+        assert_eq!(translate_debug(&sys, S_PAGE_VA | 0xce3, AccessType::Store).unwrap(), S_PAGE_PA | 0xce3);
+        assert_eq!(translate_debug(&sys, U_PAGE_VA, AccessType::Load), Err(LoadPageFault));
+
+        // No A/D bits, no TLB entries
+        assert_eq!(sys.mem.peek_u32(pte_addr), Some(pte));
+        assert_eq!((sys.ctrl.tlb.hits, sys.ctrl.tlb.misses), (0, 0));
+        sys.mem.write_u32(pte_addr, pte & !0b100, pte_access_attr(AccessType::Store)).unwrap();
+        assert_eq!(translate_debug(&sys, S_PAGE_VA, AccessType::Store), Err(StorePageFault));
+        assert_eq!(translate(&mut sys, S_PAGE_VA, AccessType::Load).unwrap(), S_PAGE_PA);
+        assert_eq!((sys.ctrl.tlb.hits, sys.ctrl.tlb.misses), (0, 1));
+    }
 }
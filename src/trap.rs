@@ -38,6 +38,7 @@ pub enum Interrupt {
     MTimer,
     SExt,
     MExt,
+    LocalCountOverflow, // Sscofpmf
 }
 
 impl Trap {
@@ -128,6 +129,7 @@ impl Interrupt {
             7 => Some(Interrupt::MTimer),
             9 => Some(Interrupt::SExt),
             11 => Some(Interrupt::MExt),
+            13 => Some(Interrupt::LocalCountOverflow),
             _ => None,
         }
     }
@@ -140,6 +142,7 @@ impl Interrupt {
             Interrupt::MTimer => 7,
             Interrupt::SExt => 9,
             Interrupt::MExt => 11,
+            Interrupt::LocalCountOverflow => 13,
         }
     }
 }
@@ -10,24 +10,50 @@ fn run_test(binary_file: &str) {
         dtb: None,
         kernel: None,
         verbose: true,
+        pmp_entries: 16,
+        bitmanip: true,
+        vlen: 128,
+        pause_skip: 0,
+        gdb: None,
+        snapshot: None,
+        snapshot_at: None,
+        restore: None,
+        harts: 1,
+        quantum: 100,
+        sched_seed: None,
+        sbi: false,
+        user: false,
+        args: vec![],
+        semihosting: false,
+        tohost: None,
+        drive: vec![],
+        nic: vec![],
     };
 
     let mut sys = System::from_config(cfg); // 1MB
 
-    if let Ok(()) = run_for_or_until_ecall(&mut sys, 10000) {
-        println!("{:#?}", &sys);
-        panic!("Timeout");
-    }
-
-    let gp = sys.reg(&Reg::new(3)) >> 1;
-    if gp != 0 {
-        println!("{:#?}", sys);
-        panic!("Test number {} failed", gp);
+    // The test reports through HTIF: 0 if passed, or the number of the failed test
+    match run_for_or_until_stop(&mut sys, 100000) {
+        Some(StopReason::Exit(0)) => {}
+        Some(StopReason::Exit(num)) => {
+            println!("{:#?}", sys);
+            panic!("Test number {} failed", num);
+        }
+        _ => {
+            println!("{:#?}", &sys);
+            panic!("Timeout");
+        }
     }
 }
 
 mod rv32mi;
 mod rv32si;
 mod rv32ua;
+mod rv32ud;
+mod rv32uf;
 mod rv32ui;
 mod rv32um;
+mod rv32uzba;
+mod rv32uzbb;
+mod rv32uzbc;
+mod rv32uzbs;
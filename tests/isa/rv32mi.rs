@@ -2,53 +2,57 @@ use super::*;
 
 #[test]
 fn csr() {
-    run_test("target/isa/rv32mi-p-csr.bin");
+    run_test("target/isa/rv32mi-p-csr");
 }
 #[test]
 fn mcsr() {
-    run_test("target/isa/rv32mi-p-mcsr.bin");
+    run_test("target/isa/rv32mi-p-mcsr");
 }
 #[test]
 fn illegal() {
-    run_test("target/isa/rv32mi-p-illegal.bin");
+    run_test("target/isa/rv32mi-p-illegal");
 }
 #[test]
 fn ma_fetch() {
-    run_test("target/isa/rv32mi-p-ma_fetch.bin");
+    run_test("target/isa/rv32mi-p-ma_fetch");
 }
 #[test]
 fn ma_addr() {
-    run_test("target/isa/rv32mi-p-ma_addr.bin");
+    run_test("target/isa/rv32mi-p-ma_addr");
 }
 #[test]
 fn scall() {
-    run_test("target/isa/rv32mi-p-scall.bin");
+    run_test("target/isa/rv32mi-p-scall");
 }
 #[test]
 fn sbreak() {
-    run_test("target/isa/rv32mi-p-sbreak.bin");
+    run_test("target/isa/rv32mi-p-sbreak");
 }
 #[test]
 fn shamt() {
-    run_test("target/isa/rv32mi-p-shamt.bin");
+    run_test("target/isa/rv32mi-p-shamt");
 }
 #[test]
 fn lw() {
-    run_test("target/isa/rv32mi-p-lw-misaligned.bin");
+    run_test("target/isa/rv32mi-p-lw-misaligned");
 }
 #[test]
 fn lh() {
-    run_test("target/isa/rv32mi-p-lh-misaligned.bin");
+    run_test("target/isa/rv32mi-p-lh-misaligned");
 }
 #[test]
 fn sh() {
-    run_test("target/isa/rv32mi-p-sh-misaligned.bin");
+    run_test("target/isa/rv32mi-p-sh-misaligned");
 }
 #[test]
 fn sw() {
-    run_test("target/isa/rv32mi-p-sw-misaligned.bin");
+    run_test("target/isa/rv32mi-p-sw-misaligned");
 }
 #[test]
 fn zicntr() {
-    run_test("target/isa/rv32mi-p-zicntr.bin");
+    run_test("target/isa/rv32mi-p-zicntr");
+}
+#[test]
+fn pmpaddr() {
+    run_test("target/isa/rv32mi-p-pmpaddr");
 }
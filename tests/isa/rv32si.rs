@@ -2,25 +2,25 @@ use super::*;
 
 #[test]
 fn csr() {
-    run_test("target/isa/rv32si-p-csr.bin");
+    run_test("target/isa/rv32si-p-csr");
 }
 #[test]
 fn dirty() {
-    run_test("target/isa/rv32si-p-dirty.bin");
+    run_test("target/isa/rv32si-p-dirty");
 }
 #[test]
 fn ma_fetch() {
-    run_test("target/isa/rv32si-p-ma_fetch.bin");
+    run_test("target/isa/rv32si-p-ma_fetch");
 }
 #[test]
 fn scall() {
-    run_test("target/isa/rv32si-p-scall.bin");
+    run_test("target/isa/rv32si-p-scall");
 }
 #[test]
 fn sbreak() {
-    run_test("target/isa/rv32si-p-sbreak.bin");
+    run_test("target/isa/rv32si-p-sbreak");
 }
 #[test]
 fn wfi() {
-    run_test("target/isa/rv32si-p-wfi.bin");
+    run_test("target/isa/rv32si-p-wfi");
 }
@@ -2,41 +2,41 @@ use super::*;
 
 #[test]
 fn amoadd_w() {
-    run_test("target/isa/rv32ua-p-amoadd_w.bin");
+    run_test("target/isa/rv32ua-p-amoadd_w");
 }
 #[test]
 fn amoand_w() {
-    run_test("target/isa/rv32ua-p-amoand_w.bin");
+    run_test("target/isa/rv32ua-p-amoand_w");
 }
 #[test]
 fn amomax_w() {
-    run_test("target/isa/rv32ua-p-amomax_w.bin");
+    run_test("target/isa/rv32ua-p-amomax_w");
 }
 #[test]
 fn amomaxu_w() {
-    run_test("target/isa/rv32ua-p-amomaxu_w.bin");
+    run_test("target/isa/rv32ua-p-amomaxu_w");
 }
 #[test]
 fn amomin_w() {
-    run_test("target/isa/rv32ua-p-amomin_w.bin");
+    run_test("target/isa/rv32ua-p-amomin_w");
 }
 #[test]
 fn amominu_w() {
-    run_test("target/isa/rv32ua-p-amominu_w.bin");
+    run_test("target/isa/rv32ua-p-amominu_w");
 }
 #[test]
 fn amoor_w() {
-    run_test("target/isa/rv32ua-p-amoor_w.bin");
+    run_test("target/isa/rv32ua-p-amoor_w");
 }
 #[test]
 fn amoxor_w() {
-    run_test("target/isa/rv32ua-p-amoxor_w.bin");
+    run_test("target/isa/rv32ua-p-amoxor_w");
 }
 #[test]
 fn amoswap_w() {
-    run_test("target/isa/rv32ua-p-amoswap_w.bin");
+    run_test("target/isa/rv32ua-p-amoswap_w");
 }
 #[test]
 fn lrsc() {
-    run_test("target/isa/rv32ua-p-lrsc.bin");
+    run_test("target/isa/rv32ua-p-lrsc");
 }
@@ -0,0 +1,42 @@
+use super::*;
+
+#[test]
+fn fadd() {
+    run_test("target/isa/rv32ud-p-fadd");
+}
+#[test]
+fn fclass() {
+    run_test("target/isa/rv32ud-p-fclass");
+}
+#[test]
+fn fcmp() {
+    run_test("target/isa/rv32ud-p-fcmp");
+}
+#[test]
+fn fcvt() {
+    run_test("target/isa/rv32ud-p-fcvt");
+}
+#[test]
+fn fcvt_w() {
+    run_test("target/isa/rv32ud-p-fcvt_w");
+}
+#[test]
+fn fdiv() {
+    run_test("target/isa/rv32ud-p-fdiv");
+}
+#[test]
+fn fmadd() {
+    run_test("target/isa/rv32ud-p-fmadd");
+}
+#[test]
+fn fmin() {
+    run_test("target/isa/rv32ud-p-fmin");
+}
+#[test]
+fn ldst() {
+    run_test("target/isa/rv32ud-p-ldst");
+}
+#[test]
+fn recoding() {
+    run_test("target/isa/rv32ud-p-recoding");
+}
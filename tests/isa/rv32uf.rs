@@ -0,0 +1,46 @@
+use super::*;
+
+#[test]
+fn fadd() {
+    run_test("target/isa/rv32uf-p-fadd");
+}
+#[test]
+fn fclass() {
+    run_test("target/isa/rv32uf-p-fclass");
+}
+#[test]
+fn fcmp() {
+    run_test("target/isa/rv32uf-p-fcmp");
+}
+#[test]
+fn fcvt() {
+    run_test("target/isa/rv32uf-p-fcvt");
+}
+#[test]
+fn fcvt_w() {
+    run_test("target/isa/rv32uf-p-fcvt_w");
+}
+#[test]
+fn fdiv() {
+    run_test("target/isa/rv32uf-p-fdiv");
+}
+#[test]
+fn fmadd() {
+    run_test("target/isa/rv32uf-p-fmadd");
+}
+#[test]
+fn fmin() {
+    run_test("target/isa/rv32uf-p-fmin");
+}
+#[test]
+fn ldst() {
+    run_test("target/isa/rv32uf-p-ldst");
+}
+#[test]
+fn r#move() {
+    run_test("target/isa/rv32uf-p-move");
+}
+#[test]
+fn recoding() {
+    run_test("target/isa/rv32uf-p-recoding");
+}
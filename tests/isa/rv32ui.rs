@@ -2,157 +2,157 @@ use super::*;
 
 #[test]
 fn simple() {
-    run_test("target/isa/rv32ui-p-simple.bin");
+    run_test("target/isa/rv32ui-p-simple");
 }
 #[test]
 fn add() {
-    run_test("target/isa/rv32ui-p-add.bin");
+    run_test("target/isa/rv32ui-p-add");
 }
 #[test]
 fn addi() {
-    run_test("target/isa/rv32ui-p-addi.bin");
+    run_test("target/isa/rv32ui-p-addi");
 }
 #[test]
 fn and() {
-    run_test("target/isa/rv32ui-p-and.bin");
+    run_test("target/isa/rv32ui-p-and");
 }
 #[test]
 fn andi() {
-    run_test("target/isa/rv32ui-p-andi.bin");
+    run_test("target/isa/rv32ui-p-andi");
 }
 #[test]
 fn auipc() {
-    run_test("target/isa/rv32ui-p-auipc.bin");
+    run_test("target/isa/rv32ui-p-auipc");
 }
 #[test]
 fn beq() {
-    run_test("target/isa/rv32ui-p-beq.bin");
+    run_test("target/isa/rv32ui-p-beq");
 }
 #[test]
 fn bge() {
-    run_test("target/isa/rv32ui-p-bge.bin");
+    run_test("target/isa/rv32ui-p-bge");
 }
 #[test]
 fn bgeu() {
-    run_test("target/isa/rv32ui-p-bgeu.bin");
+    run_test("target/isa/rv32ui-p-bgeu");
 }
 #[test]
 fn blt() {
-    run_test("target/isa/rv32ui-p-blt.bin");
+    run_test("target/isa/rv32ui-p-blt");
 }
 #[test]
 fn bltu() {
-    run_test("target/isa/rv32ui-p-bltu.bin");
+    run_test("target/isa/rv32ui-p-bltu");
 }
 #[test]
 fn bne() {
-    run_test("target/isa/rv32ui-p-bne.bin");
+    run_test("target/isa/rv32ui-p-bne");
 }
 #[test]
 fn fence_i() {
-    run_test("target/isa/rv32ui-p-fence_i.bin");
+    run_test("target/isa/rv32ui-p-fence_i");
 }
 #[test]
 fn jal() {
-    run_test("target/isa/rv32ui-p-jal.bin");
+    run_test("target/isa/rv32ui-p-jal");
 }
 #[test]
 fn jalr() {
-    run_test("target/isa/rv32ui-p-jalr.bin");
+    run_test("target/isa/rv32ui-p-jalr");
 }
 #[test]
 fn lb() {
-    run_test("target/isa/rv32ui-p-lb.bin");
+    run_test("target/isa/rv32ui-p-lb");
 }
 #[test]
 fn lbu() {
-    run_test("target/isa/rv32ui-p-lbu.bin");
+    run_test("target/isa/rv32ui-p-lbu");
 }
 #[test]
 fn lh() {
-    run_test("target/isa/rv32ui-p-lh.bin");
+    run_test("target/isa/rv32ui-p-lh");
 }
 #[test]
 fn lhu() {
-    run_test("target/isa/rv32ui-p-lhu.bin");
+    run_test("target/isa/rv32ui-p-lhu");
 }
 #[test]
 fn lw() {
-    run_test("target/isa/rv32ui-p-lw.bin");
+    run_test("target/isa/rv32ui-p-lw");
 }
 #[test]
 fn lui() {
-    run_test("target/isa/rv32ui-p-lui.bin");
+    run_test("target/isa/rv32ui-p-lui");
 }
 #[test]
 fn or() {
-    run_test("target/isa/rv32ui-p-or.bin");
+    run_test("target/isa/rv32ui-p-or");
 }
 #[test]
 fn ori() {
-    run_test("target/isa/rv32ui-p-ori.bin");
+    run_test("target/isa/rv32ui-p-ori");
 }
 #[test]
 fn sb() {
-    run_test("target/isa/rv32ui-p-sb.bin");
+    run_test("target/isa/rv32ui-p-sb");
 }
 #[test]
 fn sh() {
-    run_test("target/isa/rv32ui-p-sh.bin");
+    run_test("target/isa/rv32ui-p-sh");
 }
 #[test]
 fn sw() {
-    run_test("target/isa/rv32ui-p-sw.bin");
+    run_test("target/isa/rv32ui-p-sw");
 }
 #[test]
 fn sll() {
-    run_test("target/isa/rv32ui-p-sll.bin");
+    run_test("target/isa/rv32ui-p-sll");
 }
 #[test]
 fn slli() {
-    run_test("target/isa/rv32ui-p-slli.bin");
+    run_test("target/isa/rv32ui-p-slli");
 }
 #[test]
 fn slt() {
-    run_test("target/isa/rv32ui-p-slt.bin");
+    run_test("target/isa/rv32ui-p-slt");
 }
 #[test]
 fn slti() {
-    run_test("target/isa/rv32ui-p-slti.bin");
+    run_test("target/isa/rv32ui-p-slti");
 }
 #[test]
 fn sltiu() {
-    run_test("target/isa/rv32ui-p-sltiu.bin");
+    run_test("target/isa/rv32ui-p-sltiu");
 }
 #[test]
 fn sltu() {
-    run_test("target/isa/rv32ui-p-sltu.bin");
+    run_test("target/isa/rv32ui-p-sltu");
 }
 #[test]
 fn sra() {
-    run_test("target/isa/rv32ui-p-sra.bin");
+    run_test("target/isa/rv32ui-p-sra");
 }
 #[test]
 fn srai() {
-    run_test("target/isa/rv32ui-p-srai.bin");
+    run_test("target/isa/rv32ui-p-srai");
 }
 #[test]
 fn srl() {
-    run_test("target/isa/rv32ui-p-srl.bin");
+    run_test("target/isa/rv32ui-p-srl");
 }
 #[test]
 fn srli() {
-    run_test("target/isa/rv32ui-p-srli.bin");
+    run_test("target/isa/rv32ui-p-srli");
 }
 #[test]
 fn sub() {
-    run_test("target/isa/rv32ui-p-sub.bin");
+    run_test("target/isa/rv32ui-p-sub");
 }
 #[test]
 fn xor() {
-    run_test("target/isa/rv32ui-p-xor.bin");
+    run_test("target/isa/rv32ui-p-xor");
 }
 #[test]
 fn xori() {
-    run_test("target/isa/rv32ui-p-xori.bin");
+    run_test("target/isa/rv32ui-p-xori");
 }
@@ -2,33 +2,33 @@ use super::*;
 
 #[test]
 fn div() {
-    run_test("target/isa/rv32um-p-div.bin");
+    run_test("target/isa/rv32um-p-div");
 }
 #[test]
 fn divu() {
-    run_test("target/isa/rv32um-p-divu.bin");
+    run_test("target/isa/rv32um-p-divu");
 }
 #[test]
 fn mul() {
-    run_test("target/isa/rv32um-p-mul.bin");
+    run_test("target/isa/rv32um-p-mul");
 }
 #[test]
 fn mulh() {
-    run_test("target/isa/rv32um-p-mulh.bin");
+    run_test("target/isa/rv32um-p-mulh");
 }
 #[test]
 fn mulhsu() {
-    run_test("target/isa/rv32um-p-mulhsu.bin");
+    run_test("target/isa/rv32um-p-mulhsu");
 }
 #[test]
 fn mulhu() {
-    run_test("target/isa/rv32um-p-mulhu.bin");
+    run_test("target/isa/rv32um-p-mulhu");
 }
 #[test]
 fn rem() {
-    run_test("target/isa/rv32um-p-rem.bin");
+    run_test("target/isa/rv32um-p-rem");
 }
 #[test]
 fn remu() {
-    run_test("target/isa/rv32um-p-remu.bin");
+    run_test("target/isa/rv32um-p-remu");
 }
@@ -0,0 +1,14 @@
+use super::*;
+
+#[test]
+fn sh1add() {
+    run_test("target/isa/rv32uzba-p-sh1add");
+}
+#[test]
+fn sh2add() {
+    run_test("target/isa/rv32uzba-p-sh2add");
+}
+#[test]
+fn sh3add() {
+    run_test("target/isa/rv32uzba-p-sh3add");
+}
@@ -0,0 +1,74 @@
+use super::*;
+
+#[test]
+fn andn() {
+    run_test("target/isa/rv32uzbb-p-andn");
+}
+#[test]
+fn clz() {
+    run_test("target/isa/rv32uzbb-p-clz");
+}
+#[test]
+fn cpop() {
+    run_test("target/isa/rv32uzbb-p-cpop");
+}
+#[test]
+fn ctz() {
+    run_test("target/isa/rv32uzbb-p-ctz");
+}
+#[test]
+fn max() {
+    run_test("target/isa/rv32uzbb-p-max");
+}
+#[test]
+fn maxu() {
+    run_test("target/isa/rv32uzbb-p-maxu");
+}
+#[test]
+fn min() {
+    run_test("target/isa/rv32uzbb-p-min");
+}
+#[test]
+fn minu() {
+    run_test("target/isa/rv32uzbb-p-minu");
+}
+#[test]
+fn orc_b() {
+    run_test("target/isa/rv32uzbb-p-orc_b");
+}
+#[test]
+fn orn() {
+    run_test("target/isa/rv32uzbb-p-orn");
+}
+#[test]
+fn rev8() {
+    run_test("target/isa/rv32uzbb-p-rev8");
+}
+#[test]
+fn rol() {
+    run_test("target/isa/rv32uzbb-p-rol");
+}
+#[test]
+fn ror() {
+    run_test("target/isa/rv32uzbb-p-ror");
+}
+#[test]
+fn rori() {
+    run_test("target/isa/rv32uzbb-p-rori");
+}
+#[test]
+fn sext_b() {
+    run_test("target/isa/rv32uzbb-p-sext_b");
+}
+#[test]
+fn sext_h() {
+    run_test("target/isa/rv32uzbb-p-sext_h");
+}
+#[test]
+fn xnor() {
+    run_test("target/isa/rv32uzbb-p-xnor");
+}
+#[test]
+fn zext_h() {
+    run_test("target/isa/rv32uzbb-p-zext_h");
+}
@@ -0,0 +1,14 @@
+use super::*;
+
+#[test]
+fn clmul() {
+    run_test("target/isa/rv32uzbc-p-clmul");
+}
+#[test]
+fn clmulh() {
+    run_test("target/isa/rv32uzbc-p-clmulh");
+}
+#[test]
+fn clmulr() {
+    run_test("target/isa/rv32uzbc-p-clmulr");
+}
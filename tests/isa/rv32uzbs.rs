@@ -0,0 +1,34 @@
+use super::*;
+
+#[test]
+fn bclr() {
+    run_test("target/isa/rv32uzbs-p-bclr");
+}
+#[test]
+fn bclri() {
+    run_test("target/isa/rv32uzbs-p-bclri");
+}
+#[test]
+fn bext() {
+    run_test("target/isa/rv32uzbs-p-bext");
+}
+#[test]
+fn bexti() {
+    run_test("target/isa/rv32uzbs-p-bexti");
+}
+#[test]
+fn binv() {
+    run_test("target/isa/rv32uzbs-p-binv");
+}
+#[test]
+fn binvi() {
+    run_test("target/isa/rv32uzbs-p-binvi");
+}
+#[test]
+fn bset() {
+    run_test("target/isa/rv32uzbs-p-bset");
+}
+#[test]
+fn bseti() {
+    run_test("target/isa/rv32uzbs-p-bseti");
+}
@@ -10,6 +10,24 @@ fn test_with_ref(binary_file: &str, ref_file: &str, num_words: usize, is_hex: bo
         dtb: None,
         kernel: None,
         verbose: true,
+        pmp_entries: 16,
+        bitmanip: true,
+        vlen: 128,
+        pause_skip: 0,
+        gdb: None,
+        snapshot: None,
+        snapshot_at: None,
+        restore: None,
+        harts: 1,
+        quantum: 100,
+        sched_seed: None,
+        sbi: false,
+        user: false,
+        args: vec![],
+        semihosting: false,
+        tohost: None,
+        drive: vec![],
+        nic: vec![],
     };
 
     let mut sys = System::from_config(cfg);